@@ -0,0 +1,247 @@
+//! Carrying a placement along decoder motion vectors between tracking updates
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::polygon_contains;
+use crate::{log_error, log_info};
+
+/// Vectors further than this (pixels) from the region's median motion
+/// count as disagreeing with it
+const INLIER_DISTANCE: f32 = 1.5;
+
+/// Fewest agreeing vectors worth fitting a scale to
+const MIN_SCALE_VECTORS: usize = 3;
+
+/// A block center and the distance its content moved
+pub(crate) type BlockMotion = ((f32, f32), (f32, f32));
+
+/// Shift, and uniform scale about a center
+pub(crate) type Motion = ((f32, f32), f32);
+
+/// Keeps a placement quad glued to its surface on frames the tracker skips,
+/// moving it by the shift and uniform scale that best explain the decoder's
+/// block motion vectors inside it. When too few vectors agree (intra blocks,
+/// occluders crossing, noise) the quad holds still instead, and after
+/// `max_frames` frames without fresh tracking it is dropped so a lost
+/// placement does not drift across the frame
+#[wasm_bindgen]
+pub struct MotionAdvector {
+    block_size: f32,
+    max_frames: u32,
+    min_agreement: f32,
+    /// Corners, tracked or advected since, None once lost
+    quad: Option<[(f32, f32); 4]>,
+    stale_frames: u32,
+    agreement: f32,
+}
+
+#[wasm_bindgen]
+impl MotionAdvector {
+    /// Vectors come one per `block_size` pixel block; `min_agreement` is the
+    /// share of the region's blocks (0..1) that must agree before it moves
+    #[wasm_bindgen(constructor)]
+    pub fn new(block_size: u32, max_frames: u32, min_agreement: f32) -> MotionAdvector {
+        MotionAdvector {
+            block_size: block_size.max(1) as f32,
+            max_frames,
+            min_agreement: min_agreement.clamp(0.0, 1.0),
+            quad: None,
+            stale_frames: 0,
+            agreement: 0.0,
+        }
+    }
+
+    /// Fresh tracking for this frame, flat `[x, y]` corners in order. False,
+    /// keeping the previous state, for anything but four finite corners
+    pub fn track(&mut self, quad: &[f32]) -> bool {
+        if quad.len() != 8 || !quad.iter().all(|c| c.is_finite()) {
+            log_error("WASM compositor: Tracked region needs four finite corners");
+            return false;
+        }
+        self.quad = Some(std::array::from_fn(|k| (quad[k * 2], quad[k * 2 + 1])));
+        self.stale_frames = 0;
+        self.agreement = 1.0;
+        true
+    }
+
+    /// Move the region by this frame's motion vectors, flat `[dx, dy]` per
+    /// block in row-major order over `columns` blocks a row, each the
+    /// distance the block's content moved since the previous frame; NaN
+    /// marks blocks without a vector. Returns the advected corners, empty
+    /// once the region is lost
+    pub fn advect(&mut self, vectors: &[f32], columns: u32) -> Vec<f32> {
+        let Some(quad) = self.quad else {
+            return Vec::new();
+        };
+        self.stale_frames += 1;
+        if self.stale_frames > self.max_frames {
+            log_info("WASM compositor: No tracking for too long, placement dropped");
+            self.quad = None;
+            self.agreement = 0.0;
+            return Vec::new();
+        }
+
+        // On unreliable vectors the quad holds: better to lag the surface
+        // than to jump off it
+        if let Some((shift, scale)) = self.fit(&quad, vectors, columns as usize) {
+            let (cx, cy) = centroid(&quad);
+            let quad = quad.map(|(x, y)| (cx + shift.0 + scale * (x - cx), cy + shift.1 + scale * (y - cy)));
+            self.quad = Some(quad);
+        }
+        self.quad()
+    }
+
+    /// Current corners, empty once lost
+    pub fn quad(&self) -> Vec<f32> {
+        self.quad.map(|quad| quad.iter().flat_map(|&(x, y)| [x, y]).collect()).unwrap_or_default()
+    }
+
+    /// Frames since the last `track`
+    pub fn stale_frames(&self) -> u32 {
+        self.stale_frames
+    }
+
+    /// Share of the region's blocks that agreed on the last motion, 1 on a
+    /// tracked frame, for hosts fading the creative as confidence drops
+    pub fn agreement(&self) -> f32 {
+        self.agreement
+    }
+
+    pub fn reset(&mut self) {
+        self.quad = None;
+        self.stale_frames = 0;
+        self.agreement = 0.0;
+    }
+}
+
+impl MotionAdvector {
+    /// Shift and scale about the quad's centroid, None when the vectors are
+    /// too few or disagree too much to trust
+    fn fit(&mut self, quad: &[(f32, f32); 4], vectors: &[f32], columns: usize) -> Option<Motion> {
+        let half = self.block_size * 0.5;
+        let mut blocks = 0usize;
+        let mut samples = Vec::new();
+        if columns > 0 {
+            for (k, vector) in vectors.chunks_exact(2).enumerate() {
+                let x = (k % columns) as f32 * self.block_size + half;
+                let y = (k / columns) as f32 * self.block_size + half;
+                if !polygon_contains(quad, x, y) {
+                    continue;
+                }
+                blocks += 1;
+                if vector[0].is_finite() && vector[1].is_finite() {
+                    samples.push(((x, y), (vector[0], vector[1])));
+                }
+            }
+        }
+        let Some((motion, inliers)) = fit_motion(&samples, centroid(quad)) else {
+            self.agreement = 0.0;
+            return None;
+        };
+        self.agreement = inliers as f32 / blocks as f32;
+        (self.agreement >= self.min_agreement).then_some(motion)
+    }
+}
+
+/// Shift and scale about `center` that best explain the motion of the
+/// samples, fitted to those agreeing with the median motion; with how many
+/// agreed, None without samples
+pub(crate) fn fit_motion(samples: &[BlockMotion], center: (f32, f32)) -> Option<(Motion, usize)> {
+    if samples.is_empty() {
+        return None;
+    }
+    let shift = (median(samples.iter().map(|s| s.1 .0)), median(samples.iter().map(|s| s.1 .1)));
+    let inliers: Vec<_> = samples
+        .iter()
+        .filter(|(_, (dx, dy))| (dx - shift.0).hypot(dy - shift.1) <= INLIER_DISTANCE)
+        .collect();
+
+    // Least-squares shift and scale of the agreeing blocks, the scale about
+    // the center so the shift is the center's own motion
+    let (cx, cy) = center;
+    let count = inliers.len() as f32;
+    let sums = inliers.iter().fold([0.0; 4], |[x, y, dx, dy], ((bx, by), (bdx, bdy))| {
+        [x + bx, y + by, dx + bdx, dy + bdy]
+    });
+    let (ox, oy) = (sums[0] / count - cx, sums[1] / count - cy);
+    let (vx, vy) = (sums[2] / count, sums[3] / count);
+    let (mut along, mut spread) = (0.0, 0.0);
+    for ((x, y), (dx, dy)) in &inliers {
+        let (px, py) = (x - cx - ox, y - cy - oy);
+        along += (dx - vx) * px + (dy - vy) * py;
+        spread += px * px + py * py;
+    }
+    let growth = if inliers.len() >= MIN_SCALE_VECTORS && spread > 0.0 { along / spread } else { 0.0 };
+    let scale = (1.0 + growth).max(f32::EPSILON);
+    Some((((vx - growth * ox, vy - growth * oy), scale), inliers.len()))
+}
+
+fn centroid(quad: &[(f32, f32); 4]) -> (f32, f32) {
+    let (x, y) = quad.iter().fold((0.0, 0.0), |(x, y), p| (x + p.0, y + p.1));
+    (x / 4.0, y / 4.0)
+}
+
+fn median(values: impl Iterator<Item = f32>) -> f32 {
+    let mut values: Vec<f32> = values.collect();
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vectors for a 6x6 grid of 8 pixel blocks given a motion per block center
+    fn field(motion: impl Fn(f32, f32) -> (f32, f32)) -> Vec<f32> {
+        (0..36)
+            .flat_map(|k| {
+                let (dx, dy) = motion((k % 6) as f32 * 8.0 + 4.0, (k / 6) as f32 * 8.0 + 4.0);
+                [dx, dy]
+            })
+            .collect()
+    }
+
+    const QUAD: [f32; 8] = [8.0, 8.0, 40.0, 8.0, 40.0, 40.0, 8.0, 40.0];
+
+    #[test]
+    fn test_follows_pan_and_zoom() {
+        let mut advector = MotionAdvector::new(8, 10, 0.5);
+        assert!(advector.track(&QUAD));
+        let quad = advector.advect(&field(|_, _| (2.0, -1.0)), 6);
+        assert_eq!(quad, [10.0, 7.0, 42.0, 7.0, 42.0, 39.0, 10.0, 39.0]);
+
+        // Zooming in 5% about the region's center
+        let quad = advector.advect(&field(|x, y| ((x - 26.0) * 0.05, (y - 23.0) * 0.05)), 6);
+        let expected = [9.2, 6.2, 42.8, 6.2, 42.8, 39.8, 9.2, 39.8];
+        assert!(quad.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-3), "{:?}", quad);
+        assert_eq!(advector.stale_frames(), 2);
+    }
+
+    #[test]
+    fn test_holds_on_unreliable_vectors() {
+        let mut advector = MotionAdvector::new(8, 10, 0.5);
+        assert!(advector.track(&QUAD));
+
+        // Every block moving its own way, then intra blocks only
+        let noise = field(|x, y| ((x * 7.0) % 11.0 - 5.0, (y * 3.0) % 13.0 - 6.0));
+        assert_eq!(advector.advect(&noise, 6), QUAD);
+        assert!(advector.agreement() < 0.5);
+        assert_eq!(advector.advect(&[f32::NAN; 72], 6), QUAD);
+        assert_eq!(advector.agreement(), 0.0);
+    }
+
+    #[test]
+    fn test_drops_region_after_max_frames() {
+        let mut advector = MotionAdvector::new(8, 2, 0.5);
+        assert!(advector.track(&QUAD));
+        let still = field(|_, _| (0.0, 0.0));
+        assert_eq!(advector.advect(&still, 6), QUAD);
+        assert_eq!(advector.advect(&still, 6), QUAD);
+        assert!(advector.advect(&still, 6).is_empty());
+
+        assert!(!advector.track(&QUAD[..6]));
+        assert!(advector.quad().is_empty());
+        assert!(advector.track(&QUAD));
+        assert_eq!(advector.quad(), QUAD);
+    }
+}
@@ -0,0 +1,93 @@
+//! Audio-reactive modulation of layer parameters
+
+use wasm_bindgen::prelude::*;
+
+/// Turns per-frame audio energy bands (computed by the host) into a smooth
+/// multiplier for a layer parameter such as scale or opacity, e.g. a subtle
+/// pulse on the beat
+#[wasm_bindgen]
+pub struct AudioModulator {
+    band: usize,
+    depth: f32,
+    attack_seconds: f32,
+    release_seconds: f32,
+    envelope: f32,
+}
+
+#[wasm_bindgen]
+impl AudioModulator {
+    /// Follow energy band `band`; `depth` is the multiplier swing at full
+    /// energy (0.05 = up to 5% larger/more opaque)
+    #[wasm_bindgen(constructor)]
+    pub fn new(band: usize, depth: f32) -> AudioModulator {
+        AudioModulator {
+            band,
+            depth,
+            attack_seconds: 0.01,
+            release_seconds: 0.2,
+            envelope: 0.0,
+        }
+    }
+
+    /// Envelope time constants: fast attack catches beats, slower release
+    /// avoids flicker
+    pub fn set_envelope(&mut self, attack_seconds: f32, release_seconds: f32) {
+        self.attack_seconds = attack_seconds.max(0.0);
+        self.release_seconds = release_seconds.max(0.0);
+    }
+
+    pub fn reset(&mut self) {
+        self.envelope = 0.0;
+    }
+
+    /// Feed this frame's energy bands (normalized 0..1) and frame duration;
+    /// returns the multiplier `1 + depth * envelope`
+    pub fn update(&mut self, bands: &[f32], frame_seconds: f32) -> f32 {
+        let energy = bands.get(self.band).copied().unwrap_or(0.0).clamp(0.0, 1.0);
+
+        let time_constant = if energy > self.envelope {
+            self.attack_seconds
+        } else {
+            self.release_seconds
+        };
+
+        // One-pole follower driven by frame duration, so VFR input is handled
+        let coefficient = if time_constant > 0.0 {
+            1.0 - (-frame_seconds.max(0.0) / time_constant).exp()
+        } else {
+            1.0
+        };
+        self.envelope += (energy - self.envelope) * coefficient;
+
+        1.0 + self.depth * self.envelope
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modulator_rises_on_beat_and_releases() {
+        let mut modulator = AudioModulator::new(1, 0.1);
+        modulator.set_envelope(0.0, 0.1);
+
+        // Instant attack: full swing on the beat
+        let on_beat = modulator.update(&[0.0, 1.0], 1.0 / 30.0);
+        assert!((on_beat - 1.1).abs() < 1e-6);
+
+        // Release decays towards the neutral value over several frames
+        let after = modulator.update(&[0.0, 0.0], 1.0 / 30.0);
+        assert!(after < on_beat && after > 1.0);
+        for _ in 0..60 {
+            modulator.update(&[0.0, 0.0], 1.0 / 30.0);
+        }
+        assert!((modulator.update(&[0.0, 0.0], 1.0 / 30.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_missing_band_is_silent() {
+        let mut modulator = AudioModulator::new(4, 0.5);
+        assert_eq!(modulator.update(&[1.0, 1.0], 0.033), 1.0);
+    }
+}
@@ -0,0 +1,25 @@
+//! Conformance suite generator: `conformance <output dir>` writes the input
+//! bundles and expected output hashes partners certify their ports against
+
+use std::path::Path;
+use std::process::ExitCode;
+use std::{env, fs};
+
+fn main() -> ExitCode {
+    let Some(output) = env::args().nth(1) else {
+        eprintln!("usage: conformance <output dir>");
+        return ExitCode::FAILURE;
+    };
+    let suite = edge_worker_wasm::conformance_suite();
+    for (file, contents) in &suite {
+        let path = Path::new(&output).join(file);
+        let parent = path.parent().map_or(Ok(()), fs::create_dir_all);
+        let written = parent.and_then(|_| fs::write(&path, contents));
+        if let Err(error) = written {
+            eprintln!("conformance: cannot write {}: {}", path.display(), error);
+            return ExitCode::FAILURE;
+        }
+    }
+    println!("Wrote {} files to {}", suite.len(), output);
+    ExitCode::SUCCESS
+}
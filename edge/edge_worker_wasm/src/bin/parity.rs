@@ -0,0 +1,32 @@
+//! Parity check of the native build against a wasm build: `parity` prints
+//! this build's report, `parity <report.json>` checks another build's
+//! (from `parity_report()`) against it, failing on any divergence
+
+use std::process::ExitCode;
+use std::{env, fs};
+
+use edge_worker_wasm::{parity_report, ParityCheck};
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        println!("{}", parity_report());
+        return ExitCode::SUCCESS;
+    };
+    let checked = fs::read_to_string(&path).map_err(|error| error.to_string()).and_then(|report| {
+        ParityCheck::compare(&report)
+    });
+    match checked {
+        Ok(check) => {
+            println!("{}", check.report());
+            if check.identical() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(error) => {
+            eprintln!("parity: cannot check {}: {}", path, error);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,591 @@
+//! Per-pixel blend kernels
+
+use wasm_bindgen::prelude::*;
+
+/// Arithmetic used for the per-channel blend
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendPrecision {
+    /// f32 per channel
+    Float = 0,
+    /// 16.16 fixed point, within 1 LSB of `Float`; cheaper on CPUs with slow
+    /// float units
+    FixedPoint = 1,
+    /// Integer per channel with 16-bit coverage weights, so 16-bit masks
+    /// keep their precision; exact for 8-bit masks
+    #[default]
+    Integer = 2,
+}
+
+/// How blended channel values are quantized back to 8 bits, the same for
+/// every `BlendPrecision`
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards zero
+    Truncate = 0,
+    /// To nearest, halves up
+    #[default]
+    HalfUp = 1,
+    /// To nearest, halves to even
+    HalfEven = 2,
+}
+
+/// How the creative's color combines with the scene under it before the
+/// result is mixed in by coverage; alpha always mixes normally
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The creative's color as is
+    #[default]
+    Normal = 0,
+    /// Darkens; keeps scene shadows on the creative
+    Multiply = 1,
+    /// Lightens, the inverse of multiply
+    Screen = 2,
+    /// Multiply in the scene's shadows, screen in its highlights
+    Overlay = 3,
+    /// Linear dodge, for light-emitting creatives such as screens
+    Add = 4,
+    /// Gentle contrast from the creative, as the W3C compositing spec
+    /// defines it
+    SoftLight = 5,
+}
+
+impl BlendMode {
+    /// Blended channel of 0..1 `base` and `creative`
+    fn channel(self, base: f32, creative: f32) -> f32 {
+        match self {
+            BlendMode::Normal => creative,
+            BlendMode::Multiply => base * creative,
+            BlendMode::Screen => base + creative - base * creative,
+            BlendMode::Overlay => {
+                if base <= 0.5 {
+                    2.0 * base * creative
+                } else {
+                    1.0 - 2.0 * (1.0 - base) * (1.0 - creative)
+                }
+            }
+            BlendMode::Add => (base + creative).min(1.0),
+            BlendMode::SoftLight => {
+                if creative <= 0.5 {
+                    base - (1.0 - 2.0 * creative) * base * (1.0 - base)
+                } else {
+                    let lifted = if base <= 0.25 {
+                        ((16.0 * base - 12.0) * base + 4.0) * base
+                    } else {
+                        base.sqrt()
+                    };
+                    base + (2.0 * creative - 1.0) * (lifted - base)
+                }
+            }
+        }
+    }
+
+    /// Creative color with the mode applied against `pixel`, keeping the
+    /// creative's alpha channel
+    pub fn apply(self, pixel: &[u8], creative: &[u8]) -> [u8; 4] {
+        let mut color = [0, 0, 0, creative[3]];
+        for channel in 0..3 {
+            let base = UNIT_FROM_U8[pixel[channel] as usize];
+            let blended = self.channel(base, UNIT_FROM_U8[creative[channel] as usize]);
+            color[channel] = (blended * 255.0 + 0.5) as u8;
+        }
+        color
+    }
+}
+
+/// The equation mixing the creative into the scene by coverage `a`, for
+/// partners whose pipelines are tuned to a particular convention
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendEquation {
+    /// `creative * a + scene * (1 - a)`
+    #[default]
+    SourceOver = 0,
+    /// `(creative * a + scene) / (1 + a)`: the scene always keeps a share,
+    /// half at full coverage
+    WeightedAverage = 1,
+    /// `max(scene, creative * a)`: the creative only ever lightens
+    Max = 2,
+}
+
+impl BlendEquation {
+    /// Blended 0..255 channel value, unrounded
+    fn mix(self, base: f32, creative: f32, alpha: f32) -> f32 {
+        match self {
+            BlendEquation::SourceOver => creative * alpha + base * (1.0 - alpha),
+            BlendEquation::WeightedAverage => (creative * alpha + base) / (1.0 + alpha),
+            BlendEquation::Max => base.max(creative * alpha),
+        }
+    }
+}
+
+/// How a frame's color channels relate to its alpha channel
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Color independent of alpha, as canvas `ImageData` holds it
+    #[default]
+    Straight = 0,
+    /// Color already multiplied by alpha, as WebGL and `ImageBitmap`
+    /// premultiplied uploads expect
+    Premultiplied = 1,
+}
+
+impl AlphaMode {
+    /// 0..1 straight color of an encoded channel at 0..1 `alpha`
+    fn straight(self, value: u8, alpha: f32) -> f32 {
+        match self {
+            AlphaMode::Straight => UNIT_FROM_U8[value as usize],
+            AlphaMode::Premultiplied if alpha > 0.0 => (UNIT_FROM_U8[value as usize] / alpha).min(1.0),
+            AlphaMode::Premultiplied => 0.0,
+        }
+    }
+}
+
+/// Alpha encodings of the base frame, which the result keeps, and of the
+/// creative
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AlphaModes {
+    pub frame: AlphaMode,
+    pub creative: AlphaMode,
+}
+
+/// Arithmetic, rounding, mode, equation and alpha handling of a layer's
+/// blend, and the depth band and opacity scaling its coverage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlendMath {
+    pub precision: BlendPrecision,
+    pub rounding: Rounding,
+    pub mode: BlendMode,
+    pub equation: BlendEquation,
+    /// Porter-Duff "over" with these encodings; None blends the alpha
+    /// channel like color, which only suits opaque frames
+    pub alpha: Option<AlphaModes>,
+    /// Soft depth test band in scene depth units, 0 for the hard test
+    pub depth_feather: f32,
+    /// 0..1 factor on every pixel's coverage
+    pub opacity: f32,
+}
+
+impl Default for BlendMath {
+    fn default() -> Self {
+        BlendMath {
+            precision: BlendPrecision::default(),
+            rounding: Rounding::default(),
+            mode: BlendMode::default(),
+            equation: BlendEquation::default(),
+            alpha: None,
+            depth_feather: 0.0,
+            opacity: 1.0,
+        }
+    }
+}
+
+/// Float results this close to a whole number are taken as exact, so f32
+/// error (127.0 computed as 126.99999) rounds like the integer paths
+const FLOAT_SNAP: f32 = 1e-3;
+
+fn quantize_float(value: f32, rounding: Rounding) -> u8 {
+    let value = value.clamp(0.0, 255.0);
+    let nearest = value.round();
+    let value = if (value - nearest).abs() < FLOAT_SNAP { nearest } else { value };
+    (match rounding {
+        Rounding::Truncate => value.trunc(),
+        Rounding::HalfUp => (value + 0.5).floor(),
+        Rounding::HalfEven => value.round_ties_even(),
+    }) as u8
+}
+
+/// `value / 65536` quantized
+fn quantize_fixed(value: u32, rounding: Rounding) -> u8 {
+    let (quotient, remainder) = (value >> 16, value & 0xffff);
+    let up = match rounding {
+        Rounding::Truncate => false,
+        Rounding::HalfUp => remainder >= 0x8000,
+        Rounding::HalfEven => remainder > 0x8000 || (remainder == 0x8000 && quotient & 1 == 1),
+    };
+    (quotient + up as u32) as u8
+}
+
+/// `value / WEIGHT_ONE` quantized; the divisor is odd, so there are no
+/// halves to break
+fn quantize_weighted(value: u32, rounding: Rounding) -> u8 {
+    (match rounding {
+        Rounding::Truncate => value / WEIGHT_ONE,
+        Rounding::HalfUp | Rounding::HalfEven => (value + WEIGHT_ONE / 2) / WEIGHT_ONE,
+    }) as u8
+}
+
+/// 1.0 in 16.16 fixed point
+const FIXED_ONE: u32 = 1 << 16;
+
+/// Full coverage weight of the integer blend; a multiple of 255, so 8-bit
+/// mask values map onto it exactly
+pub const WEIGHT_ONE: u32 = 65535;
+
+/// Integer blend weight of 0..1 coverage
+pub fn integer_weight(alpha: f32) -> u32 {
+    (alpha.clamp(0.0, 1.0) * WEIGHT_ONE as f32 + 0.5) as u32
+}
+
+/// `value / 255` for every 8-bit value, so mask coverage needs no division
+pub static UNIT_FROM_U8: [f32; 256] = unit_table();
+
+const fn unit_table() -> [f32; 256] {
+    let mut table = [0.0f32; 256];
+    let mut value = 0;
+    while value < 256 {
+        table[value] = value as f32 / 255.0;
+        value += 1;
+    }
+    table
+}
+
+/// Blend one RGBA pixel in place as `creative * alpha + pixel * (1 - alpha)`,
+/// the creative first combined with the pixel per the blend mode. With alpha
+/// modes set, non-opaque pixels take the Porter-Duff "over" instead, in float.
+/// Other equations run in float on all four channels and ignore alpha modes.
+/// `alpha` is scaled by the layer's opacity here
+pub fn blend_pixel(pixel: &mut [u8], creative: &[u8], alpha: f32, math: BlendMath) {
+    let (pixel, creative) = (&mut pixel[..4], &creative[..4]);
+    let alpha = alpha * math.opacity;
+    if let (Some(modes), BlendEquation::SourceOver) = (math.alpha, math.equation) {
+        // Over opaque pixels, encodings agree and "over" is the plain blend
+        if pixel[3] != 255 || creative[3] != 255 {
+            return blend_over(pixel, creative, alpha, math.mode, modes, math.rounding);
+        }
+    }
+    let mixed;
+    let creative = if math.mode == BlendMode::Normal {
+        creative
+    } else {
+        mixed = math.mode.apply(pixel, creative);
+        &mixed[..]
+    };
+    let rounding = math.rounding;
+    if math.equation != BlendEquation::SourceOver {
+        let alpha = alpha.clamp(0.0, 1.0);
+        for channel in 0..4 {
+            let blended = math.equation.mix(pixel[channel] as f32, creative[channel] as f32, alpha);
+            pixel[channel] = quantize_float(blended, rounding);
+        }
+        return;
+    }
+    match math.precision {
+        BlendPrecision::Float => {
+            for channel in 0..4 {
+                let base_val = pixel[channel] as f32;
+                let creative_val = creative[channel] as f32;
+                let blended = creative_val * alpha + base_val * (1.0 - alpha);
+                pixel[channel] = quantize_float(blended, rounding);
+            }
+        }
+        BlendPrecision::FixedPoint => {
+            // One conversion per pixel, integer math per channel
+            let weight = (alpha.clamp(0.0, 1.0) * FIXED_ONE as f32 + 0.5) as u32;
+            let inverse = FIXED_ONE - weight;
+            for channel in 0..4 {
+                let blended = creative[channel] as u32 * weight + pixel[channel] as u32 * inverse;
+                pixel[channel] = quantize_fixed(blended, rounding);
+            }
+        }
+        BlendPrecision::Integer => {
+            let weight = integer_weight(alpha);
+            let inverse = WEIGHT_ONE - weight;
+            for channel in 0..4 {
+                let blended = creative[channel] as u32 * weight + pixel[channel] as u32 * inverse;
+                pixel[channel] = quantize_weighted(blended, rounding);
+            }
+        }
+    }
+}
+
+/// Blend one RGBA pixel of 16-bit channels in place with a 0..1 creative,
+/// in float throughout so deep frames keep their precision. The blend mode
+/// and equation apply as in `blend_pixel`; alpha modes do not, video frames
+/// being opaque. `alpha` is scaled by the layer's opacity here
+pub(crate) fn blend_pixel_deep(pixel: &mut [u16], creative: [f32; 4], alpha: f32, math: BlendMath) {
+    let alpha = (alpha * math.opacity).clamp(0.0, 1.0);
+    for (channel, (value, creative)) in pixel[..4].iter_mut().zip(creative).enumerate() {
+        let base = *value as f32 / 65535.0;
+        let color = if channel < 3 { math.mode.channel(base, creative) } else { creative };
+        let blended = math.equation.mix(base, color, alpha) * 65535.0;
+        *value = quantize_deep(blended, 65535.0, math.rounding);
+    }
+}
+
+/// `value` rounded onto `0..=max`
+pub(crate) fn quantize_deep(value: f32, max: f32, rounding: Rounding) -> u16 {
+    let value = value.clamp(0.0, max);
+    (match rounding {
+        Rounding::Truncate => value.trunc(),
+        Rounding::HalfUp => (value + 0.5).floor(),
+        Rounding::HalfEven => value.round_ties_even(),
+    }) as u16
+}
+
+/// Porter-Duff "over" of `creative`, its own alpha times `alpha` coverage,
+/// onto `pixel`, decoding and encoding each per its alpha mode. The blend
+/// mode's color mixes in by the scene's alpha, as the W3C compositing spec
+/// has it, so it has no effect over transparency
+fn blend_over(
+    pixel: &mut [u8],
+    creative: &[u8],
+    alpha: f32,
+    mode: BlendMode,
+    modes: AlphaModes,
+    rounding: Rounding,
+) {
+    let (scene, layer) = (UNIT_FROM_U8[pixel[3] as usize], UNIT_FROM_U8[creative[3] as usize]);
+    let source = alpha.clamp(0.0, 1.0) * layer;
+    let coverage = source + scene * (1.0 - source);
+    for channel in 0..3 {
+        let base = modes.frame.straight(pixel[channel], scene);
+        let color = modes.creative.straight(creative[channel], layer);
+        let color = (1.0 - scene) * color + scene * mode.channel(base, color);
+        let premultiplied = color * source + base * scene * (1.0 - source);
+        let value = match modes.frame {
+            AlphaMode::Straight if coverage > 0.0 => premultiplied / coverage,
+            AlphaMode::Straight => 0.0,
+            AlphaMode::Premultiplied => premultiplied,
+        };
+        pixel[channel] = quantize_float(value * 255.0, rounding);
+    }
+    pixel[3] = quantize_float(coverage * 255.0, rounding);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rounded(base: [u8; 4], creative: [u8; 4], alpha: f32, math: BlendMath) -> [u8; 4] {
+        let mut pixel = base;
+        blend_pixel(&mut pixel, &creative, alpha, math);
+        pixel
+    }
+
+    fn blended(base: [u8; 4], creative: [u8; 4], alpha: f32, precision: BlendPrecision) -> [u8; 4] {
+        rounded(base, creative, alpha, BlendMath { precision, ..BlendMath::default() })
+    }
+
+    fn max_deviation(alphas: impl Iterator<Item = f32> + Clone) -> i32 {
+        max_deviation_between(alphas, BlendPrecision::Float, BlendPrecision::FixedPoint, Rounding::default())
+    }
+
+    fn max_deviation_between(
+        alphas: impl Iterator<Item = f32> + Clone,
+        a: BlendPrecision,
+        b: BlendPrecision,
+        rounding: Rounding,
+    ) -> i32 {
+        let mut worst = 0;
+        let math = |precision| BlendMath { precision, rounding, ..BlendMath::default() };
+        for base in (0..=255u8).step_by(3) {
+            for creative in (0..=255u8).step_by(5) {
+                for alpha in alphas.clone() {
+                    let base_px = [base, 255 - base, base / 2, 255];
+                    let creative_px = [creative, creative / 3, 255 - creative, 255];
+                    let float = rounded(base_px, creative_px, alpha, math(a));
+                    let fixed = rounded(base_px, creative_px, alpha, math(b));
+
+                    for channel in 0..4 {
+                        worst = worst.max((float[channel] as i32 - fixed[channel] as i32).abs());
+                    }
+                }
+            }
+        }
+        worst
+    }
+
+    #[test]
+    fn test_fixed_point_within_one_lsb_for_mask_alpha() {
+        // Every 8-bit mask value
+        assert!(max_deviation((0..=255).map(|a| a as f32 / 255.0)) <= 1);
+    }
+
+    #[test]
+    fn test_fixed_point_within_one_lsb_for_fractional_coverage() {
+        // Products such as mask * transmittance are not multiples of 1/255
+        assert!(max_deviation((0..=1000).map(|a| a as f32 / 1000.0)) <= 1);
+    }
+
+    #[test]
+    fn test_weighted_division_is_correctly_rounded() {
+        for value in (0..=255 * WEIGHT_ONE).step_by(97) {
+            let expected = (value as f64 / WEIGHT_ONE as f64).round() as u8;
+            assert_eq!(quantize_weighted(value, Rounding::HalfUp), expected, "value {}", value);
+        }
+    }
+
+    fn mode(mode: BlendMode, base: u8, creative: u8) -> u8 {
+        mode.apply(&[base, base, base, 255], &[creative, creative, creative, 255])[0]
+    }
+
+    #[test]
+    fn test_normal_mode_is_the_creative() {
+        for (base, creative) in [(0, 255), (37, 200), (255, 0)] {
+            assert_eq!(mode(BlendMode::Normal, base, creative), creative);
+        }
+    }
+
+    #[test]
+    fn test_multiply_mode() {
+        assert_eq!(mode(BlendMode::Multiply, 255, 77), 77);
+        assert_eq!(mode(BlendMode::Multiply, 0, 200), 0);
+        assert_eq!(mode(BlendMode::Multiply, 128, 128), 64);
+    }
+
+    #[test]
+    fn test_screen_mode() {
+        assert_eq!(mode(BlendMode::Screen, 0, 77), 77);
+        assert_eq!(mode(BlendMode::Screen, 255, 10), 255);
+        assert_eq!(mode(BlendMode::Screen, 128, 128), 192);
+    }
+
+    #[test]
+    fn test_overlay_mode() {
+        // Multiply below mid-grey, screen above
+        assert_eq!(mode(BlendMode::Overlay, 64, 128), 64);
+        assert_eq!(mode(BlendMode::Overlay, 192, 128), 192);
+        assert_eq!(mode(BlendMode::Overlay, 64, 255), 128);
+        assert_eq!(mode(BlendMode::Overlay, 0, 255), 0);
+    }
+
+    #[test]
+    fn test_add_mode_saturates() {
+        assert_eq!(mode(BlendMode::Add, 100, 50), 150);
+        assert_eq!(mode(BlendMode::Add, 200, 100), 255);
+    }
+
+    #[test]
+    fn test_soft_light_mode() {
+        // Mid-grey creative leaves the scene alone; black and white only nudge it
+        for base in [0, 40, 128, 230, 255] {
+            assert_eq!(mode(BlendMode::SoftLight, base, 128).abs_diff(base), 0, "base {}", base);
+        }
+        assert_eq!(mode(BlendMode::SoftLight, 128, 0), 64);
+        assert_eq!(mode(BlendMode::SoftLight, 64, 255), 128);
+    }
+
+    #[test]
+    fn test_modes_keep_alpha_and_mix_by_coverage() {
+        let math = BlendMath { mode: BlendMode::Multiply, ..BlendMath::default() };
+        let mixed = BlendMode::Multiply.apply(&[200, 100, 50, 255], &[128, 128, 128, 40]);
+        assert_eq!(mixed[3], 40);
+        assert_eq!(rounded([200, 100, 50, 255], [128, 128, 128, 40], 0.5, math), [150, 75, 37, 147]);
+    }
+
+    #[test]
+    fn test_opacity_scales_coverage() {
+        let (base, creative) = ([0, 100, 200, 255], [200, 100, 0, 255]);
+        let faded = BlendMath { opacity: 0.5, ..BlendMath::default() };
+        assert_eq!(rounded(base, creative, 1.0, faded), rounded(base, creative, 0.5, BlendMath::default()));
+        assert_eq!(rounded(base, creative, 0.5, faded), [50, 100, 150, 255]);
+        let hidden = BlendMath { opacity: 0.0, ..BlendMath::default() };
+        assert_eq!(rounded(base, creative, 1.0, hidden), base);
+    }
+
+    #[test]
+    fn test_blend_equations() {
+        let equation = |equation| BlendMath { equation, ..BlendMath::default() };
+        let (base, creative) = ([100, 200, 0, 255], [200, 100, 50, 255]);
+        let blend = |alpha, math| rounded(base, creative, alpha, math);
+
+        assert_eq!(blend(0.5, equation(BlendEquation::SourceOver)), blend(0.5, BlendMath::default()));
+        assert_eq!(blend(0.5, equation(BlendEquation::SourceOver)), [150, 150, 25, 255]);
+        // 200 * 0.5 + 100 over 1.5, and an even mix at full coverage
+        assert_eq!(blend(0.5, equation(BlendEquation::WeightedAverage)), [133, 167, 17, 255]);
+        assert_eq!(blend(1.0, equation(BlendEquation::WeightedAverage)), [150, 150, 25, 255]);
+        // The scene survives wherever it is brighter than the scaled creative
+        assert_eq!(blend(0.5, equation(BlendEquation::Max)), [100, 200, 25, 255]);
+        assert_eq!(blend(1.0, equation(BlendEquation::Max)), [200, 200, 50, 255]);
+        for math in [BlendEquation::WeightedAverage, BlendEquation::Max].map(equation) {
+            assert_eq!(blend(0.0, math), base);
+        }
+    }
+
+    #[test]
+    fn test_alpha_over_transparency_and_premultiplied_input() {
+        let (straight, premultiplied) = (AlphaMode::Straight, AlphaMode::Premultiplied);
+        let over = |frame, creative| BlendMath {
+            alpha: Some(AlphaModes { frame, creative }),
+            ..BlendMath::default()
+        };
+        let clear = [0, 0, 0, 0];
+        let creative = [200, 100, 50, 255];
+
+        // Half coverage over nothing keeps the creative's color and halves
+        // the alpha, where the plain blend darkens towards the empty base
+        assert_eq!(rounded(clear, creative, 0.5, BlendMath::default()), [100, 50, 25, 128]);
+        assert_eq!(rounded(clear, creative, 0.5, over(straight, straight)), [200, 100, 50, 128]);
+        assert_eq!(rounded(clear, creative, 0.5, over(premultiplied, straight)), [100, 50, 25, 128]);
+
+        // A creative's own alpha counts as coverage, decoded per its mode
+        let black = [0, 0, 0, 255];
+        let translucent = [100, 50, 25, 128];
+        assert_eq!(rounded(black, translucent, 1.0, over(straight, premultiplied)), [100, 50, 25, 255]);
+        assert_eq!(rounded(black, translucent, 1.0, over(straight, straight)), [50, 25, 13, 255]);
+
+        // Opaque pixels blend exactly as without alpha modes
+        let opaque = over(premultiplied, premultiplied);
+        let plain = rounded(black, creative, 0.5, BlendMath::default());
+        assert_eq!(rounded(black, creative, 0.5, opaque), plain);
+    }
+
+    #[test]
+    fn test_unit_table_round_trips() {
+        for (value, &unit) in UNIT_FROM_U8.iter().enumerate() {
+            assert_eq!((unit * 255.0 + 0.5) as usize, value);
+        }
+    }
+
+    #[test]
+    fn test_integer_is_rounded_float() {
+        // 128/255 of blue over red: exact values 127.0 and 128.0
+        let out = blended([255, 0, 0, 255], [0, 0, 255, 255], UNIT_FROM_U8[128], BlendPrecision::Integer);
+        assert_eq!(out, [127, 0, 128, 255]);
+
+        // Where float truncates 89.6 down, integer rounds up
+        let out = blended([0, 0, 0, 255], [224, 224, 224, 255], UNIT_FROM_U8[102], BlendPrecision::Integer);
+        assert_eq!(out[0], 90);
+    }
+
+    #[test]
+    fn test_rounding_modes() {
+        let modes = [Rounding::Truncate, Rounding::HalfUp, Rounding::HalfEven];
+        for (value, expected) in [(2.5, [2, 3, 2]), (3.5, [3, 4, 4]), (3.25, [3, 3, 3])] {
+            for (rounding, expected) in modes.into_iter().zip(expected) {
+                assert_eq!(quantize_float(value, rounding), expected, "{} {:?}", value, rounding);
+                assert_eq!(quantize_fixed((value * 65536.0) as u32, rounding), expected);
+            }
+        }
+
+        // f32 error just under a whole number does not truncate it away
+        assert_eq!(quantize_float(126.99999, Rounding::Truncate), 127);
+        assert_eq!(quantize_weighted(WEIGHT_ONE * 7 + 50_000, Rounding::Truncate), 7);
+        assert_eq!(quantize_weighted(WEIGHT_ONE * 7 + 50_000, Rounding::HalfEven), 8);
+    }
+
+    #[test]
+    fn test_precisions_agree_under_every_rounding() {
+        let mask_alphas = (0..=255).map(|a| UNIT_FROM_U8[a]);
+        for rounding in [Rounding::Truncate, Rounding::HalfUp, Rounding::HalfEven] {
+            // Mask coverage is a multiple of 1/255, where float and integer
+            // math compute the same exact value
+            let float = BlendPrecision::Float;
+            let deviation = |other| max_deviation_between(mask_alphas.clone(), float, other, rounding);
+            assert_eq!(deviation(BlendPrecision::Integer), 0);
+            assert!(deviation(BlendPrecision::FixedPoint) <= 1);
+        }
+    }
+
+    #[test]
+    fn test_fixed_point_endpoints_are_exact() {
+        let base = [10u8, 20, 30, 255];
+        let creative = [200u8, 150, 100, 255];
+        assert_eq!(blended(base, creative, 0.0, BlendPrecision::FixedPoint), base);
+        assert_eq!(blended(base, creative, 1.0, BlendPrecision::FixedPoint), creative);
+    }
+}
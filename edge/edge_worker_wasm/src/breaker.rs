@@ -0,0 +1,184 @@
+//! Circuit breaker for layers that keep failing
+
+use wasm_bindgen::prelude::*;
+
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::{composite_layer, log_info};
+
+/// Whether a guarded layer is composited
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Composited every frame
+    #[default]
+    Closed = 0,
+    /// Skipped, with a retry every few frames
+    Open = 1,
+}
+
+/// Wraps `composite_layer` for one layer: after `threshold` consecutive
+/// failing frames (errors logged, e.g. a corrupt or short creative) the
+/// layer is skipped, and one frame in every `retry_frames` tries it again.
+/// Running out of memory is not the layer's fault and does not count
+#[wasm_bindgen]
+pub struct LayerBreaker {
+    threshold: u32,
+    retry_frames: u32,
+    state: BreakerState,
+    consecutive_failures: u32,
+    /// Frames skipped since the breaker last opened or retried
+    skipped: u32,
+    transitions: u32,
+    changed: bool,
+}
+
+#[wasm_bindgen]
+impl LayerBreaker {
+    #[wasm_bindgen(constructor)]
+    pub fn new(threshold: u32, retry_frames: u32) -> LayerBreaker {
+        LayerBreaker {
+            threshold: threshold.max(1),
+            retry_frames: retry_frames.max(1),
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            skipped: 0,
+            transitions: 0,
+            changed: false,
+        }
+    }
+
+    /// Same as `composite_layer`; while the breaker is open the base frame
+    /// is returned without touching the layer
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_layer(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        self.changed = false;
+        if self.state == BreakerState::Open && self.skipped + 1 < self.retry_frames {
+            self.skipped += 1;
+            return base_frame.to_vec();
+        }
+
+        // Errors logged during a frame that was not degraded for memory
+        let errors = health::errors();
+        let frame = composite_layer(
+            base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth, options,
+        );
+        let failed = health::errors() > errors && health::degradation_tier() == DegradationTier::Full;
+        self.record(failed);
+        frame
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    /// The last frame opened or closed the breaker
+    pub fn changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn transitions(&self) -> u32 {
+        self.transitions
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// Close the breaker, e.g. after the creative was replaced
+    pub fn reset(&mut self) {
+        self.consecutive_failures = 0;
+        self.transition(BreakerState::Closed);
+    }
+}
+
+impl LayerBreaker {
+    fn record(&mut self, failed: bool) {
+        if !failed {
+            self.consecutive_failures = 0;
+            self.transition(BreakerState::Closed);
+            return;
+        }
+        self.consecutive_failures += 1;
+        self.skipped = 0;
+        if self.consecutive_failures >= self.threshold {
+            self.transition(BreakerState::Open);
+        }
+    }
+
+    fn transition(&mut self, state: BreakerState) {
+        if state == self.state {
+            return;
+        }
+        self.state = state;
+        self.changed = true;
+        self.transitions += 1;
+        log_info(&match state {
+            BreakerState::Open => format!(
+                "WASM compositor: Layer disabled after {} failing frames, retrying every {} frames",
+                self.consecutive_failures, self.retry_frames
+            ),
+            BreakerState::Closed => "WASM compositor: Layer re-enabled".to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_failures_and_retries() {
+        let (width, height) = (4u32, 4u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![70u8; pixel_count * 4];
+        let creative_frame = vec![200u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let alpha_mask = vec![255u8; pixel_count];
+        let options = LayerOptions::new();
+        let mut breaker = LayerBreaker::new(3, 4);
+        let composite = |breaker: &mut LayerBreaker, creative: &[u8]| {
+            let errors = health::errors();
+            let frame = breaker.composite_layer(
+                &base_frame, creative, &depth_map, &alpha_mask, width, height, 5.0, &options,
+            );
+            (frame, health::errors() - errors)
+        };
+
+        // A short creative fails every frame until the breaker opens
+        for _ in 0..2 {
+            composite(&mut breaker, &creative_frame[..8]);
+        }
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        composite(&mut breaker, &creative_frame[..8]);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(breaker.changed());
+
+        // Open: skipped silently, then retried on the fourth frame
+        for _ in 0..3 {
+            assert_eq!(composite(&mut breaker, &creative_frame[..8]), (base_frame.clone(), 0));
+        }
+        assert_eq!(composite(&mut breaker, &creative_frame[..8]).1, 1);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.changed());
+
+        // A retry that succeeds closes it
+        for _ in 0..3 {
+            composite(&mut breaker, &creative_frame);
+        }
+        let (frame, errors) = composite(&mut breaker, &creative_frame);
+        assert_eq!((frame[..4].to_vec(), errors), (vec![200; 4], 0));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.transitions(), 2);
+    }
+}
@@ -0,0 +1,237 @@
+//! On-device calibration of compositing quality against a frame budget
+
+use wasm_bindgen::prelude::*;
+
+use crate::composite_layer;
+#[cfg(feature = "effects")]
+use crate::effects::EffectChain;
+use crate::layer::LayerOptions;
+#[cfg(feature = "threads")]
+use crate::tiled;
+
+/// Presets from cheapest to most expensive
+const PRESET_LADDER: [&str; 3] = ["mobile-low", "broadcast", "cinematic"];
+
+/// Lowest resolution scale ever recommended
+const MIN_RESOLUTION_SCALE: f32 = 0.25;
+
+/// Minimum wall time per measurement, well above the millisecond resolution
+/// of the wasm clock
+const MIN_SAMPLE_MS: f64 = 30.0;
+
+/// Recommended configuration from `calibrate`, with the measured cost
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    backend: &'static str,
+    preset: String,
+    resolution_scale: f32,
+    effects: bool,
+    frame_ms: f64,
+    budget_ms: f64,
+}
+
+#[wasm_bindgen]
+impl Calibration {
+    /// Compositing backend the costs were measured on: "threads" when the
+    /// frames were split across the thread pool, "cpu" otherwise. WebGPU
+    /// needs a device the host requests asynchronously, so is not measured
+    pub fn backend(&self) -> String {
+        self.backend.to_string()
+    }
+
+    /// Name for `LayerOptions::from_preset`
+    pub fn preset(&self) -> String {
+        self.preset.clone()
+    }
+
+    /// Factor to apply to the composited resolution (1 = full size)
+    pub fn resolution_scale(&self) -> f32 {
+        self.resolution_scale
+    }
+
+    /// Whether an effect chain fits in the budget on top of the preset
+    pub fn effects(&self) -> bool {
+        self.effects
+    }
+
+    /// Measured cost of one frame at the recommended settings, in ms
+    pub fn frame_ms(&self) -> f64 {
+        self.frame_ms
+    }
+
+    /// Fraction of the budget left over (negative when even the cheapest
+    /// settings overrun)
+    pub fn headroom(&self) -> f64 {
+        if self.budget_ms <= 0.0 {
+            return -1.0;
+        }
+        (self.budget_ms - self.frame_ms) / self.budget_ms
+    }
+
+    pub fn options(&self) -> LayerOptions {
+        LayerOptions::from_preset(&self.preset).unwrap_or_default()
+    }
+}
+
+/// Time a battery of composites at the stream's frame size, cheapest preset
+/// first, and recommend the richest settings that fit `frame_budget_ms`
+#[wasm_bindgen]
+pub fn calibrate(width: u32, height: u32, frame_budget_ms: f64) -> Calibration {
+    let frame = TestFrame::new(width, height);
+    #[cfg(feature = "threads")]
+    let backend = if tiled::splits(height as usize) { "threads" } else { "cpu" };
+    #[cfg(not(feature = "threads"))]
+    let backend = "cpu";
+    let mut calibration = Calibration {
+        backend,
+        preset: PRESET_LADDER[0].to_string(),
+        resolution_scale: 1.0,
+        effects: false,
+        frame_ms: 0.0,
+        budget_ms: frame_budget_ms,
+    };
+
+    for (step, name) in PRESET_LADDER.iter().enumerate() {
+        let options = LayerOptions::from_preset(name).unwrap_or_default();
+        let cost = frame.measure(&options);
+        if step > 0 && cost > frame_budget_ms {
+            break;
+        }
+        calibration.preset = name.to_string();
+        calibration.frame_ms = cost;
+    }
+
+    // Too slow even at the cheapest preset: composite at a lower resolution,
+    // cost scaling with pixel count
+    if calibration.frame_ms > frame_budget_ms {
+        let scale = (frame_budget_ms.max(0.0) / calibration.frame_ms).sqrt() as f32;
+        calibration.resolution_scale = ((scale * 20.0).floor() / 20.0).clamp(MIN_RESOLUTION_SCALE, 1.0);
+        calibration.frame_ms *= (calibration.resolution_scale * calibration.resolution_scale) as f64;
+        return calibration;
+    }
+
+    #[cfg(feature = "effects")]
+    {
+        let mut options = calibration.options();
+        let mut effects = EffectChain::new();
+        effects.add_color_adjust(0.05, 1.1, 1.05);
+        effects.add_grain(0.03);
+        options.set_effects(&effects);
+
+        let cost = frame.measure(&options);
+        if cost <= frame_budget_ms {
+            calibration.effects = true;
+            calibration.frame_ms = cost;
+        }
+    }
+
+    calibration
+}
+
+/// Synthetic frame with a centered placement covering a quarter of it
+pub(crate) struct TestFrame {
+    pub base: Vec<u8>,
+    pub creative: Vec<u8>,
+    pub depth: Vec<f32>,
+    pub alpha: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TestFrame {
+    pub fn new(width: u32, height: u32) -> TestFrame {
+        let pixel_count = (width * height) as usize;
+        let (w, h) = (width as usize, height as usize);
+        let mut alpha = vec![0u8; pixel_count];
+        for y in h / 4..h * 3 / 4 {
+            alpha[y * w + w / 4..y * w + w * 3 / 4].fill(200);
+        }
+
+        TestFrame {
+            base: vec![60u8; pixel_count * 4],
+            creative: (0..pixel_count * 4).map(|i| (i % 253) as u8).collect(),
+            depth: vec![10.0f32; pixel_count],
+            alpha,
+            width,
+            height,
+        }
+    }
+
+    /// Mean ms per composite with `options`
+    fn measure(&self, options: &LayerOptions) -> f64 {
+        let mut options = options.clone();
+        options.set_transform(TEST_DX, TEST_DY, TEST_SCALE);
+
+        time_ms(|| {
+            let frame = composite_layer(
+                &self.base, &self.creative, &self.depth, &self.alpha, self.width, self.height, 5.0, &options,
+            );
+            std::hint::black_box(frame);
+        })
+    }
+}
+
+/// Sub-pixel move applied to test composites so the warp does real filtering
+pub(crate) const TEST_DX: f32 = 0.37;
+pub(crate) const TEST_DY: f32 = -0.21;
+pub(crate) const TEST_SCALE: f32 = 0.98;
+
+/// Mean wall time of `run` in ms, repeated until the sample is long enough
+pub(crate) fn time_ms(mut run: impl FnMut()) -> f64 {
+    let start = now_ms();
+    let mut runs = 0;
+    while runs < 3 || (now_ms() - start < MIN_SAMPLE_MS && runs < 50) {
+        run();
+        runs += 1;
+    }
+    (now_ms() - start) / runs as f64
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn now_ms() -> f64 {
+    use std::sync::OnceLock;
+    use std::time::Instant;
+
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generous_budget_gets_full_quality() {
+        let calibration = calibrate(64, 36, 1_000.0);
+        assert_eq!(calibration.preset(), "cinematic");
+        // Too few rows to split, even on a pool of threads
+        assert_eq!(calibration.backend(), "cpu");
+        assert_eq!(calibration.resolution_scale(), 1.0);
+        assert_eq!(calibration.effects(), cfg!(feature = "effects"));
+        assert!(calibration.headroom() > 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "threads")]
+    fn test_reports_the_thread_pool_it_measured() {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        assert_eq!(pool.install(|| calibrate(32, 64, 1_000.0)).backend(), "threads");
+        assert_eq!(pool.install(|| calibrate(32, 63, 1_000.0)).backend(), "cpu");
+    }
+
+    #[test]
+    fn test_impossible_budget_falls_back_to_cheapest() {
+        let calibration = calibrate(64, 36, 0.0);
+        assert_eq!(calibration.preset(), "mobile-low");
+        assert_eq!(calibration.resolution_scale(), MIN_RESOLUTION_SCALE);
+        assert!(!calibration.effects());
+        assert!(calibration.headroom() < 0.0);
+        assert_eq!(calibration.options(), LayerOptions::from_preset("mobile-low").unwrap());
+    }
+}
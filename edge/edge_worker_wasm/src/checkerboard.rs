@@ -0,0 +1,179 @@
+//! Checkerboard rendering of effect chains with temporal reconstruction
+
+use crate::effects::{EffectChain, EffectContext, EffectFrame};
+use crate::log_error;
+use crate::memory::try_zeroed;
+use crate::sampler::Coverage;
+
+/// What the effects did to the pixels the last frame rendered, for the
+/// frame after to reconstruct them from; kept between frames by stateful
+/// compositors. Each frame renders the pixels of the other parity, so only
+/// half the frame is stored, pixel `i` at `i / 2`
+#[derive(Default)]
+pub(crate) struct EffectHistory {
+    /// Output minus input per RGBA channel
+    color: Vec<[i16; 4]>,
+    /// Output minus input coverage
+    coverage: Vec<i32>,
+    width: usize,
+    height: usize,
+    /// Parity, `(x + y) % 2`, of the pixels the next frame renders
+    parity: usize,
+    /// The deltas come from the previous frame of this size
+    valid: bool,
+}
+
+impl EffectHistory {
+    /// Forget the last frame, e.g. for a new stream
+    pub(crate) fn clear(&mut self) {
+        self.valid = false;
+    }
+
+    /// Make room for a `width * height` frame; false when there is none
+    fn fit(&mut self, width: usize, height: usize) -> bool {
+        if (self.width, self.height) == (width, height) {
+            return true;
+        }
+        let len = (width * height).div_ceil(2);
+        let Some((color, coverage)) = try_zeroed(len).zip(try_zeroed(len)) else {
+            return false;
+        };
+        *self = EffectHistory { color, coverage, width, height, parity: 0, valid: false };
+        true
+    }
+}
+
+/// Run `chain` over half of `frame`'s pixels, alternating between frames,
+/// and reconstruct the other half. The effects' change to a skipped pixel
+/// is taken from `history` when it has the last frame, clamped to the
+/// changes around it so moving content does not ghost, or averaged from
+/// the rendered neighbours otherwise. `creative` and `alpha` are the
+/// chain's input, which `frame` holds a copy of
+pub(crate) fn apply_checkerboard<A: Coverage>(
+    chain: &EffectChain,
+    frame: &mut EffectFrame,
+    context: &EffectContext,
+    creative: &[u8],
+    alpha: &[A],
+    mut history: Option<&mut EffectHistory>,
+) {
+    let (width, height) = (frame.width, frame.height);
+    if history.as_mut().is_some_and(|history| !history.fit(width, height)) {
+        log_error("WASM compositor: Out of memory, checkerboard reconstruction is spatial only");
+        history = None;
+    }
+    let parity = history.as_ref().map_or(0, |history| history.parity);
+    frame.checkerboard = Some(parity);
+    chain.apply(frame, context);
+
+    let delta = |frame: &EffectFrame, i: usize| -> ([i16; 4], i32) {
+        let color = [0, 1, 2, 3].map(|c| frame.creative[i * 4 + c] as i16 - creative[i * 4 + c] as i16);
+        (color, frame.alpha[i] as i32 - alpha[i].to_u16() as i32)
+    };
+    // A skipped pixel's neighbours were all rendered, so it can be
+    // reconstructed in place
+    let previous = history.as_ref().filter(|history| history.valid);
+    for y in 0..height {
+        for x in (0..width).filter(|x| (x + y) % 2 != parity) {
+            let neighbours = [
+                (x > 0).then(|| y * width + x - 1),
+                (x + 1 < width).then(|| y * width + x + 1),
+                (y > 0).then(|| (y - 1) * width + x),
+                (y + 1 < height).then(|| (y + 1) * width + x),
+            ];
+            let (mut low, mut high, mut sum) = ([i32::MAX; 5], [i32::MIN; 5], [0i32; 5]);
+            let mut count = 0;
+            for n in neighbours.into_iter().flatten() {
+                let (color, coverage) = delta(frame, n);
+                let values = [color[0] as i32, color[1] as i32, color[2] as i32, color[3] as i32, coverage];
+                for c in 0..5 {
+                    low[c] = low[c].min(values[c]);
+                    high[c] = high[c].max(values[c]);
+                    sum[c] += values[c];
+                }
+                count += 1;
+            }
+            if count == 0 {
+                continue;
+            }
+            let i = y * width + x;
+            let change: [i32; 5] = match previous {
+                Some(history) => {
+                    let (color, coverage) = (history.color[i / 2], history.coverage[i / 2]);
+                    let values = [color[0] as i32, color[1] as i32, color[2] as i32, color[3] as i32, coverage];
+                    [0, 1, 2, 3, 4].map(|c| values[c].clamp(low[c], high[c]))
+                }
+                None => sum.map(|total| (total as f32 / count as f32).round() as i32),
+            };
+            for c in 0..4 {
+                frame.creative[i * 4 + c] = (creative[i * 4 + c] as i32 + change[c]).clamp(0, 255) as u8;
+            }
+            frame.alpha[i] = (alpha[i].to_u16() as i32 + change[4]).clamp(0, 65535) as u16;
+        }
+    }
+
+    if let Some(history) = history {
+        for i in (0..width * height).filter(|i| (i % width + i / width) % 2 == parity) {
+            (history.color[i / 2], history.coverage[i / 2]) = delta(frame, i);
+        }
+        history.parity = 1 - parity;
+        history.valid = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 8;
+
+    /// Checkerboarded when given `history`, None in it for none kept
+    fn render(chain: &EffectChain, creative: &[u8], history: Option<Option<&mut EffectHistory>>) -> Vec<u8> {
+        let alpha = vec![65535u16; creative.len() / 4];
+        let (mut output, mut coverage) = (creative.to_vec(), alpha.clone());
+        let (width, height) = (WIDTH, alpha.len() / WIDTH);
+        let mut frame =
+            EffectFrame { creative: &mut output, alpha: &mut coverage, width, height, checkerboard: None };
+        let context = EffectContext { seed: 0, scale: 1.0 };
+        match history {
+            Some(history) => apply_checkerboard(chain, &mut frame, &context, creative, &alpha, history),
+            None => chain.apply(&mut frame, &context),
+        }
+        output
+    }
+
+    #[test]
+    fn test_reconstructs_uniform_effects_exactly() {
+        // A gradient, darkened evenly: every skipped pixel takes its
+        // neighbours' change, not their color
+        let creative: Vec<u8> = (0..64).flat_map(|i| [60 + i as u8 * 2, 100, 50, 255]).collect();
+        let mut chain = EffectChain::new();
+        chain.add_color_adjust(-0.2, 1.0, 1.0);
+        assert_eq!(render(&chain, &creative, Some(None)), render(&chain, &creative, None));
+    }
+
+    #[test]
+    fn test_history_reconstructs_better_than_neighbours() {
+        // Thin vertical stripes blurred sideways, which the neighbours of a
+        // skipped pixel do not predict
+        let stripe = |i: usize| if i.is_multiple_of(3) { [240, 240, 240, 255] } else { [20, 20, 20, 255] };
+        let creative: Vec<u8> = (0..64).flat_map(stripe).collect();
+        let mut chain = EffectChain::new();
+        chain.add_motion_blur(3.0, 0.0, 1.0);
+        let full = render(&chain, &creative, None);
+        let error = |frame: &[u8]| frame.iter().zip(&full).map(|(&a, &b)| a.abs_diff(b) as u32).sum::<u32>();
+
+        let mut history = EffectHistory::default();
+        let spatial = render(&chain, &creative, Some(Some(&mut history)));
+        let temporal = render(&chain, &creative, Some(Some(&mut history)));
+        assert!(error(&temporal) < error(&spatial), "{} {}", error(&temporal), error(&spatial));
+        // Rendered pixels are exact; the next frame renders the others
+        let rendered = |parity: usize| (0..64).filter(move |&i| (i % WIDTH + i / WIDTH) % 2 == parity);
+        assert!(rendered(1).all(|i| temporal[i * 4..i * 4 + 4] == full[i * 4..i * 4 + 4]));
+        assert_eq!(history.parity, 0);
+
+        // Without the last frame reconstruction falls back to the neighbours
+        history.clear();
+        assert_eq!(render(&chain, &creative, Some(Some(&mut history))), spatial);
+    }
+}
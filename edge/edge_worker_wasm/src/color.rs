@@ -0,0 +1,311 @@
+//! Color space conversion between the BT.601, BT.709 and BT.2020 standards
+
+use std::borrow::Cow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::try_to_mut;
+use crate::yuv::YuvMatrix;
+
+/// D65, the white point of all three standards
+const WHITE: (f32, f32) = (0.3127, 0.3290);
+
+/// Display gamma of BT.1886, used both ways so conversions round trip
+const GAMMA: f32 = 2.4;
+
+/// Code values a signal spans
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorRange {
+    /// 0..255, as canvas and image decoders produce
+    #[default]
+    Full = 0,
+    /// 16..235 (16..240 for chroma), as broadcast video carries
+    Limited = 1,
+}
+
+impl ColorRange {
+    /// Code value of 0 and the span to 1, for luma or RGB
+    fn scale(self) -> (f32, f32) {
+        match self {
+            ColorRange::Full => (0.0, 255.0),
+            ColorRange::Limited => (16.0, 219.0),
+        }
+    }
+
+    /// Span of the chroma difference signals around 128
+    fn chroma_span(self) -> f32 {
+        match self {
+            ColorRange::Full => 255.0,
+            ColorRange::Limited => 224.0,
+        }
+    }
+}
+
+/// The color space a frame or creative is in: the standard's primaries and
+/// matrix, and the range of its code values
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ColorSpace {
+    standard: YuvMatrix,
+    range: ColorRange,
+}
+
+#[wasm_bindgen]
+impl ColorSpace {
+    #[wasm_bindgen(constructor)]
+    pub fn new(standard: YuvMatrix, range: ColorRange) -> ColorSpace {
+        ColorSpace { standard, range }
+    }
+
+    pub fn standard(&self) -> YuvMatrix {
+        self.standard
+    }
+
+    pub fn range(&self) -> ColorRange {
+        self.range
+    }
+}
+
+impl YuvMatrix {
+    /// CIE xy of the red, green and blue primaries
+    fn primaries(self) -> [(f32, f32); 3] {
+        match self {
+            // SMPTE 170M, the 525-line form
+            YuvMatrix::Bt601 => [(0.630, 0.340), (0.310, 0.595), (0.155, 0.070)],
+            YuvMatrix::Bt709 => [(0.640, 0.330), (0.300, 0.600), (0.150, 0.060)],
+            YuvMatrix::Bt2020 => [(0.708, 0.292), (0.170, 0.797), (0.131, 0.046)],
+        }
+    }
+
+    /// Linear RGB to CIE XYZ
+    fn to_xyz(self) -> [[f32; 3]; 3] {
+        let xyz = |(x, y): (f32, f32)| [x / y, 1.0, (1.0 - x - y) / y];
+        let [r, g, b] = self.primaries().map(xyz);
+        let columns = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        // Scale each primary so that equal RGB is the white point
+        let gains = multiply(&invert(&columns), xyz(WHITE));
+        columns.map(|row| [row[0] * gains[0], row[1] * gains[1], row[2] * gains[2]])
+    }
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], vector: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+fn compose(a: &[[f32; 3]; 3], b: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+/// Inverse by cofactors; primaries matrices are never singular
+fn invert(m: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adjugate = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ];
+    let determinant: f32 = (0..3).map(|j| m[0][j] * adjugate[j][0]).sum();
+    adjugate.map(|row| row.map(|value| value / determinant))
+}
+
+/// Y'CbCr code values (on the 8-bit scale, unrounded) of a 0..1 R'G'B'
+/// color per `matrix` and `range`
+pub(crate) fn rgb_to_ycbcr(matrix: YuvMatrix, range: ColorRange, [r, g, b]: [f32; 3]) -> [f32; 3] {
+    let (kr, kb) = matrix.weights();
+    let luma = kr * r + (1.0 - kr - kb) * g + kb * b;
+    let (black, span) = range.scale();
+    let chroma = range.chroma_span();
+    [
+        black + span * luma,
+        128.0 + chroma * (b - luma) / (2.0 * (1.0 - kb)),
+        128.0 + chroma * (r - luma) / (2.0 * (1.0 - kr)),
+    ]
+}
+
+/// The sRGB transfer curve, 0..1 signal to linear light
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of `srgb_to_linear`
+pub(crate) fn linear_to_srgb(light: f32) -> f32 {
+    if light <= 0.0031308 {
+        light * 12.92
+    } else {
+        1.055 * light.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Per-pixel mapping of 8-bit RGB from one color space to another
+enum Conversion {
+    /// Same primaries: only the range changes, one table for every channel
+    Range(Box<[u8; 256]>),
+    /// Other primaries: decode to linear light, re-map, encode
+    Primaries {
+        decode: Box<[f32; 256]>,
+        matrix: [[f32; 3]; 3],
+        to: ColorRange,
+    },
+}
+
+impl Conversion {
+    /// None when the spaces match and colors pass as they are
+    fn between(from: ColorSpace, to: ColorSpace) -> Option<Conversion> {
+        if from == to {
+            return None;
+        }
+        let (black, span) = from.range.scale();
+        let unit = |code: usize| ((code as f32 - black) / span).clamp(0.0, 1.0);
+        if from.standard == to.standard {
+            let mut table = Box::new([0u8; 256]);
+            for (code, value) in table.iter_mut().enumerate() {
+                *value = encode(unit(code), to.range);
+            }
+            return Some(Conversion::Range(table));
+        }
+        let mut decode = Box::new([0.0f32; 256]);
+        for (code, value) in decode.iter_mut().enumerate() {
+            *value = unit(code).powf(GAMMA);
+        }
+        let matrix = compose(&invert(&to.standard.to_xyz()), &from.standard.to_xyz());
+        Some(Conversion::Primaries { decode, matrix, to: to.range })
+    }
+
+    fn apply(&self, pixel: &mut [u8]) {
+        match self {
+            Conversion::Range(table) => {
+                for channel in &mut pixel[..3] {
+                    *channel = table[*channel as usize];
+                }
+            }
+            Conversion::Primaries { decode, matrix, to } => {
+                let linear = [0, 1, 2].map(|c| decode[pixel[c] as usize]);
+                for (channel, light) in pixel.iter_mut().zip(multiply(matrix, linear)) {
+                    *channel = encode(light.clamp(0.0, 1.0).powf(1.0 / GAMMA), *to);
+                }
+            }
+        }
+    }
+}
+
+/// 0..1 signal as an 8-bit code value in `range`
+fn encode(value: f32, range: ColorRange) -> u8 {
+    let (black, span) = range.scale();
+    (black + value * span + 0.5) as u8
+}
+
+/// RGBA `creative` in `from` converted to `to`, alpha untouched; borrowed
+/// through when the spaces match, converted in place when already owned.
+/// Short of memory the creative goes unconverted
+pub(crate) fn convert_creative(
+    mut creative: Cow<'_, [u8]>,
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Cow<'_, [u8]> {
+    let Some(conversion) = Conversion::between(from, to) else {
+        return creative;
+    };
+    match try_to_mut(&mut creative) {
+        Some(pixels) => pixels.chunks_exact_mut(4).for_each(|pixel| conversion.apply(pixel)),
+        None => {
+            log_error("WASM compositor: Out of memory, creative color left unconverted");
+            health::degrade(DegradationTier::Reduced);
+        }
+    }
+    creative
+}
+
+/// An RGBA frame in `from` converted to `to`, alpha untouched. Empty when
+/// memory runs out
+#[wasm_bindgen]
+pub fn convert_color(frame: &[u8], from: &ColorSpace, to: &ColorSpace) -> Vec<u8> {
+    match convert_creative(Cow::Borrowed(frame), *from, *to) {
+        Cow::Owned(converted) => converted,
+        Cow::Borrowed(_) if from != to => Vec::new(),
+        Cow::Borrowed(same) => same.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(rgb: [u8; 3], from: ColorSpace, to: ColorSpace) -> [u8; 3] {
+        let frame = convert_color(&[rgb[0], rgb[1], rgb[2], 77], &from, &to);
+        assert_eq!(frame[3], 77);
+        [frame[0], frame[1], frame[2]]
+    }
+
+    #[test]
+    fn test_range_conversion() {
+        let full = ColorSpace::new(YuvMatrix::Bt709, ColorRange::Full);
+        let limited = ColorSpace::new(YuvMatrix::Bt709, ColorRange::Limited);
+        assert_eq!(convert([0, 128, 255], full, limited), [16, 126, 235]);
+        assert_eq!(convert([16, 126, 235], limited, full), [0, 128, 255]);
+        // Below black and above white clip
+        assert_eq!(convert([4, 250, 128], limited, full), [0, 255, 130]);
+    }
+
+    #[test]
+    fn test_primaries_conversion() {
+        let bt709 = ColorSpace::new(YuvMatrix::Bt709, ColorRange::Full);
+        let bt2020 = ColorSpace::new(YuvMatrix::Bt2020, ColorRange::Full);
+        // Neutrals keep their value; saturated red sits inside the wider gamut
+        assert_eq!(convert([255, 255, 255], bt709, bt2020), [255, 255, 255]);
+        assert_eq!(convert([90, 90, 90], bt709, bt2020), [90, 90, 90]);
+        let red = convert([255, 0, 0], bt709, bt2020);
+        let expected = [210i32, 84, 46];
+        assert!(red.iter().zip(expected).all(|(&a, b)| (a as i32 - b).abs() <= 1), "{:?}", red);
+        // Within a code value or two of where it started, away from the
+        // steep dark end of the curve
+        let orange = [200u8, 120, 60];
+        let back = convert(convert(orange, bt709, bt2020), bt2020, bt709);
+        assert!(back.iter().zip(orange).all(|(&a, b)| a.abs_diff(b) <= 2), "{:?}", back);
+    }
+
+    #[test]
+    fn test_ycbcr_in_both_ranges() {
+        let white = rgb_to_ycbcr(YuvMatrix::Bt709, ColorRange::Full, [1.0, 1.0, 1.0]);
+        assert_eq!(white, [255.0, 128.0, 128.0]);
+        let black = rgb_to_ycbcr(YuvMatrix::Bt2020, ColorRange::Limited, [0.0, 0.0, 0.0]);
+        assert_eq!(black, [16.0, 128.0, 128.0]);
+        // Full-range red reaches the top of Cr
+        let [y, cb, cr] = rgb_to_ycbcr(YuvMatrix::Bt601, ColorRange::Full, [1.0, 0.0, 0.0]);
+        assert!((y - 76.245).abs() < 1e-3 && (cb - 84.97).abs() < 1e-2 && (cr - 255.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_compositor_matches_the_frame_space() {
+        use crate::{composite_layer, LayerOptions};
+
+        let base_frame = [0u8, 0, 0, 255].repeat(2);
+        // Limited-range video white and black
+        let creative_frame = [235u8, 235, 235, 255, 16, 16, 16, 255];
+        let (depth_map, alpha_mask) = ([10.0f32; 2], [255u8; 2]);
+        let mut options = LayerOptions::new();
+        let composite = |options: &LayerOptions| {
+            let (base, creative) = (&base_frame[..], &creative_frame[..]);
+            composite_layer(base, creative, &depth_map, &alpha_mask, 2, 1, 5.0, options)
+        };
+        assert_eq!(composite(&options), creative_frame);
+
+        options.set_creative_color_space(&ColorSpace::new(YuvMatrix::Bt709, ColorRange::Limited));
+        assert_eq!(composite(&options), [255, 255, 255, 255, 0, 0, 0, 255]);
+        // A frame tagged the same way takes the creative as it is
+        options.set_frame_color_space(&ColorSpace::new(YuvMatrix::Bt709, ColorRange::Limited));
+        assert_eq!(composite(&options), creative_frame);
+    }
+}
@@ -0,0 +1,592 @@
+//! Stateful compositing for streams that reuse their buffers every frame
+
+use wasm_bindgen::prelude::*;
+
+#[cfg(feature = "threads")]
+use crate::calibration::now_ms;
+use crate::flow::FlowTracker;
+use crate::fusion::TransformFilter;
+#[cfg(feature = "webgpu")]
+use crate::gpu::GpuBlend;
+use crate::layer::LayerOptions;
+#[cfg(feature = "threads")]
+use crate::tiled::Tiling;
+use crate::transform::LayerTransform;
+#[cfg(feature = "webgpu")]
+use crate::warp_to_frame_into;
+use crate::blend::UNIT_FROM_U8;
+use crate::{blend_span, composite_layer_into, log_error, memory, Bands, WarpedLayer};
+
+/// `composite_layer` for a stream of one frame size, built once by the host.
+/// The output frame and the warp bands are kept between frames, so a steady
+/// stream stops allocating after its first frame
+#[wasm_bindgen]
+pub struct Compositor {
+    width: u32,
+    height: u32,
+    options: LayerOptions,
+    /// Transform the host set, or the flow carried on, before stabilization
+    tracked: LayerTransform,
+    /// The host set options since the last frame, so `tracked` is fresh
+    fresh: bool,
+    flow: Option<FlowTracker>,
+    /// Smoothing of the options' profile, when stabilizing
+    smoothing: Option<TransformFilter>,
+    frame: Vec<u8>,
+    bands: Bands<u8>,
+    /// The last composited frame's warped layer and depth, for synthesized
+    /// frames to be blended from
+    layer: WarpedLayer,
+    depth: Vec<f32>,
+    creative_depth: f32,
+    composited: u64,
+    /// Timings of the tile strategies auto mode is choosing between
+    #[cfg(feature = "threads")]
+    tiling: Tiling,
+    #[cfg(feature = "webgpu")]
+    gpu: Option<GpuBlend>,
+}
+
+#[wasm_bindgen]
+impl Compositor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32, options: &LayerOptions) -> Compositor {
+        Compositor {
+            width,
+            height,
+            options: options.clone(),
+            tracked: options.transform(),
+            fresh: true,
+            flow: None,
+            smoothing: None,
+            frame: Vec::new(),
+            bands: Bands::default(),
+            layer: WarpedLayer::default(),
+            depth: Vec::new(),
+            creative_depth: 0.0,
+            composited: 0,
+            #[cfg(feature = "threads")]
+            tiling: Tiling::default(),
+            #[cfg(feature = "webgpu")]
+            gpu: None,
+        }
+    }
+
+    /// Options for the frames that follow
+    pub fn set_options(&mut self, options: &LayerOptions) {
+        if self.smoothing.is_some() && options.smoothing_profile() != self.options.smoothing_profile() {
+            self.smoothing = Some(options.transform_filter());
+        }
+        self.options = options.clone();
+        self.tracked = options.transform();
+        self.fresh = true;
+    }
+
+    /// Coast through tracking dropouts: on frames the host did not call
+    /// `set_options` for, the transform is carried on by `tracker`'s flow
+    /// from the previous frame, and frames with options track it afresh.
+    /// Once the tracker gives the placement up, frames are dropped as for
+    /// `composite`
+    pub fn set_flow_tracker(&mut self, tracker: FlowTracker) {
+        self.flow = Some(tracker);
+    }
+
+    pub fn clear_flow_tracker(&mut self) {
+        self.flow = None;
+    }
+
+    /// Smooth the transform of the options over time with the
+    /// `TransformFilter` of their smoothing profile, so tracker jitter does
+    /// not make the creative vibrate. Frames are timed by the options'
+    /// `set_time`, so variable frame rates are followed
+    pub fn set_stabilization(&mut self) {
+        self.smoothing = Some(self.options.transform_filter());
+    }
+
+    /// Composite with the transform as set again
+    pub fn clear_stabilization(&mut self) {
+        self.smoothing = None;
+        self.options.set_layer_transform(&self.tracked);
+    }
+
+    /// Transform the last frame was composited with, after stabilization
+    pub fn applied_transform(&self) -> LayerTransform {
+        self.options.transform()
+    }
+
+    /// Same as `composite_layer` at the compositor's frame size, into its
+    /// own frame. Returns false, leaving the frame empty, for a base frame
+    /// of the wrong size, a frame dropped for memory, a placement the flow
+    /// tracker lost or one over the options' latency budget; the host then
+    /// shows its base frame
+    pub fn composite(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        creative_depth: f32,
+    ) -> bool {
+        if base_frame.len() != (self.width * self.height * 4) as usize {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            self.frame.clear();
+            return false;
+        }
+        if !self.place(base_frame) || !self.within_budget(alpha_mask) {
+            self.frame.clear();
+            return false;
+        }
+        self.composite_cpu(base_frame, creative_frame, depth_map, alpha_mask, creative_depth)
+    }
+
+    /// Composite a frame the player synthesized between decoded ones (see
+    /// `Timeline::advance_frame`) by blending the last composited frame's
+    /// warped layer, at its depth, onto `base_frame`, without placing or
+    /// warping again. False, leaving the frame alone, when there is no such
+    /// layer, as after a dropped frame or `reset`, or `base_frame` is the
+    /// wrong size
+    pub fn reuse_frame(&mut self, base_frame: &[u8]) -> bool {
+        if self.frame.is_empty() || self.layer.is_empty() || base_frame.len() != self.frame.len() {
+            return false;
+        }
+        if !memory::try_copy_into(&mut self.frame, base_frame) {
+            log_error("WASM compositor: Out of memory, synthesized frame dropped");
+            self.frame.clear();
+            return false;
+        }
+        let coverage = &self.layer.coverage;
+        let math = self.options.blend_math();
+        blend_span(&mut self.frame, &self.layer.creative, &self.depth, self.creative_depth, math, |i| {
+            UNIT_FROM_U8[coverage[i] as usize]
+        });
+        true
+    }
+
+    /// Copy of the last composited frame
+    pub fn frame(&self) -> Vec<u8> {
+        self.frame.clone()
+    }
+
+    /// Address of the last composited frame in wasm memory, valid until the
+    /// next `composite`, for a zero-copy view; it stays put once the stream
+    /// is running
+    pub fn frame_ptr(&self) -> *const u8 {
+        self.frame.as_ptr()
+    }
+
+    pub fn frame_bytes(&self) -> usize {
+        self.frame.len()
+    }
+
+    pub fn composited_frames(&self) -> u64 {
+        self.composited
+    }
+
+    /// Forget the last frame, and the motion being followed, for a new
+    /// stream of the same size, keeping the buffers
+    pub fn reset(&mut self) {
+        self.frame.clear();
+        self.layer.clear();
+        self.composited = 0;
+        #[cfg(feature = "effects")]
+        self.bands.history.clear();
+        if let Some(flow) = self.flow.as_mut() {
+            flow.reset();
+        }
+        if let Some(smoothing) = self.smoothing.as_mut() {
+            smoothing.reset();
+        }
+        #[cfg(feature = "threads")]
+        self.tiling.reset();
+    }
+}
+
+#[cfg(feature = "threads")]
+#[wasm_bindgen]
+impl Compositor {
+    /// Frames and mean milliseconds per tile strategy, and the one auto
+    /// mode settled on, as JSON:
+    /// `{"chosen":"tiles","rows":{"frames":3,"mean_ms":4.1},...}`
+    pub fn tile_stats(&self) -> String {
+        self.tiling.report()
+    }
+}
+
+impl Compositor {
+    /// Move the transform on to `base_frame`: through the flow tracker,
+    /// if any, then the smoothing. False when the flow lost the placement
+    fn place(&mut self, base_frame: &[u8]) -> bool {
+        let fresh = std::mem::replace(&mut self.fresh, false);
+        if let Some(flow) = self.flow.as_mut() {
+            if fresh {
+                flow.track(base_frame, &self.tracked);
+            } else {
+                match flow.propagate(base_frame) {
+                    Some(transform) => self.tracked = transform,
+                    None => return false,
+                }
+            }
+            self.options.set_layer_transform(&self.tracked);
+        }
+        if let Some(smoothing) = self.smoothing.as_mut() {
+            let tracked = &self.tracked;
+            let smoothed = smoothing.update(tracked, 0.0, tracked, 1.0, self.options.time);
+            self.options.set_layer_transform(&smoothed);
+        }
+        true
+    }
+
+    /// Whether the placed layer meets the options' latency budget
+    fn within_budget(&self, alpha_mask: &[u8]) -> bool {
+        self.options.latency_budget().admit(alpha_mask, self.width, self.height, &self.options)
+    }
+
+    /// `composite` once the base frame is checked and the transform placed
+    fn composite_cpu(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        creative_depth: f32,
+    ) -> bool {
+        #[cfg(feature = "threads")]
+        let (requested, start) = (self.options.tile_strategy, now_ms());
+        #[cfg(feature = "threads")]
+        {
+            self.options.tile_strategy = self.tiling.next(requested);
+        }
+        composite_layer_into(
+            &mut self.frame,
+            &mut self.bands,
+            Some(&mut self.layer),
+            base_frame,
+            creative_frame,
+            depth_map,
+            None,
+            alpha_mask,
+            self.width,
+            self.height,
+            creative_depth,
+            &self.options,
+        );
+        #[cfg(feature = "threads")]
+        {
+            let strategy = std::mem::replace(&mut self.options.tile_strategy, requested);
+            self.tiling.record(strategy, now_ms() - start);
+        }
+        self.composited += 1;
+        self.keep_depth(depth_map, creative_depth);
+        !self.frame.is_empty()
+    }
+
+    /// Keep the depth the kept layer was blended at, for synthesized frames
+    fn keep_depth(&mut self, depth_map: &[f32], creative_depth: f32) {
+        let depth = depth_map.get(..(self.width * self.height) as usize);
+        if !depth.is_some_and(|depth| memory::try_copy_into(&mut self.depth, depth)) {
+            self.layer.clear();
+        }
+        self.creative_depth = creative_depth;
+    }
+}
+
+/// WebGPU blending: the host requests the device (an async step only it
+/// can take) and hands it over; without one, or for blends the shader does
+/// not cover, frames are composited on the CPU as by `composite`
+#[cfg(feature = "webgpu")]
+#[wasm_bindgen]
+impl Compositor {
+    /// Blend on `device` from now on; false, staying on the CPU, when the
+    /// GPU resources cannot be created
+    pub fn use_gpu(&mut self, device: web_sys::GpuDevice) -> bool {
+        match GpuBlend::new(device, self.width, self.height) {
+            Ok(gpu) => {
+                self.gpu = Some(gpu);
+                true
+            }
+            Err(_) => {
+                log_error("WASM compositor: WebGPU unavailable, compositing on the CPU");
+                false
+            }
+        }
+    }
+
+    pub fn uses_gpu(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    /// Same as `composite`, blending in a compute shader: the creative is
+    /// placed on the CPU, then base, creative and depth go up as GPU
+    /// resources. Once the promise resolves, `finish_gpu` returns what
+    /// `composite` would; results can differ from the CPU's by one level.
+    /// Frames the GPU cannot take are composited right away on the CPU
+    pub fn composite_gpu(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        creative_depth: f32,
+    ) -> js_sys::Promise {
+        if self.gpu.as_ref().is_some_and(GpuBlend::is_pending) {
+            log_error("WASM compositor: Previous WebGPU frame not finished, frame dropped");
+            return js_sys::Promise::resolve(&JsValue::FALSE);
+        }
+        let math = self.options.blend_math();
+        let (width, height) = (self.width, self.height);
+        let pixel_count = (width * height) as usize;
+        let ready = base_frame.len() == pixel_count * 4
+            && depth_map.len() >= pixel_count
+            && self.gpu.is_some()
+            && GpuBlend::supports(&math);
+        if !ready {
+            let composited = self.composite(base_frame, creative_frame, depth_map, alpha_mask, creative_depth);
+            return js_sys::Promise::resolve(&JsValue::from_bool(composited));
+        }
+        if !self.place(base_frame) || !self.within_budget(alpha_mask) {
+            self.frame.clear();
+            return js_sys::Promise::resolve(&JsValue::FALSE);
+        }
+        // Warped into the kept layer, so a steady stream reuses its buffers
+        let placed = warp_to_frame_into(
+            &mut self.layer,
+            &mut self.bands,
+            (creative_frame, alpha_mask),
+            width,
+            height,
+            &self.options,
+            self.options.frame_color,
+        );
+        if let (true, Some(gpu)) = (placed, self.gpu.as_mut()) {
+            if memory::try_copy_into(&mut self.frame, base_frame) {
+                let (creative, coverage) = (&self.layer.creative, &self.layer.coverage);
+                match gpu.dispatch(base_frame, creative, coverage, depth_map, creative_depth, &math) {
+                    Ok(readback) => {
+                        self.composited += 1;
+                        self.keep_depth(depth_map, creative_depth);
+                        return readback;
+                    }
+                    Err(_) => log_error("WASM compositor: WebGPU composite failed, compositing on the CPU"),
+                }
+            }
+        }
+        let composited = self.composite_cpu(base_frame, creative_frame, depth_map, alpha_mask, creative_depth);
+        js_sys::Promise::resolve(&JsValue::from_bool(composited))
+    }
+
+    /// Collect the frame of the last `composite_gpu` once its promise has
+    /// resolved; false as for `composite`. A frame must be finished before
+    /// the next `composite_gpu`
+    pub fn finish_gpu(&mut self) -> bool {
+        if let Some(gpu) = self.gpu.as_mut().filter(|gpu| gpu.is_pending()) {
+            if gpu.read_into(&mut self.frame).is_err() {
+                log_error("WASM compositor: WebGPU readback failed, frame dropped");
+                self.frame.clear();
+            }
+        }
+        !self.frame.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::PlacementRng;
+    use crate::timeline::Timeline;
+    use crate::{composite_layer, memory};
+
+    #[test]
+    fn test_matches_composite_layer_without_reallocating() {
+        let (width, height) = (64u32, 32u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 211) as u8).collect();
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 197) as u8).collect();
+        let depth_map = vec![10.0f32; pixel_count];
+        let mut alpha_mask = vec![0u8; pixel_count];
+        alpha_mask[8 * 64..24 * 64].fill(200);
+        let mut options = LayerOptions::new();
+        options.set_transform(1.5, -2.25, 1.1);
+        let expected = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+
+        let mut compositor = Compositor::new(width, height, &options);
+        assert!(compositor.composite(&base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0));
+        assert_eq!(compositor.frame(), expected);
+
+        // Every fallible allocation failing is harmless once the buffers exist
+        let frame = compositor.frame_ptr();
+        memory::fail_allocations(u64::MAX);
+        let composited = compositor.composite(&base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0);
+        memory::fail_allocations(0);
+        assert!(composited);
+        assert_eq!(compositor.frame(), expected);
+        assert_eq!(compositor.frame_ptr(), frame);
+        assert_eq!(compositor.composited_frames(), 2);
+
+        compositor.reset();
+        assert_eq!(compositor.frame_bytes(), 0);
+        assert_eq!(compositor.composited_frames(), 0);
+    }
+
+    #[test]
+    fn test_stabilizes_tracker_jitter() {
+        let (width, height) = (8u32, 4u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let (depth_map, alpha_mask) = (vec![10.0f32; pixel_count], vec![255u8; pixel_count]);
+        let mut options = LayerOptions::new();
+        assert!(options.set_smoothing_profile("rigid"));
+        let mut compositor = Compositor::new(width, height, &options);
+        compositor.set_stabilization();
+
+        let mut jitter = |pts: f64, dx: f32| {
+            options.set_time(pts);
+            options.set_transform(dx, 0.0, 1.0);
+            compositor.set_options(&options);
+            assert!(compositor.composite(&base_frame, &base_frame, &depth_map, &alpha_mask, 5.0));
+            compositor.applied_transform().dx()
+        };
+        assert_eq!(jitter(0.0, 2.0), 2.0);
+        let shaken = jitter(0.04, 3.0);
+        assert!(shaken > 2.0 && shaken < 2.5, "{}", shaken);
+
+        compositor.clear_stabilization();
+        assert_eq!(compositor.applied_transform().dx(), 3.0);
+    }
+
+    #[test]
+    fn test_flow_carries_transform_without_options() {
+        let (width, height) = (64usize, 48usize);
+        let mut rng = PlacementRng::from_seed(3);
+        let texture: Vec<u8> = (0..(width + 8) * height).map(|_| rng.next_u32() as u8).collect();
+        // The texture panned `dx` pixels right
+        let frame = |dx: usize| -> Vec<u8> {
+            (0..width * height)
+                .flat_map(|i| {
+                    let v = texture[(i / width) * (width + 8) + i % width + 8 - dx];
+                    [v, v, v, 255]
+                })
+                .collect()
+        };
+        let (depth_map, alpha_mask) = (vec![10.0f32; width * height], vec![255u8; width * height]);
+        let mut options = LayerOptions::new();
+        options.set_transform(1.0, 0.0, 1.0);
+        let mut compositor = Compositor::new(width as u32, height as u32, &options);
+        let mut tracker = FlowTracker::new(width as u32, height as u32, 8, 4, 1, 0.5);
+        tracker.set_region(16.0, 12.0, 32.0, 24.0);
+        compositor.set_flow_tracker(tracker);
+
+        let mut composite = |base: &[u8]| compositor.composite(base, base, &depth_map, &alpha_mask, 5.0);
+        assert!(composite(&frame(0)));
+        // No options for this frame: the transform follows the pan
+        assert!(composite(&frame(3)));
+        let coasted = compositor.applied_transform();
+        assert!((coasted.dx() - 4.0).abs() < 0.1 && coasted.dy().abs() < 0.1, "{:?}", coasted);
+
+        // Past the tracker's one stale frame the placement is dropped, until
+        // the host tracks it again
+        assert!(!compositor.composite(&frame(3), &frame(3), &depth_map, &alpha_mask, 5.0));
+        compositor.set_options(&options);
+        assert!(compositor.composite(&frame(3), &frame(3), &depth_map, &alpha_mask, 5.0));
+        assert_eq!(compositor.applied_transform().dx(), 1.0);
+    }
+
+    #[test]
+    fn test_synthesized_frames_blend_last_layer() {
+        let (width, height) = (16u32, 8u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 251) as u8).collect();
+        let creative_frame = vec![200u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        // A half-covered patch in the middle of the frame
+        let inside = |i: usize| (4..12).contains(&(i % 16)) && (2..6).contains(&(i / 16));
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| if inside(i) { 128 } else { 0 }).collect();
+        let options = LayerOptions::new();
+        let mut compositor = Compositor::new(width, height, &options);
+        assert!(!compositor.reuse_frame(&base_frame));
+
+        let mut timeline = Timeline::new();
+        timeline.advance_frame(0.0, false);
+        assert!(compositor.composite(&base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0));
+        let frame = compositor.frame_ptr();
+
+        // 60 to 120 fps: the in-between frame has a base of its own, and the
+        // layer is blended onto it without being placed or warped again
+        timeline.advance_frame(1.0 / 120.0, true);
+        assert!(timeline.is_synthesized());
+        let synthesized: Vec<u8> = base_frame.iter().map(|&v| v.wrapping_add(40)).collect();
+        assert!(compositor.reuse_frame(&synthesized));
+        let output = compositor.frame();
+        let outside = (0..pixel_count).find(|&i| !inside(i)).unwrap();
+        assert_ne!(output[outside * 4..outside * 4 + 4], base_frame[outside * 4..outside * 4 + 4]);
+        let layer = (&creative_frame, &depth_map, &alpha_mask);
+        let expected = composite_layer(&synthesized, layer.0, layer.1, layer.2, width, height, 5.0, &options);
+        assert_eq!(output, expected);
+        assert_eq!(compositor.frame_ptr(), frame);
+        assert_eq!(compositor.composited_frames(), 1);
+
+        compositor.reset();
+        assert!(!compositor.reuse_frame(&synthesized));
+    }
+
+    #[test]
+    #[cfg(feature = "effects")]
+    fn test_checkerboard_effects_reconstruct_from_last_frame() {
+        let (width, height) = (48u32, 16u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        // Thin stripes blurred sideways, which neighbours do not predict
+        let stripe = |i: usize| if i.is_multiple_of(3) { [240, 240, 240, 255] } else { [20, 20, 20, 255] };
+        let creative_frame: Vec<u8> = (0..pixel_count).flat_map(stripe).collect();
+        let (depth_map, alpha_mask) = (vec![10.0f32; pixel_count], vec![255u8; pixel_count]);
+        let mut effects = crate::effects::EffectChain::new();
+        effects.add_motion_blur(3.0, 0.0, 1.0);
+        let mut options = LayerOptions::new();
+        options.set_effects(&effects);
+        let composite = |options: &LayerOptions| {
+            composite_layer(&base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, options)
+        };
+        let full = composite(&options);
+        options.set_checkerboard_effects(true);
+        let spatial = composite(&options);
+        let error = |frame: &[u8]| frame.iter().zip(&full).map(|(&a, &b)| a.abs_diff(b) as u32).sum::<u32>();
+        assert!(error(&spatial) > 0);
+
+        let mut compositor = Compositor::new(width, height, &options);
+        assert!(compositor.composite(&base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0));
+        assert_eq!(compositor.frame(), spatial);
+        assert!(compositor.composite(&base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0));
+        assert!(error(&compositor.frame()) < error(&spatial));
+
+        // A new stream has no last frame to reconstruct from
+        compositor.reset();
+        assert!(compositor.composite(&base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0));
+        assert_eq!(compositor.frame(), spatial);
+    }
+
+    #[test]
+    fn test_drops_layers_over_the_latency_budget() {
+        let (width, height) = (16u32, 8u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let (depth_map, alpha_mask) = (vec![10.0f32; pixel_count], vec![255u8; pixel_count]);
+        let mut options = LayerOptions::new();
+        options.set_transform(0.5, 0.0, 1.0);
+        options.set_latency_budget(64, 0);
+        let mut compositor = Compositor::new(width, height, &options);
+        assert!(!compositor.composite(&base_frame, &base_frame, &depth_map, &alpha_mask, 5.0));
+        assert_eq!(compositor.frame_bytes(), 0);
+
+        options.set_latency_budget(0, 0);
+        compositor.set_options(&options);
+        assert!(compositor.composite(&base_frame, &base_frame, &depth_map, &alpha_mask, 5.0));
+    }
+
+    #[test]
+    fn test_rejects_wrong_size_base_frame() {
+        let mut compositor = Compositor::new(4, 2, &LayerOptions::new());
+        assert!(!compositor.composite(&[0; 8], &[], &[], &[], 5.0));
+        assert_eq!(compositor.frame_bytes(), 0);
+    }
+}
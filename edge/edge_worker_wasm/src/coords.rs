@@ -0,0 +1,168 @@
+//! Coordinate conventions of host-supplied geometry
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::Rect;
+
+/// Corner the host's y axis starts from
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Origin {
+    /// y grows downwards, as in frame pixels
+    #[default]
+    TopLeft = 0,
+    /// y grows upwards, as in most tracking and GL data
+    BottomLeft = 1,
+}
+
+/// How a host expresses transforms, rectangles and masks; the compositor
+/// converts to top-left frame pixels on the way in and back on the way out
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CoordinateSystem {
+    origin: Origin,
+    normalized: bool,
+    flip_masks: bool,
+}
+
+#[wasm_bindgen]
+impl CoordinateSystem {
+    /// `normalized` means positions and sizes are fractions of the frame
+    #[wasm_bindgen(constructor)]
+    pub fn new(origin: Origin, normalized: bool) -> CoordinateSystem {
+        CoordinateSystem { origin, normalized, flip_masks: false }
+    }
+
+    /// Mask rasters arrive bottom row first
+    pub fn set_flip_masks(&mut self, flip_masks: bool) {
+        self.flip_masks = flip_masks;
+    }
+}
+
+impl CoordinateSystem {
+    pub fn flip_masks(&self) -> bool {
+        self.flip_masks
+    }
+
+    fn units(&self, width: u32, height: u32) -> (f32, f32) {
+        if self.normalized {
+            (width as f32, height as f32)
+        } else {
+            (1.0, 1.0)
+        }
+    }
+
+    /// A displacement, e.g. a layer shift, in frame pixels
+    pub fn offset_to_pixels(&self, dx: f32, dy: f32, width: u32, height: u32) -> (f32, f32) {
+        let (unit_x, unit_y) = self.units(width, height);
+        let dy = match self.origin {
+            Origin::TopLeft => dy,
+            Origin::BottomLeft => -dy,
+        };
+        (dx * unit_x, dy * unit_y)
+    }
+
+    pub fn offset_from_pixels(&self, dx: f32, dy: f32, width: u32, height: u32) -> (f32, f32) {
+        let (unit_x, unit_y) = self.units(width, height);
+        let dy = match self.origin {
+            Origin::TopLeft => dy,
+            Origin::BottomLeft => -dy,
+        };
+        (dx / unit_x, dy / unit_y)
+    }
+
+    pub fn point_to_pixels(&self, x: f32, y: f32, width: u32, height: u32) -> (f32, f32) {
+        let (unit_x, unit_y) = self.units(width, height);
+        match self.origin {
+            Origin::TopLeft => (x * unit_x, y * unit_y),
+            Origin::BottomLeft => (x * unit_x, height as f32 - y * unit_y),
+        }
+    }
+
+    /// A frame pixel position in the host's coordinates
+    pub fn point_from_pixels(&self, x: f32, y: f32, width: u32, height: u32) -> (f32, f32) {
+        let (unit_x, unit_y) = self.units(width, height);
+        match self.origin {
+            Origin::TopLeft => (x / unit_x, y / unit_y),
+            Origin::BottomLeft => (x / unit_x, (height as f32 - y) / unit_y),
+        }
+    }
+
+    /// A rectangle given by its corner nearest the origin, in frame pixels
+    pub fn rect_to_pixels(&self, rect: &Rect, width: u32, height: u32) -> Rect {
+        let (unit_x, unit_y) = self.units(width, height);
+        let scaled = Rect::new(rect.x * unit_x, rect.y * unit_y, rect.width * unit_x, rect.height * unit_y);
+        match self.origin {
+            Origin::TopLeft => scaled,
+            Origin::BottomLeft => Rect { y: height as f32 - scaled.bottom(), ..scaled },
+        }
+    }
+}
+
+/// `width * height` mask with its rows in reverse order
+pub(crate) fn flip_rows<T: Copy>(mask: &[T], width: usize, height: usize) -> Vec<T> {
+    mask[..width * height]
+        .chunks_exact(width.max(1))
+        .rev()
+        .flatten()
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{composite_layer, LayerOptions};
+
+    #[test]
+    fn test_bottom_left_normalized_rect() {
+        let coordinates = CoordinateSystem::new(Origin::BottomLeft, true);
+        let rect = coordinates.rect_to_pixels(&Rect::new(0.25, 0.0, 0.5, 0.25), 200, 100);
+        assert_eq!(rect, Rect::new(50.0, 75.0, 100.0, 25.0));
+    }
+
+    #[test]
+    fn test_offsets_round_trip() {
+        let coordinates = CoordinateSystem::new(Origin::BottomLeft, true);
+        let (dx, dy) = coordinates.offset_to_pixels(0.1, 0.2, 200, 100);
+        assert_eq!((dx, dy), (20.0, -20.0));
+        assert_eq!(coordinates.offset_from_pixels(dx, dy, 200, 100), (0.1, 0.2));
+
+        // Default is top-left pixels, unchanged
+        assert_eq!(CoordinateSystem::default().offset_to_pixels(3.0, 4.0, 200, 100), (3.0, 4.0));
+    }
+
+    #[test]
+    fn test_layer_transform_and_mask_conventions() {
+        let (width, height) = (16u32, 8u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![50u8; pixel_count * 4];
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 251) as u8).collect();
+        let depth_map = vec![10.0f32; pixel_count];
+        let mut alpha_mask = vec![0u8; pixel_count];
+        alpha_mask[2 * 16 + 4..2 * 16 + 10].fill(255);
+
+        let mut options = LayerOptions::new();
+        options.set_transform(2.0, 3.0, 1.0);
+        let expected = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+
+        // The same shift in bottom-left normalized units, with the mask
+        // delivered bottom row first; the creative raster stays top-down
+        let mut coordinates = CoordinateSystem::new(Origin::BottomLeft, true);
+        coordinates.set_flip_masks(true);
+        options.set_coordinates(&coordinates);
+        options.set_transform(2.0 / 16.0, -3.0 / 8.0, 1.0);
+        let flipped = flip_rows(&alpha_mask, 16, 8);
+        let composited = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &flipped, width, height, 5.0, &options,
+        );
+        assert_eq!(composited, expected);
+    }
+
+    #[test]
+    fn test_flip_rows() {
+        assert_eq!(flip_rows(&[1, 2, 3, 4, 5, 6], 2, 3), vec![5, 6, 3, 4, 1, 2]);
+    }
+}
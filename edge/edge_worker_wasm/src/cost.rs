@@ -0,0 +1,279 @@
+//! Per-stage cost accounting for composited frames
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::blend::UNIT_FROM_U8;
+use crate::calibration::{time_ms, TestFrame, TEST_DX, TEST_DY, TEST_SCALE};
+#[cfg(feature = "effects")]
+use crate::effects::EffectChain;
+use crate::geometry::mask_bounds;
+use crate::layer::{LayerOptions, LayerWarp};
+use crate::sampler::{FilterMode, Source};
+use crate::blend_span;
+
+/// Bytes moved per pixel: RGBA read and written
+const COPY_BYTES: usize = 8;
+/// Four RGBA + coverage taps read, one written
+const WARP_BILINEAR_BYTES: usize = 25;
+const WARP_NEAREST_BYTES: usize = 10;
+/// Depth, creative and coverage read, result updated in place
+const BLEND_BYTES: usize = 17;
+/// RGBA and coverage read, RGBA written
+#[cfg(feature = "effects")]
+const EFFECT_BYTES: usize = 9;
+
+/// Default ns per pixel, measured at 1080p on a desktop x86_64 core; `measure`
+/// on the device is far more representative
+const DEFAULT_RATES: [(&str, f64); 7] = [
+    ("copy", 0.5),
+    ("warp_bilinear", 47.0),
+    ("warp_nearest", 24.0),
+    ("blend", 3.1),
+    ("effect:color_adjust", 4.5),
+    ("effect:grain", 6.4),
+    ("effect:opacity", 1.8),
+];
+
+#[derive(Serialize)]
+struct StageCost {
+    stage: String,
+    pixels: usize,
+    ms: f64,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct FrameCost {
+    total_ms: f64,
+    total_bytes: usize,
+    stages: Vec<StageCost>,
+}
+
+/// Attributes the estimated time and memory traffic of `composite_layer` to
+/// its stages and to each effect, from per-pixel rates, so experiments can
+/// correlate features with device cost
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct CostModel {
+    /// ns per pixel by stage
+    rates: BTreeMap<String, f64>,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            rates: DEFAULT_RATES.iter().map(|&(stage, rate)| (stage.to_string(), rate)).collect(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl CostModel {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CostModel {
+        CostModel::default()
+    }
+
+    /// Time every stage on a synthetic frame of the stream's size
+    pub fn measure(width: u32, height: u32) -> CostModel {
+        let mut model = CostModel::default();
+        let frame = TestFrame::new(width, height);
+        let pixel_count = (width * height) as usize;
+        if pixel_count == 0 {
+            return model;
+        }
+        let per_pixel = |ms: f64, pixels: usize| ms * 1e6 / pixels.max(1) as f64;
+
+        let copy = time_ms(|| {
+            std::hint::black_box(frame.base.to_vec());
+        });
+        model.set_rate("copy", per_pixel(copy, pixel_count));
+
+        let source = Source {
+            creative: &frame.creative,
+            alpha: &frame.alpha,
+            width: width as usize,
+            height: height as usize,
+        };
+        if let Some(bounds) = mask_bounds(&frame.alpha, width, height) {
+            let mut creative = vec![0u8; pixel_count * 4];
+            let mut alpha = vec![0u8; pixel_count];
+            let filters = [("warp_bilinear", FilterMode::Bilinear), ("warp_nearest", FilterMode::Nearest)];
+            for (stage, filter) in filters {
+                let mut options = LayerOptions::new();
+                options.set_transform(TEST_DX, TEST_DY, TEST_SCALE);
+                options.set_filter_mode(filter);
+                let warp = LayerWarp::new(&source, &bounds, &options);
+                let (rows, columns) = warp.extent();
+                let ms = time_ms(|| {
+                    warp.warp_rows(0..height as usize, &mut creative, &mut alpha);
+                });
+                model.set_rate(stage, per_pixel(ms, rows * columns));
+            }
+        }
+
+        let mut result = frame.base.clone();
+        let blend = time_ms(|| {
+            blend_span(&mut result, &frame.creative, &frame.depth, 5.0, Default::default(), |i| {
+                UNIT_FROM_U8[frame.alpha[i] as usize]
+            });
+        });
+        model.set_rate("blend", per_pixel(blend, pixel_count));
+
+        #[cfg(feature = "effects")]
+        for (stage, add) in [
+            ("effect:color_adjust", (|chain| chain.add_color_adjust(0.1, 1.1, 1.1)) as fn(&mut EffectChain)),
+            ("effect:grain", |chain| chain.add_grain(0.05)),
+            ("effect:opacity", |chain| chain.add_opacity(0.8)),
+        ] {
+            let mut chain = EffectChain::new();
+            add(&mut chain);
+            let mut options = LayerOptions::new();
+            options.set_effects(&chain);
+            let ms = time_ms(|| {
+                std::hint::black_box(options.apply_effects(
+                    &frame.creative,
+                    &frame.alpha,
+                    width as usize,
+                    height as usize,
+                    None,
+                ));
+            });
+            model.set_rate(stage, per_pixel(ms, pixel_count));
+        }
+
+        model
+    }
+
+    /// Override one stage's rate in ns per pixel
+    pub fn set_rate(&mut self, stage: &str, ns_per_pixel: f64) {
+        self.rates.insert(stage.to_string(), ns_per_pixel.max(0.0));
+    }
+
+    pub fn rate(&self, stage: &str) -> f64 {
+        self.rates.get(stage).copied().unwrap_or(0.0)
+    }
+
+    /// Estimated per-stage cost of compositing a layer with `options` over the
+    /// given mask, as JSON `{total_ms, total_bytes, stages: [{stage, pixels,
+    /// ms, bytes}]}`
+    pub fn estimate(&self, alpha_mask: &[u8], width: u32, height: u32, options: &LayerOptions) -> String {
+        let pixel_count = (width * height) as usize;
+        let mut stages = Vec::new();
+
+        if alpha_mask.len() >= pixel_count && pixel_count > 0 {
+            stages.push(self.stage("copy", pixel_count, COPY_BYTES));
+
+            #[cfg(feature = "effects")]
+            for spec in options.effects.specs() {
+                stages.push(self.stage(&format!("effect:{}", spec.name()), pixel_count, EFFECT_BYTES));
+            }
+
+            if let Some(bounds) = mask_bounds(alpha_mask, width, height).filter(|_| options.scale > 0.0) {
+                // Only geometry is needed to size the warp
+                let source = Source {
+                    creative: &[],
+                    alpha: alpha_mask,
+                    width: width as usize,
+                    height: height as usize,
+                };
+                let warp = LayerWarp::new(&source, &bounds, options);
+                let (rows, columns) = warp.extent();
+
+                if !options.is_identity() {
+                    let (stage, bytes) = match options.filter_mode {
+                        FilterMode::Bilinear => ("warp_bilinear", WARP_BILINEAR_BYTES),
+                        FilterMode::Nearest => ("warp_nearest", WARP_NEAREST_BYTES),
+                    };
+                    stages.push(self.stage(stage, rows * columns, bytes));
+                }
+                // Bands with coverage are blended across the full width
+                stages.push(self.stage("blend", rows * width as usize, BLEND_BYTES));
+            }
+        }
+
+        let cost = FrameCost {
+            total_ms: stages.iter().map(|s| s.ms).sum(),
+            total_bytes: stages.iter().map(|s| s.bytes).sum(),
+            stages,
+        };
+        serde_json::to_string(&cost).unwrap_or_default()
+    }
+}
+
+impl CostModel {
+    fn stage(&self, stage: &str, pixels: usize, bytes_per_pixel: usize) -> StageCost {
+        StageCost {
+            stage: stage.to_string(),
+            pixels,
+            ms: pixels as f64 * self.rate(stage) / 1e6,
+            bytes: pixels * bytes_per_pixel,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stages(json: &str) -> Vec<(String, usize)> {
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        value["stages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["stage"].as_str().unwrap().to_string(), s["pixels"].as_u64().unwrap() as usize))
+            .collect()
+    }
+
+    #[test]
+    fn test_estimate_attributes_stages() {
+        let (width, height) = (100u32, 50u32);
+        let mut alpha_mask = vec![0u8; 5000];
+        for y in 10..20 {
+            alpha_mask[y * 100 + 30..y * 100 + 70].fill(255);
+        }
+
+        // Untransformed: no warp stage, the layer is blended as is
+        let model = CostModel::new();
+        let identity = stages(&model.estimate(&alpha_mask, width, height, &LayerOptions::new()));
+        assert_eq!(identity, vec![("copy".to_string(), 5000), ("blend".to_string(), 5000)]);
+
+        let mut options = LayerOptions::new();
+        options.set_transform(0.5, 0.0, 1.0);
+        let moved = stages(&model.estimate(&alpha_mask, width, height, &options));
+        assert_eq!(moved[1].0, "warp_bilinear");
+        // Half-pixel move: bilinear taps reach one extra row and column
+        assert_eq!(moved[1].1, 11 * 41);
+        assert_eq!(moved[2], ("blend".to_string(), 11 * 100));
+    }
+
+    #[test]
+    #[cfg(feature = "effects")]
+    fn test_estimate_includes_each_effect() {
+        let mut effects = EffectChain::new();
+        effects.add_grain(0.1);
+        effects.add_opacity(0.5);
+        let mut options = LayerOptions::new();
+        options.set_effects(&effects);
+
+        let mut model = CostModel::new();
+        model.set_rate("effect:grain", 1000.0);
+        let json = model.estimate(&[255; 4], 2, 2, &options);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["stages"][1]["stage"], "effect:grain");
+        assert_eq!(value["stages"][1]["ms"], 0.004);
+        assert_eq!(value["stages"][2]["stage"], "effect:opacity");
+    }
+
+    #[test]
+    fn test_measure_produces_positive_rates() {
+        let model = CostModel::measure(64, 36);
+        for stage in ["copy", "warp_bilinear", "warp_nearest", "blend"] {
+            assert!(model.rate(stage) > 0.0, "{}", stage);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Self-updating countdown and clock text layers
+
+use wasm_bindgen::prelude::*;
+
+use crate::macros::format_countdown;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// Seconds remaining until `target`
+    Countdown { target: f64 },
+    /// Time of day for an epoch timestamp shifted by a UTC offset
+    Clock { utc_offset_seconds: f64 },
+}
+
+/// Text layer whose content is derived from time; `tick` reports whether the
+/// displayed text changed so the host only re-rasterizes when it has to
+#[wasm_bindgen]
+pub struct CountdownLayer {
+    mode: Mode,
+    format: String,
+    text: Option<String>,
+}
+
+#[wasm_bindgen]
+impl CountdownLayer {
+    /// Count down to `target_seconds`, on the same clock (wall-clock or PTS)
+    /// later passed to `tick`
+    pub fn countdown(target_seconds: f64, format: &str) -> CountdownLayer {
+        CountdownLayer::with_mode(Mode::Countdown { target: target_seconds }, format)
+    }
+
+    /// Show the time of day for epoch timestamps passed to `tick`
+    pub fn clock(utc_offset_minutes: i32, format: &str) -> CountdownLayer {
+        let utc_offset_seconds = utc_offset_minutes as f64 * 60.0;
+        CountdownLayer::with_mode(Mode::Clock { utc_offset_seconds }, format)
+    }
+
+    /// Update for the frame at `now_seconds`; true when the text changed
+    pub fn tick(&mut self, now_seconds: f64) -> bool {
+        let text = self.text_at(now_seconds);
+        if self.text.as_deref() == Some(text.as_str()) {
+            return false;
+        }
+        self.text = Some(text);
+        true
+    }
+
+    /// Update from the browser wall clock
+    #[cfg(target_arch = "wasm32")]
+    pub fn tick_wall_clock(&mut self) -> bool {
+        self.tick(js_sys::Date::now() / 1000.0)
+    }
+
+    /// Text as of the last `tick` (empty before the first one)
+    pub fn text(&self) -> String {
+        self.text.clone().unwrap_or_default()
+    }
+}
+
+impl CountdownLayer {
+    fn with_mode(mode: Mode, format: &str) -> CountdownLayer {
+        CountdownLayer {
+            mode,
+            format: format.to_string(),
+            text: None,
+        }
+    }
+
+    fn text_at(&self, now_seconds: f64) -> String {
+        match self.mode {
+            Mode::Countdown { target } => format_countdown(target - now_seconds, &self.format),
+            Mode::Clock { utc_offset_seconds } => {
+                let local = (now_seconds + utc_offset_seconds).floor();
+                format_countdown(local.rem_euclid(SECONDS_PER_DAY), &self.format)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_countdown_only_dirty_when_text_changes() {
+        let mut layer = CountdownLayer::countdown(10.0, "mm:ss");
+
+        assert!(layer.tick(0.0));
+        assert_eq!(layer.text(), "00:10");
+
+        // Frames within the same displayed second do not re-render
+        assert!(!layer.tick(0.01));
+        assert!(!layer.tick(0.5));
+        assert!(layer.tick(1.0));
+        assert_eq!(layer.text(), "00:09");
+    }
+
+    #[test]
+    fn test_clock_applies_utc_offset() {
+        // 2024-01-01T12:34:56Z
+        let epoch = 1_704_112_496.0;
+
+        let mut utc = CountdownLayer::clock(0, "hh:mm:ss");
+        utc.tick(epoch);
+        assert_eq!(utc.text(), "12:34:56");
+
+        let mut offset = CountdownLayer::clock(-300, "hh:mm");
+        offset.tick(epoch);
+        assert_eq!(offset.text(), "07:34");
+    }
+}
@@ -0,0 +1,308 @@
+//! Cylindrical and spherical mapping of creatives onto curved objects
+
+use std::f32::consts::PI;
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::blend_pixel;
+use crate::geometry::Rect;
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::log_error;
+use crate::memory::{try_copy, Scope, Subsystem};
+use crate::occlusion::visibility;
+use crate::sampler::{Coverage, Source};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Shape {
+    /// Axis through the center, `length` pixels long
+    Cylinder { length: f32 },
+    Sphere,
+}
+
+/// Where a frame point lands on the surface
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Hit {
+    /// Normalized creative position
+    u: f32,
+    v: f32,
+    /// Unit surface normal, z towards the camera
+    normal: [f32; 3],
+}
+
+/// Creative wrapped around a cylinder (bottle, column) or sphere (ball) seen
+/// under orthographic projection. The creative spans `angular_extent`
+/// radians around the axis, centered on the side facing the camera, so it
+/// foreshortens towards the silhouette. Per-pixel normals drive an optional
+/// diffuse shading term and are exported for the host's own shading
+#[wasm_bindgen]
+pub struct CurvedSurface {
+    shape: Shape,
+    center: (f32, f32),
+    radius: f32,
+    /// Unit frame-space direction of the axis, and across it
+    axis: (f32, f32),
+    across: (f32, f32),
+    angular_extent: f32,
+    /// Unit direction towards the light and the unlit share, None for no shading
+    light: Option<([f32; 3], f32)>,
+}
+
+#[wasm_bindgen]
+impl CurvedSurface {
+    /// Cylinder centered on `(cx, cy)` whose axis leans `axis_angle` radians
+    /// clockwise from vertical
+    pub fn cylinder(
+        cx: f32,
+        cy: f32,
+        radius: f32,
+        length: f32,
+        axis_angle: f32,
+        angular_extent: f32,
+    ) -> CurvedSurface {
+        CurvedSurface::with_shape(Shape::Cylinder { length }, cx, cy, radius, axis_angle, angular_extent)
+    }
+
+    /// Sphere centered on `(cx, cy)` with its poles along `axis_angle`; the
+    /// creative keeps its aspect at the equator
+    pub fn sphere(cx: f32, cy: f32, radius: f32, axis_angle: f32, angular_extent: f32) -> CurvedSurface {
+        CurvedSurface::with_shape(Shape::Sphere, cx, cy, radius, axis_angle, angular_extent)
+    }
+
+    /// Shade the creative by `ambient + (1 - ambient) * max(0, n.l)` for a
+    /// light from `(x, y, z)` (frame axes, z towards the camera)
+    pub fn set_light(&mut self, x: f32, y: f32, z: f32, ambient: f32) {
+        let length = (x * x + y * y + z * z).sqrt();
+        self.light = (length > 0.0).then(|| ([x / length, y / length, z / length], ambient.clamp(0.0, 1.0)));
+    }
+
+    pub fn clear_light(&mut self) {
+        self.light = None;
+    }
+
+    /// Surface normals of a `width x height` frame, flat `[x, y, z]` per
+    /// pixel and zero off the surface
+    pub fn normals(&self, width: u32, height: u32) -> Vec<f32> {
+        let mut normals = vec![0.0; (width * height) as usize * 3];
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                if let Some(hit) = self.map(x as f32 + 0.5, y as f32 + 0.5) {
+                    let i = y * width as usize + x;
+                    normals[i * 3..i * 3 + 3].copy_from_slice(&hit.normal);
+                }
+            }
+        }
+        normals
+    }
+
+    /// Depth-tested composite of the creative (`creative_width x
+    /// creative_height` RGBA and mask) wrapped around the surface, sampled
+    /// per `options` edge and filter modes. An empty result means the frame
+    /// was dropped for memory
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite(
+        &self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        creative_alpha: &[u8],
+        creative_width: u32,
+        creative_height: u32,
+        depth_map: &[f32],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        let _memory = Scope::enter(Subsystem::Composite);
+        health::frame_started();
+        let pixel_count = (width * height) as usize;
+        let creative_count = (creative_width * creative_height) as usize;
+        if base_frame.len() < pixel_count * 4
+            || depth_map.len() < pixel_count
+            || creative_count == 0
+            || creative_frame.len() < creative_count * 4
+            || creative_alpha.len() < creative_count
+        {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return base_frame.to_vec();
+        }
+
+        let Some(mut result) = try_copy(base_frame) else {
+            log_error("WASM compositor: Out of memory, frame dropped");
+            health::degrade(DegradationTier::FrameDropped);
+            return Vec::new();
+        };
+        let source = Source {
+            creative: creative_frame,
+            alpha: creative_alpha,
+            width: creative_width as usize,
+            height: creative_height as usize,
+        };
+        let frame = Rect::new(0.0, 0.0, width as f32, height as f32);
+        let Some(area) = self.bounds().intersection(&frame) else {
+            return result;
+        };
+        let math = options.blend_math();
+        let columns = area.x.floor() as usize..(area.right().ceil() as usize).min(width as usize);
+        for y in area.y.floor() as usize..(area.bottom().ceil() as usize).min(height as usize) {
+            for x in columns.clone() {
+                let i = y * width as usize + x;
+                let Some(hit) = self.map(x as f32 + 0.5, y as f32 + 0.5) else {
+                    continue;
+                };
+                let visible = visibility(creative_depth, depth_map[i], math.depth_feather);
+                if visible == 0.0 {
+                    continue;
+                }
+
+                let (u, v) = (hit.u * source.width as f32, hit.v * source.height as f32);
+                let (mut color, coverage) = source.sample(u, v, options.edge_mode, options.filter_mode);
+                if coverage == 0 {
+                    continue;
+                }
+                if let Some((light, ambient)) = self.light {
+                    let lit = (0..3).map(|k| hit.normal[k] * light[k]).sum::<f32>().max(0.0);
+                    let shade = ambient + (1.0 - ambient) * lit;
+                    for channel in &mut color[..3] {
+                        *channel = (*channel as f32 * shade + 0.5) as u8;
+                    }
+                }
+                blend_pixel(&mut result[i * 4..i * 4 + 4], &color, coverage.unit() * visible, math);
+            }
+        }
+        result
+    }
+}
+
+impl CurvedSurface {
+    fn with_shape(
+        shape: Shape,
+        cx: f32,
+        cy: f32,
+        radius: f32,
+        axis_angle: f32,
+        angular_extent: f32,
+    ) -> CurvedSurface {
+        let (sin, cos) = axis_angle.sin_cos();
+        CurvedSurface {
+            shape,
+            center: (cx, cy),
+            radius: radius.max(0.0),
+            axis: (-sin, cos),
+            across: (cos, sin),
+            angular_extent: angular_extent.max(f32::EPSILON),
+            light: None,
+        }
+    }
+
+    /// Frame-space box holding the visible surface
+    fn bounds(&self) -> Rect {
+        let (cx, cy) = self.center;
+        let (along, across) = match self.shape {
+            Shape::Cylinder { length } => (length * 0.5, self.radius),
+            Shape::Sphere => (self.radius, self.radius),
+        };
+        let half_width = (self.axis.0 * along).abs() + (self.across.0 * across).abs();
+        let half_height = (self.axis.1 * along).abs() + (self.across.1 * across).abs();
+        Rect::new(cx - half_width, cy - half_height, half_width * 2.0, half_height * 2.0)
+    }
+
+    /// Creative position and normal under a frame point, None off the
+    /// surface or outside the creative's angular extent
+    fn map(&self, x: f32, y: f32) -> Option<Hit> {
+        let (dx, dy) = (x - self.center.0, y - self.center.1);
+        let along = (dx * self.axis.0 + dy * self.axis.1) / self.radius;
+        let across = (dx * self.across.0 + dy * self.across.1) / self.radius;
+        let (u, v, local) = match self.shape {
+            Shape::Cylinder { length } => {
+                if across.abs() >= 1.0 || (along * self.radius).abs() > length * 0.5 {
+                    return None;
+                }
+                // Angle around the axis from the side facing the camera
+                let angle = across.asin();
+                let v = 0.5 + along * self.radius / length;
+                (0.5 + angle / self.angular_extent, v, [across, 0.0, (1.0 - across * across).sqrt()])
+            }
+            Shape::Sphere => {
+                let depth = 1.0 - across * across - along * along;
+                if depth <= 0.0 {
+                    return None;
+                }
+                let depth = depth.sqrt();
+                let (longitude, latitude) = (across.atan2(depth), along.asin());
+                // Latitude spans a share of a half turn matching the longitude span
+                let vertical = self.angular_extent.min(PI);
+                (0.5 + longitude / self.angular_extent, 0.5 + latitude / vertical, [across, along, depth])
+            }
+        };
+        if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return None;
+        }
+
+        // Back from the surface's axes into the frame's
+        let normal = [
+            local[0] * self.across.0 + local[1] * self.axis.0,
+            local[0] * self.across.1 + local[1] * self.axis.1,
+            local[2],
+        ];
+        Some(Hit { u, v, normal })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cylinder_wraps_and_foreshortens() {
+        let cylinder = CurvedSurface::cylinder(50.0, 50.0, 20.0, 40.0, 0.0, PI);
+
+        // The creative's middle faces the camera on the axis
+        let center = cylinder.map(50.0, 50.0).unwrap();
+        assert!((center.u - 0.5).abs() < 1e-6 && (center.v - 0.5).abs() < 1e-6);
+        assert!((center.normal[2] - 1.0).abs() < 1e-6);
+
+        // Halfway to the silhouette is a sixth of a turn, not a quarter of
+        // the creative; the normal tilts towards the edge
+        let side = cylinder.map(60.0, 50.0).unwrap();
+        assert!((side.u - (0.5 + 1.0 / 6.0)).abs() < 1e-5, "{}", side.u);
+        assert!(side.normal[0] > 0.49 && side.normal[2] < 0.87);
+
+        assert_eq!(cylinder.map(71.0, 50.0), None);
+        assert_eq!(cylinder.map(50.0, 71.0), None);
+    }
+
+    #[test]
+    fn test_tilted_axis_rotates_normals() {
+        // Axis lying horizontal: across the axis is vertical in the frame
+        let cylinder = CurvedSurface::cylinder(50.0, 50.0, 20.0, 60.0, PI / 2.0, PI);
+        let normal = cylinder.map(50.0, 60.0).unwrap().normal;
+        assert!(normal[0].abs() < 1e-5 && normal[1] > 0.49, "{:?}", normal);
+        let bounds = cylinder.bounds();
+        assert!((bounds.width - 60.0).abs() < 1e-3 && (bounds.height - 40.0).abs() < 1e-3, "{:?}", bounds);
+    }
+
+    #[test]
+    fn test_sphere_is_shaded_towards_the_rim() {
+        let (width, height) = (40u32, 40u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let (creative, alpha) = (vec![200u8; 4], vec![255u8]);
+        let mut sphere = CurvedSurface::sphere(20.0, 20.0, 15.0, 0.0, PI);
+        sphere.set_light(0.0, 0.0, 1.0, 0.2);
+        let mut options = LayerOptions::new();
+        options.set_edge_mode(crate::EdgeMode::Clamp);
+        let result = sphere.composite(
+            &base_frame, &creative, &alpha, 1, 1, &depth_map, width, height, 5.0, &options,
+        );
+
+        let red = |x: usize, y: usize| result[(y * width as usize + x) * 4];
+        assert_eq!(red(20, 20), 200);
+        assert!(red(33, 20) < 120, "{}", red(33, 20));
+        assert_eq!(red(2, 2), 0);
+
+        let normals = sphere.normals(width, height);
+        assert_eq!(normals[..3], [0.0; 3]);
+    }
+}
@@ -0,0 +1,161 @@
+//! Depth maps in the packed formats depth providers emit
+
+use wasm_bindgen::prelude::*;
+
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::memory::{try_zeroed, Scope, Subsystem};
+use crate::{composite_layer, log_error};
+
+/// Sample format of a packed depth map, little endian
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepthFormat {
+    /// 32-bit float, the format of `composite_layer`'s depth map
+    #[default]
+    F32 = 0,
+    /// 16-bit unsigned normalized: 0..65535 is 0..1
+    U16 = 1,
+    /// IEEE half float
+    F16 = 2,
+}
+
+impl DepthFormat {
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            DepthFormat::F32 => 4,
+            DepthFormat::U16 | DepthFormat::F16 => 2,
+        }
+    }
+
+    fn value(self, sample: &[u8]) -> f32 {
+        match self {
+            DepthFormat::F32 => f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]),
+            DepthFormat::U16 => u16::from_le_bytes([sample[0], sample[1]]) as f32 / u16::MAX as f32,
+            DepthFormat::F16 => half_to_f32(u16::from_le_bytes([sample[0], sample[1]])),
+        }
+    }
+}
+
+/// Widen an IEEE 754 binary16 value, keeping subnormals, infinities and NaN
+fn half_to_f32(half: u16) -> f32 {
+    let sign = (half as u32 & 0x8000) << 16;
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = half as u32 & 0x3ff;
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        // Subnormal: mantissa * 2^-24, exact in f32
+        0 => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            return if sign != 0 { -magnitude } else { magnitude };
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent as u32 + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// Scene depth from `data`, `format` samples in row order, each mapped from
+/// 0..1 onto `near..far` (`0, 1` leaves values as they are, so f32 and f16
+/// maps already in scene units pass through). NaN samples stay NaN. Empty
+/// for a buffer of partial samples or when memory runs out
+#[wasm_bindgen]
+pub fn decode_depth(data: &[u8], format: DepthFormat, near: f32, far: f32) -> Vec<f32> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    let step = format.bytes_per_sample();
+    if !data.len().is_multiple_of(step) {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return Vec::new();
+    }
+    let Some(mut depth) = try_zeroed::<f32>(data.len() / step) else {
+        log_error("WASM compositor: Out of memory, depth map dropped");
+        return Vec::new();
+    };
+    let range = far - near;
+    for (depth, sample) in depth.iter_mut().zip(data.chunks_exact(step)) {
+        *depth = near + format.value(sample) * range;
+    }
+    depth
+}
+
+/// `composite_layer` with the depth map in `depth_format`, decoded in wasm
+/// per `decode_depth` so the host does not convert it to f32
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_packed_depth(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_data: &[u8],
+    depth_format: DepthFormat,
+    depth_near: f32,
+    depth_far: f32,
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    if depth_data.len() != (width * height) as usize * depth_format.bytes_per_sample() {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+    let depth_map = decode_depth(depth_data, depth_format, depth_near, depth_far);
+    if depth_map.is_empty() && !depth_data.is_empty() {
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    }
+    let (base, creative) = (base_frame, creative_frame);
+    composite_layer(base, creative, &depth_map, alpha_mask, width, height, creative_depth, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decodes_each_format() {
+        let floats: Vec<u8> = [0.25f32, 7.5, f32::NAN].iter().flat_map(|d| d.to_le_bytes()).collect();
+        let decoded = decode_depth(&floats, DepthFormat::F32, 0.0, 1.0);
+        assert_eq!(decoded[..2], [0.25, 7.5]);
+        assert!(decoded[2].is_nan());
+
+        // Normalized 16-bit onto a 0.5..10.5 range
+        let unorm: Vec<u8> = [0u16, 32768, 65535].iter().flat_map(|d| d.to_le_bytes()).collect();
+        let decoded = decode_depth(&unorm, DepthFormat::U16, 0.5, 10.5);
+        assert_eq!(decoded[0], 0.5);
+        assert!((decoded[1] - 5.5).abs() < 1e-3);
+        assert_eq!(decoded[2], 10.5);
+
+        // 1, -2, 65504, the smallest subnormal, infinity and NaN as halves
+        let halves: Vec<u8> = [0x3c00u16, 0xc000, 0x7bff, 0x0001, 0x7c00, 0x7e00]
+            .iter()
+            .flat_map(|d| d.to_le_bytes())
+            .collect();
+        let decoded = decode_depth(&halves, DepthFormat::F16, 0.0, 1.0);
+        assert_eq!(decoded[..5], [1.0, -2.0, 65504.0, 2f32.powi(-24), f32::INFINITY]);
+        assert!(decoded[5].is_nan());
+        assert!(decode_depth(&halves[..3], DepthFormat::F16, 0.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_packed_depth_matches_f32_composite() {
+        let (width, height) = (4u32, 2u32);
+        let base_frame = vec![20u8; 32];
+        let creative_frame = vec![220u8; 32];
+        let alpha_mask = vec![255u8; 8];
+        let options = LayerOptions::new();
+        // Alternating near and far geometry, normalized over 0..20
+        let unorm: Vec<u16> = (0..8).map(|i| if i % 2 == 0 { 6553 } else { 52428 }).collect();
+        let depth_map: Vec<f32> = unorm.iter().map(|&d| d as f32 / 65535.0 * 20.0).collect();
+        let expected = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+        let bytes: Vec<u8> = unorm.iter().flat_map(|d| d.to_le_bytes()).collect();
+        let frame = composite_layer_packed_depth(
+            &base_frame, &creative_frame, &bytes, DepthFormat::U16, 0.0, 20.0, &alpha_mask, width, height,
+            5.0, &options,
+        );
+        assert_eq!(frame, expected);
+        assert_eq!(frame[..8], [20, 20, 20, 20, 220, 220, 220, 220]);
+    }
+}
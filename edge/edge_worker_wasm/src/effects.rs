@@ -0,0 +1,331 @@
+//! Ordered per-layer effect chains
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::motion_blur::MotionBlur;
+use crate::noise::estimate_noise;
+use crate::random::PlacementRng;
+
+/// Creative pixels an effect works on: straight RGBA plus 16-bit coverage,
+/// both `width * height`
+pub struct EffectFrame<'a> {
+    pub creative: &'a mut [u8],
+    pub alpha: &'a mut [u16],
+    pub width: usize,
+    pub height: usize,
+    /// Under checkerboard rendering, the parity, `(x + y) % 2`, of the
+    /// pixels to render; heavy effects skip the others, which are
+    /// reconstructed afterwards
+    pub checkerboard: Option<usize>,
+}
+
+impl EffectFrame<'_> {
+    /// Whether pixel `i` is rendered this frame
+    pub fn renders(&self, i: usize) -> bool {
+        self.checkerboard.is_none_or(|parity| (i % self.width + i / self.width) % 2 == parity)
+    }
+}
+
+/// Per-frame inputs shared by every effect in a chain
+pub struct EffectContext {
+    /// From `frame_seed`, so stochastic effects reproduce exactly
+    pub seed: u64,
+    /// Frame pixels per creative pixel, for effects given in frame pixels
+    pub scale: f32,
+}
+
+/// One step of a layer's effect chain, applied in creative space before the
+/// layer is placed; this is the CPU implementation, and a GPU backend would
+/// add its own entry point next to `apply`
+pub trait Effect {
+    fn apply(&self, frame: &mut EffectFrame, context: &EffectContext);
+}
+
+/// Brightness offset, contrast about mid-grey and saturation about luma
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize), serde(default))]
+pub struct ColorAdjust {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+}
+
+impl Default for ColorAdjust {
+    fn default() -> Self {
+        Self { brightness: 0.0, contrast: 1.0, saturation: 1.0 }
+    }
+}
+
+impl Effect for ColorAdjust {
+    fn apply(&self, frame: &mut EffectFrame, _context: &EffectContext) {
+        for (pixel, &alpha) in frame.creative.chunks_exact_mut(4).zip(frame.alpha.iter()) {
+            if alpha == 0 {
+                continue;
+            }
+
+            let rgb = [0, 1, 2].map(|c| {
+                let value = pixel[c] as f32 / 255.0;
+                (value - 0.5) * self.contrast + 0.5 + self.brightness
+            });
+            let luma = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+            for (channel, value) in rgb.into_iter().enumerate() {
+                let saturated = luma + (value - luma) * self.saturation;
+                pixel[channel] = (saturated * 255.0 + 0.5).clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Monochrome film grain, `amount` being the peak offset as a fraction of
+/// full scale
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize), serde(default))]
+pub struct Grain {
+    pub amount: f32,
+}
+
+impl Effect for Grain {
+    fn apply(&self, frame: &mut EffectFrame, context: &EffectContext) {
+        let mut rng = PlacementRng::from_seed(context.seed);
+        for i in 0..frame.width * frame.height {
+            if frame.alpha[i] == 0 || !frame.renders(i) {
+                continue;
+            }
+            let pixel = &mut frame.creative[i * 4..i * 4 + 4];
+
+            let offset = (rng.next_f32() * 2.0 - 1.0) * self.amount * 255.0;
+            for value in &mut pixel[..3] {
+                *value = (*value as f32 + offset).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Scales coverage
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize), serde(default))]
+pub struct Opacity {
+    pub value: f32,
+}
+
+impl Default for Opacity {
+    fn default() -> Self {
+        Self { value: 1.0 }
+    }
+}
+
+impl Effect for Opacity {
+    fn apply(&self, frame: &mut EffectFrame, _context: &EffectContext) {
+        let value = self.value.clamp(0.0, 1.0);
+        for alpha in frame.alpha.iter_mut() {
+            *alpha = (*alpha as f32 * value + 0.5) as u16;
+        }
+    }
+}
+
+/// Manifest form of an effect, e.g. `{"type": "grain", "amount": 0.05}`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "json", serde(tag = "type", rename_all = "snake_case"))]
+pub enum EffectSpec {
+    ColorAdjust(ColorAdjust),
+    Grain(Grain),
+    MotionBlur(MotionBlur),
+    Opacity(Opacity),
+}
+
+impl EffectSpec {
+    /// Manifest `type` tag
+    pub fn name(&self) -> &'static str {
+        match self {
+            EffectSpec::ColorAdjust(_) => "color_adjust",
+            EffectSpec::Grain(_) => "grain",
+            EffectSpec::MotionBlur(_) => "motion_blur",
+            EffectSpec::Opacity(_) => "opacity",
+        }
+    }
+
+    fn effect(&self) -> &dyn Effect {
+        match self {
+            EffectSpec::ColorAdjust(effect) => effect,
+            EffectSpec::Grain(effect) => effect,
+            EffectSpec::MotionBlur(effect) => effect,
+            EffectSpec::Opacity(effect) => effect,
+        }
+    }
+}
+
+/// Effects applied to a layer in order
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EffectChain {
+    effects: Vec<EffectSpec>,
+}
+
+#[wasm_bindgen]
+impl EffectChain {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> EffectChain {
+        EffectChain::default()
+    }
+
+    /// Parse a manifest array of effects; None when it is malformed
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Option<EffectChain> {
+        serde_json::from_str(json).ok().map(|effects| EffectChain { effects })
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.effects).unwrap_or_default()
+    }
+
+    pub fn add_color_adjust(&mut self, brightness: f32, contrast: f32, saturation: f32) {
+        self.effects.push(EffectSpec::ColorAdjust(ColorAdjust { brightness, contrast, saturation }));
+    }
+
+    pub fn add_grain(&mut self, amount: f32) {
+        self.effects.push(EffectSpec::Grain(Grain { amount }));
+    }
+
+    /// Grain matching the footage's noise around the placement in
+    /// `alpha_mask` (`estimate_noise`), `intensity` times as strong, so the
+    /// creative does not look too clean against it
+    pub fn add_matched_grain(
+        &mut self,
+        base_frame: &[u8],
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        intensity: f32,
+    ) {
+        let sigma = estimate_noise(base_frame, alpha_mask, width, height);
+        // Grain is uniform over +-amount, of deviation amount / sqrt(3)
+        let amount = sigma * 3f32.sqrt() / 255.0 * intensity.max(0.0);
+        self.add_grain(amount);
+    }
+
+    /// Motion blur for a surface moving `dx, dy` frame pixels a frame, as
+    /// exposed for `shutter` of the frame interval (0.5 for 180 degrees)
+    pub fn add_motion_blur(&mut self, dx: f32, dy: f32, shutter: f32) {
+        self.effects.push(EffectSpec::MotionBlur(MotionBlur { dx, dy, shutter, flow: Vec::new() }));
+    }
+
+    /// Motion blur from a per-pixel flow field: interleaved `[dx, dy]` frame
+    /// pixels a frame for each frame pixel. A field of the wrong size blurs
+    /// nothing
+    pub fn add_motion_flow(&mut self, flow: &[f32], shutter: f32) {
+        let flow = flow.to_vec();
+        self.effects.push(EffectSpec::MotionBlur(MotionBlur { dx: 0.0, dy: 0.0, shutter, flow }));
+    }
+
+    pub fn add_opacity(&mut self, value: f32) {
+        self.effects.push(EffectSpec::Opacity(Opacity { value }));
+    }
+
+    pub fn len(&self) -> usize {
+        self.effects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effects.is_empty()
+    }
+}
+
+impl EffectChain {
+    #[cfg(feature = "json")]
+    pub(crate) fn from_specs(effects: Vec<EffectSpec>) -> EffectChain {
+        EffectChain { effects }
+    }
+
+    pub fn specs(&self) -> &[EffectSpec] {
+        &self.effects
+    }
+
+    pub fn apply(&self, frame: &mut EffectFrame, context: &EffectContext) {
+        for (position, spec) in self.effects.iter().enumerate() {
+            // Each step draws its own stream, so repeated effects differ
+            let seed = context.seed ^ (position as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            let step = EffectContext { seed, scale: context.scale };
+            spec.effect().apply(frame, &step);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(chain: &EffectChain, creative: &mut [u8], alpha: &mut [u16], seed: u64) {
+        let width = alpha.len();
+        let mut frame = EffectFrame { creative, alpha, width, height: 1, checkerboard: None };
+        chain.apply(&mut frame, &EffectContext { seed, scale: 1.0 });
+    }
+
+    #[test]
+    fn test_chain_order_matters() {
+        let mut brighten_first = EffectChain::new();
+        brighten_first.add_color_adjust(0.2, 1.0, 1.0);
+        brighten_first.add_color_adjust(0.0, 2.0, 1.0);
+
+        let mut contrast_first = EffectChain::new();
+        contrast_first.add_color_adjust(0.0, 2.0, 1.0);
+        contrast_first.add_color_adjust(0.2, 1.0, 1.0);
+
+        let mut a = [102u8, 102, 102, 255];
+        let mut b = a;
+        run(&brighten_first, &mut a, &mut [65535], 0);
+        run(&contrast_first, &mut b, &mut [65535], 0);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_json_round_trip() {
+        let json = r#"[
+            {"type": "opacity", "value": 0.5},
+            {"type": "grain", "amount": 0.1},
+            {"type": "color_adjust"}
+        ]"#;
+        let chain = EffectChain::from_json(json).unwrap();
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain.effects[2], EffectSpec::ColorAdjust(ColorAdjust::default()));
+        assert_eq!(EffectChain::from_json(&chain.to_json()), Some(chain));
+
+        let pan = EffectChain::from_json(r#"[{"type": "motion_blur", "dx": 12}]"#).unwrap();
+        assert_eq!(pan.effects[0], EffectSpec::MotionBlur(MotionBlur { dx: 12.0, ..MotionBlur::default() }));
+        assert!(EffectChain::from_json(r#"[{"type": "unknown"}]"#).is_none());
+    }
+
+    #[test]
+    fn test_grain_is_seeded() {
+        let mut chain = EffectChain::new();
+        chain.add_grain(0.1);
+
+        let original = vec![128u8; 64 * 4];
+        let mut alpha = vec![65535u16; 64];
+        let (mut a, mut b, mut c) = (original.clone(), original.clone(), original.clone());
+        run(&chain, &mut a, &mut alpha, 7);
+        run(&chain, &mut b, &mut alpha, 7);
+        run(&chain, &mut c, &mut alpha, 8);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, original);
+    }
+
+    #[test]
+    fn test_effects_skip_uncovered_pixels() {
+        let mut chain = EffectChain::new();
+        chain.add_color_adjust(0.5, 1.0, 0.0);
+        chain.add_grain(0.2);
+
+        let mut creative = [10u8, 20, 30, 255, 10, 20, 30, 255];
+        let mut alpha = [0u16, 65535];
+        run(&chain, &mut creative, &mut alpha, 1);
+        assert_eq!(creative[..4], [10, 20, 30, 255]);
+        assert_ne!(creative[4..], [10, 20, 30, 255]);
+    }
+}
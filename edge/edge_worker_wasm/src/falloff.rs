@@ -0,0 +1,106 @@
+//! Soft edges: fading a layer out towards its placement boundary
+
+use std::ops::Range;
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::Rect;
+use crate::sampler::Coverage;
+
+/// Shape of the fade applied on top of the layer's mask
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Falloff {
+    /// Hard placement edges
+    #[default]
+    None = 0,
+    /// Fade in from each side of the placement rectangle
+    Edge = 1,
+    /// Elliptical vignette from the placement center
+    Radial = 2,
+}
+
+/// Smooth 0..1 ramp, so the fade has no visible start or end line
+fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Fade factor at frame point `(x, y)` for a placement covering `placed`;
+/// `width` is the fade's extent as a fraction of the placement's half size
+pub(crate) fn falloff_at(falloff: Falloff, placed: &Rect, width: f32, x: f32, y: f32) -> f32 {
+    let (cx, cy) = placed.center();
+    let (half_width, half_height) = (placed.width * 0.5, placed.height * 0.5);
+    if width <= 0.0 || half_width <= 0.0 || half_height <= 0.0 {
+        return 1.0;
+    }
+    match falloff {
+        Falloff::None => 1.0,
+        Falloff::Edge => {
+            let inset = (half_width - (x - cx).abs()).min(half_height - (y - cy).abs());
+            smoothstep(inset / (width * half_width.min(half_height)))
+        }
+        Falloff::Radial => {
+            let radius = ((x - cx) / half_width).hypot((y - cy) / half_height);
+            smoothstep((1.0 - radius) / width)
+        }
+    }
+}
+
+/// Attenuate band coverage for output `rows` of a `frame_width` wide frame,
+/// sampling the fade at pixel centers
+pub(crate) fn fade_rows<A: Coverage>(
+    falloff: Falloff,
+    placed: &Rect,
+    width: f32,
+    rows: Range<usize>,
+    frame_width: usize,
+    alpha: &mut [A],
+) {
+    for (row, y) in alpha.chunks_exact_mut(frame_width).zip(rows) {
+        for (x, coverage) in row.iter_mut().enumerate() {
+            if *coverage == A::ZERO {
+                continue;
+            }
+            let fade = falloff_at(falloff, placed, width, x as f32 + 0.5, y as f32 + 0.5);
+            *coverage = A::quantize((*coverage).into() * fade);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_fade_reaches_zero_at_boundary() {
+        let placed = Rect::new(0.0, 0.0, 100.0, 50.0);
+        assert_eq!(falloff_at(Falloff::Edge, &placed, 0.4, 0.0, 25.0), 0.0);
+        assert_eq!(falloff_at(Falloff::Edge, &placed, 0.4, 50.0, 25.0), 1.0);
+
+        // 0.4 of the 25 pixel half height: full strength 10 pixels in
+        assert_eq!(falloff_at(Falloff::Edge, &placed, 0.4, 50.0, 10.0), 1.0);
+        assert_eq!(falloff_at(Falloff::Edge, &placed, 0.4, 50.0, 5.0), 0.5);
+        assert_eq!(falloff_at(Falloff::Edge, &placed, 0.0, 0.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn test_radial_fade_is_elliptical() {
+        let placed = Rect::new(0.0, 0.0, 100.0, 50.0);
+        assert_eq!(falloff_at(Falloff::Radial, &placed, 1.0, 50.0, 25.0), 1.0);
+        let side = falloff_at(Falloff::Radial, &placed, 1.0, 75.0, 25.0);
+        let top = falloff_at(Falloff::Radial, &placed, 1.0, 50.0, 12.5);
+        assert_eq!(side, top);
+        assert_eq!(side, 0.5);
+        assert_eq!(falloff_at(Falloff::Radial, &placed, 1.0, 100.0, 50.0), 0.0);
+    }
+
+    #[test]
+    fn test_fade_rows_skips_uncovered_pixels() {
+        // Every pixel center is half a pixel inside the one pixel fade
+        let placed = Rect::new(0.0, 0.0, 4.0, 2.0);
+        let mut alpha = vec![255u8, 0, 255, 255];
+        fade_rows(Falloff::Edge, &placed, 1.0, 0..1, 4, &mut alpha);
+        assert_eq!(alpha, vec![128, 0, 128, 128]);
+    }
+}
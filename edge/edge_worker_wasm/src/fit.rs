@@ -0,0 +1,237 @@
+//! Fitting creatives whose size differs from the frame
+
+use std::borrow::Cow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::coords::flip_rows;
+use crate::layer::LayerOptions;
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::try_zeroed;
+use crate::sampler::{AxisTap, Coverage, EdgeMode, FilterMode, Source};
+
+/// What to do when a layer's creative is not the frame size
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FitMode {
+    /// Drop the layer; the frame passes through untouched
+    #[default]
+    Strict = 0,
+    /// Native size from the top-left corner, overflow cut off
+    Crop = 1,
+    /// Uniformly scaled to fit inside the frame and centered
+    Scale = 2,
+    /// Native size centered, transparent around it (overflow cut off evenly)
+    Pad = 3,
+}
+
+/// How a frame's creative was fitted, for per-frame reporting
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitOutcome {
+    /// Creative already matches the frame
+    Exact = 0,
+    /// Mismatch under `FitMode::Strict`; the layer was dropped
+    Rejected = 1,
+    Cropped = 2,
+    Scaled = 3,
+    Padded = 4,
+}
+
+pub(crate) fn fit_outcome(mode: FitMode, creative: (u32, u32), frame: (u32, u32)) -> FitOutcome {
+    if creative == frame {
+        return FitOutcome::Exact;
+    }
+    match mode {
+        FitMode::Strict => FitOutcome::Rejected,
+        FitMode::Crop => FitOutcome::Cropped,
+        FitMode::Scale => FitOutcome::Scaled,
+        FitMode::Pad => FitOutcome::Padded,
+    }
+}
+
+/// Creative and coverage of a layer
+type LayerPixels<'a, A> = (Cow<'a, [u8]>, Cow<'a, [A]>);
+
+/// The layer's creative and mask at frame size and top-down row order:
+/// borrowed when they already match, flipped and resampled per the layer's
+/// conventions otherwise. None when the layer is rejected or its buffers are
+/// short
+pub(crate) fn fit_to_frame<'a, A: Coverage>(
+    creative: &'a [u8],
+    alpha: &'a [A],
+    width: u32,
+    height: u32,
+    options: &LayerOptions,
+) -> Option<LayerPixels<'a, A>> {
+    let (creative_width, creative_height) = options.creative_size(width, height);
+    let pixel_count = (creative_width * creative_height) as usize;
+    let alpha = if options.coordinates.flip_masks() && alpha.len() >= pixel_count {
+        Cow::Owned(flip_rows(alpha, creative_width as usize, creative_height as usize))
+    } else {
+        Cow::Borrowed(alpha)
+    };
+
+    let (scale, offset, filter) = match options.fit_outcome(width, height) {
+        FitOutcome::Exact => return Some((Cow::Borrowed(creative), alpha)),
+        FitOutcome::Rejected => {
+            log_error("WASM compositor: Creative size does not match the frame");
+            return None;
+        }
+        FitOutcome::Cropped => (1.0, (0.0, 0.0), FilterMode::Nearest),
+        FitOutcome::Padded => {
+            // Whole-pixel offsets keep the copy exact
+            let center = |frame: u32, size: u32| ((frame as f32 - size as f32) / 2.0).floor();
+            (1.0, (center(width, creative_width), center(height, creative_height)), FilterMode::Nearest)
+        }
+        FitOutcome::Scaled => {
+            let scale = (width as f32 / creative_width as f32).min(height as f32 / creative_height as f32);
+            let center = |frame: u32, size: u32| (frame as f32 - size as f32 * scale) / 2.0;
+            (scale, (center(width, creative_width), center(height, creative_height)), options.filter_mode)
+        }
+    };
+
+    if creative.len() < pixel_count * 4 || alpha.len() < pixel_count {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return None;
+    }
+    let source = Source {
+        creative,
+        alpha: &alpha,
+        width: creative_width as usize,
+        height: creative_height as usize,
+    };
+    let resampled = resample(&source, width as usize, height as usize, scale, offset, filter);
+    let Some((creative, alpha)) = resampled else {
+        log_error("WASM compositor: Out of memory, layer dropped");
+        health::degrade(DegradationTier::LayerDropped);
+        return None;
+    };
+    Some((Cow::Owned(creative), Cow::Owned(alpha)))
+}
+
+/// Map `source` into a `width * height` frame: frame point `p` samples the
+/// creative at `(p - offset) / scale`, transparent outside it
+fn resample<A: Coverage>(
+    source: &Source<A>,
+    width: usize,
+    height: usize,
+    scale: f32,
+    offset: (f32, f32),
+    filter: FilterMode,
+) -> Option<(Vec<u8>, Vec<A>)> {
+    let mut creative = try_zeroed(width * height * 4)?;
+    let mut alpha = try_zeroed(width * height)?;
+
+    let tap = |coord: usize, offset: f32, size: usize| {
+        AxisTap::new((coord as f32 + 0.5 - offset) / scale, size, EdgeMode::Transparent, filter)
+    };
+    let columns: Vec<AxisTap> = (0..width).map(|x| tap(x, offset.0, source.width)).collect();
+    for y in 0..height {
+        let row = tap(y, offset.1, source.height);
+        if !row.reaches(0.0, source.height as f32) {
+            continue;
+        }
+        for (x, column) in columns.iter().enumerate() {
+            let (color, coverage) = source.sample_taps(column, &row);
+            if coverage == A::ZERO {
+                continue;
+            }
+            let index = y * width + x;
+            creative[index * 4..index * 4 + 4].copy_from_slice(&color);
+            alpha[index] = coverage;
+        }
+    }
+    Some((creative, alpha))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_layer;
+
+    /// 2x2 creative with distinct opaque pixels
+    fn creative() -> (Vec<u8>, Vec<u8>) {
+        let creative = vec![10, 0, 0, 255, 20, 0, 0, 255, 30, 0, 0, 255, 40, 0, 0, 255];
+        (creative, vec![255; 4])
+    }
+
+    fn fitted(mode: FitMode, filter: FilterMode, width: u32, height: u32) -> (Vec<u8>, Vec<u8>) {
+        let (creative, alpha) = creative();
+        let mut options = LayerOptions::new();
+        options.set_creative_size(2, 2);
+        options.set_fit_mode(mode);
+        options.set_filter_mode(filter);
+        let (creative, alpha) = fit_to_frame(&creative, &alpha, width, height, &options).unwrap();
+        (creative.into_owned(), alpha.into_owned())
+    }
+
+    fn red(creative: &[u8]) -> Vec<u8> {
+        creative.chunks_exact(4).map(|pixel| pixel[0]).collect()
+    }
+
+    #[test]
+    fn test_outcome_per_mode() {
+        let mut options = LayerOptions::new();
+        assert_eq!(options.fit_outcome(4, 4), FitOutcome::Exact);
+
+        options.set_creative_size(2, 2);
+        assert_eq!(options.fit_outcome(4, 4), FitOutcome::Rejected);
+        assert_eq!(options.fit_outcome(2, 2), FitOutcome::Exact);
+        for (mode, outcome) in [
+            (FitMode::Crop, FitOutcome::Cropped),
+            (FitMode::Scale, FitOutcome::Scaled),
+            (FitMode::Pad, FitOutcome::Padded),
+        ] {
+            options.set_fit_mode(mode);
+            assert_eq!(options.fit_outcome(4, 3), outcome);
+        }
+    }
+
+    #[test]
+    fn test_crop_and_pad_place_native_pixels() {
+        let (creative, alpha) = fitted(FitMode::Crop, FilterMode::Bilinear, 3, 2);
+        assert_eq!(red(&creative), vec![10, 20, 0, 30, 40, 0]);
+        assert_eq!(alpha, vec![255, 255, 0, 255, 255, 0]);
+
+        // Odd leftovers go below and to the right
+        let (creative, alpha) = fitted(FitMode::Pad, FilterMode::Bilinear, 4, 3);
+        assert_eq!(red(&creative), vec![0, 10, 20, 0, 0, 30, 40, 0, 0, 0, 0, 0]);
+        assert_eq!(alpha.iter().filter(|&&a| a == 255).count(), 4);
+
+        // Larger than the frame: cut off evenly
+        let (creative, _) = fitted(FitMode::Pad, FilterMode::Bilinear, 1, 1);
+        assert_eq!(red(&creative), vec![40]);
+    }
+
+    #[test]
+    fn test_scale_fits_inside_frame() {
+        // 2x2 into 4x2 scales by 1 and centers horizontally
+        let (creative, alpha) = fitted(FitMode::Scale, FilterMode::Nearest, 4, 2);
+        assert_eq!(red(&creative), vec![0, 10, 20, 0, 0, 30, 40, 0]);
+        assert_eq!(alpha, vec![0, 255, 255, 0, 0, 255, 255, 0]);
+
+        // 2x2 into 4x4 doubles every pixel
+        let (creative, alpha) = fitted(FitMode::Scale, FilterMode::Nearest, 4, 4);
+        assert_eq!(red(&creative)[..8], [10, 10, 20, 20, 10, 10, 20, 20]);
+        assert!(alpha.iter().all(|&a| a == 255));
+    }
+
+    #[test]
+    fn test_strict_rejects_mismatch() {
+        let (creative, alpha) = creative();
+        let base_frame = vec![90u8; 16 * 4];
+        let depth_map = vec![10.0f32; 16];
+        let mut options = LayerOptions::new();
+        options.set_creative_size(2, 2);
+
+        let frame = composite_layer(&base_frame, &creative, &depth_map, &alpha, 4, 4, 5.0, &options);
+        assert_eq!(frame, base_frame);
+
+        options.set_fit_mode(FitMode::Crop);
+        let frame = composite_layer(&base_frame, &creative, &depth_map, &alpha, 4, 4, 5.0, &options);
+        assert_eq!(frame[..4], [10, 0, 0, 255]);
+        assert_eq!(frame[8..12], [90; 4]);
+    }
+}
@@ -0,0 +1,169 @@
+//! Runtime feature flags for gradual rollout of algorithm variants
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::BlendPrecision;
+use crate::random::fnv1a;
+
+/// Layers on the default integer blend use 16.16 fixed point instead
+pub(crate) const FIXED_POINT_BLEND: &str = "fixed_point_blend";
+
+/// Reference output for certification: every approximation and fast path
+/// is off, so the worker produces the canonical result partners compare
+/// their implementations against; blends run in float whatever the layer
+/// asks for and sampling never drops to nearest on unimportant pixels
+pub(crate) const REFERENCE_MODE: &str = "reference_mode";
+
+/// How a flag's state is decided
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rule {
+    Fixed(bool),
+    /// On for this percentage of sessions
+    Rollout(f64),
+}
+
+#[derive(Default)]
+struct Registry {
+    session_id: String,
+    rules: BTreeMap<String, Rule>,
+}
+
+impl Registry {
+    fn is_enabled(&self, name: &str) -> bool {
+        match self.rules.get(name) {
+            Some(&Rule::Fixed(enabled)) => enabled,
+            Some(&Rule::Rollout(percent)) => bucket(name, &self.session_id) < percent,
+            None => false,
+        }
+    }
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry::default());
+}
+
+/// Stable 0..100 bucket of a session for one flag; hashing the flag name in
+/// keeps different rollouts from landing on the same sessions
+fn bucket(name: &str, session_id: &str) -> f64 {
+    let hash = fnv1a(format!("{}:{}", name, session_id).as_bytes());
+    (hash % 10_000) as f64 / 100.0
+}
+
+/// Session that percentage rollouts are bucketed by, set once at init
+#[wasm_bindgen]
+pub fn set_flag_session(session_id: &str) {
+    REGISTRY.with(|registry| registry.borrow_mut().session_id = session_id.to_string());
+}
+
+/// Force a flag on or off for this session
+#[wasm_bindgen]
+pub fn set_flag(name: &str, enabled: bool) {
+    REGISTRY.with(|registry| registry.borrow_mut().rules.insert(name.to_string(), Rule::Fixed(enabled)));
+}
+
+/// Enable a flag for `percent` (0 to 100) of sessions
+#[wasm_bindgen]
+pub fn set_flag_rollout(name: &str, percent: f64) {
+    let rule = Rule::Rollout(percent.clamp(0.0, 100.0));
+    REGISTRY.with(|registry| registry.borrow_mut().rules.insert(name.to_string(), rule));
+}
+
+#[wasm_bindgen]
+pub fn clear_flags() {
+    REGISTRY.with(|registry| registry.borrow_mut().rules.clear());
+}
+
+#[wasm_bindgen]
+pub fn flag_enabled(name: &str) -> bool {
+    REGISTRY.with(|registry| registry.borrow().is_enabled(name))
+}
+
+/// Every registered flag's state for this session as JSON `{"name": bool}`,
+/// for the host to attach to each segment report
+#[wasm_bindgen]
+pub fn flag_report() -> String {
+    REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        let entries: Vec<String> = registry
+            .rules
+            .keys()
+            .map(|name| format!("\"{}\":{}", name.replace(['"', '\\'], ""), registry.is_enabled(name)))
+            .collect();
+        format!("{{{}}}", entries.join(","))
+    })
+}
+
+/// Blend arithmetic for a layer once flags are applied
+pub(crate) fn blend_precision(precision: BlendPrecision) -> BlendPrecision {
+    if reference_mode() {
+        BlendPrecision::Float
+    } else if precision == BlendPrecision::Integer && flag_enabled(FIXED_POINT_BLEND) {
+        BlendPrecision::FixedPoint
+    } else {
+        precision
+    }
+}
+
+pub(crate) fn reference_mode() -> bool {
+    flag_enabled(REFERENCE_MODE)
+}
+
+/// Run with flag `name` forced to `enabled`, then restore its rule
+#[cfg(feature = "json")]
+pub(crate) fn with_flag<T>(name: &str, enabled: bool, run: impl FnOnce() -> T) -> T {
+    let rule = Rule::Fixed(enabled);
+    let previous = REGISTRY.with(|registry| registry.borrow_mut().rules.insert(name.to_string(), rule));
+    let result = run();
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        match previous {
+            Some(rule) => registry.rules.insert(name.to_string(), rule),
+            None => registry.rules.remove(name),
+        }
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_flags_and_report() {
+        clear_flags();
+        assert!(!flag_enabled("refined_masks"));
+        set_flag("refined_masks", true);
+        set_flag(FIXED_POINT_BLEND, false);
+        assert!(flag_enabled("refined_masks"));
+        assert_eq!(flag_report(), r#"{"fixed_point_blend":false,"refined_masks":true}"#);
+
+        assert_eq!(blend_precision(BlendPrecision::Integer), BlendPrecision::Integer);
+        set_flag(FIXED_POINT_BLEND, true);
+        assert_eq!(blend_precision(BlendPrecision::Integer), BlendPrecision::FixedPoint);
+        assert_eq!(blend_precision(BlendPrecision::Float), BlendPrecision::Float);
+        clear_flags();
+    }
+
+    #[test]
+    fn test_rollout_is_stable_per_session() {
+        clear_flags();
+        set_flag_rollout("simd_blend", 30.0);
+        let enabled = (0..1000)
+            .filter(|i| {
+                set_flag_session(&format!("session-{}", i));
+                flag_enabled("simd_blend")
+            })
+            .count();
+        assert!((250..350).contains(&enabled), "{}", enabled);
+
+        set_flag_session("session-7");
+        let first = flag_enabled("simd_blend");
+        assert_eq!(flag_enabled("simd_blend"), first);
+        set_flag_rollout("simd_blend", 100.0);
+        assert!(flag_enabled("simd_blend"));
+        clear_flags();
+    }
+}
@@ -0,0 +1,324 @@
+//! Block-matching flow over a placement's neighborhood, for coasting
+//! through tracking dropouts
+
+use wasm_bindgen::prelude::*;
+
+use crate::advection::{fit_motion, Motion};
+use crate::geometry::Rect;
+use crate::transform::LayerTransform;
+use crate::{log_error, log_info};
+
+/// Blocks whose luma varies less than this match anywhere equally well and
+/// are left out
+const MIN_CONTRAST: u8 = 12;
+
+/// A match must cost at most this share of the mean over the search window
+/// to stand out from it; otherwise it may be anywhere (a block that became
+/// flat, an occluder, a cut)
+const MAX_MATCH_COST: f32 = 0.5;
+
+/// Carries a placement's transform through frames the tracker misses by
+/// estimating how its neighborhood moved since the previous frame: each
+/// block of luma is matched within `search_radius` pixels (sub-pixel from
+/// the match cost around the best offset), and the shift and uniform scale
+/// the agreeing blocks share is applied to the transform. Flat or
+/// disagreeing neighborhoods hold the transform still, and after
+/// `max_frames` frames without fresh tracking the placement is dropped
+#[wasm_bindgen]
+pub struct FlowTracker {
+    width: usize,
+    height: usize,
+    block_size: usize,
+    search_radius: usize,
+    max_frames: u32,
+    min_agreement: f32,
+    /// Neighborhood at the identity transform, centered on the placement
+    region: Rect,
+    /// Luma of the last frame seen
+    previous: Vec<u8>,
+    /// Tracked or propagated since, None once lost
+    transform: Option<LayerTransform>,
+    stale_frames: u32,
+    agreement: f32,
+}
+
+#[wasm_bindgen]
+impl FlowTracker {
+    /// Frames of `width` x `height`, matched in `block_size` pixel blocks;
+    /// `min_agreement` is the share of the neighborhood's blocks (0..1)
+    /// that must agree before the transform moves
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        width: u32,
+        height: u32,
+        block_size: u32,
+        search_radius: u32,
+        max_frames: u32,
+        min_agreement: f32,
+    ) -> FlowTracker {
+        FlowTracker {
+            width: width as usize,
+            height: height as usize,
+            block_size: block_size.max(2) as usize,
+            search_radius: search_radius as usize,
+            max_frames,
+            min_agreement: min_agreement.clamp(0.0, 1.0),
+            region: Rect::new(0.0, 0.0, width as f32, height as f32),
+            previous: Vec::new(),
+            transform: None,
+            stale_frames: 0,
+            agreement: 0.0,
+        }
+    }
+
+    /// Neighborhood the flow is estimated over, in frame pixels at the
+    /// identity transform: the placement's bounds plus some margin of the
+    /// surface around it, centered on the placement so the transform's
+    /// scale is about its center. The whole frame by default
+    pub fn set_region(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.region = Rect::new(x, y, width, height);
+    }
+
+    /// Fresh tracking for this RGBA frame. False, keeping the previous
+    /// state, for a frame of the wrong size
+    pub fn track(&mut self, frame: &[u8], transform: &LayerTransform) -> bool {
+        if !self.store(frame) {
+            return false;
+        }
+        self.transform = Some(*transform);
+        self.stale_frames = 0;
+        self.agreement = 1.0;
+        true
+    }
+
+    /// No tracking for this RGBA frame: move the last transform by the
+    /// flow of its neighborhood since the previous frame. Returns the
+    /// propagated transform, None once the placement is lost
+    pub fn propagate(&mut self, frame: &[u8]) -> Option<LayerTransform> {
+        let transform = self.transform?;
+        self.stale_frames += 1;
+        if self.stale_frames > self.max_frames {
+            log_info("WASM compositor: No tracking for too long, placement dropped");
+            self.transform = None;
+            self.agreement = 0.0;
+            return None;
+        }
+        if frame.len() != self.width * self.height * 4 {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return self.transform;
+        }
+        let current = luma(frame);
+
+        // On unreliable flow the transform holds: better to lag the
+        // surface than to jump off it
+        if let Some(((dx, dy), scale)) = self.fit(&transform, &current) {
+            self.transform =
+                Some(LayerTransform::new(transform.dx() + dx, transform.dy() + dy, transform.scale() * scale));
+        }
+        self.previous = current;
+        self.transform
+    }
+
+    /// Last tracked or propagated transform, None once lost
+    pub fn transform(&self) -> Option<LayerTransform> {
+        self.transform
+    }
+
+    /// Frames since the last `track`
+    pub fn stale_frames(&self) -> u32 {
+        self.stale_frames
+    }
+
+    /// Share of the neighborhood's blocks that agreed on the last flow, 1
+    /// on a tracked frame, for hosts fading the creative as confidence drops
+    pub fn agreement(&self) -> f32 {
+        self.agreement
+    }
+
+    pub fn reset(&mut self) {
+        self.previous.clear();
+        self.transform = None;
+        self.stale_frames = 0;
+        self.agreement = 0.0;
+    }
+}
+
+impl FlowTracker {
+    /// Keep the luma of `frame` for the next flow estimate
+    fn store(&mut self, frame: &[u8]) -> bool {
+        if frame.len() != self.width * self.height * 4 {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return false;
+        }
+        self.previous = luma(frame);
+        true
+    }
+
+    /// Shift and scale of the neighborhood, as placed by `transform`, from
+    /// the previous frame to `current`; None when too few blocks agree
+    fn fit(&mut self, transform: &LayerTransform, current: &[u8]) -> Option<Motion> {
+        let (cx, cy) = self.region.center();
+        let center = (cx + transform.dx(), cy + transform.dy());
+        let half_extent = transform.scale() * 0.5;
+        let (half_width, half_height) = (self.region.width * half_extent, self.region.height * half_extent);
+
+        // Blocks stay a search radius clear of the frame edges so every
+        // offset is inside the frame
+        let (size, radius) = (self.block_size, self.search_radius);
+        let clamp = |value: f32, limit: usize| {
+            value.max(radius as f32).min(limit.saturating_sub(radius) as f32) as usize
+        };
+        let (left, right) = (clamp(center.0 - half_width, self.width), clamp(center.0 + half_width, self.width));
+        let (top, bottom) = (clamp(center.1 - half_height, self.height), clamp(center.1 + half_height, self.height));
+
+        let half = size as f32 * 0.5;
+        let mut blocks = 0usize;
+        let mut samples = Vec::new();
+        for y in (top..bottom.saturating_sub(size - 1)).step_by(size) {
+            for x in (left..right.saturating_sub(size - 1)).step_by(size) {
+                blocks += 1;
+                if let Some(motion) = self.match_block(current, x, y) {
+                    samples.push(((x as f32 + half, y as f32 + half), motion));
+                }
+            }
+        }
+        let Some((motion, inliers)) = fit_motion(&samples, center) else {
+            self.agreement = 0.0;
+            return None;
+        };
+        self.agreement = inliers as f32 / blocks as f32;
+        (self.agreement >= self.min_agreement).then_some(motion)
+    }
+
+    /// Where the block at `(x, y)` of the previous frame moved to in
+    /// `current`, None for a flat block or one without a distinct match
+    fn match_block(&self, current: &[u8], x: usize, y: usize) -> Option<(f32, f32)> {
+        let (size, radius, width) = (self.block_size, self.search_radius, self.width);
+        let rows = || (y..y + size).map(|row| row * width + x);
+        let (low, high) = rows()
+            .flat_map(|start| &self.previous[start..start + size])
+            .fold((u8::MAX, u8::MIN), |(low, high), &v| (low.min(v), high.max(v)));
+        if high - low < MIN_CONTRAST {
+            return None;
+        }
+
+        // Sum of absolute differences at every offset within the radius
+        let span = radius * 2 + 1;
+        let costs: Vec<u32> = (0..span * span)
+            .map(|k| {
+                let offset = (k % span) as isize - radius as isize + (k / span) as isize * width as isize
+                    - (radius * width) as isize;
+                rows()
+                    .map(|start| {
+                        let moved = (start as isize + offset) as usize;
+                        let (a, b) = (&self.previous[start..start + size], &current[moved..moved + size]);
+                        a.iter().zip(b).map(|(&a, &b)| a.abs_diff(b) as u32).sum::<u32>()
+                    })
+                    .sum()
+            })
+            .collect();
+        let best = (0..costs.len()).min_by_key(|&k| costs[k])?;
+        let mean = costs.iter().map(|&cost| cost as f32).sum::<f32>() / costs.len() as f32;
+        if costs[best] as f32 > MAX_MATCH_COST * mean {
+            return None;
+        }
+        let (u, v) = (best % span, best / span);
+
+        // A parabola through the costs either side of the best offset
+        let refine = |before: Option<u32>, at: u32, after: Option<u32>| match (before, after) {
+            (Some(before), Some(after)) => {
+                let curvature = before as f32 - 2.0 * at as f32 + after as f32;
+                if curvature > 0.0 {
+                    0.5 * (before as f32 - after as f32) / curvature
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+        let cost = |u: usize, v: usize| (u < span && v < span).then(|| costs[v * span + u]);
+        let fx = refine(u.checked_sub(1).and_then(|u| cost(u, v)), costs[best], cost(u + 1, v));
+        let fy = refine(v.checked_sub(1).and_then(|v| cost(u, v)), costs[best], cost(u, v + 1));
+        Some((u as f32 - radius as f32 + fx, v as f32 - radius as f32 + fy))
+    }
+}
+
+/// BT.601 luma of an RGBA frame, 8-bit
+fn luma(frame: &[u8]) -> Vec<u8> {
+    frame
+        .chunks_exact(4)
+        .map(|p| ((77 * p[0] as u32 + 150 * p[1] as u32 + 29 * p[2] as u32 + 128) >> 8) as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::PlacementRng;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+
+    /// RGBA frame of a random texture, whose content sits `(dx, dy)` pixels
+    /// from where it is at `(0, 0)`
+    fn frame(dx: isize, dy: isize) -> Vec<u8> {
+        let mut rng = PlacementRng::from_seed(7);
+        let texture: Vec<u8> = (0..(WIDTH + 16) * (HEIGHT + 16)).map(|_| rng.next_u32() as u8).collect();
+        (0..WIDTH * HEIGHT)
+            .flat_map(|i| {
+                let x = (i % WIDTH) as isize + 8 - dx;
+                let y = (i / WIDTH) as isize + 8 - dy;
+                let v = texture[y as usize * (WIDTH + 16) + x as usize];
+                [v, v, v, 255]
+            })
+            .collect()
+    }
+
+    fn tracker(max_frames: u32) -> FlowTracker {
+        let mut tracker = FlowTracker::new(WIDTH as u32, HEIGHT as u32, 8, 4, max_frames, 0.5);
+        tracker.set_region(16.0, 12.0, 32.0, 24.0);
+        tracker
+    }
+
+    #[test]
+    fn test_propagates_transform_along_the_flow() {
+        let mut tracker = tracker(10);
+        assert!(tracker.track(&frame(0, 0), &LayerTransform::new(1.0, 0.0, 1.0)));
+        let moved = tracker.propagate(&frame(2, -1)).unwrap();
+        assert!((moved.dx() - 3.0).abs() < 0.1 && (moved.dy() + 1.0).abs() < 0.1, "{:?}", moved);
+        assert!((moved.scale() - 1.0).abs() < 0.01, "{:?}", moved);
+        assert!(tracker.agreement() > 0.9);
+
+        // The next frame is matched against this one, not the tracked one
+        let moved = tracker.propagate(&frame(5, -1)).unwrap();
+        assert!((moved.dx() - 6.0).abs() < 0.1 && (moved.dy() + 1.0).abs() < 0.1, "{:?}", moved);
+        assert_eq!(tracker.stale_frames(), 2);
+    }
+
+    #[test]
+    fn test_holds_on_flat_or_unrelated_frames() {
+        let mut tracker = tracker(10);
+        let tracked = LayerTransform::new(0.0, 0.0, 1.0);
+        assert!(tracker.track(&frame(0, 0), &tracked));
+        assert_eq!(tracker.propagate(&vec![128; WIDTH * HEIGHT * 4]), Some(tracked));
+        assert_eq!(tracker.agreement(), 0.0);
+
+        let mut rng = PlacementRng::from_seed(99);
+        let unrelated: Vec<u8> = (0..WIDTH * HEIGHT * 4).map(|_| rng.next_u32() as u8).collect();
+        assert!(tracker.track(&frame(0, 0), &tracked));
+        assert_eq!(tracker.propagate(&unrelated), Some(tracked));
+        assert!(tracker.agreement() < 0.5);
+    }
+
+    #[test]
+    fn test_drops_placement_after_max_frames() {
+        let mut tracker = tracker(2);
+        assert_eq!(tracker.propagate(&frame(0, 0)), None);
+        assert!(!tracker.track(&frame(0, 0)[..8], &LayerTransform::default()));
+        assert!(tracker.track(&frame(0, 0), &LayerTransform::default()));
+        assert!(tracker.propagate(&frame(0, 0)).is_some());
+        assert!(tracker.propagate(&frame(0, 0)).is_some());
+        assert_eq!(tracker.propagate(&frame(0, 0)), None);
+        assert_eq!(tracker.transform(), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Confidence-weighted fusion of predicted and tracked placement transforms
+
+use wasm_bindgen::prelude::*;
+
+use crate::transform::LayerTransform;
+
+const PROFILES: [&str; 3] = ["balanced", "rigid", "handheld"];
+
+/// Filter state of one transform parameter
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Channel {
+    value: f32,
+    /// Change per second
+    velocity: f32,
+}
+
+impl Channel {
+    /// One alpha-beta step `dt` seconds on towards `measured`, trusted by
+    /// `confidence` (0..1); the result stays within `max_lag` of the
+    /// measurement. Without a time step the velocity is left alone
+    fn step(
+        &mut self,
+        measured: f32,
+        confidence: f32,
+        alpha: f32,
+        beta: f32,
+        max_lag: f32,
+        dt: Option<f32>,
+    ) -> f32 {
+        let predicted = self.value + self.velocity * dt.unwrap_or(0.0);
+        let residual = measured - predicted;
+        let value = predicted + alpha * confidence * residual;
+        if let Some(dt) = dt {
+            self.velocity += beta * confidence * residual / dt;
+        }
+        self.value = measured + (value - measured).clamp(-max_lag, max_lag);
+        self.value
+    }
+
+    /// Carry on `dt` seconds at the current velocity with nothing to go on
+    fn coast(&mut self, dt: Option<f32>) -> f32 {
+        self.value += self.velocity * dt.unwrap_or(0.0);
+        self.value
+    }
+}
+
+/// Smooths placement motion by fusing a motion model's predicted transform
+/// with the tracker's, each weighted by the confidence its source supplies,
+/// through an alpha-beta filter per parameter (shift, and scale in log
+/// space so zooms run evenly). Disagreements blend in over a few frames
+/// instead of jumping, while the lag behind the fused measurement is
+/// bounded so fast motion is not smeared away. Velocity is kept per second
+/// and frames carry their timestamps, so variable frame rates and dropped
+/// frames predict as far as the time that actually passed
+#[wasm_bindgen]
+pub struct TransformFilter {
+    alpha: f32,
+    beta: f32,
+    /// Furthest the output may trail the measurement, in pixels of shift
+    /// and log units of scale
+    max_shift_lag: f32,
+    max_scale_lag: f32,
+    /// dx, dy and ln(scale), None before the first measurement
+    channels: Option<[Channel; 3]>,
+    previous_pts: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl TransformFilter {
+    /// `alpha` (position) and `beta` (velocity) gains in 0..1; higher
+    /// follows faster, lower smooths more
+    #[wasm_bindgen(constructor)]
+    pub fn new(alpha: f32, beta: f32) -> TransformFilter {
+        TransformFilter {
+            alpha: alpha.clamp(0.0, 1.0),
+            beta: beta.clamp(0.0, 1.0),
+            max_shift_lag: 8.0,
+            max_scale_lag: 0.05f32.ln_1p(),
+            channels: None,
+            previous_pts: None,
+        }
+    }
+
+    /// Tuned smoothing by name, for manifests that pick it per placement:
+    /// "rigid" for tripod shots of architecture (heavy smoothing, long lag
+    /// allowed), "handheld" for shaky footage that needs responsiveness, and
+    /// "balanced" in between. None for an unknown name
+    pub fn from_profile(name: &str) -> Option<TransformFilter> {
+        let (alpha, beta, shift_lag, scale_lag) = match name {
+            "balanced" => (0.5, 0.1, 8.0, 0.05),
+            // The camera does not move, so any jitter is tracker noise
+            "rigid" => (0.15, 0.01, 24.0, 0.1),
+            "handheld" => (0.8, 0.3, 3.0, 0.02),
+            _ => return None,
+        };
+        let mut filter = TransformFilter::new(alpha, beta);
+        filter.set_max_lag(shift_lag, scale_lag);
+        Some(filter)
+    }
+
+    /// Names accepted by `from_profile`
+    pub fn profile_names() -> Vec<String> {
+        PROFILES.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Lag bound in pixels of shift and as a fraction of scale (default 8
+    /// and 0.05)
+    pub fn set_max_lag(&mut self, shift: f32, scale: f32) {
+        self.max_shift_lag = shift.max(0.0);
+        self.max_scale_lag = scale.max(0.0).ln_1p();
+    }
+
+    /// Fuse the predicted and tracked transforms of the frame at
+    /// `pts_seconds`, with their 0..1 confidences, into the transform to
+    /// composite with. With neither confident the filter coasts on its
+    /// velocity; non-positive scales count as no confidence. A timestamp
+    /// that does not move forward, as on a seek back, predicts no motion
+    pub fn update(
+        &mut self,
+        predicted: &LayerTransform,
+        predicted_confidence: f32,
+        tracked: &LayerTransform,
+        tracked_confidence: f32,
+        pts_seconds: f64,
+    ) -> LayerTransform {
+        let dt = self
+            .previous_pts
+            .map(|prev| pts_seconds - prev)
+            .filter(|&dt| dt > 0.0)
+            .map(|dt| dt as f32);
+        self.previous_pts = Some(pts_seconds);
+
+        let weight = |transform: &LayerTransform, confidence: f32| {
+            if transform.scale() > 0.0 && confidence.is_finite() {
+                confidence.clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        };
+        let (wp, wt) = (weight(predicted, predicted_confidence), weight(tracked, tracked_confidence));
+        let total = wp + wt;
+        let measured = |p: f32, t: f32| (p * wp + t * wt) / total;
+
+        let Some(channels) = self.channels.as_mut() else {
+            if total <= 0.0 {
+                return LayerTransform::default();
+            }
+            // The first measurement is taken as is
+            let value = [
+                measured(predicted.dx(), tracked.dx()),
+                measured(predicted.dy(), tracked.dy()),
+                measured(predicted.scale().ln(), tracked.scale().ln()),
+            ];
+            self.channels = Some(value.map(|value| Channel { value, velocity: 0.0 }));
+            return self.current();
+        };
+        if total <= 0.0 {
+            channels.iter_mut().for_each(|channel| {
+                channel.coast(dt);
+            });
+            return self.current();
+        }
+
+        // Either source alone at full confidence gets the full gain
+        let confidence = total.min(1.0);
+        let (alpha, beta) = (self.alpha, self.beta);
+        let lags = [self.max_shift_lag, self.max_shift_lag, self.max_scale_lag];
+        let measurements = [
+            measured(predicted.dx(), tracked.dx()),
+            measured(predicted.dy(), tracked.dy()),
+            measured(predicted.scale().ln(), tracked.scale().ln()),
+        ];
+        for ((channel, measured), max_lag) in channels.iter_mut().zip(measurements).zip(lags) {
+            channel.step(measured, confidence, alpha, beta, max_lag, dt);
+        }
+        self.current()
+    }
+
+    /// Last fused transform, identity before any measurement
+    pub fn current(&self) -> LayerTransform {
+        match self.channels {
+            Some([dx, dy, scale]) => LayerTransform::new(dx.value, dy.value, scale.value.exp()),
+            None => LayerTransform::default(),
+        }
+    }
+
+    /// Forget the motion, e.g. on a cut
+    pub fn reset(&mut self) {
+        self.channels = None;
+        self.previous_pts = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Timestamp of frame `n` at 25 fps
+    fn pts(n: u32) -> f64 {
+        n as f64 / 25.0
+    }
+
+    fn shift(dx: f32) -> LayerTransform {
+        LayerTransform::new(dx, 0.0, 1.0)
+    }
+
+    #[test]
+    fn test_confidence_weights_the_sources() {
+        let mut filter = TransformFilter::new(1.0, 0.0);
+        let fused = filter.update(&shift(0.0), 0.25, &shift(4.0), 0.75, pts(0));
+        assert_eq!(fused.dx(), 3.0);
+
+        // A doubtful tracker moves the estimate less than a confident one
+        let mut doubtful = TransformFilter::new(0.5, 0.0);
+        let mut confident = TransformFilter::new(0.5, 0.0);
+        doubtful.update(&shift(0.0), 0.0, &shift(0.0), 1.0, pts(0));
+        confident.update(&shift(0.0), 0.0, &shift(0.0), 1.0, pts(0));
+        let low = doubtful.update(&shift(0.0), 0.0, &shift(4.0), 0.25, pts(1)).dx();
+        let high = confident.update(&shift(0.0), 0.0, &shift(4.0), 1.0, pts(1)).dx();
+        assert!(low > 0.0 && low < high, "{} {}", low, high);
+        assert_eq!(high, 2.0);
+    }
+
+    #[test]
+    fn test_smooths_jumps_within_the_lag_bound() {
+        let mut filter = TransformFilter::new(0.3, 0.05);
+        filter.set_max_lag(5.0, 0.05);
+        filter.update(&shift(0.0), 0.0, &LayerTransform::new(0.0, 0.0, 1.0), 1.0, pts(0));
+
+        // A 100 pixel jump: the output trails by at most 5 and converges
+        let first = filter.update(&shift(0.0), 0.0, &shift(100.0), 1.0, pts(1)).dx();
+        assert_eq!(first, 95.0);
+        let mut settled = LayerTransform::default();
+        for n in 2..62 {
+            settled = filter.update(&shift(0.0), 0.0, &shift(100.0), 1.0, pts(n));
+        }
+        assert!((settled.dx() - 100.0).abs() < 0.5, "{:?}", settled);
+
+        // Scale lag is bounded as a fraction
+        let zoomed = filter.update(&shift(0.0), 0.0, &LayerTransform::new(100.0, 0.0, 2.0), 1.0, pts(62));
+        assert!((zoomed.scale() - 2.0 / 1.05).abs() < 1e-4, "{:?}", zoomed);
+    }
+
+    #[test]
+    fn test_profiles_trade_smoothing_for_response() {
+        let step = |name: &str| {
+            let mut filter = TransformFilter::from_profile(name).unwrap();
+            filter.update(&shift(0.0), 0.0, &shift(0.0), 1.0, pts(0));
+            // Half a pixel of tracker jitter
+            filter.update(&shift(0.0), 0.0, &shift(0.5), 1.0, pts(1)).dx()
+        };
+        let (rigid, balanced, handheld) = (step("rigid"), step("balanced"), step("handheld"));
+        assert!(rigid < balanced && balanced < handheld, "{} {} {}", rigid, balanced, handheld);
+
+        let names = TransformFilter::profile_names();
+        assert!(names.iter().all(|name| TransformFilter::from_profile(name).is_some()));
+        assert!(TransformFilter::from_profile("tripod").is_none());
+    }
+
+    #[test]
+    fn test_coasts_without_confident_sources() {
+        let mut filter = TransformFilter::new(1.0, 1.0);
+        assert_eq!(filter.update(&shift(1.0), 0.0, &shift(1.0), 0.0, pts(0)), LayerTransform::default());
+        filter.update(&shift(0.0), 1.0, &shift(0.0), 0.0, pts(1));
+        filter.update(&shift(2.0), 1.0, &shift(0.0), 0.0, pts(2));
+
+        // Moving 2 pixels a frame, then nothing to go on
+        let before = filter.current().dx();
+        let coasted = filter.update(&shift(0.0), 0.0, &LayerTransform::new(50.0, 0.0, -1.0), 1.0, pts(3));
+        assert!((coasted.dx() - (before + 2.0)).abs() < 1e-4, "{:?}", coasted);
+        filter.reset();
+        assert_eq!(filter.current(), LayerTransform::default());
+    }
+
+    #[test]
+    fn test_motion_scales_with_frame_time() {
+        // The same 50 pixels per second pan, at 25 fps and then at 50 fps
+        let mut filter = TransformFilter::new(1.0, 1.0);
+        filter.update(&shift(0.0), 1.0, &shift(0.0), 0.0, 0.0);
+        filter.update(&shift(2.0), 1.0, &shift(0.0), 0.0, 0.04);
+        let coast = |filter: &mut TransformFilter, pts: f64| {
+            let before = filter.current().dx();
+            filter.update(&shift(0.0), 0.0, &shift(0.0), 0.0, pts).dx() - before
+        };
+        assert!((coast(&mut filter, 0.06) - 1.0).abs() < 1e-4);
+        // A dropped frame coasts twice as far, a repeated timestamp not at all
+        assert!((coast(&mut filter, 0.10) - 2.0).abs() < 1e-4);
+        assert_eq!(coast(&mut filter, 0.10), 0.0);
+    }
+}
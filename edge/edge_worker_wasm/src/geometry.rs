@@ -0,0 +1,561 @@
+//! Frame-space geometry helpers shared by the compositor
+
+use wasm_bindgen::prelude::*;
+
+use crate::sampler::Coverage;
+
+/// Axis-aligned rectangle in frame pixel coordinates
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Parse a flat `[x, y, w, h, x, y, w, h, ...]` list as passed from JS
+    pub fn from_flat(data: &[f32]) -> Vec<Rect> {
+        data.chunks_exact(4)
+            .map(|r| Rect::new(r[0], r[1], r[2], r[3]))
+            .collect()
+    }
+
+    pub fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+
+    /// True when the two rectangles share a non-zero area
+    pub fn intersects(&self, other: &Rect) -> bool {
+        !self.is_empty()
+            && !other.is_empty()
+            && self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    pub fn area(&self) -> f32 {
+        if self.is_empty() {
+            0.0
+        } else {
+            self.width * self.height
+        }
+    }
+
+    pub fn center(&self) -> (f32, f32) {
+        (self.x + self.width * 0.5, self.y + self.height * 0.5)
+    }
+
+    /// Overlapping part of two rectangles, if any
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        if !self.intersects(other) {
+            return None;
+        }
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        Some(Rect::new(
+            x,
+            y,
+            self.right().min(other.right()) - x,
+            self.bottom().min(other.bottom()) - y,
+        ))
+    }
+
+    /// True when `other` lies entirely inside this rectangle
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// Corners clockwise from the top-left, as `(x, y)` points
+    pub fn corners(&self) -> [(f32, f32); 4] {
+        [
+            (self.x, self.y),
+            (self.right(), self.y),
+            (self.right(), self.bottom()),
+            (self.x, self.bottom()),
+        ]
+    }
+
+    /// Shift by `(dx, dy)` and scale by `scale` about the rectangle center
+    pub fn offset_scaled(&self, dx: f32, dy: f32, scale: f32) -> Rect {
+        let (cx, cy) = self.center();
+        let width = self.width * scale;
+        let height = self.height * scale;
+        Rect::new(cx + dx - width * 0.5, cy + dy - height * 0.5, width, height)
+    }
+}
+
+/// Clip a polygon against an axis-aligned rectangle (Sutherland-Hodgman);
+/// returns an empty list when nothing remains
+pub fn clip_polygon(points: &[(f32, f32)], clip: &Rect) -> Vec<(f32, f32)> {
+    let edges = [
+        ClipEdge::Left(clip.x),
+        ClipEdge::Right(clip.right()),
+        ClipEdge::Top(clip.y),
+        ClipEdge::Bottom(clip.bottom()),
+    ];
+
+    let mut output = points.to_vec();
+    for edge in edges {
+        let input = std::mem::take(&mut output);
+        let Some(&last) = input.last() else {
+            break;
+        };
+        let mut prev = last;
+        for &point in &input {
+            match (edge.inside(prev), edge.inside(point)) {
+                (true, true) => output.push(point),
+                (true, false) => output.push(edge.crossing(prev, point)),
+                (false, true) => {
+                    output.push(edge.crossing(prev, point));
+                    output.push(point);
+                }
+                (false, false) => {}
+            }
+            prev = point;
+        }
+    }
+    output
+}
+
+/// One boundary of the clip rectangle
+#[derive(Clone, Copy)]
+enum ClipEdge {
+    Left(f32),
+    Right(f32),
+    Top(f32),
+    Bottom(f32),
+}
+
+impl ClipEdge {
+    fn inside(self, p: (f32, f32)) -> bool {
+        match self {
+            ClipEdge::Left(x) => p.0 >= x,
+            ClipEdge::Right(x) => p.0 <= x,
+            ClipEdge::Top(y) => p.1 >= y,
+            ClipEdge::Bottom(y) => p.1 <= y,
+        }
+    }
+
+    /// Point where segment `a`-`b` crosses this edge
+    fn crossing(self, a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+        match self {
+            ClipEdge::Left(x) | ClipEdge::Right(x) => {
+                let t = (x - a.0) / (b.0 - a.0);
+                (x, a.1 + (b.1 - a.1) * t)
+            }
+            ClipEdge::Top(y) | ClipEdge::Bottom(y) => {
+                let t = (y - a.1) / (b.1 - a.1);
+                (a.0 + (b.0 - a.0) * t, y)
+            }
+        }
+    }
+}
+
+/// Even-odd point-in-polygon test
+pub fn polygon_contains(points: &[(f32, f32)], x: f32, y: f32) -> bool {
+    let mut inside = false;
+    let mut j = points.len().wrapping_sub(1);
+    for i in 0..points.len() {
+        let (xi, yi) = points[i];
+        let (xj, yj) = points[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// Area of `rect` inside `allowed` that no exclusion region covers
+pub fn visible_area(rect: &Rect, allowed: &Rect, exclusions: &[Rect]) -> f32 {
+    let Some(visible) = rect.intersection(allowed) else {
+        return 0.0;
+    };
+
+    let clipped: Vec<Rect> = exclusions
+        .iter()
+        .filter_map(|region| region.intersection(&visible))
+        .collect();
+
+    visible.area() - union_area(&clipped)
+}
+
+/// Exact area covered by a set of possibly overlapping rectangles
+fn union_area(rects: &[Rect]) -> f32 {
+    if rects.is_empty() {
+        return 0.0;
+    }
+
+    // Sweep over the compressed x coordinates, merging y intervals per column
+    let mut xs: Vec<f32> = rects.iter().flat_map(|r| [r.x, r.right()]).collect();
+    xs.sort_by(f32::total_cmp);
+    xs.dedup();
+
+    let mut area = 0.0;
+    for column in xs.windows(2) {
+        let (left, right) = (column[0], column[1]);
+        let mut spans: Vec<(f32, f32)> = rects
+            .iter()
+            .filter(|r| r.x <= left && r.right() >= right)
+            .map(|r| (r.y, r.bottom()))
+            .collect();
+        spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut covered = 0.0;
+        let mut current: Option<(f32, f32)> = None;
+        for (top, bottom) in spans {
+            current = match current {
+                Some((start, end)) if top <= end => Some((start, end.max(bottom))),
+                Some((start, end)) => {
+                    covered += end - start;
+                    Some((top, bottom))
+                }
+                None => Some((top, bottom)),
+            };
+        }
+        if let Some((start, end)) = current {
+            covered += end - start;
+        }
+        area += covered * (right - left);
+    }
+
+    area
+}
+
+/// Spans `[left, right)` of the horizontal line at `y` inside a polygon,
+/// left to right, under the even-odd rule of `polygon_contains`
+pub fn polygon_spans(points: &[(f32, f32)], y: f32) -> Vec<(f32, f32)> {
+    let mut crossings: Vec<f32> = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .filter(|(a, b)| (a.1 > y) != (b.1 > y))
+        .map(|(a, b)| a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1))
+        .collect();
+    crossings.sort_by(f32::total_cmp);
+    crossings.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+/// Area of a simple polygon (shoelace formula), either winding
+pub fn polygon_area(points: &[(f32, f32)]) -> f32 {
+    let twice: f32 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum();
+    twice.abs() * 0.5
+}
+
+/// Projective map of the plane, a row-major 3x3 matrix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Homography([f64; 9]);
+
+impl Homography {
+    /// Map taking the unit square's corners `(0, 0), (1, 0), (1, 1), (0, 1)`
+    /// onto `quad` in that order (Heckbert's closed form); None unless the
+    /// quad is convex
+    pub fn square_to_quad(quad: &[(f32, f32); 4]) -> Option<Homography> {
+        let turns: Vec<f32> = (0..4)
+            .map(|i| {
+                let (a, b, c) = (quad[i], quad[(i + 1) % 4], quad[(i + 2) % 4]);
+                (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0)
+            })
+            .collect();
+        if !(turns.iter().all(|&turn| turn > 0.0) || turns.iter().all(|&turn| turn < 0.0)) {
+            return None;
+        }
+
+        let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = quad.map(|(x, y)| (x as f64, y as f64));
+        let (sx, sy) = (x0 - x1 + x2 - x3, y0 - y1 + y2 - y3);
+        // A parallelogram is affine, no perspective terms
+        let (g, h) = if sx == 0.0 && sy == 0.0 {
+            (0.0, 0.0)
+        } else {
+            let (dx1, dx2, dy1, dy2) = (x1 - x2, x3 - x2, y1 - y2, y3 - y2);
+            let den = dx1 * dy2 - dx2 * dy1;
+            ((sx * dy2 - dx2 * sy) / den, (dx1 * sy - sx * dy1) / den)
+        };
+        Some(Homography([
+            x1 - x0 + g * x1,
+            x3 - x0 + h * x3,
+            x0,
+            y1 - y0 + g * y1,
+            y3 - y0 + h * y3,
+            y0,
+            g,
+            h,
+            1.0,
+        ]))
+    }
+
+    /// From a row-major 3x3 matrix, None unless it has nine finite entries
+    pub fn from_matrix(matrix: &[f32]) -> Option<Homography> {
+        let matrix: [f32; 9] = matrix.try_into().ok()?;
+        matrix.iter().all(|m| m.is_finite()).then(|| Homography(matrix.map(f64::from)))
+    }
+
+    /// This map applied after scaling the input by `(sx, sy)`
+    pub fn scale_input(&self, sx: f32, sy: f32) -> Homography {
+        let (sx, sy) = (sx as f64, sy as f64);
+        let mut matrix = self.0;
+        for row in matrix.chunks_exact_mut(3) {
+            row[0] *= sx;
+            row[1] *= sy;
+        }
+        Homography(matrix)
+    }
+
+    pub fn invert(&self) -> Option<Homography> {
+        let [a, b, c, d, e, f, g, h, i] = self.0;
+        let adjugate = [
+            e * i - f * h,
+            c * h - b * i,
+            b * f - c * e,
+            f * g - d * i,
+            a * i - c * g,
+            c * d - a * f,
+            d * h - e * g,
+            b * g - a * h,
+            a * e - b * d,
+        ];
+        let det = a * adjugate[0] + b * adjugate[3] + c * adjugate[6];
+        if det.abs() < 1e-12 || !det.is_finite() {
+            return None;
+        }
+        Some(Homography(adjugate.map(|value| value / det)))
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        let [a, b, c, d, e, f, g, h, i] = self.0;
+        let (x, y) = (x as f64, y as f64);
+        let w = g * x + h * y + i;
+        (((a * x + b * y + c) / w) as f32, ((d * x + e * y + f) / w) as f32)
+    }
+}
+
+// Host-side exports of the math above, so integrators reproduce the
+// compositor exactly. Rectangles are flat `[x, y, w, h]` and polygons flat
+// `[x, y, ...]`, in frame pixels
+
+fn flat_rect(data: &[f32]) -> Option<Rect> {
+    Rect::from_flat(data).first().copied()
+}
+
+fn flat_points(data: &[f32]) -> Vec<(f32, f32)> {
+    data.chunks_exact(2).map(|p| (p[0], p[1])).collect()
+}
+
+/// Corners clockwise from the top-left of `rect` after a layer shift and
+/// scale about its center, as the warp places it
+#[wasm_bindgen]
+pub fn project_quad(rect: &[f32], dx: f32, dy: f32, scale: f32) -> Vec<f32> {
+    let Some(rect) = flat_rect(rect) else {
+        return Vec::new();
+    };
+    rect.offset_scaled(dx, dy, scale).corners().into_iter().flat_map(|(x, y)| [x, y]).collect()
+}
+
+/// Overlap of two rectangles, empty when they only touch or are apart
+#[wasm_bindgen]
+pub fn rect_intersection(a: &[f32], b: &[f32]) -> Vec<f32> {
+    match flat_rect(a).zip(flat_rect(b)).and_then(|(a, b)| a.intersection(&b)) {
+        Some(r) => vec![r.x, r.y, r.width, r.height],
+        None => Vec::new(),
+    }
+}
+
+/// Part of `rect` inside a `width x height` frame
+#[wasm_bindgen]
+pub fn clamp_rect(rect: &[f32], width: u32, height: u32) -> Vec<f32> {
+    rect_intersection(rect, &[0.0, 0.0, width as f32, height as f32])
+}
+
+/// True when `inner` lies entirely inside `outer`
+#[wasm_bindgen]
+pub fn rect_contains(outer: &[f32], inner: &[f32]) -> bool {
+    flat_rect(outer).zip(flat_rect(inner)).is_some_and(|(outer, inner)| outer.contains_rect(&inner))
+}
+
+/// Zero for empty or malformed rectangles
+#[wasm_bindgen]
+pub fn rect_area(rect: &[f32]) -> f32 {
+    flat_rect(rect).map_or(0.0, |rect| rect.area())
+}
+
+/// Area of a polygon such as one returned by `placement_hotspot`
+#[wasm_bindgen]
+pub fn quad_area(polygon: &[f32]) -> f32 {
+    polygon_area(&flat_points(polygon))
+}
+
+/// Area of `rect` inside `allowed` and outside every exclusion (flat list of
+/// rectangles), exactly as `PlacementSolver` scores candidates
+#[wasm_bindgen]
+pub fn rect_visible_area(rect: &[f32], allowed: &[f32], exclusion_rects: &[f32]) -> f32 {
+    match flat_rect(rect).zip(flat_rect(allowed)) {
+        Some((rect, allowed)) => visible_area(&rect, &allowed, &Rect::from_flat(exclusion_rects)),
+        None => 0.0,
+    }
+}
+
+/// Bounding box of all pixels with non-zero mask coverage
+pub fn mask_bounds<A: Coverage>(alpha_mask: &[A], width: u32, height: u32) -> Option<Rect> {
+    let width = width as usize;
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+
+    for (y, row) in alpha_mask.chunks_exact(width.max(1)).take(height as usize).enumerate() {
+        let Some(first) = row.iter().position(|&a| a != A::ZERO) else {
+            continue;
+        };
+        // A row with a first covered pixel always has a last one
+        let last = row.iter().rposition(|&a| a != A::ZERO).unwrap_or(first);
+        bounds = Some(match bounds {
+            Some((min_x, min_y, max_x, _)) => (min_x.min(first), min_y, max_x.max(last), y),
+            None => (first, y, last, y),
+        });
+    }
+
+    bounds.map(|(min_x, min_y, max_x, max_y)| {
+        Rect::new(
+            min_x as f32,
+            min_y as f32,
+            (max_x - min_x + 1) as f32,
+            (max_y - min_y + 1) as f32,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_visible_area_subtracts_overlapping_exclusions_once() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let allowed = Rect::new(0.0, 0.0, 100.0, 100.0);
+
+        // Two exclusions overlapping each other inside the rect
+        let exclusions = [Rect::new(0.0, 0.0, 5.0, 5.0), Rect::new(2.0, 2.0, 5.0, 5.0)];
+
+        // Union of the exclusions is 25 + 25 - 9 = 41
+        assert_eq!(visible_area(&rect, &allowed, &exclusions), 59.0);
+    }
+
+    #[test]
+    fn test_visible_area_clips_to_allowed_region() {
+        let rect = Rect::new(-5.0, 0.0, 10.0, 10.0);
+        let allowed = Rect::new(0.0, 0.0, 100.0, 100.0);
+
+        assert_eq!(visible_area(&rect, &allowed, &[]), 50.0);
+    }
+
+    #[test]
+    fn test_clip_polygon_to_frame() {
+        let frame = Rect::new(0.0, 0.0, 10.0, 10.0);
+
+        // Square hanging off the right edge is trimmed at x = 10
+        let square = Rect::new(5.0, 2.0, 10.0, 4.0).corners();
+        let clipped = clip_polygon(&square, &frame);
+        assert_eq!(clipped.len(), 4);
+        assert!(clipped.iter().all(|p| p.0 <= 10.0));
+
+        // Fully outside: nothing left
+        let outside = Rect::new(20.0, 20.0, 5.0, 5.0).corners();
+        assert!(clip_polygon(&outside, &frame).is_empty());
+    }
+
+    #[test]
+    fn test_polygon_contains() {
+        let triangle = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+        assert!(polygon_contains(&triangle, 2.0, 2.0));
+        assert!(!polygon_contains(&triangle, 8.0, 8.0));
+        assert!(!polygon_contains(&[], 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_polygon_spans() {
+        let triangle = [(0.0, 0.0), (10.0, 0.0), (0.0, 10.0)];
+        assert_eq!(polygon_spans(&triangle, 5.0), vec![(0.0, 5.0)]);
+        assert!(polygon_spans(&triangle, 12.0).is_empty());
+
+        // U shape: two spans through the arms
+        let u = [
+            (0.0, 0.0),
+            (9.0, 0.0),
+            (9.0, 4.0),
+            (6.0, 4.0),
+            (6.0, 2.0),
+            (3.0, 2.0),
+            (3.0, 4.0),
+            (0.0, 4.0),
+        ];
+        assert_eq!(polygon_spans(&u, 3.0), vec![(0.0, 3.0), (6.0, 9.0)]);
+    }
+
+    #[test]
+    fn test_polygon_area() {
+        assert_eq!(polygon_area(&Rect::new(1.0, 1.0, 4.0, 3.0).corners()), 12.0);
+        assert_eq!(polygon_area(&[(0.0, 0.0), (0.0, 10.0), (10.0, 0.0)]), 50.0);
+        assert_eq!(polygon_area(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_flat_exports() {
+        let quad = project_quad(&[0.0, 0.0, 4.0, 2.0], 1.0, 0.0, 0.5);
+        assert_eq!(quad, vec![2.0, 0.5, 4.0, 0.5, 4.0, 1.5, 2.0, 1.5]);
+        assert_eq!(rect_intersection(&[0.0, 0.0, 4.0, 4.0], &[2.0, 2.0, 4.0, 4.0]), vec![2.0, 2.0, 2.0, 2.0]);
+        assert!(rect_intersection(&[0.0, 0.0, 2.0, 2.0], &[2.0, 0.0, 2.0, 2.0]).is_empty());
+        assert_eq!(clamp_rect(&[-2.0, 1.0, 4.0, 4.0], 10, 3), vec![0.0, 1.0, 2.0, 2.0]);
+        assert!(rect_contains(&[0.0, 0.0, 4.0, 4.0], &[1.0, 1.0, 2.0, 2.0]));
+        assert!(!rect_contains(&[0.0, 0.0, 4.0, 4.0], &[]));
+        assert_eq!(rect_area(&[0.0, 0.0, 3.0, -1.0]), 0.0);
+        assert_eq!(quad_area(&[0.0, 0.0, 2.0, 0.0, 2.0, 2.0, 0.0, 2.0]), 4.0);
+        let (rect, allowed) = ([0.0, 0.0, 10.0, 10.0], [0.0, 0.0, 5.0, 10.0]);
+        let visible = rect_visible_area(&rect, &allowed, &[0.0, 0.0, 5.0, 5.0]);
+        assert_eq!(visible, 25.0);
+    }
+
+    #[test]
+    fn test_homography_maps_square_to_quad() {
+        // A trapezoid, as a board seen at an angle
+        let quad = [(10.0, 10.0), (50.0, 20.0), (50.0, 40.0), (10.0, 50.0)];
+        let map = Homography::square_to_quad(&quad).unwrap();
+        let square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let inverse = map.invert().unwrap();
+        for (corner, &(x, y)) in square.iter().zip(&quad) {
+            let (mx, my) = map.apply(corner.0, corner.1);
+            assert!((mx - x).abs() < 1e-4 && (my - y).abs() < 1e-4, "{:?}", (mx, my));
+            let (s, t) = inverse.apply(x, y);
+            assert!((s - corner.0).abs() < 1e-5 && (t - corner.1).abs() < 1e-5);
+        }
+
+        // Bow-tie and degenerate quads have no such map
+        let bow_tie = [(0.0, 0.0), (10.0, 10.0), (10.0, 0.0), (0.0, 10.0)];
+        assert_eq!(Homography::square_to_quad(&bow_tie), None);
+        assert_eq!(Homography::square_to_quad(&[(0.0, 0.0); 4]), None);
+    }
+
+    #[test]
+    fn test_mask_bounds() {
+        // 3x3 mask with coverage in the middle column only
+        let mask = [0u8, 10, 0, 0, 20, 0, 0, 0, 0];
+        assert_eq!(mask_bounds(&mask, 3, 3), Some(Rect::new(1.0, 0.0, 1.0, 2.0)));
+        assert_eq!(mask_bounds(&[0u8; 9], 3, 3), None);
+    }
+}
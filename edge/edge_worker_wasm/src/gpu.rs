@@ -0,0 +1,212 @@
+//! Depth-aware blending in a WebGPU compute shader
+
+use js_sys::{Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{gpu_buffer_usage, gpu_map_mode, gpu_texture_usage};
+use web_sys::{
+    GpuAutoLayoutMode, GpuBindGroup, GpuBindGroupDescriptor, GpuBindGroupEntry, GpuBuffer, GpuBufferBinding,
+    GpuBufferDescriptor, GpuComputePipeline, GpuComputePipelineDescriptor, GpuDevice, GpuExtent3dDict,
+    GpuProgrammableStage, GpuShaderModuleDescriptor, GpuTexelCopyBufferLayout, GpuTexelCopyTextureInfo,
+    GpuTexture, GpuTextureDescriptor, GpuTextureFormat,
+};
+
+use crate::blend::{BlendEquation, BlendMath, BlendMode};
+
+/// Side of the square workgroup, in pixels
+const WORKGROUP: u32 = 8;
+
+/// The blend of `blend_pixel` for normal source-over layers: coverage
+/// scaled by opacity and the (feathered) depth test, a straight lerp per
+/// channel rounded to nearest. NaN depth never shows the layer, tested on
+/// the bits since shader compilers may assume there is no NaN
+const SHADER: &str = r#"
+struct Params {
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    feather: f32,
+    opacity: f32,
+}
+
+@group(0) @binding(0) var base: texture_2d<f32>;
+@group(0) @binding(1) var creative: texture_2d<f32>;
+@group(0) @binding(2) var coverage: texture_2d<f32>;
+@group(0) @binding(3) var<storage, read> depth: array<f32>;
+@group(0) @binding(4) var<storage, read_write> frame: array<u32>;
+@group(0) @binding(5) var<uniform> params: Params;
+
+fn is_nan(value: f32) -> bool {
+    return (bitcast<u32>(value) & 0x7fffffffu) > 0x7f800000u;
+}
+
+fn visibility(scene: f32) -> f32 {
+    let creative_depth = params.creative_depth;
+    if (is_nan(scene) || is_nan(creative_depth)) {
+        return 0.0;
+    }
+    if (params.feather <= 0.0) {
+        return select(0.0, 1.0, creative_depth < scene);
+    }
+    let t = clamp((scene - creative_depth) / params.feather + 0.5, 0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+    let at = vec2<i32>(id.xy);
+    let i = id.y * params.width + id.x;
+    let alpha = textureLoad(coverage, at, 0).r * params.opacity * visibility(depth[i]);
+    let blended = mix(textureLoad(base, at, 0), textureLoad(creative, at, 0), clamp(alpha, 0.0, 1.0));
+    frame[i] = pack4x8unorm(blended);
+}
+"#;
+
+/// GPU resources for one frame size, created once and reused every frame
+pub(crate) struct GpuBlend {
+    device: GpuDevice,
+    width: u32,
+    height: u32,
+    pipeline: GpuComputePipeline,
+    bind_group: GpuBindGroup,
+    base: GpuTexture,
+    creative: GpuTexture,
+    coverage: GpuTexture,
+    depth: GpuBuffer,
+    params: GpuBuffer,
+    output: GpuBuffer,
+    readback: GpuBuffer,
+    /// Scene depth as bytes for upload, kept between frames
+    depth_bytes: Vec<u8>,
+    /// A frame is in flight or mapped, waiting for `read_into`
+    pending: bool,
+}
+
+impl GpuBlend {
+    pub(crate) fn new(device: GpuDevice, width: u32, height: u32) -> Result<GpuBlend, JsValue> {
+        let pixel_count = width * height;
+        let texture = |format: GpuTextureFormat| {
+            let size = GpuExtent3dDict::new(width);
+            size.set_height(height);
+            let usage = gpu_texture_usage::TEXTURE_BINDING | gpu_texture_usage::COPY_DST;
+            device.create_texture(&GpuTextureDescriptor::new_with_gpu_extent_3d_dict(format, &size, usage))
+        };
+        let buffer = |size: u32, usage: u32| device.create_buffer(&GpuBufferDescriptor::new(size, usage));
+        let base = texture(GpuTextureFormat::Rgba8unorm)?;
+        let creative = texture(GpuTextureFormat::Rgba8unorm)?;
+        let coverage = texture(GpuTextureFormat::R8unorm)?;
+        let depth = buffer(pixel_count * 4, gpu_buffer_usage::STORAGE | gpu_buffer_usage::COPY_DST)?;
+        let params = buffer(32, gpu_buffer_usage::UNIFORM | gpu_buffer_usage::COPY_DST)?;
+        let output = buffer(pixel_count * 4, gpu_buffer_usage::STORAGE | gpu_buffer_usage::COPY_SRC)?;
+        let readback = buffer(pixel_count * 4, gpu_buffer_usage::MAP_READ | gpu_buffer_usage::COPY_DST)?;
+
+        let module = device.create_shader_module(&GpuShaderModuleDescriptor::new(SHADER));
+        let stage = GpuProgrammableStage::new(&module);
+        stage.set_entry_point("main");
+        let pipeline = device.create_compute_pipeline(
+            &GpuComputePipelineDescriptor::new_with_gpu_auto_layout_mode(GpuAutoLayoutMode::Auto, &stage),
+        );
+        let entries = [
+            GpuBindGroupEntry::new_with_gpu_texture_view(0, &base.create_view()?),
+            GpuBindGroupEntry::new_with_gpu_texture_view(1, &creative.create_view()?),
+            GpuBindGroupEntry::new_with_gpu_texture_view(2, &coverage.create_view()?),
+            GpuBindGroupEntry::new_with_gpu_buffer_binding(3, &GpuBufferBinding::new(&depth)),
+            GpuBindGroupEntry::new_with_gpu_buffer_binding(4, &GpuBufferBinding::new(&output)),
+            GpuBindGroupEntry::new_with_gpu_buffer_binding(5, &GpuBufferBinding::new(&params)),
+        ];
+        let layout = pipeline.get_bind_group_layout(0);
+        let bind_group = device.create_bind_group(&GpuBindGroupDescriptor::new(&entries, &layout));
+        Ok(GpuBlend {
+            device,
+            width,
+            height,
+            pipeline,
+            bind_group,
+            base,
+            creative,
+            coverage,
+            depth,
+            params,
+            output,
+            readback,
+            depth_bytes: Vec::new(),
+            pending: false,
+        })
+    }
+
+    /// Whether the shader reproduces `math`; other blends stay on the CPU
+    pub(crate) fn supports(math: &BlendMath) -> bool {
+        math.mode == BlendMode::Normal && math.equation == BlendEquation::SourceOver && math.alpha.is_none()
+    }
+
+    pub(crate) fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Upload a frame's inputs, blend and start reading the result back.
+    /// `creative` and `coverage` are the placed, frame-sized creative and
+    /// its 8-bit coverage; the promise resolves once `read_into` may run
+    pub(crate) fn dispatch(
+        &mut self,
+        base_frame: &[u8],
+        creative: &[u8],
+        coverage: &[u8],
+        depth_map: &[f32],
+        creative_depth: f32,
+        math: &BlendMath,
+    ) -> Result<Promise, JsValue> {
+        let (width, height) = (self.width, self.height);
+        let queue = self.device.queue();
+        let size = GpuExtent3dDict::new(width);
+        size.set_height(height);
+        let upload = |texture: &GpuTexture, data: &[u8], bytes_per_pixel: u32| {
+            let layout = GpuTexelCopyBufferLayout::new();
+            layout.set_bytes_per_row(width * bytes_per_pixel);
+            let destination = GpuTexelCopyTextureInfo::new(texture);
+            queue.write_texture_with_u8_slice_and_gpu_extent_3d_dict(&destination, data, &layout, &size)
+        };
+        upload(&self.base, base_frame, 4)?;
+        upload(&self.creative, creative, 4)?;
+        upload(&self.coverage, coverage, 1)?;
+        self.depth_bytes.clear();
+        self.depth_bytes.extend(depth_map[..(width * height) as usize].iter().flat_map(|d| d.to_le_bytes()));
+        queue.write_buffer_with_u32_and_u8_slice(&self.depth, 0, &self.depth_bytes)?;
+        let mut params = [0u8; 32];
+        let fields = [
+            width.to_le_bytes(),
+            height.to_le_bytes(),
+            creative_depth.to_le_bytes(),
+            math.depth_feather.to_le_bytes(),
+            math.opacity.to_le_bytes(),
+        ];
+        for (slot, field) in params.chunks_exact_mut(4).zip(&fields) {
+            slot.copy_from_slice(field);
+        }
+        queue.write_buffer_with_u32_and_u8_slice(&self.params, 0, &params)?;
+
+        let encoder = self.device.create_command_encoder();
+        let pass = encoder.begin_compute_pass();
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, Some(&self.bind_group));
+        let (groups_x, groups_y) = (width.div_ceil(WORKGROUP), height.div_ceil(WORKGROUP));
+        pass.dispatch_workgroups_with_workgroup_count_y(groups_x, groups_y);
+        pass.end();
+        encoder.copy_buffer_to_buffer(&self.output, &self.readback)?;
+        queue.submit(&[encoder.finish()]);
+        self.pending = true;
+        Ok(self.readback.map_async(gpu_map_mode::READ).unchecked_into())
+    }
+
+    /// Copy the frame read back by `dispatch` into `frame`, which is frame
+    /// sized
+    pub(crate) fn read_into(&mut self, frame: &mut [u8]) -> Result<(), JsValue> {
+        self.pending = false;
+        let mapped = self.readback.get_mapped_range()?;
+        Uint8Array::new(&mapped).copy_to(frame);
+        self.readback.unmap();
+        Ok(())
+    }
+}
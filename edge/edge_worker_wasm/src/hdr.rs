@@ -0,0 +1,283 @@
+//! Compositing into 10-bit and 16-bit frames, such as HDR10 content
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::{blend_pixel_deep, BlendMath};
+use crate::color::{srgb_to_linear, ColorRange, ColorSpace};
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::memory::{try_copy, Scope, Subsystem};
+use crate::occlusion::visibility;
+use crate::yuv::{blend_420, YuvMatrix};
+use crate::{log_error, warp_to_frame_in, UNIT_FROM_U8};
+
+/// Luminance PQ encodes as 1.0, in nits
+const PQ_PEAK_NITS: f32 = 10000.0;
+
+/// BT.709 to BT.2020 primaries on linear light, per BT.2087
+const BT709_TO_BT2020: [[f32; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+
+/// How an 8-bit creative's colors map into a deep frame's code values
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HdrTransfer {
+    /// Widen the creative's values onto the deep range as they are, for
+    /// frames in the creative's own color space
+    #[default]
+    Direct = 0,
+    /// HDR10: the creative is taken as sRGB, converted to BT.2020 primaries
+    /// with its white at `sdr_white_nits` and encoded with the PQ curve
+    Pq = 1,
+}
+
+/// Maps 8-bit creative colors to 0..1 code values of the frame
+#[derive(Clone, Copy)]
+struct CreativeTransfer {
+    transfer: HdrTransfer,
+    /// Share of the PQ peak the creative's white sits at
+    white: f32,
+}
+
+impl CreativeTransfer {
+    /// Space the creative is converted to before the mapping: the frame's
+    /// own for `Direct`, sRGB's for `Pq`, which converts primaries itself
+    fn source_space(transfer: HdrTransfer, frame: ColorSpace) -> ColorSpace {
+        match transfer {
+            HdrTransfer::Direct => frame,
+            HdrTransfer::Pq => ColorSpace::new(YuvMatrix::Bt709, ColorRange::Full),
+        }
+    }
+
+    fn new(transfer: HdrTransfer, sdr_white_nits: f32) -> CreativeTransfer {
+        let nits = if sdr_white_nits.is_finite() { sdr_white_nits } else { 0.0 };
+        CreativeTransfer {
+            transfer,
+            white: nits.clamp(0.0, PQ_PEAK_NITS) / PQ_PEAK_NITS,
+        }
+    }
+
+    fn color(self, rgb: &[u8]) -> [f32; 3] {
+        let unit = [0, 1, 2].map(|c| UNIT_FROM_U8[rgb[c] as usize]);
+        match self.transfer {
+            HdrTransfer::Direct => unit,
+            HdrTransfer::Pq => {
+                let linear = unit.map(srgb_to_linear);
+                BT709_TO_BT2020.map(|row| {
+                    let light = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                    pq_encode(light * self.white)
+                })
+            }
+        }
+    }
+}
+
+/// SMPTE ST 2084 inverse EOTF of 0..1 luminance relative to the PQ peak
+fn pq_encode(luminance: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+    let power = luminance.clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * power) / (1.0 + C3 * power)).powf(M2)
+}
+
+/// Coverage after the depth test, before opacity
+fn layer_weight<'a>(
+    coverage: &'a [u8],
+    depth_map: &'a [f32],
+    creative_depth: f32,
+    math: &'a BlendMath,
+) -> impl Fn(usize) -> f32 + 'a {
+    move |i| {
+        if coverage[i] > 0 {
+            let visible = visibility(creative_depth, depth_map[i], math.depth_feather);
+            UNIT_FROM_U8[coverage[i] as usize] * visible
+        } else {
+            0.0
+        }
+    }
+}
+
+/// `composite_layer` onto an RGBA frame of 16-bit channels, returning the
+/// same, so HDR frames keep their precision: the 8-bit creative is warped
+/// as usual, mapped per `transfer` and blended in float. An empty result
+/// means the frame was dropped for memory
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_rgba16(
+    base_frame: &[u16],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    transfer: HdrTransfer,
+    sdr_white_nits: f32,
+    options: &LayerOptions,
+) -> Vec<u16> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let pixel_count = (width * height) as usize;
+    if base_frame.len() < pixel_count * 4 || depth_map.len() < pixel_count {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    let space = CreativeTransfer::source_space(transfer, options.frame_color);
+    let warped = warp_to_frame_in(creative_frame, alpha_mask, width, height, options, space);
+    let Some((creative, coverage)) = warped else {
+        return base_frame.to_vec();
+    };
+    let Some(mut result) = try_copy(base_frame) else {
+        log_error("WASM compositor: Out of memory, frame dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    };
+    let math = options.blend_math();
+    let weight = layer_weight(&coverage, depth_map, creative_depth, &math);
+    let mapping = CreativeTransfer::new(transfer, sdr_white_nits);
+    for i in 0..pixel_count {
+        let alpha = weight(i);
+        if alpha == 0.0 {
+            continue;
+        }
+        let [r, g, b] = mapping.color(&creative[i * 4..i * 4 + 3]);
+        let color = [r, g, b, UNIT_FROM_U8[creative[i * 4 + 3] as usize]];
+        blend_pixel_deep(&mut result[i * 4..i * 4 + 4], color, alpha, math);
+    }
+    result
+}
+
+/// `composite_layer_yuv` onto a P010 frame: a plane of 16-bit luma samples,
+/// then one half-size plane of interleaved U and V, each holding 10 bits in
+/// its high bits. The creative is mapped per `transfer` (HDR10 pairs `Pq`
+/// with `Bt2020`) and blended in float, write-back being the only rounding
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_p010(
+    base_frame: &[u16],
+    matrix: YuvMatrix,
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    transfer: HdrTransfer,
+    sdr_white_nits: f32,
+    options: &LayerOptions,
+) -> Vec<u16> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let (w, h) = (width as usize, height as usize);
+    let (luma, chroma) = (w * h, w.div_ceil(2) * h.div_ceil(2));
+    if base_frame.len() < luma + chroma * 2 || depth_map.len() < luma {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    let space = CreativeTransfer::source_space(transfer, ColorSpace::new(matrix, ColorRange::Full));
+    let warped = warp_to_frame_in(creative_frame, alpha_mask, width, height, options, space);
+    let Some((creative, coverage)) = warped else {
+        return base_frame.to_vec();
+    };
+    let Some(mut result) = try_copy(base_frame) else {
+        log_error("WASM compositor: Out of memory, frame dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    };
+    let math = options.blend_math();
+    let weight = layer_weight(&coverage, depth_map, creative_depth, &math);
+    let weight = |i| weight(i) * math.opacity;
+    let mapping = CreativeTransfer::new(transfer, sdr_white_nits);
+    // 10-bit code values are four times the 8-bit ones
+    let creative_yuv = |i: usize| {
+        let yuv = matrix.unit_to_yuv(mapping.color(&creative[i * 4..i * 4 + 3]));
+        yuv.map(|value| value * 4.0)
+    };
+    let chroma_at = |k: usize| (luma + k * 2, luma + k * 2 + 1);
+    blend_420(w, h, weight, creative_yuv, chroma_at, |at, value, alpha| {
+        let base = (result[at] >> 6) as f32;
+        let mixed = (base + (value - base) * alpha + 0.5).clamp(0.0, 1023.0) as u16;
+        result[at] = mixed << 6;
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pq_places_sdr_white() {
+        // BT.2408 reference white, 203 nits, sits at 58% of the PQ signal
+        let white = CreativeTransfer::new(HdrTransfer::Pq, 203.0).color(&[255, 255, 255]);
+        assert!(white.iter().all(|&value| (value - 0.5807).abs() < 1e-3), "{:?}", white);
+        assert!((pq_encode(1.0) - 1.0).abs() < 1e-6);
+        // Black encodes as `C1 ^ M2`, just above zero
+        assert!(pq_encode(0.0) < 1e-6);
+        // Pure red stays saturated but inside the wider primaries
+        let [r, g, b] = CreativeTransfer::new(HdrTransfer::Pq, 203.0).color(&[255, 0, 0]);
+        assert!(r > g && g > b && b > 0.0);
+    }
+
+    #[test]
+    fn test_rgba16_keeps_low_bits() {
+        let (width, height) = (2u32, 1u32);
+        // Values 8-bit frames cannot hold
+        let base_frame = vec![1000u16, 2001, 3002, 65535, 40000, 40001, 40002, 65535];
+        let creative_frame = [255u8, 255, 255, 255].repeat(2);
+        let depth_map = [10.0f32, 10.0];
+        let alpha_mask = [0u8, 128];
+        let mut options = LayerOptions::new();
+        let frame = composite_layer_rgba16(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, HdrTransfer::Direct,
+            0.0, &options,
+        );
+        assert_eq!(frame[..4], base_frame[..4]);
+        let alpha = 128.0 / 255.0;
+        let expected = |base: u16| (base as f32 + (65535.0 - base as f32) * alpha + 0.5) as u16;
+        assert_eq!(frame[4..7], [expected(40000), expected(40001), expected(40002)]);
+
+        options.set_opacity(0.0);
+        let frame = composite_layer_rgba16(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, HdrTransfer::Direct,
+            0.0, &options,
+        );
+        assert_eq!(frame, base_frame);
+    }
+
+    #[test]
+    fn test_p010_blends_in_ten_bits() {
+        let (width, height) = (2u32, 2u32);
+        // Limited-range black, one chroma sample
+        let mut base_frame = vec![64u16 << 6; 4];
+        base_frame.extend([512u16 << 6, 512 << 6]);
+        let creative_frame = [255u8, 255, 255, 255].repeat(4);
+        let depth_map = [10.0f32; 4];
+        let alpha_mask = [255u8, 255, 0, 0];
+        let options = LayerOptions::new();
+        let frame = composite_layer_p010(
+            &base_frame, YuvMatrix::Bt2020, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0,
+            HdrTransfer::Direct, 0.0, &options,
+        );
+        // White is 940 in 10 bits; chroma stays neutral
+        assert_eq!(frame[..4], [940 << 6, 940 << 6, 64 << 6, 64 << 6]);
+        assert_eq!(frame[4..], [512 << 6, 512 << 6]);
+        assert!(frame.iter().all(|sample| sample & 0x3f == 0));
+
+        // HDR10 white at 203 nits, 58% of the signal
+        let frame = composite_layer_p010(
+            &base_frame, YuvMatrix::Bt2020, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0,
+            HdrTransfer::Pq, 203.0, &options,
+        );
+        let luma = (frame[0] >> 6) as f32;
+        assert!((luma - (64.0 + 876.0 * 0.5807)).abs() < 1.5, "{}", luma);
+    }
+}
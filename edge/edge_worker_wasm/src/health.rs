@@ -0,0 +1,139 @@
+//! Liveness and health snapshot for the host's supervisor
+
+use std::cell::Cell;
+
+use wasm_bindgen::prelude::*;
+
+use crate::calibration::now_ms;
+use crate::memory::{allocation_failures, heap_bytes, total_live_bytes};
+
+/// How far the last frame fell short of full quality to survive low memory
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DegradationTier {
+    #[default]
+    Full = 0,
+    /// Effects, the source window or full warp bands were skipped
+    Reduced = 1,
+    /// A layer was left out
+    LayerDropped = 2,
+    /// No frame was produced; the host showed the base frame
+    FrameDropped = 3,
+}
+
+const TIER_NAMES: [&str; 4] = ["full", "reduced", "layer_dropped", "frame_dropped"];
+
+// The worker is single threaded; per-thread state keeps parallel tests apart
+thread_local! {
+    static FRAMES: Cell<u64> = const { Cell::new(0) };
+    static LAST_FRAME_MS: Cell<Option<f64>> = const { Cell::new(None) };
+    static ERRORS: Cell<u64> = const { Cell::new(0) };
+    static TIER: Cell<DegradationTier> = const { Cell::new(DegradationTier::Full) };
+    static QUEUED: Cell<u32> = const { Cell::new(0) };
+}
+
+/// A composite started; its degradation starts from full quality
+pub(crate) fn frame_started() {
+    FRAMES.with(|frames| frames.set(frames.get() + 1));
+    LAST_FRAME_MS.with(|last| last.set(Some(now_ms())));
+    TIER.with(|tier| tier.set(DegradationTier::Full));
+}
+
+/// The current frame had to give up `tier` worth of quality
+pub(crate) fn degrade(tier: DegradationTier) {
+    TIER.with(|current| current.set(current.get().max(tier)));
+}
+
+pub(crate) fn record_error() {
+    ERRORS.with(|errors| errors.set(errors.get() + 1));
+}
+
+/// Errors logged on this thread so far
+pub(crate) fn errors() -> u64 {
+    ERRORS.with(Cell::get)
+}
+
+/// A composited frame is waiting to be presented, or no longer is
+pub(crate) fn frame_queued(queued: bool) {
+    QUEUED.with(|depth| depth.set(if queued { depth.get() + 1 } else { depth.get().saturating_sub(1) }));
+}
+
+/// Degradation of the last frame
+#[wasm_bindgen]
+pub fn degradation_tier() -> DegradationTier {
+    TIER.with(Cell::get)
+}
+
+/// One cheap snapshot for a supervisor, as JSON: `last_frame_age_ms` (null
+/// before the first frame), `frames`, `errors` logged, `allocation_failures`,
+/// `live_bytes` and `heap_bytes` (wasm memory size, 0 natively) for memory
+/// pressure, `queue_depth` (frames composited but not yet presented) and the
+/// last frame's degradation `tier`
+#[wasm_bindgen]
+pub fn health() -> String {
+    let age = LAST_FRAME_MS.with(Cell::get).map(|last| now_ms() - last);
+    format!(
+        concat!(
+            r#"{{"last_frame_age_ms":{},"frames":{},"errors":{},"allocation_failures":{},"#,
+            r#""live_bytes":{},"heap_bytes":{},"queue_depth":{},"tier":"{}"}}"#
+        ),
+        age.map_or("null".to_string(), |age| format!("{:.1}", age)),
+        FRAMES.with(Cell::get),
+        errors(),
+        allocation_failures(),
+        total_live_bytes(),
+        heap_bytes(),
+        QUEUED.with(Cell::get),
+        TIER_NAMES[degradation_tier() as usize],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{composite_layer, memory, FrameBuffers, LayerOptions};
+
+    fn field<'a>(report: &'a str, name: &str) -> &'a str {
+        let start = report.find(&format!("\"{}\":", name)).unwrap() + name.len() + 3;
+        let end = report[start..].find([',', '}']).unwrap();
+        &report[start..start + end]
+    }
+
+    #[test]
+    fn test_health_follows_frames_and_queue() {
+        assert_eq!(field(&health(), "last_frame_age_ms"), "null");
+
+        let (width, height) = (8u32, 8u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![30u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let alpha_mask = vec![255u8; pixel_count];
+        let mut options = LayerOptions::new();
+        options.set_transform(1.0, 0.0, 1.0);
+        let composite = |creative: &[u8]| {
+            composite_layer(&base_frame, creative, &depth_map, &alpha_mask, width, height, 5.0, &options)
+        };
+
+        composite(&base_frame);
+        let report = health();
+        assert!(field(&report, "last_frame_age_ms").parse::<f64>().unwrap() >= 0.0);
+        assert_eq!((field(&report, "frames"), field(&report, "errors")), ("1", "0"));
+        assert_eq!(field(&report, "tier"), "\"full\"");
+
+        // A short creative is an error; running out of memory degrades
+        composite(&[]);
+        assert_eq!(field(&health(), "errors"), "1");
+        memory::fail_allocations(0b1);
+        assert!(composite(&base_frame).is_empty());
+        assert_eq!(degradation_tier(), DegradationTier::FrameDropped);
+        composite(&base_frame);
+        assert_eq!(degradation_tier(), DegradationTier::Full);
+
+        let mut buffers = FrameBuffers::new(width, height);
+        buffers.stage(&base_frame);
+        buffers.stage(&base_frame);
+        assert_eq!(field(&health(), "queue_depth"), "1");
+        buffers.present();
+        assert_eq!(field(&health(), "queue_depth"), "0");
+    }
+}
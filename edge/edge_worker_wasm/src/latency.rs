@@ -0,0 +1,188 @@
+//! Bounding a layer's per-frame work for low-latency streams
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::mask_bounds;
+use crate::health::{self, DegradationTier};
+use crate::layer::{LayerOptions, LayerWarp};
+use crate::sampler::Source;
+use crate::{composite_layer, log_error};
+
+/// Wraps `composite_layer` for one placement with hard caps on the work a
+/// frame may take: pixels resampled by the warp and effects applied. Each
+/// frame's plan is checked against the caps before any pixel is touched,
+/// and a plan over them is refused outright so no frame runs long. A cap
+/// of 0 leaves that dimension unbounded
+#[wasm_bindgen]
+pub struct LatencyBudget {
+    max_warped_pixels: u32,
+    max_effects: u32,
+    refused_frames: u32,
+    last_refusal: Option<String>,
+}
+
+#[wasm_bindgen]
+impl LatencyBudget {
+    #[wasm_bindgen(constructor)]
+    pub fn new(max_warped_pixels: u32, max_effects: u32) -> LatencyBudget {
+        LatencyBudget {
+            max_warped_pixels,
+            max_effects,
+            refused_frames: 0,
+            last_refusal: None,
+        }
+    }
+
+    /// Pixels the warp resamples to place the mask with `options`; 0 for an
+    /// untransformed layer, blended as is
+    pub fn warped_pixels(&self, alpha_mask: &[u8], width: u32, height: u32, options: &LayerOptions) -> u32 {
+        if options.is_identity() || options.scale <= 0.0 || alpha_mask.len() < (width * height) as usize {
+            return 0;
+        }
+        let Some(bounds) = mask_bounds(alpha_mask, width, height) else {
+            return 0;
+        };
+        // Only geometry is needed to size the warp
+        let source = Source {
+            creative: &[],
+            alpha: alpha_mask,
+            width: width as usize,
+            height: height as usize,
+        };
+        let (rows, columns) = LayerWarp::new(&source, &bounds, options).extent();
+        (rows * columns).min(u32::MAX as usize) as u32
+    }
+
+    /// Whether the plan for `options` over the mask meets the caps; the
+    /// reason it does not is kept for `last_refusal`
+    pub fn verify(&mut self, alpha_mask: &[u8], width: u32, height: u32, options: &LayerOptions) -> bool {
+        let refusal = self.check(alpha_mask, width, height, options);
+        let meets = refusal.is_none();
+        if !meets {
+            self.last_refusal = refusal;
+        }
+        meets
+    }
+
+    /// Same as `composite_layer` when the plan meets the caps; otherwise
+    /// the layer is refused and the base frame comes back unchanged
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_layer(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        if !self.admit(alpha_mask, width, height, options) {
+            return base_frame.to_vec();
+        }
+        let (base, creative) = (base_frame, creative_frame);
+        composite_layer(base, creative, depth_map, alpha_mask, width, height, creative_depth, options)
+    }
+
+    /// Frames whose layer was refused so far
+    pub fn refused_frames(&self) -> u32 {
+        self.refused_frames
+    }
+
+    /// Why the latest refused plan did not meet the caps
+    pub fn last_refusal(&self) -> Option<String> {
+        self.last_refusal.clone()
+    }
+}
+
+impl LatencyBudget {
+    /// `verify`, counting and reporting a refused frame as dropping its layer
+    pub(crate) fn admit(
+        &mut self,
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        options: &LayerOptions,
+    ) -> bool {
+        if self.verify(alpha_mask, width, height, options) {
+            return true;
+        }
+        self.refused_frames += 1;
+        if let Some(reason) = &self.last_refusal {
+            log_error(&format!("WASM compositor: Low-latency plan refused, {}", reason));
+        }
+        health::frame_started();
+        health::degrade(DegradationTier::LayerDropped);
+        false
+    }
+
+    fn check(&self, alpha_mask: &[u8], width: u32, height: u32, options: &LayerOptions) -> Option<String> {
+        #[cfg(feature = "effects")]
+        let effects = options.effects.len();
+        #[cfg(not(feature = "effects"))]
+        let effects = 0;
+        if self.max_effects > 0 && effects > self.max_effects as usize {
+            return Some(format!("{} effects over the cap of {}", effects, self.max_effects));
+        }
+        if self.max_warped_pixels > 0 {
+            let warped = self.warped_pixels(alpha_mask, width, height, options);
+            if warped > self.max_warped_pixels {
+                return Some(format!("{} warped pixels over the cap of {}", warped, self.max_warped_pixels));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refuses_plans_over_the_caps() {
+        let (width, height) = (100u32, 50u32);
+        let base_frame = vec![10u8; 20000];
+        let creative_frame = vec![200u8; 20000];
+        let depth_map = vec![10.0f32; 5000];
+        let mut alpha_mask = vec![0u8; 5000];
+        for y in 10..20 {
+            alpha_mask[y * 100 + 30..y * 100 + 70].fill(255);
+        }
+
+        let mut budget = LatencyBudget::new(400, 1);
+        // Untransformed layers resample nothing
+        let options = LayerOptions::new();
+        assert_eq!(budget.warped_pixels(&alpha_mask, width, height, &options), 0);
+        let frame = budget.composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+        assert_eq!(frame[(15 * 100 + 50) * 4], 200);
+
+        // Half-pixel move: 11 rows of 41 pixels, over the cap of 400
+        let mut moved = LayerOptions::new();
+        moved.set_transform(0.5, 0.0, 1.0);
+        assert_eq!(budget.warped_pixels(&alpha_mask, width, height, &moved), 11 * 41);
+        let frame = budget.composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &moved,
+        );
+        assert_eq!(frame, base_frame);
+        assert_eq!(budget.refused_frames(), 1);
+        assert_eq!(budget.last_refusal().as_deref(), Some("451 warped pixels over the cap of 400"));
+
+        // No warp cap
+        let mut budget = LatencyBudget::new(0, 1);
+        assert!(budget.verify(&alpha_mask, width, height, &moved));
+        #[cfg(feature = "effects")]
+        {
+            use crate::effects::EffectChain;
+
+            let mut effects = EffectChain::new();
+            effects.add_grain(0.1);
+            effects.add_opacity(0.5);
+            moved.set_effects(&effects);
+            assert!(!budget.verify(&alpha_mask, width, height, &moved));
+            assert_eq!(budget.last_refusal().as_deref(), Some("2 effects over the cap of 1"));
+        }
+    }
+}
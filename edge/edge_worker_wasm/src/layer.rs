@@ -0,0 +1,1118 @@
+//! Per-layer placement and sampling options
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::{AlphaMode, AlphaModes, BlendEquation, BlendMath, BlendMode, BlendPrecision, Rounding};
+#[cfg(feature = "effects")]
+use crate::checkerboard::{apply_checkerboard, EffectHistory};
+#[cfg(feature = "effects")]
+use crate::effects::{EffectChain, EffectContext, EffectFrame};
+use crate::color::ColorSpace;
+use crate::coords::CoordinateSystem;
+use crate::falloff::{fade_rows, Falloff};
+use crate::fusion::TransformFilter;
+use crate::fit::{fit_outcome, FitMode, FitOutcome};
+use crate::flags;
+use crate::geometry::{polygon_spans, Rect};
+use crate::latency::LatencyBudget;
+use crate::lighting::SceneLight;
+use crate::lut::Lut3d;
+use crate::master;
+#[cfg(feature = "effects")]
+use crate::memory::{try_copy, try_map};
+use crate::memory::{Scope, Subsystem};
+#[cfg(feature = "effects")]
+use crate::random::frame_seed;
+#[cfg(feature = "threads")]
+use crate::tiled::TileStrategy;
+use crate::sampler::{AxisTap, Coverage, EdgeMode, FilterMode, Source};
+use crate::transform::LayerTransform;
+use crate::window::{clamp_window, panned, Easing, KenBurns, FULL_WINDOW};
+
+/// Opacity ramp from `from` to `to` over `duration` seconds from
+/// `start_time`, then holding
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OpacityFade {
+    from: f32,
+    to: f32,
+    start_time: f64,
+    duration: f64,
+    easing: Easing,
+}
+
+impl OpacityFade {
+    fn opacity_at(&self, time: f64) -> f32 {
+        let t = if self.duration > 0.0 { ((time - self.start_time) / self.duration) as f32 } else { 1.0 };
+        self.from + (self.to - self.from) * self.easing.apply(t)
+    }
+}
+
+/// How a layer's creative is mapped into the frame before blending
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerOptions {
+    pub(crate) dx: f32,
+    pub(crate) dy: f32,
+    pub(crate) scale: f32,
+    pub(crate) edge_mode: EdgeMode,
+    pub(crate) filter_mode: FilterMode,
+    pub(crate) blend_precision: BlendPrecision,
+    pub(crate) rounding: Rounding,
+    pub(crate) blend_mode: BlendMode,
+    pub(crate) blend_equation: BlendEquation,
+    pub(crate) alpha_modes: Option<AlphaModes>,
+    pub(crate) depth_feather: f32,
+    pub(crate) opacity: f32,
+    opacity_fade: Option<OpacityFade>,
+    pub(crate) importance: Vec<u8>,
+    pub(crate) importance_threshold: u8,
+    pub(crate) coordinates: CoordinateSystem,
+    /// Frame-space clip polygon in the layer's coordinates, empty for none
+    pub(crate) clip: Vec<(f32, f32)>,
+    /// Soft placement edge and its extent as a fraction of the half size
+    pub(crate) falloff: Falloff,
+    pub(crate) falloff_width: f32,
+    pub(crate) fit_mode: FitMode,
+    /// Normalized part of the creative that is shown, and its pan speed
+    pub(crate) source_window: Rect,
+    pub(crate) source_pan: (f32, f32),
+    pub(crate) ken_burns: Option<KenBurns>,
+    /// Presentation time of the frame, driving animated windows
+    pub(crate) time: f64,
+    /// Creative size when it differs from the frame; zero means frame sized
+    pub(crate) creative_width: u32,
+    pub(crate) creative_height: u32,
+    /// Color spaces the creative and the base frame are tagged with
+    pub(crate) creative_color: ColorSpace,
+    pub(crate) frame_color: ColorSpace,
+    /// Grade toning the creative to the scene, shared by clones
+    pub(crate) lut: Option<Arc<Lut3d>>,
+    /// Linear-light gain relighting the creative to the scene
+    pub(crate) scene_light: Option<[f32; 3]>,
+    /// `TransformFilter` profile the placement's motion is smoothed with
+    smoothing_profile: String,
+    /// Caps of the layer's `LatencyBudget`, 0 for unbounded
+    max_warped_pixels: u32,
+    max_effects: u32,
+    #[cfg(feature = "effects")]
+    pub(crate) effects: EffectChain,
+    #[cfg(feature = "effects")]
+    pub(crate) seed: u64,
+    #[cfg(feature = "effects")]
+    checkerboard: bool,
+    #[cfg(feature = "threads")]
+    pub(crate) tile_strategy: TileStrategy,
+}
+
+const PRESETS: [&str; 3] = ["broadcast", "mobile-low", "cinematic"];
+
+impl Default for LayerOptions {
+    fn default() -> Self {
+        Self {
+            dx: 0.0,
+            dy: 0.0,
+            scale: 1.0,
+            edge_mode: EdgeMode::default(),
+            filter_mode: FilterMode::default(),
+            blend_precision: BlendPrecision::default(),
+            rounding: Rounding::default(),
+            blend_mode: BlendMode::default(),
+            blend_equation: BlendEquation::default(),
+            alpha_modes: None,
+            depth_feather: 0.0,
+            opacity: 1.0,
+            opacity_fade: None,
+            importance: Vec::new(),
+            importance_threshold: 0,
+            coordinates: CoordinateSystem::default(),
+            clip: Vec::new(),
+            falloff: Falloff::default(),
+            falloff_width: 0.0,
+            fit_mode: FitMode::default(),
+            source_window: FULL_WINDOW,
+            source_pan: (0.0, 0.0),
+            ken_burns: None,
+            time: 0.0,
+            creative_width: 0,
+            creative_height: 0,
+            creative_color: ColorSpace::default(),
+            frame_color: ColorSpace::default(),
+            lut: None,
+            scene_light: None,
+            smoothing_profile: "balanced".to_string(),
+            max_warped_pixels: 0,
+            max_effects: 0,
+            #[cfg(feature = "effects")]
+            effects: EffectChain::default(),
+            #[cfg(feature = "effects")]
+            seed: 0,
+            #[cfg(feature = "effects")]
+            checkerboard: false,
+            #[cfg(feature = "threads")]
+            tile_strategy: TileStrategy::default(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl LayerOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> LayerOptions {
+        LayerOptions::default()
+    }
+
+    /// Shift (in the layer's coordinate system) and scale about the placement
+    /// center, as from `PlacementSolver`
+    pub fn set_transform(&mut self, dx: f32, dy: f32, scale: f32) {
+        self.dx = dx;
+        self.dy = dy;
+        self.scale = scale;
+    }
+
+    /// Same as `set_transform`, from a composed or interpolated transform
+    pub fn set_layer_transform(&mut self, transform: &LayerTransform) {
+        self.set_transform(transform.dx(), transform.dy(), transform.scale());
+    }
+
+    pub fn transform(&self) -> LayerTransform {
+        LayerTransform::new(self.dx, self.dy, self.scale)
+    }
+
+    /// Show the layer only inside this frame rectangle, after warping and
+    /// regardless of its mask
+    pub fn set_clip_rect(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.clip = Rect::new(x, y, width, height).corners().to_vec();
+    }
+
+    /// Show the layer only inside this frame polygon (flat `[x, y, ...]`,
+    /// even-odd fill), e.g. a doorway the placement is seen through
+    pub fn set_clip_path(&mut self, points: &[f32]) {
+        self.clip = points.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+    }
+
+    pub fn clear_clip(&mut self) {
+        self.clip.clear();
+    }
+
+    /// Fade the layer out towards its placement boundary, over `width` of the
+    /// placement's half size (0 to 1), on top of whatever the mask says
+    pub fn set_falloff(&mut self, falloff: Falloff, width: f32) {
+        self.falloff = falloff;
+        self.falloff_width = width.clamp(0.0, 1.0);
+    }
+
+    /// Conventions of the transform and mask; top-left pixels by default
+    pub fn set_coordinates(&mut self, coordinates: &CoordinateSystem) {
+        self.coordinates = *coordinates;
+    }
+
+    pub fn set_edge_mode(&mut self, edge_mode: EdgeMode) {
+        self.edge_mode = edge_mode;
+    }
+
+    /// Bilinear (default) keeps slow sub-pixel motion smooth
+    pub fn set_filter_mode(&mut self, filter_mode: FilterMode) {
+        self.filter_mode = filter_mode;
+    }
+
+    /// Fixed point trades up to 1 LSB for speed on slow-float devices; meant
+    /// to be switched by whatever governs quality for the stream
+    pub fn set_blend_precision(&mut self, blend_precision: BlendPrecision) {
+        self.blend_precision = blend_precision;
+    }
+
+    /// Quantization of blended values; round half up by default
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
+    /// How the creative's color combines with the scene; normal by default
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    /// Depth band, in scene depth units, across which the creative fades out
+    /// behind the scene instead of cutting off, so noisy depth maps give soft
+    /// occlusion edges; 0 (default) keeps the hard test
+    pub fn set_depth_feather(&mut self, band: f32) {
+        self.depth_feather = if band.is_finite() { band.max(0.0) } else { 0.0 };
+    }
+
+    /// 0..1 opacity multiplied with the mask at blend time, so a fade needs
+    /// no new masks; replaces any opacity fade. 1 by default
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = if opacity.is_nan() { 1.0 } else { opacity.clamp(0.0, 1.0) };
+        self.opacity_fade = None;
+    }
+
+    /// Fade opacity from `from` to `to` over `duration_seconds` from
+    /// `start_seconds` of presentation time (see `set_time`), holding `to`
+    /// afterwards and `from` before
+    pub fn set_opacity_fade(
+        &mut self,
+        from: f32,
+        to: f32,
+        start_seconds: f64,
+        duration_seconds: f64,
+        easing: Easing,
+    ) {
+        let unit = |value: f32| if value.is_nan() { 1.0 } else { value.clamp(0.0, 1.0) };
+        self.opacity_fade = Some(OpacityFade {
+            from: unit(from),
+            to: unit(to),
+            start_time: start_seconds,
+            duration: duration_seconds.max(0.0),
+            easing,
+        });
+    }
+
+    /// Opacity at the current time
+    pub fn opacity(&self) -> f32 {
+        self.opacity_at_time()
+    }
+
+    /// Equation mixing the creative in by coverage; source-over by default
+    pub fn set_blend_equation(&mut self, blend_equation: BlendEquation) {
+        self.blend_equation = blend_equation;
+    }
+
+    /// Composite with the Porter-Duff "over" for frames whose alpha matters:
+    /// `frame` is the encoding of the base frame, which the result keeps, and
+    /// `creative` that of the creative, whose alpha then counts as coverage.
+    /// Premultiplied frames are what WebGL canvases composite without a
+    /// conversion pass. Unset, alpha blends like color, fine for opaque video
+    pub fn set_alpha_modes(&mut self, frame: AlphaMode, creative: AlphaMode) {
+        self.alpha_modes = Some(AlphaModes { frame, creative });
+    }
+
+    /// Per-pixel importance of the creative (frame-sized, 0..255, e.g. high on
+    /// logo and text): pixels below `threshold` are sampled with the cheaper
+    /// nearest filter, the rest with the configured one. An empty map turns
+    /// this off
+    pub fn set_importance_map(&mut self, importance: &[u8], threshold: u8) {
+        self.importance = importance.to_vec();
+        self.importance_threshold = threshold;
+    }
+
+    /// Size of the creative and mask actually supplied, when it may differ
+    /// from the frame; `fit_mode` decides what happens then
+    pub fn set_creative_size(&mut self, width: u32, height: u32) {
+        self.creative_width = width;
+        self.creative_height = height;
+    }
+
+    /// Strict (default) drops a creative whose size does not match the frame
+    pub fn set_fit_mode(&mut self, fit_mode: FitMode) {
+        self.fit_mode = fit_mode;
+    }
+
+    /// Color space the creative was authored in; BT.709 full range, as
+    /// image decoders produce, by default
+    pub fn set_creative_color_space(&mut self, space: &ColorSpace) {
+        self.creative_color = *space;
+    }
+
+    /// Color space of the base frame; the creative is converted to it before
+    /// blending. BT.709 full range by default
+    pub fn set_frame_color_space(&mut self, space: &ColorSpace) {
+        self.frame_color = *space;
+    }
+
+    /// Grade the creative with `lut`, in the frame's color space, before it
+    /// is placed, e.g. the LUT the footage itself was graded with
+    pub fn set_lut(&mut self, lut: &Lut3d) {
+        self.lut = Some(Arc::new(lut.clone()));
+    }
+
+    pub fn clear_lut(&mut self) {
+        self.lut = None;
+    }
+
+    /// Light the creative as `light` lights the scene, `strength` (0..1) of
+    /// the way from as authored, so it does not look pasted on. Applied
+    /// before any LUT, which grades the lit result
+    pub fn set_scene_light(&mut self, light: &SceneLight, strength: f32) {
+        self.scene_light = Some(light.gain(strength));
+    }
+
+    pub fn clear_scene_light(&mut self) {
+        self.scene_light = None;
+    }
+
+    /// How a frame of this size will fit the creative, for reporting
+    pub fn fit_outcome(&self, width: u32, height: u32) -> FitOutcome {
+        fit_outcome(self.fit_mode, self.creative_size(width, height), (width, height))
+    }
+
+    /// Show only this normalized window of the creative, stretched over the
+    /// creative's own area; clamped to lie inside the creative
+    pub fn set_source_window(&mut self, u: f32, v: f32, width: f32, height: f32) {
+        self.source_window = clamp_window(u, v, width, height);
+    }
+
+    /// Move the window by this much per second (normalized units), bouncing
+    /// off the creative's edges, e.g. for a panning billboard
+    pub fn set_source_pan(&mut self, du_per_second: f32, dv_per_second: f32) {
+        self.source_pan = (du_per_second, dv_per_second);
+    }
+
+    /// Ken Burns zoom and pan from the `start` to the `end` window (flat
+    /// `[u, v, width, height]`) over `duration_seconds` from `start_seconds`,
+    /// holding the end window afterwards; takes over from the static window
+    /// and pan until cleared
+    pub fn set_ken_burns(
+        &mut self,
+        start: &[f32],
+        end: &[f32],
+        start_seconds: f64,
+        duration_seconds: f64,
+        easing: Easing,
+    ) {
+        let window = |w: &[f32]| match w {
+            &[u, v, width, height, ..] => clamp_window(u, v, width, height),
+            _ => FULL_WINDOW,
+        };
+        self.ken_burns = Some(KenBurns {
+            start: window(start),
+            end: window(end),
+            start_time: start_seconds,
+            duration: duration_seconds.max(0.0),
+            easing,
+        });
+    }
+
+    pub fn clear_source_window(&mut self) {
+        self.source_window = FULL_WINDOW;
+        self.source_pan = (0.0, 0.0);
+        self.ken_burns = None;
+    }
+
+    /// Presentation time of the frame about to be composited
+    pub fn set_time(&mut self, pts_seconds: f64) {
+        self.time = pts_seconds;
+    }
+
+    /// Window shown at the current time as `[u, v, width, height]`
+    pub fn source_window(&self) -> Vec<f32> {
+        let window = self.window_at_time();
+        vec![window.x, window.y, window.width, window.height]
+    }
+
+    /// Curated settings by name; the setters still override individual
+    /// fields afterwards. None for an unknown name
+    ///
+    /// | preset     | filter   | blend       | edge, depth feather | smoothing | budget    |
+    /// |------------|----------|-------------|---------------------|-----------|-----------|
+    /// | broadcast  | bilinear | integer     | 2% edge, 0.5        | balanced  | 1080p, 4  |
+    /// | mobile-low | nearest  | fixed point | hard, none          | handheld  | 360p, 1   |
+    /// | cinematic  | bilinear | float       | 4% edge, 1          | rigid     | unbounded |
+    ///
+    /// Budgets are in warped pixels and effects, enforced by `Compositor`
+    /// only (see `set_latency_budget`). The frame color space describes
+    /// the stream, not a quality level, so presets leave it to
+    /// `set_frame_color_space`
+    pub fn from_preset(name: &str) -> Option<LayerOptions> {
+        let options = match name {
+            // Smooth motion and correctly rounded output
+            "broadcast" => LayerOptions {
+                filter_mode: FilterMode::Bilinear,
+                blend_precision: BlendPrecision::Integer,
+                falloff: Falloff::Edge,
+                falloff_width: 0.02,
+                depth_feather: 0.5,
+                smoothing_profile: "balanced".to_string(),
+                max_warped_pixels: 1920 * 1080,
+                max_effects: 4,
+                ..LayerOptions::default()
+            },
+            // Cheapest sampling, integer-only blending and no edge work
+            // for slow devices
+            "mobile-low" => LayerOptions {
+                filter_mode: FilterMode::Nearest,
+                blend_precision: BlendPrecision::FixedPoint,
+                falloff: Falloff::None,
+                falloff_width: 0.0,
+                depth_feather: 0.0,
+                smoothing_profile: "handheld".to_string(),
+                max_warped_pixels: 640 * 360,
+                max_effects: 1,
+                ..LayerOptions::default()
+            },
+            // Float blending throughout and the softest edges
+            "cinematic" => LayerOptions {
+                filter_mode: FilterMode::Bilinear,
+                blend_precision: BlendPrecision::Float,
+                falloff: Falloff::Edge,
+                falloff_width: 0.04,
+                depth_feather: 1.0,
+                smoothing_profile: "rigid".to_string(),
+                max_warped_pixels: 0,
+                max_effects: 0,
+                ..LayerOptions::default()
+            },
+            _ => return None,
+        };
+        Some(options)
+    }
+
+    /// Names accepted by `from_preset`
+    pub fn preset_names() -> Vec<String> {
+        PRESETS.iter().map(|name| name.to_string()).collect()
+    }
+
+    /// Smooth the placement's motion with this `TransformFilter` profile;
+    /// false, keeping the current one, for an unknown name
+    pub fn set_smoothing_profile(&mut self, name: &str) -> bool {
+        let known = TransformFilter::from_profile(name).is_some();
+        if known {
+            self.smoothing_profile = name.to_string();
+        }
+        known
+    }
+
+    pub fn smoothing_profile(&self) -> String {
+        self.smoothing_profile.clone()
+    }
+
+    /// A fresh filter for the placement's motion, per the smoothing profile
+    pub fn transform_filter(&self) -> TransformFilter {
+        TransformFilter::from_profile(&self.smoothing_profile).unwrap_or_else(|| TransformFilter::new(0.5, 0.1))
+    }
+
+    /// Caps on a frame's work, which `Compositor` holds every frame to,
+    /// dropping the layer of a frame over them; 0 leaves a dimension
+    /// unbounded. The stateless `composite_layer` does not check them, so
+    /// callers of it go through `latency_budget`
+    pub fn set_latency_budget(&mut self, max_warped_pixels: u32, max_effects: u32) {
+        self.max_warped_pixels = max_warped_pixels;
+        self.max_effects = max_effects;
+    }
+
+    /// A `LatencyBudget` holding the layer to its caps
+    pub fn latency_budget(&self) -> LatencyBudget {
+        LatencyBudget::new(self.max_warped_pixels, self.max_effects)
+    }
+
+    /// Effects applied in order to the creative before it is placed
+    #[cfg(feature = "effects")]
+    pub fn set_effects(&mut self, effects: &EffectChain) {
+        self.effects = effects.clone();
+    }
+
+    /// Seed stochastic effects for this placement and frame
+    #[cfg(feature = "effects")]
+    pub fn set_frame_seed(&mut self, placement_id: &str, pts_seconds: f64) {
+        self.seed = frame_seed(placement_id, pts_seconds);
+    }
+
+    /// Render heavy effects (grain, motion blur) on alternating pixels each
+    /// frame, about halving their cost, and reconstruct the rest from the
+    /// last frame of a `Compositor`, or from their neighbours in one-off
+    /// composites
+    #[cfg(feature = "effects")]
+    pub fn set_checkerboard_effects(&mut self, enabled: bool) {
+        self.checkerboard = enabled;
+    }
+
+    /// How the frame is split across the thread pool; auto by default
+    #[cfg(feature = "threads")]
+    pub fn set_tile_strategy(&mut self, strategy: TileStrategy) {
+        self.tile_strategy = strategy;
+    }
+}
+
+impl LayerOptions {
+    fn opacity_at_time(&self) -> f32 {
+        match &self.opacity_fade {
+            Some(fade) => fade.opacity_at(self.time),
+            None => self.opacity,
+        }
+    }
+
+    pub(crate) fn window_at_time(&self) -> Rect {
+        match &self.ken_burns {
+            Some(ken_burns) => ken_burns.window_at(self.time),
+            None => panned(&self.source_window, self.source_pan, self.time),
+        }
+    }
+
+    pub(crate) fn creative_size(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.creative_width == 0 || self.creative_height == 0 {
+            return (width, height);
+        }
+        (self.creative_width, self.creative_height)
+    }
+
+    /// Blend arithmetic after feature flags, with the layer's blend settings
+    pub(crate) fn blend_math(&self) -> BlendMath {
+        BlendMath {
+            precision: flags::blend_precision(self.blend_precision),
+            rounding: self.rounding,
+            mode: self.blend_mode,
+            equation: self.blend_equation,
+            alpha: self.alpha_modes,
+            depth_feather: self.depth_feather,
+            opacity: self.opacity_at_time() * master::master_factor(),
+        }
+    }
+
+    pub(crate) fn is_identity(&self) -> bool {
+        self.dx == 0.0 && self.dy == 0.0 && self.scale == 1.0
+    }
+
+    /// Run the effect chain over copies of the frame-sized creative and mask,
+    /// the mask widened to 16 bits so opacity does not band it; None when
+    /// there is no memory for the copies. Checkerboard rendering reconstructs
+    /// from `history` when the caller keeps one between frames
+    #[cfg(feature = "effects")]
+    pub(crate) fn apply_effects<A: Coverage>(
+        &self,
+        creative: &[u8],
+        alpha: &[A],
+        width: usize,
+        height: usize,
+        history: Option<&mut EffectHistory>,
+    ) -> Option<(Vec<u8>, Vec<u16>)> {
+        let _memory = Scope::enter(Subsystem::Effects);
+        let pixel_count = width * height;
+        let (input, input_alpha) = (&creative[..pixel_count * 4], &alpha[..pixel_count]);
+        let mut creative = try_copy(input)?;
+        let mut alpha = try_map(input_alpha, A::to_u16)?;
+        let mut frame =
+            EffectFrame { creative: &mut creative, alpha: &mut alpha, width, height, checkerboard: None };
+        let context = EffectContext { seed: self.seed, scale: self.scale };
+        if self.checkerboard {
+            apply_checkerboard(&self.effects, &mut frame, &context, input, input_alpha, history);
+        } else {
+            self.effects.apply(&mut frame, &context);
+        }
+        Some((creative, alpha))
+    }
+}
+
+/// Separable inverse mapping of a layer into the frame. The transform is
+/// axis-aligned, so column taps are resolved once up front and row taps per
+/// row, and rows and columns that cannot reach the placement are skipped
+pub struct LayerWarp<'a, A: Coverage = u8> {
+    source: &'a Source<'a, A>,
+    bounds: Rect,
+    options: &'a LayerOptions,
+    /// Layer shift in frame pixels
+    offset: (f32, f32),
+    /// Clip path in frame pixels, empty for none
+    clip: Vec<(f32, f32)>,
+    /// Placement rectangle in frame pixels, for the edge falloff
+    placed: Rect,
+    columns: Vec<(usize, AxisTap)>,
+    /// Nearest taps matching `columns`, present only when importance-based
+    /// filtering applies
+    nearest_columns: Vec<AxisTap>,
+}
+
+impl<'a, A: Coverage> LayerWarp<'a, A> {
+    /// `bounds` must enclose all source coverage
+    pub fn new(source: &'a Source<'a, A>, bounds: &Rect, options: &'a LayerOptions) -> LayerWarp<'a, A> {
+        let _memory = Scope::enter(Subsystem::Warp);
+        let (cx, _) = bounds.center();
+        let (width, height) = (source.width as u32, source.height as u32);
+        let offset = options.coordinates.offset_to_pixels(options.dx, options.dy, width, height);
+        let filter = options.filter_mode;
+        let columns: Vec<(usize, AxisTap)> = (0..source.width)
+            .map(|x| (x, Self::tap(options, filter, x, cx, offset.0, source.width)))
+            .filter(|(_, column)| column.reaches(bounds.x, bounds.right()))
+            .collect();
+
+        let adaptive = filter != FilterMode::Nearest
+            && options.importance.len() >= source.width * source.height
+            && !flags::reference_mode();
+        let nearest_columns = if adaptive {
+            columns
+                .iter()
+                .map(|&(x, _)| Self::tap(options, FilterMode::Nearest, x, cx, offset.0, source.width))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let clip = options
+            .clip
+            .iter()
+            .map(|&(x, y)| options.coordinates.point_to_pixels(x, y, width, height))
+            .collect();
+        let placed = bounds.offset_scaled(offset.0, offset.1, options.scale);
+        LayerWarp { source, bounds: *bounds, options, offset, clip, placed, columns, nearest_columns }
+    }
+
+    /// Map an output pixel center back into the original placement
+    fn tap(
+        options: &LayerOptions,
+        filter: FilterMode,
+        coord: usize,
+        center: f32,
+        offset: f32,
+        size: usize,
+    ) -> AxisTap {
+        let src = (coord as f32 + 0.5 - center - offset) / options.scale + center;
+        AxisTap::new(src, size, options.edge_mode, filter)
+    }
+
+    /// Output rows and columns that can receive coverage
+    pub fn extent(&self) -> (usize, usize) {
+        if self.options.is_identity() {
+            return (self.source.height, self.source.width);
+        }
+        (self.rows().len(), self.columns.len())
+    }
+
+    /// Output rows that can receive coverage, all of them for an identity
+    /// transform
+    pub fn rows(&self) -> Range<usize> {
+        let height = self.source.height;
+        if self.options.is_identity() {
+            return 0..height;
+        }
+        let (_, cy) = self.bounds.center();
+        let options = self.options;
+        let reaches = |y: usize| {
+            let row = Self::tap(options, options.filter_mode, y, cy, self.offset.1, height);
+            row.reaches(self.bounds.y, self.bounds.bottom())
+        };
+        match (0..height).position(reaches) {
+            Some(first) => first..(0..height).rposition(reaches).map_or(first, |last| last + 1),
+            None => 0..0,
+        }
+    }
+
+    /// Resample output `rows` into band buffers holding `rows.len()` rows of
+    /// RGBA and coverage, then clip and fade; pixels without coverage are
+    /// zeroed. Returns false when nothing in the band is covered
+    pub fn warp_rows(&self, rows: Range<usize>, creative: &mut [u8], alpha: &mut [A]) -> bool {
+        let covered = self.resample_rows(rows.clone(), creative, alpha);
+        if covered && !self.clip.is_empty() {
+            self.clip_rows(rows.clone(), alpha);
+        }
+        let options = self.options;
+        if covered && options.falloff != Falloff::None {
+            fade_rows(options.falloff, &self.placed, options.falloff_width, rows, self.source.width, alpha);
+        }
+        covered
+    }
+
+    /// Zero coverage outside the clip path, which holds in frame space
+    /// whatever the mask says; pixels count as inside by their centers
+    fn clip_rows(&self, rows: Range<usize>, alpha: &mut [A]) {
+        let width = self.source.width;
+        let column = |x: f32| ((x - 0.5).ceil().max(0.0) as usize).min(width);
+        for (row, y) in alpha.chunks_exact_mut(width).zip(rows) {
+            let mut cleared = 0;
+            for (left, right) in polygon_spans(&self.clip, y as f32 + 0.5) {
+                let (start, end) = (column(left).max(cleared), column(right).max(cleared));
+                row[cleared..start].fill(A::ZERO);
+                cleared = end;
+            }
+            row[cleared..].fill(A::ZERO);
+        }
+    }
+
+    fn resample_rows(&self, rows: Range<usize>, creative: &mut [u8], alpha: &mut [A]) -> bool {
+        let width = self.source.width;
+        if self.options.is_identity() {
+            let span = rows.start * width..rows.end * width;
+            creative.copy_from_slice(&self.source.creative[span.start * 4..span.end * 4]);
+            alpha.copy_from_slice(&self.source.alpha[span]);
+            return true;
+        }
+
+        creative.fill(0);
+        alpha.fill(A::ZERO);
+
+        let (_, cy) = self.bounds.center();
+        let mut covered = false;
+        let (options, height) = (self.options, self.source.height);
+        for (band_y, y) in rows.enumerate() {
+            let row = Self::tap(options, options.filter_mode, y, cy, self.offset.1, height);
+            if !row.reaches(self.bounds.y, self.bounds.bottom()) {
+                continue;
+            }
+            covered = true;
+
+            let nearest_row = (!self.nearest_columns.is_empty())
+                .then(|| Self::tap(options, FilterMode::Nearest, y, cy, self.offset.1, height));
+            for (i, (x, column)) in self.columns.iter().enumerate() {
+                let (color, coverage) = match &nearest_row {
+                    // Spend filter taps only where the creative matters
+                    Some(nearest_row) => {
+                        let nearest_column = &self.nearest_columns[i];
+                        let index = nearest_row.index[0] * width + nearest_column.index[0];
+                        if options.importance[index] < options.importance_threshold {
+                            self.source.sample_taps(nearest_column, nearest_row)
+                        } else {
+                            self.source.sample_taps(column, &row)
+                        }
+                    }
+                    None => self.source.sample_taps(column, &row),
+                };
+                let dst = band_y * width + x;
+                alpha[dst] = coverage;
+                creative[dst * 4..dst * 4 + 4].copy_from_slice(&color);
+            }
+        }
+        covered
+    }
+}
+
+/// Full-frame warp, returning frame-sized creative and mask buffers
+#[cfg(test)]
+pub fn warp_layer(source: &Source, bounds: &Rect, options: &LayerOptions) -> (Vec<u8>, Vec<u8>) {
+    let pixel_count = source.width * source.height;
+    let mut creative = vec![0u8; pixel_count * 4];
+    let mut alpha = vec![0u8; pixel_count];
+    LayerWarp::new(source, bounds, options).warp_rows(0..source.height, &mut creative, &mut alpha);
+    (creative, alpha)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::polygon_contains;
+    use std::time::Instant;
+
+    /// General per-pixel warp: every output pixel resolves its own taps
+    fn warp_per_pixel(source: &Source, bounds: &Rect, options: &LayerOptions) -> (Vec<u8>, Vec<u8>) {
+        let (width, height) = (source.width, source.height);
+        let (cx, cy) = bounds.center();
+        let mut creative = vec![0u8; width * height * 4];
+        let mut alpha = vec![0u8; width * height];
+
+        for y in 0..height {
+            for x in 0..width {
+                let src_x = (x as f32 + 0.5 - cx - options.dx) / options.scale + cx;
+                let src_y = (y as f32 + 0.5 - cy - options.dy) / options.scale + cy;
+                let (color, coverage) =
+                    source.sample(src_x, src_y, options.edge_mode, options.filter_mode);
+
+                let dst = y * width + x;
+                alpha[dst] = coverage;
+                creative[dst * 4..dst * 4 + 4].copy_from_slice(&color);
+            }
+        }
+
+        (creative, alpha)
+    }
+
+    /// Frame with a gradient placement at `bounds`
+    fn gradient_source(width: usize, height: usize, bounds: &Rect) -> (Vec<u8>, Vec<u8>) {
+        let mut creative = vec![0u8; width * height * 4];
+        let mut alpha = vec![0u8; width * height];
+        for y in bounds.y as usize..bounds.bottom() as usize {
+            for x in bounds.x as usize..bounds.right() as usize {
+                let i = y * width + x;
+                creative[i * 4..i * 4 + 4].copy_from_slice(&[(x * 7) as u8, (y * 5) as u8, 90, 255]);
+                alpha[i] = 128 + (x % 128) as u8;
+            }
+        }
+        (creative, alpha)
+    }
+
+    /// Coverage sum and coverage-weighted x centroid of a warped mask
+    fn energy_and_centroid(alpha: &[u8], width: usize) -> (f32, f32) {
+        let energy: f32 = alpha.iter().map(|&a| a as f32).sum();
+        let moment: f32 = alpha
+            .iter()
+            .enumerate()
+            .map(|(i, &a)| (i % width) as f32 * a as f32)
+            .sum();
+        (energy, moment / energy)
+    }
+
+    #[test]
+    fn test_subpixel_motion_is_smooth() {
+        let width = 16;
+        let height = 1;
+
+        // Two-pixel wide opaque creative in the middle of the row
+        let creative = vec![255u8; width * height * 4];
+        let mut alpha = vec![0u8; width * height];
+        alpha[6] = 255;
+        alpha[7] = 255;
+        let source = Source { creative: &creative, alpha: &alpha, width, height };
+        let bounds = Rect::new(6.0, 0.0, 2.0, 1.0);
+
+        let mut options = LayerOptions::new();
+        let mut previous_centroid = None;
+        for step in 0..=20 {
+            // Move a tenth of a pixel per frame
+            let dx = step as f32 * 0.1;
+            options.set_transform(dx, 0.0, 1.0);
+            let (_, warped) = warp_layer(&source, &bounds, &options);
+            let (energy, centroid) = energy_and_centroid(&warped, width);
+
+            // No energy popping: total coverage stays within rounding of 510
+            assert!((energy - 510.0).abs() <= 2.0, "energy {} at step {}", energy, step);
+
+            // Centroid advances by the sub-pixel step every frame
+            if let Some(previous) = previous_centroid {
+                let advance: f32 = centroid - previous;
+                assert!((advance - 0.1).abs() < 0.01, "advance {} at step {}", advance, step);
+            }
+            previous_centroid = Some(centroid);
+        }
+    }
+
+    #[test]
+    fn test_opacity_fade_follows_time() {
+        let mut options = LayerOptions::new();
+        assert_eq!(options.blend_math().opacity, 1.0);
+        options.set_opacity_fade(0.0, 1.0, 10.0, 2.0, Easing::Linear);
+        let at = |options: &mut LayerOptions, time: f64| {
+            options.set_time(time);
+            options.blend_math().opacity
+        };
+        let opacities = [5.0, 10.0, 10.5, 12.0, 20.0].map(|time| at(&mut options, time));
+        assert_eq!(opacities, [0.0, 0.0, 0.25, 1.0, 1.0]);
+
+        // A fixed opacity replaces the fade
+        options.set_opacity(1.5);
+        assert_eq!(at(&mut options, 10.5), 1.0);
+        options.set_opacity(0.4);
+        assert_eq!(options.opacity(), 0.4);
+    }
+
+    #[test]
+    fn test_importance_map_selects_filter() {
+        let (width, height) = (32, 16);
+        let bounds = Rect::new(4.0, 2.0, 24.0, 12.0);
+        let (creative, alpha) = gradient_source(width, height, &bounds);
+        let source = Source { creative: &creative, alpha: &alpha, width, height };
+
+        let mut options = LayerOptions::new();
+        options.set_transform(0.35, -0.6, 1.0);
+        let (_, bilinear) = warp_layer(&source, &bounds, &options);
+        options.set_filter_mode(FilterMode::Nearest);
+        let (_, nearest) = warp_layer(&source, &bounds, &options);
+        options.set_filter_mode(FilterMode::Bilinear);
+
+        // Important everywhere: full quality; nowhere: nearest throughout
+        options.set_importance_map(&vec![255; width * height], 128);
+        assert_eq!(warp_layer(&source, &bounds, &options).1, bilinear);
+        options.set_importance_map(&vec![0; width * height], 128);
+        assert_eq!(warp_layer(&source, &bounds, &options).1, nearest);
+
+        // Only the left half (in source space) matters
+        let importance: Vec<u8> = (0..width * height).map(|i| if i % width < 16 { 255 } else { 0 }).collect();
+        options.set_importance_map(&importance, 128);
+        let (_, mixed) = warp_layer(&source, &bounds, &options);
+        for y in 0..height {
+            assert_eq!(mixed[y * width..y * width + 15], bilinear[y * width..y * width + 15]);
+            assert_eq!(mixed[y * width + 17..(y + 1) * width], nearest[y * width + 17..(y + 1) * width]);
+        }
+    }
+
+    #[test]
+    fn test_clip_applies_after_warp() {
+        let (width, height) = (40, 30);
+        let bounds = Rect::new(0.0, 0.0, 40.0, 30.0);
+        let (creative, alpha) = gradient_source(width, height, &bounds);
+        let source = Source { creative: &creative, alpha: &alpha, width, height };
+
+        // The clip stays put in the frame while the layer moves under it
+        let mut options = LayerOptions::new();
+        options.set_transform(3.0, 2.0, 1.0);
+        let (_, unclipped) = warp_layer(&source, &bounds, &options);
+        options.set_clip_rect(10.0, 5.0, 12.0, 8.0);
+        let (_, clipped) = warp_layer(&source, &bounds, &options);
+        for (i, (&a, &b)) in clipped.iter().zip(&unclipped).enumerate() {
+            let inside = (10..22).contains(&(i % width)) && (5..13).contains(&(i / width));
+            assert_eq!(a, if inside { b } else { 0 }, "{}", i);
+        }
+
+        let triangle = [(2.0, 2.0), (30.0, 4.0), (8.0, 27.0)];
+        options.set_clip_path(&triangle.iter().flat_map(|&(x, y)| [x, y]).collect::<Vec<_>>());
+        let (_, clipped) = warp_layer(&source, &bounds, &options);
+        for (i, (&a, &b)) in clipped.iter().zip(&unclipped).enumerate() {
+            let center = ((i % width) as f32 + 0.5, (i / width) as f32 + 0.5);
+            let inside = polygon_contains(&triangle, center.0, center.1);
+            assert_eq!(a, if inside { b } else { 0 }, "{}", i);
+        }
+
+        options.clear_clip();
+        assert_eq!(warp_layer(&source, &bounds, &options).1, unclipped);
+    }
+
+    #[test]
+    fn test_presets_are_overridable() {
+        for name in LayerOptions::preset_names() {
+            assert!(LayerOptions::from_preset(&name).is_some(), "{}", name);
+        }
+        assert!(LayerOptions::from_preset("ultra").is_none());
+
+        let mut options = LayerOptions::from_preset("mobile-low").unwrap();
+        assert_eq!(options.filter_mode, FilterMode::Nearest);
+        options.set_filter_mode(FilterMode::Bilinear);
+        assert_eq!(options.filter_mode, FilterMode::Bilinear);
+        assert_eq!(options.blend_precision, BlendPrecision::FixedPoint);
+        assert!(!options.set_smoothing_profile("wobbly"));
+        assert!(options.set_smoothing_profile("rigid"));
+        assert_eq!(options.smoothing_profile(), "rigid");
+        options.set_latency_budget(100, 0);
+        assert_eq!(options.max_warped_pixels, 100);
+    }
+
+    #[test]
+    fn test_presets_match_documented_values() {
+        let expected = [
+            (
+                "broadcast",
+                FilterMode::Bilinear,
+                BlendPrecision::Integer,
+                (Falloff::Edge, 0.02, 0.5),
+                "balanced",
+                (1920 * 1080, 4),
+            ),
+            (
+                "mobile-low",
+                FilterMode::Nearest,
+                BlendPrecision::FixedPoint,
+                (Falloff::None, 0.0, 0.0),
+                "handheld",
+                (640 * 360, 1),
+            ),
+            (
+                "cinematic",
+                FilterMode::Bilinear,
+                BlendPrecision::Float,
+                (Falloff::Edge, 0.04, 1.0),
+                "rigid",
+                (0, 0),
+            ),
+        ];
+        assert_eq!(expected.map(|preset| preset.0.to_string()).to_vec(), LayerOptions::preset_names());
+        for (name, filter_mode, blend_precision, edges, smoothing, budget) in expected {
+            let options = LayerOptions::from_preset(name).unwrap();
+            assert_eq!((options.filter_mode, options.blend_precision), (filter_mode, blend_precision), "{}", name);
+            // The stream's color space is not the preset's to choose
+            assert_eq!(options.frame_color, ColorSpace::default(), "{}", name);
+            assert_eq!((options.falloff, options.falloff_width, options.depth_feather), edges, "{}", name);
+            assert_eq!(options.smoothing_profile(), smoothing, "{}", name);
+            assert!(TransformFilter::from_profile(&options.smoothing_profile()).is_some());
+            assert_eq!((options.max_warped_pixels, options.max_effects), budget, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_nearest_filter_steps() {
+        let width = 8;
+        let creative = vec![255u8; width * 4];
+        let mut alpha = vec![0u8; width];
+        alpha[3] = 255;
+        let source = Source { creative: &creative, alpha: &alpha, width, height: 1 };
+        let bounds = Rect::new(3.0, 0.0, 1.0, 1.0);
+
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(FilterMode::Nearest);
+        options.set_transform(0.3, 0.0, 1.0);
+
+        // A 0.3 px shift is invisible without filtering
+        let (_, warped) = warp_layer(&source, &bounds, &options);
+        assert_eq!(warped, alpha);
+    }
+
+    #[test]
+    fn test_separable_warp_matches_per_pixel() {
+        let (width, height) = (64, 48);
+        let bounds = Rect::new(10.0, 8.0, 30.0, 20.0);
+        let (creative, alpha) = gradient_source(width, height, &bounds);
+        let source = Source { creative: &creative, alpha: &alpha, width, height };
+
+        let transforms = [(3.25, -2.5, 1.0), (-4.0, 6.7, 0.75), (0.4, 0.6, 1.3)];
+        let edge_modes = [EdgeMode::Transparent, EdgeMode::Clamp, EdgeMode::Mirror, EdgeMode::Wrap];
+        let filters = [FilterMode::Nearest, FilterMode::Bilinear];
+
+        for (dx, dy, scale) in transforms {
+            for edge_mode in edge_modes {
+                for filter_mode in filters {
+                    let mut options = LayerOptions::new();
+                    options.set_transform(dx, dy, scale);
+                    options.set_edge_mode(edge_mode);
+                    options.set_filter_mode(filter_mode);
+
+                    let (fast_creative, fast_alpha) = warp_layer(&source, &bounds, &options);
+                    let (ref_creative, ref_alpha) = warp_per_pixel(&source, &bounds, &options);
+
+                    // Coverage is identical; color only matters where covered
+                    assert_eq!(fast_alpha, ref_alpha, "{:?} {:?}", edge_mode, filter_mode);
+                    for (i, &a) in ref_alpha.iter().enumerate() {
+                        if a > 0 {
+                            assert_eq!(fast_creative[i * 4..i * 4 + 4], ref_creative[i * 4..i * 4 + 4]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_separable_warp() {
+        let (width, height) = (1920, 1080);
+        let bounds = Rect::new(1200.0, 600.0, 480.0, 270.0);
+        let (creative, alpha) = gradient_source(width, height, &bounds);
+        let source = Source { creative: &creative, alpha: &alpha, width, height };
+        let mut options = LayerOptions::new();
+        options.set_transform(12.5, -7.25, 0.9);
+
+        let iterations = 10;
+        let time = |warp: &dyn Fn() -> (Vec<u8>, Vec<u8>)| {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                std::hint::black_box(warp());
+            }
+            start.elapsed().as_secs_f64() * 1000.0 / iterations as f64
+        };
+
+        let per_pixel = time(&|| warp_per_pixel(&source, &bounds, &options));
+        let separable = time(&|| warp_layer(&source, &bounds, &options));
+        println!(
+            "1080p warp: per-pixel {:.2} ms, separable {:.2} ms ({:.1}x)",
+            per_pixel,
+            separable,
+            per_pixel / separable
+        );
+        assert!(separable < per_pixel);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_importance_filtering() {
+        let (width, height) = (1920, 1080);
+        let bounds = Rect::new(480.0, 270.0, 960.0, 540.0);
+        let (creative, alpha) = gradient_source(width, height, &bounds);
+        let source = Source { creative: &creative, alpha: &alpha, width, height };
+
+        // A logo-sized important region, a quarter of the placement
+        let mut importance = vec![0u8; width * height];
+        for y in 270..540 {
+            importance[y * width + 480..y * width + 960].fill(255);
+        }
+
+        let mut options = LayerOptions::new();
+        options.set_transform(3.3, -1.7, 0.97);
+        let iterations = 10;
+        let time = |options: &LayerOptions| {
+            let start = Instant::now();
+            for _ in 0..iterations {
+                std::hint::black_box(warp_layer(&source, &bounds, options));
+            }
+            start.elapsed().as_secs_f64() * 1000.0 / iterations as f64
+        };
+
+        let uniform = time(&options);
+        options.set_importance_map(&importance, 128);
+        let adaptive = time(&options);
+        println!("1080p warp: uniform bilinear {:.2} ms, importance-based {:.2} ms", uniform, adaptive);
+    }
+}
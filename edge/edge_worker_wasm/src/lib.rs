@@ -1,13 +1,193 @@
 //! Inscenium Edge Worker - WebAssembly compositor
 
+use std::borrow::Cow;
+use std::ops::Range;
+
 use wasm_bindgen::prelude::*;
 
+mod advection;
+mod audio;
+mod blend;
+mod breaker;
+mod calibration;
+#[cfg(feature = "effects")]
+mod checkerboard;
+mod color;
+mod compositor;
+mod coords;
+mod countdown;
+mod curved;
+mod depth;
+#[cfg(feature = "json")]
+mod cost;
+#[cfg(feature = "effects")]
+mod effects;
+mod falloff;
+mod fit;
+mod flags;
+mod flow;
+mod fusion;
+mod geometry;
+#[cfg(feature = "webgpu")]
+mod gpu;
+mod hdr;
+mod health;
+mod latency;
+mod layer;
+mod lighting;
+mod logging;
+mod lut;
+mod macros;
+mod master;
+mod memory;
+mod mesh;
+#[cfg(feature = "effects")]
+mod motion_blur;
+mod noise;
+mod occlusion;
+#[cfg(feature = "parity")]
+mod parity;
+#[cfg(feature = "policy")]
+mod policy;
+#[cfg(feature = "json")]
+mod plan;
+mod plate;
+mod present;
+#[cfg(feature = "json")]
+mod preview;
+mod quota;
+mod random;
+mod reposition;
+mod resample;
+mod resize;
+mod roi;
+mod sampler;
+mod selection;
+mod sequence;
+mod shadow;
+mod simd;
+mod streaming;
+mod surfaces;
+#[cfg(feature = "json")]
+mod test_support;
+#[cfg(feature = "threads")]
+mod tiled;
+mod timeline;
+mod transform;
+mod uvmap;
+mod validity;
+mod variants;
+#[cfg(feature = "webcodecs")]
+mod webcodecs;
+mod window;
+mod yuv;
+
+use blend::{blend_pixel, BlendMath, UNIT_FROM_U8};
+#[cfg(feature = "effects")]
+use checkerboard::EffectHistory;
+use geometry::{clip_polygon, mask_bounds, polygon_contains, Rect};
+use layer::LayerWarp;
+use memory::{Scope, Subsystem};
+use occlusion::{creative_depth_at, in_front, visibility};
+use sampler::{Coverage, Source};
+
+pub use advection::MotionAdvector;
+pub use audio::AudioModulator;
+pub use blend::{AlphaMode, BlendEquation, BlendMode, BlendPrecision, Rounding};
+pub use breaker::{BreakerState, LayerBreaker};
+pub use calibration::{calibrate, Calibration};
+pub use color::{convert_color, ColorRange, ColorSpace};
+pub use compositor::Compositor;
+pub use coords::{CoordinateSystem, Origin};
+pub use countdown::CountdownLayer;
+pub use curved::CurvedSurface;
+pub use depth::{composite_layer_packed_depth, decode_depth, DepthFormat};
+#[cfg(feature = "json")]
+pub use cost::CostModel;
+#[cfg(feature = "effects")]
+pub use effects::EffectChain;
+pub use falloff::Falloff;
+pub use fit::{FitMode, FitOutcome};
+pub use flags::{clear_flags, flag_enabled, flag_report, set_flag, set_flag_rollout, set_flag_session};
+pub use flow::FlowTracker;
+pub use fusion::TransformFilter;
+pub use geometry::{
+    clamp_rect, project_quad, quad_area, rect_area, rect_contains, rect_intersection, rect_visible_area,
+};
+pub use hdr::{composite_layer_p010, composite_layer_rgba16, HdrTransfer};
+pub use health::{degradation_tier, health, DegradationTier};
+pub use latency::LatencyBudget;
+pub use layer::LayerOptions;
+pub use lighting::SceneLight;
+pub use logging::{clear_log_sink, set_log_context, set_log_frame, set_log_sink};
+pub use lut::Lut3d;
+pub use macros::MacroResolver;
+pub use master::{kill_placements, master_opacity, placements_killed, restore_placements, set_master_opacity};
+pub use memory::{allocation_failures, leak_report};
+pub use mesh::{MeshInterpolation, MeshWarp};
+pub use noise::estimate_noise;
+pub use occlusion::compute_occlusion;
+#[cfg(feature = "policy")]
+pub use policy::{ConsentState, FrequencyCapPolicy};
+#[cfg(feature = "json")]
+pub use plan::{compile_plan, CompiledPlan};
+#[cfg(feature = "parity")]
+pub use parity::{check_parity, parity_report, ParityCheck};
+pub use plate::{composite_layer_replacing, inpaint_surface, CleanPlate};
+pub use present::FrameBuffers;
+#[cfg(feature = "json")]
+pub use preview::{render_preview, Preview, PreviewQuality};
+pub use quota::PlacementQuota;
+pub use random::PlacementRng;
+pub use reposition::PlacementSolver;
+pub use resample::{resample_depth, resample_mask, ResampleEdge};
+pub use resize::ResizeFilter;
+pub use roi::{composite_layer_resized, composite_layer_roi};
+pub use sampler::{EdgeMode, FilterMode};
+pub use selection::CandidateSelector;
+pub use sequence::{SequenceAction, SequenceIssue, SequenceValidator};
+pub use shadow::ShadowCompositor;
+pub use streaming::SlabCompositor;
+pub use surfaces::{composite_layer_homography, composite_layer_quad, homography_hotspot, SurfacePlacement};
+#[cfg(feature = "threads")]
+pub use tiled::TileStrategy;
+#[cfg(feature = "json")]
+pub use test_support::{conformance_suite, conformance_vectors};
+#[cfg(feature = "threads")]
+pub use wasm_bindgen_rayon::init_thread_pool;
+pub use timeline::Timeline;
+pub use transform::{compose_matrices, decompose_matrix, LayerTransform};
+pub use uvmap::composite_layer_uv;
+pub use validity::ValidityGate;
+pub use variants::VariantSelector;
+#[cfg(feature = "webcodecs")]
+pub use webcodecs::{video_frame_from_rgba, VideoFrameCompositor};
+pub use window::Easing;
+pub use yuv::{composite_layer_yuv, YuvFormat, YuvMatrix};
+
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
+/// Native builds (the conformance CLI, tests) log to stderr
+#[cfg(not(target_arch = "wasm32"))]
+fn log(s: &str) {
+    eprintln!("{}", s);
+}
+
+/// Report a failure as a structured record, counted in `health()`
+fn log_error(s: &str) {
+    health::record_error();
+    logging::emit(logging::Level::Error, s);
+}
+
+fn log_info(s: &str) {
+    logging::emit(logging::Level::Info, s);
+}
+
 /// Depth-aware alpha blending of creative content onto base frame
 #[wasm_bindgen]
 pub fn composite_segment(
@@ -19,15 +199,11 @@ pub fn composite_segment(
     height: u32,
     creative_depth: f32,
 ) -> Vec<u8> {
-    log("WASM compositor: Processing frame");
+    log_info("WASM compositor: Processing frame");
     
     // Validate input parameters
-    let pixel_count = (width * height) as usize;
-    if base_frame.len() < pixel_count * 4 || 
-       creative_frame.len() < pixel_count * 4 ||
-       depth_map.len() < pixel_count ||
-       alpha_mask.len() < pixel_count {
-        log("WASM compositor: Invalid input buffer sizes");
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height) {
+        log_error("WASM compositor: Invalid input buffer sizes");
         return base_frame.to_vec();
     }
     
@@ -35,6 +211,777 @@ pub fn composite_segment(
     composite_with_depth(base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth)
 }
 
+/// `composite_segment` writing into `out`, a buffer of at least
+/// `width * height * 4` bytes the host allocates once and reuses, instead of
+/// returning a new frame. Returns false, leaving `out` untouched, for
+/// invalid input buffers
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_segment_into(
+    out: &mut [u8],
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+) -> bool {
+    let pixel_count = (width * height) as usize;
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height)
+        || out.len() < pixel_count * 4
+    {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return false;
+    }
+
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let out = &mut out[..pixel_count * 4];
+    out.copy_from_slice(&base_frame[..pixel_count * 4]);
+    blend_span(
+        out,
+        creative_frame,
+        &depth_map[..pixel_count],
+        creative_depth,
+        LayerOptions::default().blend_math(),
+        |i| UNIT_FROM_U8[alpha_mask[i] as usize],
+    );
+    true
+}
+
+/// `composite_segment` with the creative combined with the scene per `mode`
+/// (multiply, screen, ...) instead of painted over it
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_segment_blended(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    mode: BlendMode,
+) -> Vec<u8> {
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height) {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    let mut options = LayerOptions::new();
+    options.set_blend_mode(mode);
+    composite_with_coverage(
+        base_frame,
+        creative_frame,
+        depth_map,
+        width,
+        height,
+        creative_depth,
+        options.blend_math(),
+        |i| UNIT_FROM_U8[alpha_mask[i] as usize],
+    )
+}
+
+/// Depth-aware compositing that suppresses the placement when it overlaps
+/// any externally detected exclusion region (flat `[x, y, w, h, ...]` in pixels)
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_segment_with_exclusions(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    exclusion_rects: &[f32],
+) -> Vec<u8> {
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height) {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    // Brand safety wins over the placement: pass the base frame through untouched
+    let overlap = find_exclusion_overlap(alpha_mask, width, height, exclusion_rects);
+    if overlap >= 0 {
+        log_info(&format!("WASM compositor: Placement suppressed by exclusion region {}", overlap));
+        return base_frame.to_vec();
+    }
+
+    composite_with_depth(base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth)
+}
+
+/// Depth-aware compositing attenuated by a foreground transmittance map
+/// (0 = opaque foreground such as dense smoke, 1 = clear) so glass, hair and
+/// smoke partially occlude the creative instead of all-or-nothing
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_segment_with_transmittance(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    transmittance: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+) -> Vec<u8> {
+    let pixel_count = (width * height) as usize;
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height)
+        || transmittance.len() < pixel_count
+    {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    composite_with_coverage(
+        base_frame,
+        creative_frame,
+        depth_map,
+        width,
+        height,
+        creative_depth,
+        LayerOptions::default().blend_math(),
+        |i| UNIT_FROM_U8[alpha_mask[i] as usize] * transmittance[i].clamp(0.0, 1.0),
+    )
+}
+
+/// Depth-aware compositing with a depth per creative pixel, for curved
+/// billboards and rendered products whose parts sit at different depths.
+/// `creative_depth_map` is tested against the scene pixel by pixel; NaN
+/// entries, or an empty map, fall back to the scalar `creative_depth`
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_segment_depth_map(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    creative_depth_map: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+) -> Vec<u8> {
+    let pixel_count = (width * height) as usize;
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height)
+        || !(creative_depth_map.is_empty() || creative_depth_map.len() >= pixel_count)
+    {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+    if creative_depth_map.is_empty() {
+        let (creative, depth) = (creative_frame, depth_map);
+        return composite_with_depth(base_frame, creative, depth, alpha_mask, width, height, creative_depth);
+    }
+
+    // The depth test moves into the coverage so the span passes every pixel
+    let visible = |i: usize| in_front(creative_depth_at(creative_depth_map, i, creative_depth), depth_map[i]);
+    composite_with_coverage(
+        base_frame,
+        creative_frame,
+        depth_map,
+        width,
+        height,
+        f32::NEG_INFINITY,
+        LayerOptions::default().blend_math(),
+        |i| if visible(i) { UNIT_FROM_U8[alpha_mask[i] as usize] } else { 0.0 },
+    )
+}
+
+/// Index of the first exclusion region intersecting the placement coverage, or -1
+#[wasm_bindgen]
+pub fn find_exclusion_overlap(
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    exclusion_rects: &[f32],
+) -> i32 {
+    let pixel_count = (width * height) as usize;
+    if alpha_mask.len() < pixel_count {
+        return -1;
+    }
+
+    let Some(placement) = mask_bounds(alpha_mask, width, height) else {
+        return -1;
+    };
+
+    Rect::from_flat(exclusion_rects)
+        .iter()
+        .position(|region| region.intersects(&placement))
+        .map_or(-1, |index| index as i32)
+}
+
+/// Depth-aware compositing with the placement shifted by `(dx, dy)` and scaled
+/// about its center, as returned by `PlacementSolver::solve`
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_segment_repositioned(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    dx: f32,
+    dy: f32,
+    scale: f32,
+) -> Vec<u8> {
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height) {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    let mut options = LayerOptions::new();
+    options.set_transform(dx, dy, scale);
+    composite_layer(base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth, &options)
+}
+
+/// Depth-aware compositing of a layer mapped and sampled per its `LayerOptions`
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let mut result = Vec::new();
+    composite_layer_into(
+        &mut result,
+        &mut Bands::default(),
+        None,
+        base_frame,
+        creative_frame,
+        depth_map,
+        None,
+        alpha_mask,
+        width,
+        height,
+        creative_depth,
+        options,
+    );
+    result
+}
+
+/// `composite_layer` with a per-frame `occlusion_mask` of what is in front
+/// of the layer (0 = clear, 255 = hidden), kept apart from the placement
+/// alpha so the creative's shape stays static while occlusion changes.
+/// Occlusion is in frame space and does not move with the layer transform
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_occluded(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    occlusion_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    if occlusion_mask.len() < (width * height) as usize {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+    let mut result = Vec::new();
+    composite_layer_into(
+        &mut result,
+        &mut Bands::default(),
+        None,
+        base_frame,
+        creative_frame,
+        depth_map,
+        Some(occlusion_mask),
+        alpha_mask,
+        width,
+        height,
+        creative_depth,
+        options,
+    );
+    result
+}
+
+/// `composite_layer` with a 16-bit mask, for soft gradient masks that band
+/// at 256 levels; coverage keeps 16 bits through sampling, effects and blend
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_u16(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u16],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let mut result = Vec::new();
+    composite_layer_into(
+        &mut result,
+        &mut Bands::default(),
+        None,
+        base_frame,
+        creative_frame,
+        depth_map,
+        None,
+        alpha_mask,
+        width,
+        height,
+        creative_depth,
+        options,
+    );
+    result
+}
+
+/// Composite a layer into `result`, reusing its capacity and the warp
+/// `bands` across calls; an empty `result` means the frame was dropped.
+/// The layer as warped is left in `keep`, transparent when nothing was
+/// placed, or emptied when there was no memory for it
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn composite_layer_into<A: Coverage>(
+    result: &mut Vec<u8>,
+    bands: &mut Bands<A>,
+    keep: Option<&mut WarpedLayer>,
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    occlusion: Option<&[u8]>,
+    alpha_mask: &[A],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) {
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let keep = keep.and_then(|layer| layer.fit((width * height) as usize).then_some(layer));
+    // Creatives of another size are fitted to the frame per the fit mode
+    let fitted = fit::fit_to_frame(creative_frame, alpha_mask, width, height, options);
+    let Some((creative_frame, alpha_mask)) = &fitted else {
+        return pass_through(result, base_frame);
+    };
+    let windowed = window::apply_window(creative_frame, width, height, options);
+    let graded = grade_to_frame(windowed, options, options.frame_color);
+    let (creative_frame, alpha_mask) = (&graded[..], &alpha_mask[..]);
+    if !inputs_valid(base_frame, creative_frame, depth_map, alpha_mask, width, height) {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return pass_through(result, base_frame);
+    }
+
+    if options.scale <= 0.0 {
+        return pass_through(result, base_frame);
+    }
+
+    // Effects run in creative space, before placement; short of memory for
+    // the processed copy the layer goes without them
+    #[cfg(feature = "effects")]
+    if !options.effects.is_empty() {
+        let history = Some(&mut bands.history);
+        match options.apply_effects(creative_frame, alpha_mask, width as usize, height as usize, history) {
+            Some((creative, alpha)) => {
+                // Processed masks are 16-bit whatever came in, so they get
+                // bands of their own
+                return composite_placed(
+                    result,
+                    &mut Bands::default(),
+                    keep,
+                    base_frame,
+                    &creative,
+                    depth_map,
+                    occlusion,
+                    &alpha,
+                    width,
+                    height,
+                    creative_depth,
+                    options,
+                );
+            }
+            None => {
+                log_error("WASM compositor: Out of memory, skipping effects");
+                health::degrade(DegradationTier::Reduced);
+            }
+        }
+    }
+    composite_placed(
+        result,
+        bands,
+        keep,
+        base_frame,
+        creative_frame,
+        depth_map,
+        occlusion,
+        alpha_mask,
+        width,
+        height,
+        creative_depth,
+        options,
+    )
+}
+
+/// Leave `result` holding the untouched base frame
+fn pass_through(result: &mut Vec<u8>, base_frame: &[u8]) {
+    result.clear();
+    result.extend_from_slice(base_frame);
+}
+
+/// Place and blend a frame-sized creative and mask that are ready to warp
+#[allow(clippy::too_many_arguments)]
+fn composite_placed<A: Coverage>(
+    result: &mut Vec<u8>,
+    bands: &mut Bands<A>,
+    keep: Option<&mut WarpedLayer>,
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    occlusion: Option<&[u8]>,
+    alpha_mask: &[A],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) {
+    let Some(bounds) = mask_bounds(alpha_mask, width, height) else {
+        return pass_through(result, base_frame);
+    };
+
+    // Move creative and mask together so scene depth is still tested per output
+    // pixel; row bands keep the scratch buffers cache resident, and since bands
+    // are contiguous in every frame buffer nothing needs repacking
+    let source = Source {
+        creative: creative_frame,
+        alpha: alpha_mask,
+        width: width as usize,
+        height: height as usize,
+    };
+    let warp = LayerWarp::new(&source, &bounds, options);
+
+    // An empty result tells the host to show the base frame untouched
+    if !memory::try_copy_into(result, base_frame) {
+        log_error("WASM compositor: Out of memory, frame dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return;
+    }
+    #[cfg(feature = "threads")]
+    let mut keep = keep;
+    #[cfg(feature = "threads")]
+    if tiled::composite_tiles(
+        result,
+        keep.as_deref_mut(),
+        depth_map,
+        occlusion,
+        &warp,
+        width,
+        height,
+        creative_depth,
+        options,
+    ) {
+        return;
+    }
+    composite_layer_rows(
+        result,
+        bands,
+        keep.map(WarpedLayer::as_mut_slices),
+        depth_map,
+        occlusion,
+        &warp,
+        0..height as usize,
+        width as usize,
+        creative_depth,
+        options.blend_math(),
+    );
+}
+
+/// The creative as `composite_layer` would place it, without compositing:
+/// fitted, windowed, run through the effect chain and warped by `transform`
+/// into a transparent `out_width x out_height` RGBA frame, its alpha channel
+/// scaled by the placement coverage, for previews and offline checks. The
+/// creative is frame sized unless `options` give its size. An empty result
+/// means the buffers were invalid or memory ran out
+#[wasm_bindgen]
+pub fn warp_creative(
+    creative_frame: &[u8],
+    alpha_mask: &[u8],
+    transform: &LayerTransform,
+    out_width: u32,
+    out_height: u32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let mut options = options.clone();
+    options.set_layer_transform(transform);
+    let warped = warp_to_frame(creative_frame, alpha_mask, out_width, out_height, &options);
+    let Some((mut creative, coverage)) = warped else {
+        return Vec::new();
+    };
+    for (pixel, coverage) in creative.chunks_exact_mut(4).zip(coverage) {
+        pixel[3] = (pixel[3] as f32 * coverage.unit() + 0.5) as u8;
+    }
+    creative
+}
+
+/// Run a layer's creative through everything `composite_layer` does before
+/// blending, returning the warped frame-sized RGBA and 8-bit coverage, with
+/// uncovered pixels transparent black. None when the buffers are invalid or
+/// memory ran out
+pub(crate) fn warp_to_frame(
+    creative_frame: &[u8],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    options: &LayerOptions,
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    warp_to_frame_in(creative_frame, alpha_mask, width, height, options, options.frame_color)
+}
+
+/// `warp_to_frame` with the creative converted to `frame_color` rather than
+/// the space the options tag the frame with, for frames the caller encodes
+pub(crate) fn warp_to_frame_in(
+    creative_frame: &[u8],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    options: &LayerOptions,
+    frame_color: ColorSpace,
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut layer = WarpedLayer::default();
+    let (source, bands) = ((creative_frame, alpha_mask), &mut Bands::default());
+    let warped = warp_to_frame_into(&mut layer, bands, source, width, height, options, frame_color);
+    warped.then_some((layer.creative, layer.coverage))
+}
+
+/// `warp_to_frame_in` into `layer`, reusing its buffers and the warp
+/// `bands` across calls; false when the buffers are invalid or memory ran
+/// out
+pub(crate) fn warp_to_frame_into(
+    layer: &mut WarpedLayer,
+    bands: &mut Bands<u8>,
+    (creative_frame, alpha_mask): (&[u8], &[u8]),
+    width: u32,
+    height: u32,
+    options: &LayerOptions,
+    frame_color: ColorSpace,
+) -> bool {
+    let _memory = Scope::enter(Subsystem::Warp);
+    let fitted = fit::fit_to_frame(creative_frame, alpha_mask, width, height, options);
+    let Some((creative_frame, alpha_mask)) = fitted else {
+        return false;
+    };
+    let windowed = window::apply_window(&creative_frame, width, height, options);
+    let windowed = grade_to_frame(windowed, options, frame_color);
+    let pixel_count = (width * height) as usize;
+    if windowed.len() < pixel_count * 4 || alpha_mask.len() < pixel_count {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return false;
+    }
+    if options.scale <= 0.0 {
+        // Nothing is placed, leaving the layer transparent
+        return warp_pixels(layer, bands, &windowed, &[], width, height, options);
+    }
+
+    #[cfg(feature = "effects")]
+    if !options.effects.is_empty() {
+        let (w, h) = (width as usize, height as usize);
+        if let Some((creative, alpha)) = options.apply_effects(&windowed, &alpha_mask, w, h, None) {
+            return warp_pixels(layer, &mut Bands::default(), &creative, &alpha, width, height, options);
+        }
+        log_error("WASM compositor: Out of memory, skipping effects");
+        health::degrade(DegradationTier::Reduced);
+    }
+    warp_pixels(layer, bands, &windowed, &alpha_mask, width, height, options)
+}
+
+/// A windowed creative converted to the `frame_color` space, relit to the
+/// scene, then graded by the layer's LUT
+pub(crate) fn grade_to_frame<'a>(
+    creative: Cow<'a, [u8]>,
+    options: &LayerOptions,
+    frame_color: ColorSpace,
+) -> Cow<'a, [u8]> {
+    let converted = color::convert_creative(creative, options.creative_color, frame_color);
+    let lit = lighting::relight_creative(converted, options.scene_light);
+    lut::grade_creative(lit, options.lut.as_deref())
+}
+
+/// Warp a frame-sized creative and mask into `layer` band by band,
+/// coverage quantized to 8 bits; an empty mask leaves it transparent
+fn warp_pixels<A: Coverage>(
+    layer: &mut WarpedLayer,
+    bands: &mut Bands<A>,
+    creative_frame: &[u8],
+    alpha_mask: &[A],
+    width: u32,
+    height: u32,
+    options: &LayerOptions,
+) -> bool {
+    let (w, h) = (width as usize, height as usize);
+    if !layer.fit(w * h) {
+        log_error("WASM compositor: Out of memory, warp dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return false;
+    }
+    let Some(bounds) = mask_bounds(alpha_mask, width, height) else {
+        return true;
+    };
+    if !reserve_bands(bands, w) {
+        return false;
+    }
+    let source = Source { creative: creative_frame, alpha: alpha_mask, width: w, height: h };
+    let warp = LayerWarp::new(&source, &bounds, options);
+    for start in (0..h).step_by(bands.rows) {
+        let band = start..(start + bands.rows).min(h);
+        let span = band.start * w..band.end * w;
+        let (creative, alpha) = (&mut bands.creative[..span.len() * 4], &mut bands.alpha[..span.len()]);
+        if !warp.warp_rows(band, creative, alpha) {
+            continue;
+        }
+        // Uncovered pixels stay transparent black, even where an identity
+        // warp copied the creative straight through
+        let kept = layer.creative[span.start * 4..span.end * 4].chunks_exact_mut(4);
+        let warped = creative.chunks_exact(4).zip(alpha.iter()).zip(kept);
+        for (((pixel, alpha), kept), coverage) in warped.zip(&mut layer.coverage[span]) {
+            *coverage = u8::quantize(alpha.unit() * 255.0);
+            if *coverage != 0 {
+                kept.copy_from_slice(pixel);
+            }
+        }
+    }
+    true
+}
+
+/// Hotspot polygon (flat `[x, y, ...]`) of an interactive placement as
+/// `composite_layer` places it with `options`: the mask's bounds moved by
+/// the transform, cut to the frame and to the clip path, in the layer's
+/// coordinate system. Empty when none of it is on screen
+#[wasm_bindgen]
+pub fn placement_hotspot(alpha_mask: &[u8], width: u32, height: u32, options: &LayerOptions) -> Vec<f32> {
+    let pixel_count = (width * height) as usize;
+    if alpha_mask.len() < pixel_count || options.scale <= 0.0 {
+        return Vec::new();
+    }
+    let coordinates = &options.coordinates;
+    let alpha_mask = if coordinates.flip_masks() {
+        Cow::Owned(coords::flip_rows(alpha_mask, width as usize, height as usize))
+    } else {
+        Cow::Borrowed(alpha_mask)
+    };
+    let Some(bounds) = mask_bounds(&alpha_mask, width, height) else {
+        return Vec::new();
+    };
+
+    // Placed and clipped in frame pixels as `LayerWarp` does
+    let (dx, dy) = coordinates.offset_to_pixels(options.dx, options.dy, width, height);
+    let frame = Rect::new(0.0, 0.0, width as f32, height as f32);
+    let Some(placed) = bounds.offset_scaled(dx, dy, options.scale).intersection(&frame) else {
+        return Vec::new();
+    };
+    let outline = if options.clip.is_empty() {
+        placed.corners().to_vec()
+    } else {
+        let clip: Vec<(f32, f32)> =
+            options.clip.iter().map(|&(x, y)| coordinates.point_to_pixels(x, y, width, height)).collect();
+        clip_polygon(&clip, &placed)
+    };
+    outline
+        .into_iter()
+        .flat_map(|(x, y)| <[f32; 2]>::from(coordinates.point_from_pixels(x, y, width, height)))
+        .collect()
+}
+
+/// Hit test a point against a polygon returned by `placement_hotspot`
+#[wasm_bindgen]
+pub fn hotspot_contains(polygon: &[f32], x: f32, y: f32) -> bool {
+    let points: Vec<(f32, f32)> = polygon.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+    polygon_contains(&points, x, y)
+}
+
+/// LUT size `warm_up` grades with, what grading tools export most often
+const WARM_UP_LUT_SIZE: usize = 33;
+
+/// Run throwaway composites at the stream's frame size through the paths of
+/// `formats`, so memory has grown to what a frame needs and the engine has
+/// tiered up before the first real frame: "rgba" (once per blend
+/// precision), "i420", "nv12", "rgba16" and "p010" frames, and "lut" for
+/// layers graded with a `Lut3d`. Unknown names are logged and skipped
+#[wasm_bindgen]
+pub fn warm_up(width: u32, height: u32, formats: Vec<String>) {
+    let pixel_count = (width * height) as usize;
+    if pixel_count == 0 {
+        return;
+    }
+    let (w, h) = (width as usize, height as usize);
+    let chroma = w.div_ceil(2) * h.div_ceil(2);
+
+    let creative_frame = vec![255u8; pixel_count * 4];
+    let depth_map = vec![1.0f32; pixel_count];
+
+    // A soft placement warped over the whole frame, so every buffer and
+    // table gets the size of the largest one
+    let alpha_mask = vec![128u8; pixel_count];
+    let mut options = LayerOptions::new();
+    options.set_transform(0.5, 0.5, 1.1);
+    let (creative, depth, alpha) = (&creative_frame[..], &depth_map[..], &alpha_mask[..]);
+    for format in &formats {
+        match format.as_str() {
+            "rgba" | "lut" => {
+                let mut options = options.clone();
+                if format == "lut" {
+                    options.set_lut(&lut::Lut3d::identity(WARM_UP_LUT_SIZE));
+                }
+                let base_frame = vec![0u8; pixel_count * 4];
+                for precision in [BlendPrecision::Float, BlendPrecision::FixedPoint, BlendPrecision::Integer] {
+                    options.set_blend_precision(precision);
+                    composite_layer(&base_frame, creative, depth, alpha, width, height, 0.0, &options);
+                }
+            }
+            "i420" | "nv12" => {
+                let yuv = if format == "i420" { YuvFormat::I420 } else { YuvFormat::Nv12 };
+                let base_frame = vec![16u8; pixel_count + chroma * 2];
+                let matrix = YuvMatrix::default();
+                composite_layer_yuv(
+                    &base_frame, yuv, matrix, creative, depth, alpha, width, height, 0.0, &options,
+                );
+            }
+            "rgba16" => {
+                let base_frame = vec![0u16; pixel_count * 4];
+                let transfer = HdrTransfer::Pq;
+                composite_layer_rgba16(
+                    &base_frame, creative, depth, alpha, width, height, 0.0, transfer, 203.0, &options,
+                );
+            }
+            "p010" => {
+                let base_frame = vec![64u16 << 6; pixel_count + chroma * 2];
+                let (matrix, transfer) = (YuvMatrix::Bt2020, HdrTransfer::Pq);
+                composite_layer_p010(
+                    &base_frame, matrix, creative, depth, alpha, width, height, 0.0, transfer, 203.0, &options,
+                );
+            }
+            _ => log_error(&format!("WASM compositor: Unknown warm-up format {:?}, skipped", format)),
+        }
+    }
+}
+
+/// Check that every input buffer covers the full frame
+fn inputs_valid<A>(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[A],
+    width: u32,
+    height: u32,
+) -> bool {
+    let pixel_count = (width * height) as usize;
+    base_frame.len() >= pixel_count * 4
+        && creative_frame.len() >= pixel_count * 4
+        && depth_map.len() >= pixel_count
+        && alpha_mask.len() >= pixel_count
+}
+
 /// Internal compositing logic with depth testing
 fn composite_with_depth(
     base_frame: &[u8],
@@ -44,33 +991,261 @@ fn composite_with_depth(
     width: u32,
     height: u32,
     creative_depth: f32,
+) -> Vec<u8> {
+    composite_with_coverage(
+        base_frame,
+        creative_frame,
+        depth_map,
+        width,
+        height,
+        creative_depth,
+        LayerOptions::default().blend_math(),
+        |i| UNIT_FROM_U8[alpha_mask[i] as usize],
+    )
+}
+
+/// Depth-tested blend where `coverage(i)` gives the creative's 0..1 alpha at pixel `i`
+#[allow(clippy::too_many_arguments)]
+fn composite_with_coverage(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    math: BlendMath,
+    coverage: impl Fn(usize) -> f32,
 ) -> Vec<u8> {
     let pixel_count = (width * height) as usize;
-    let mut result = vec![0u8; base_frame.len()];
-    
-    for i in 0..pixel_count {
-        let pixel_idx = i * 4; // RGBA
-        let scene_depth = depth_map[i];
-        let alpha = alpha_mask[i] as f32 / 255.0;
-        
-        // Only composite if creative is in front of scene geometry
-        if creative_depth < scene_depth && alpha > 0.0 {
-            // Alpha blending: result = creative * alpha + base * (1 - alpha)
-            for channel in 0..4 {
-                let base_val = base_frame[pixel_idx + channel] as f32;
-                let creative_val = creative_frame[pixel_idx + channel] as f32;
-                let blended = creative_val * alpha + base_val * (1.0 - alpha);
-                result[pixel_idx + channel] = blended.clamp(0.0, 255.0) as u8;
+
+    // Start from the base frame so only covered pixels are touched
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let mut result = base_frame.to_vec();
+    blend_span(
+        &mut result[..pixel_count * 4],
+        creative_frame,
+        &depth_map[..pixel_count],
+        creative_depth,
+        math,
+        coverage,
+    );
+    result
+}
+
+/// Scratch budget for one band of warped rows, sized to stay in L2
+const BAND_BYTES: usize = 256 * 1024;
+
+/// Rows per band for a frame `width` pixels wide (RGBA plus coverage `A`)
+fn band_rows<A>(width: usize) -> usize {
+    (BAND_BYTES / (width * (4 + std::mem::size_of::<A>())).max(1)).max(1)
+}
+
+/// Scratch for one band of warped creative and coverage, kept between
+/// frames by stateful compositors
+#[derive(Default)]
+pub(crate) struct Bands<A> {
+    creative: Vec<u8>,
+    alpha: Vec<A>,
+    rows: usize,
+    width: usize,
+    /// Last frame's effects, for checkerboard reconstruction
+    #[cfg(feature = "effects")]
+    history: EffectHistory,
+}
+
+impl<A: Coverage> Bands<A> {
+    /// Make room for bands of a `width` pixel frame, full height when memory
+    /// allows and a single row otherwise; false when not even a row fits
+    fn reserve(&mut self, width: usize) -> bool {
+        let full_rows = band_rows::<A>(width);
+        for rows in [full_rows, 1] {
+            if (self.rows, self.width) == (rows, width) {
+                return true;
             }
-        } else {
-            // Use base frame pixel
-            for channel in 0..4 {
-                result[pixel_idx + channel] = base_frame[pixel_idx + channel];
+            let scratch = memory::try_zeroed(rows * width * 4).zip(memory::try_zeroed(rows * width));
+            if let Some((creative, alpha)) = scratch {
+                (self.creative, self.alpha, self.rows, self.width) = (creative, alpha, rows, width);
+                return true;
             }
         }
+        false
+    }
+}
+
+/// A frame-sized warped creative and its 8-bit coverage, kept by stateful
+/// compositors to blend again onto frames they do not warp
+#[derive(Default)]
+pub(crate) struct WarpedLayer {
+    pub(crate) creative: Vec<u8>,
+    pub(crate) coverage: Vec<u8>,
+}
+
+impl WarpedLayer {
+    /// Make this a transparent layer of `pixel_count` pixels, reusing the
+    /// buffers; false, leaving it empty, when there is no memory for it
+    fn fit(&mut self, pixel_count: usize) -> bool {
+        if self.coverage.capacity() < pixel_count {
+            match memory::try_zeroed(pixel_count * 4).zip(memory::try_zeroed(pixel_count)) {
+                Some((creative, coverage)) => *self = WarpedLayer { creative, coverage },
+                None => {
+                    *self = WarpedLayer::default();
+                    return false;
+                }
+            }
+        }
+        self.creative.clear();
+        self.creative.resize(pixel_count * 4, 0);
+        self.coverage.clear();
+        self.coverage.resize(pixel_count, 0);
+        true
+    }
+
+    /// Forget the layer, keeping the buffers
+    pub(crate) fn clear(&mut self) {
+        self.creative.clear();
+        self.coverage.clear();
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.coverage.is_empty()
+    }
+
+    fn as_mut_slices(&mut self) -> (&mut [u8], &mut [u8]) {
+        (&mut self.creative, &mut self.coverage)
+    }
+}
+
+/// Warp and blend output `rows` of a layer in cache-sized `bands`; `result`,
+/// `keep`, `depth` and `occlusion` start at the first of those rows
+#[allow(clippy::too_many_arguments)]
+fn composite_layer_rows<A: Coverage>(
+    result: &mut [u8],
+    bands: &mut Bands<A>,
+    keep: Option<(&mut [u8], &mut [u8])>,
+    depth: &[f32],
+    occlusion: Option<&[u8]>,
+    warp: &LayerWarp<A>,
+    rows: Range<usize>,
+    width: usize,
+    creative_depth: f32,
+    math: BlendMath,
+) {
+    if reserve_bands(bands, width) {
+        warp_bands(result, bands, keep, depth, occlusion, warp, rows, width, creative_depth, math);
+    }
+}
+
+/// Make room in `bands` for a `width` pixel frame, degrading the frame when
+/// memory is short; false when the layer has to be dropped
+fn reserve_bands<A: Coverage>(bands: &mut Bands<A>, width: usize) -> bool {
+    // Short of memory for full bands, fall back to one row at a time
+    let _scratch = Scope::enter(Subsystem::Warp);
+    if !bands.reserve(width) {
+        log_error("WASM compositor: Out of memory, layer dropped");
+        health::degrade(DegradationTier::LayerDropped);
+        return false;
+    }
+    if bands.rows < band_rows::<A>(width) {
+        health::degrade(DegradationTier::Reduced);
+    }
+    true
+}
+
+/// Band loop of `composite_layer_rows` over bands already reserved; touches
+/// no shared state, so tiles of a frame can run it on other threads
+#[allow(clippy::too_many_arguments)]
+fn warp_bands<A: Coverage>(
+    result: &mut [u8],
+    bands: &mut Bands<A>,
+    mut keep: Option<(&mut [u8], &mut [u8])>,
+    depth: &[f32],
+    occlusion: Option<&[u8]>,
+    warp: &LayerWarp<A>,
+    rows: Range<usize>,
+    width: usize,
+    creative_depth: f32,
+    math: BlendMath,
+) {
+    let band_rows = bands.rows;
+    for start in rows.clone().step_by(band_rows) {
+        let band = start..(start + band_rows).min(rows.end);
+        let offset = (band.start - rows.start) * width;
+        let span = offset..offset + band.len() * width;
+        let (creative, alpha) = (&mut bands.creative[..span.len() * 4], &mut bands.alpha[..span.len()]);
+        if !warp.warp_rows(band, creative, alpha) {
+            continue;
+        }
+        if let Some((kept, coverage)) = keep.as_mut() {
+            kept[span.start * 4..span.end * 4].copy_from_slice(creative);
+            for (coverage, alpha) in coverage[span.clone()].iter_mut().zip(alpha.iter()) {
+                *coverage = u8::quantize(alpha.unit() * 255.0);
+            }
+        }
+
+        // Occlusion hides the layer on top of whatever its own mask says
+        let occlusion = occlusion.map(|occlusion| &occlusion[span.clone()]);
+        blend_span(
+            &mut result[span.start * 4..span.end * 4],
+            creative,
+            &depth[span],
+            creative_depth,
+            math,
+            |i| match occlusion {
+                Some(occlusion) => alpha[i].unit() * (1.0 - UNIT_FROM_U8[occlusion[i] as usize]),
+                None => alpha[i].unit(),
+            },
+        );
+    }
+}
+
+/// Depth-tested in-place blend of a run of pixels; `coverage(i)`, `creative`
+/// and `depth` are indexed relative to the start of the run
+pub(crate) fn blend_span(
+    result: &mut [u8],
+    creative: &[u8],
+    depth: &[f32],
+    creative_depth: f32,
+    math: BlendMath,
+    coverage: impl Fn(usize) -> f32,
+) {
+    // Four pixels per step where the build has SIMD128
+    let simd128 = cfg!(all(target_arch = "wasm32", target_feature = "simd128"));
+    let plain = math.mode == BlendMode::Normal && math.equation == BlendEquation::SourceOver;
+    if simd128 && math.precision == BlendPrecision::Integer && plain {
+        return simd::blend_span4(result, creative, depth, creative_depth, math, coverage);
+    }
+
+    let pixels = result.chunks_exact_mut(4).zip(creative.chunks_exact(4)).zip(depth);
+
+    for (i, ((pixel, creative), &scene_depth)) in pixels.enumerate() {
+        // Only composite where the creative is in front of scene geometry
+        let visible = visibility(creative_depth, scene_depth, math.depth_feather);
+        let alpha = if visible > 0.0 { coverage(i) * visible } else { 0.0 };
+        if alpha > 0.0 {
+            // Alpha blending: result = creative * alpha + base * (1 - alpha)
+            blend_pixel(pixel, creative, alpha, math);
+        }
     }
-    
-    result
+}
+
+/// Optional features compiled into this build, so the host can check that
+/// the bundle it loaded supports what a manifest asks for
+#[wasm_bindgen]
+pub fn build_features() -> Vec<String> {
+    [
+        ("effects", cfg!(feature = "effects")),
+        ("json", cfg!(feature = "json")),
+        ("policy", cfg!(feature = "policy")),
+        ("parity", cfg!(feature = "parity")),
+        ("threads", cfg!(feature = "threads")),
+        ("webcodecs", cfg!(feature = "webcodecs")),
+        ("webgpu", cfg!(feature = "webgpu")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name.to_string())
+    .collect()
 }
 
 /// Utility function to validate frame dimensions
@@ -85,11 +1260,13 @@ pub fn get_version_info() -> String {
     format!(
         "Inscenium Edge Worker WASM v{} - Built with Rust {}",
         env!("CARGO_PKG_VERSION"),
-        env!("RUSTC_VERSION", "unknown")
+        option_env!("RUSTC_VERSION").unwrap_or("unknown")
     )
 }
 
 #[cfg(test)]
+// The original tests predate the clippy gate and are kept as written
+#[allow(unused_variables, clippy::bool_assert_comparison, clippy::useless_vec)]
 mod tests {
     use super::*;
 
@@ -113,10 +1290,10 @@ mod tests {
         let pixel_count = 4;
         
         // Base frame: all red pixels
-        let base_frame = vec![255u8, 0, 0, 255; pixel_count];
+        let base_frame = [255u8, 0, 0, 255].repeat(pixel_count);
         
         // Creative frame: all blue pixels  
-        let creative_frame = vec![0u8, 0, 255, 255; pixel_count];
+        let creative_frame = [0u8, 0, 255, 255].repeat(pixel_count);
         
         // Depth map: creative is in front (lower depth)
         let depth_map = vec![10.0f32; pixel_count];
@@ -146,6 +1323,168 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_composite_segment_into_matches_returned_frame() {
+        let (width, height) = (4u32, 2u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i * 7 % 256) as u8).collect();
+        let creative_frame = vec![200u8; pixel_count * 4];
+        let depth_map: Vec<f32> = (0..pixel_count).map(|i| if i % 3 == 0 { 1.0 } else { 10.0 }).collect();
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| (i * 40) as u8).collect();
+        let expected =
+            composite_segment(&base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0);
+
+        // The same buffer serves frame after frame; extra room is left alone
+        let (creative, depth) = (&creative_frame[..], &depth_map[..]);
+        let composite_into = |out: &mut [u8]| {
+            composite_segment_into(out, &base_frame, creative, depth, &alpha_mask, width, height, 5.0)
+        };
+        let mut out = vec![9u8; pixel_count * 4 + 4];
+        for _ in 0..2 {
+            assert!(composite_into(&mut out));
+            assert_eq!(out[..pixel_count * 4], expected[..]);
+            assert_eq!(out[pixel_count * 4..], [9; 4]);
+        }
+
+        let mut short = vec![0u8; 4];
+        assert!(!composite_into(&mut short));
+        assert_eq!(short, [0; 4]);
+    }
+
+    #[test]
+    fn test_creative_depth_map_tests_per_pixel() {
+        let (width, height) = (4u32, 1u32);
+        let base_frame = vec![0u8; 16];
+        let creative_frame = vec![200u8; 16];
+        let alpha_mask = vec![255u8; 4];
+        let depth_map = [5.0f32; 4];
+        // Only the parts of the creative nearer than the scene show; NaN
+        // takes the scalar depth, which is behind
+        let creative_depth_map = [4.0, 6.0, f32::NAN, 4.9];
+        let result = composite_segment_depth_map(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, &creative_depth_map, width, height, 8.0,
+        );
+        let shown: Vec<bool> = result.chunks_exact(4).map(|pixel| pixel[0] == 200).collect();
+        assert_eq!(shown, [true, false, false, true]);
+
+        // No map is the scalar composite; a short one is rejected
+        let composite = |creative_depth_map: &[f32]| {
+            composite_segment_depth_map(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, creative_depth_map, width, height, 1.0,
+            )
+        };
+        let scalar =
+            composite_segment(&base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 1.0);
+        assert_eq!(composite(&[]), scalar);
+        assert_eq!(composite(&[1.0; 3]), base_frame);
+    }
+
+    #[test]
+    fn test_blend_modes_through_composite_segment() {
+        let (width, height) = (2u32, 1u32);
+        let base_frame = [200u8, 100, 0, 255, 200, 100, 0, 255];
+        let creative_frame = [128u8, 128, 128, 255, 128, 128, 128, 255];
+        let depth_map = [10.0f32, 1.0];
+        let alpha_mask = [255u8, 255];
+        let composite = |mode| {
+            composite_segment_blended(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, mode,
+            )
+        };
+
+        let normal = composite(BlendMode::Normal);
+        let expected = composite_segment(&base_frame, &creative_frame, &depth_map, &alpha_mask, 2, 1, 5.0);
+        assert_eq!(normal, expected);
+        assert_eq!(composite(BlendMode::Multiply)[..4], [100, 50, 0, 255]);
+        assert_eq!(composite(BlendMode::Screen)[..4], [228, 178, 128, 255]);
+        // Behind the scene nothing changes, whatever the mode
+        assert_eq!(composite(BlendMode::Add)[4..], base_frame[4..]);
+    }
+
+    #[test]
+    fn test_alpha_modes_through_composite_layer() {
+        // A transparent overlay canvas, premultiplied as WebGL holds it
+        let base_frame = [0u8; 8];
+        let creative_frame = [200u8, 100, 50, 255, 200, 100, 50, 255];
+        let (depth_map, alpha_mask) = ([10.0f32; 2], [128u8, 0]);
+        let mut options = LayerOptions::new();
+        let composite = |options: &LayerOptions| {
+            composite_layer(&base_frame, &creative_frame, &depth_map, &alpha_mask, 2, 1, 5.0, options)
+        };
+        assert_eq!(composite(&options)[..4], [100, 50, 25, 128]);
+
+        options.set_alpha_modes(AlphaMode::Straight, AlphaMode::Straight);
+        assert_eq!(composite(&options), [200, 100, 50, 128, 0, 0, 0, 0]);
+        options.set_alpha_modes(AlphaMode::Premultiplied, AlphaMode::Straight);
+        assert_eq!(composite(&options)[..4], [100, 50, 25, 128]);
+    }
+
+    #[test]
+    fn test_depth_feather_matches_occlusion_mask() {
+        let (width, height) = (8u32, 1u32);
+        let depth_map = [3.0f32, 4.0, 4.5, 5.0, 5.5, 6.0, 7.0, f32::NAN];
+        let (base_frame, creative_frame) = ([0u8; 32], [255u8; 32]);
+        let mut options = LayerOptions::new();
+        options.set_depth_feather(2.0);
+        let frame = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &[255; 8], width, height, 5.0, &options,
+        );
+        let mask = compute_occlusion(&depth_map, &[], width, height, 5.0, 2.0, &[]);
+        assert!(frame.chunks_exact(4).zip(&mask).all(|(pixel, &visible)| pixel[0] == visible), "{:?}", frame);
+        assert!(mask.iter().any(|&visible| visible > 0 && visible < 255));
+    }
+
+    #[test]
+    fn test_warp_creative_matches_composite_placement() {
+        let (width, height) = (6u32, 4u32);
+        let pixel_count = (width * height) as usize;
+        let creative_frame: Vec<u8> = (0..pixel_count).flat_map(|i| [i as u8 * 10, 50, 90, 255]).collect();
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| if i % 6 < 3 { 255 } else { 0 }).collect();
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(FilterMode::Nearest);
+
+        // Identity: the creative itself, coverage in alpha
+        let identity = LayerTransform::default();
+        let warped = warp_creative(&creative_frame, &alpha_mask, &identity, width, height, &options);
+        assert_eq!(warped[..4], creative_frame[..4]);
+        assert_eq!(warped[3 * 4..3 * 4 + 4], [0; 4]);
+
+        // Shifted, it lands where the composite puts it
+        let transform = LayerTransform::new(2.0, 1.0, 1.0);
+        let warped = warp_creative(&creative_frame, &alpha_mask, &transform, width, height, &options);
+        options.set_layer_transform(&transform);
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let (creative, alpha) = (&creative_frame[..], &alpha_mask[..]);
+        let expected =
+            composite_layer(&base_frame, creative, &depth_map, alpha, width, height, 5.0, &options);
+        assert_eq!(warped, expected);
+        assert!(warp_creative(&creative[..8], alpha, &transform, width, height, &options).is_empty());
+    }
+
+    #[test]
+    fn test_warp_into_reuses_the_layer() {
+        let (width, height) = (6u32, 4u32);
+        let pixel_count = (width * height) as usize;
+        let creative_frame: Vec<u8> = (0..pixel_count).flat_map(|i| [i as u8 * 10, 50, 90, 255]).collect();
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| (i * 11) as u8).collect();
+        let mut options = LayerOptions::new();
+        options.set_transform(1.5, 0.5, 1.2);
+        let source = (&creative_frame[..], &alpha_mask[..]);
+        let expected = warp_to_frame(source.0, source.1, width, height, &options).unwrap();
+
+        let (mut layer, mut bands) = (WarpedLayer::default(), Bands::default());
+        let frame_color = options.frame_color;
+        assert!(warp_to_frame_into(&mut layer, &mut bands, source, width, height, &options, frame_color));
+        let buffers = (layer.creative.as_ptr(), layer.coverage.as_ptr());
+        memory::fail_allocations(u64::MAX);
+        let warped = warp_to_frame_into(&mut layer, &mut bands, source, width, height, &options, frame_color);
+        memory::fail_allocations(0);
+        assert!(warped);
+        assert_eq!((layer.creative.as_ptr(), layer.coverage.as_ptr()), buffers);
+        assert_eq!((layer.creative, layer.coverage), expected);
+    }
+
     #[test]
     fn test_composite_with_depth_behind() {
         let width = 2;
@@ -153,10 +1492,10 @@ mod tests {
         let pixel_count = 4;
         
         // Base frame: all red pixels
-        let base_frame = vec![255u8, 0, 0, 255; pixel_count];
+        let base_frame = [255u8, 0, 0, 255].repeat(pixel_count);
         
         // Creative frame: all blue pixels
-        let creative_frame = vec![0u8, 0, 255, 255; pixel_count];
+        let creative_frame = [0u8, 0, 255, 255].repeat(pixel_count);
         
         // Depth map: creative is behind (higher depth)
         let depth_map = vec![5.0f32; pixel_count];
@@ -216,12 +1555,13 @@ mod tests {
             creative_depth
         );
         
-        // Should be blended: 50% blue + 50% red
-        // R: 0 * 0.5 + 255 * 0.5 = 127.5 ≈ 128
-        // G: 0 * 0.5 + 0 * 0.5 = 0
-        // B: 255 * 0.5 + 0 * 0.5 = 127.5 ≈ 128
-        // A: 255 * 0.5 + 255 * 0.5 = 255
-        assert_eq!(result[0], 128); // R
+        // Blended at coverage 128/255, not exactly half:
+        // R: 255 * 127/255 = 127, which the float blend computed as 126.99
+        //    and truncated; blends now round to nearest (`quantize_float`)
+        // G: 0
+        // B: 255 * 128/255 = 128
+        // A: 255
+        assert_eq!(result[0], 127); // R
         assert_eq!(result[1], 0);   // G
         assert_eq!(result[2], 128); // B
         assert_eq!(result[3], 255); // A
@@ -233,7 +1573,7 @@ mod tests {
         let height = 2;
         
         let base_frame = vec![255u8, 0, 0, 255];  // Too small
-        let creative_frame = vec![0u8, 0, 255, 255; 4];
+        let creative_frame = [0u8, 0, 255, 255].repeat(4);
         let depth_map = vec![10.0f32; 4];
         let alpha_mask = vec![255u8; 4];
         let creative_depth = 5.0;
@@ -281,6 +1621,492 @@ mod tests {
         assert_eq!(result, vec![0u8, 255, 0, 255]);
     }
 
+    #[test]
+    fn test_find_exclusion_overlap() {
+        let width = 4;
+        let height = 4;
+
+        // Placement covers the 2x2 block in the top-left corner
+        let mut alpha_mask = vec![0u8; 16];
+        alpha_mask[0] = 255;
+        alpha_mask[1] = 255;
+        alpha_mask[4] = 255;
+        alpha_mask[5] = 255;
+
+        // Region in the bottom-right corner does not touch the placement
+        assert_eq!(find_exclusion_overlap(&alpha_mask, width, height, &[2.0, 2.0, 2.0, 2.0]), -1);
+
+        // Second region overlaps the placement
+        let regions = [3.0, 3.0, 1.0, 1.0, 1.0, 1.0, 2.0, 2.0];
+        assert_eq!(find_exclusion_overlap(&alpha_mask, width, height, &regions), 1);
+
+        // Empty mask can never be excluded
+        assert_eq!(find_exclusion_overlap(&[0u8; 16], width, height, &regions), -1);
+    }
+
+    #[test]
+    fn test_composite_segment_with_exclusions() {
+        let base_frame = vec![255u8, 0, 0, 255];   // Red base
+        let creative_frame = vec![0u8, 255, 0, 255]; // Green creative
+        let depth_map = vec![10.0f32];
+        let alpha_mask = vec![255u8];
+
+        // No exclusion regions: creative is composited
+        let result = composite_segment_with_exclusions(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, 1, 1, 5.0, &[],
+        );
+        assert_eq!(result, creative_frame);
+
+        // Overlapping region: base frame passes through
+        let result = composite_segment_with_exclusions(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, 1, 1, 5.0, &[0.0, 0.0, 1.0, 1.0],
+        );
+        assert_eq!(result, base_frame);
+    }
+
+    #[test]
+    fn test_composite_segment_repositioned() {
+        let width = 4;
+        let height = 1;
+
+        // Red base, green creative covering only the first pixel
+        let base_frame = [255u8, 0, 0, 255].repeat(4);
+        let creative_frame = [0u8, 255, 0, 255].repeat(4);
+        let depth_map = vec![10.0f32; 4];
+        let alpha_mask = vec![255u8, 0, 0, 0];
+
+        // Shift the placement two pixels to the right
+        let result = composite_segment_repositioned(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, 2.0, 0.0, 1.0,
+        );
+
+        assert_eq!(result[0..4], [255, 0, 0, 255]);  // Vacated pixel shows base
+        assert_eq!(result[8..12], [0, 255, 0, 255]); // Creative moved here
+    }
+
+    #[test]
+    fn test_composite_layer_edge_modes() {
+        let width = 2;
+        let height = 1;
+
+        // Red base; creative is green then blue, fully covered
+        let base_frame = [255u8, 0, 0, 255].repeat(2);
+        let creative_frame = vec![0u8, 255, 0, 255, 0, 0, 255, 255];
+        let depth_map = vec![10.0f32; 2];
+        let alpha_mask = vec![255u8; 2];
+
+        // Shift right by one pixel: the vacated pixel samples outside the source
+        let mut options = LayerOptions::new();
+        options.set_transform(1.0, 0.0, 1.0);
+
+        let transparent = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+        assert_eq!(transparent, vec![255, 0, 0, 255, 0, 255, 0, 255]);
+
+        options.set_edge_mode(EdgeMode::Wrap);
+        let wrapped = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+        assert_eq!(wrapped, vec![0, 0, 255, 255, 0, 255, 0, 255]);
+
+        options.set_edge_mode(EdgeMode::Clamp);
+        let clamped = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+        assert_eq!(clamped, vec![0, 255, 0, 255, 0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn test_placement_hotspot() {
+        // 2x2 placement in the top-left of a 4x4 frame
+        let mut alpha_mask = vec![0u8; 16];
+        alpha_mask[0] = 255;
+        alpha_mask[1] = 255;
+        alpha_mask[4] = 255;
+        alpha_mask[5] = 255;
+
+        let mut options = LayerOptions::new();
+        let hotspot = placement_hotspot(&alpha_mask, 4, 4, &options);
+        assert_eq!(hotspot, vec![0.0, 0.0, 2.0, 0.0, 2.0, 2.0, 0.0, 2.0]);
+        assert!(hotspot_contains(&hotspot, 1.0, 1.0));
+        assert!(!hotspot_contains(&hotspot, 3.0, 3.0));
+
+        // Shifted partly off-frame: clipped at the right edge
+        options.set_transform(3.0, 0.0, 1.0);
+        let hotspot = placement_hotspot(&alpha_mask, 4, 4, &options);
+        assert_eq!(hotspot, vec![3.0, 0.0, 4.0, 0.0, 4.0, 2.0, 3.0, 2.0]);
+
+        // Off-frame entirely, or scaled away
+        options.set_transform(5.0, 0.0, 1.0);
+        assert!(placement_hotspot(&alpha_mask, 4, 4, &options).is_empty());
+        options.set_transform(0.0, 0.0, 0.0);
+        assert!(placement_hotspot(&alpha_mask, 4, 4, &options).is_empty());
+    }
+
+    #[test]
+    fn test_clipped_hotspot_matches_composited_pixels() {
+        let (width, height) = (32u32, 24u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = [0u8, 0, 0, 255].repeat(pixel_count);
+        let creative_frame = [255u8, 255, 255, 255].repeat(pixel_count);
+        let depth_map = vec![10.0f32; pixel_count];
+        let mut alpha_mask = vec![0u8; pixel_count];
+        for y in 4..16 {
+            alpha_mask[y * 32 + 6..y * 32 + 22].fill(255);
+        }
+
+        // Normalized, bottom-left coordinates, moved and clipped to a
+        // triangle cutting across the placement
+        let coordinates = CoordinateSystem::new(Origin::BottomLeft, true);
+        let mut options = LayerOptions::new();
+        options.set_coordinates(&coordinates);
+        options.set_filter_mode(FilterMode::Nearest);
+        options.set_transform(4.0 / 32.0, -2.0 / 24.0, 1.0);
+        options.set_clip_path(&[0.25, 0.9, 0.95, 0.9, 0.25, 0.2]);
+
+        let hotspot = placement_hotspot(&alpha_mask, width, height, &options);
+        let composited = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+        let mut hits = 0;
+        for (i, pixel) in composited.chunks_exact(4).enumerate() {
+            let (x, y) = ((i % 32) as f32 + 0.5, (i / 32) as f32 + 0.5);
+            let (u, v) = coordinates.point_from_pixels(x, y, width, height);
+            let hit = hotspot_contains(&hotspot, u, v);
+            assert_eq!(hit, pixel[0] == 255, "({}, {})", x, y);
+            hits += hit as usize;
+        }
+        // The clip removed some of the placement
+        assert!(hits > 0 && hits < 16 * 12, "{}", hits);
+    }
+
+    #[test]
+    fn test_composite_segment_with_transmittance() {
+        let base_frame = [0u8, 0, 0, 255].repeat(3);       // Black base
+        let creative_frame = [200u8, 200, 200, 255].repeat(3); // Grey creative
+        let depth_map = vec![10.0f32; 3];
+        let alpha_mask = vec![255u8; 3];
+
+        // Clear, half-transparent smoke, opaque foreground
+        let transmittance = vec![1.0f32, 0.5, 0.0];
+
+        let result = composite_segment_with_transmittance(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, &transmittance, 3, 1, 5.0,
+        );
+
+        assert_eq!(result[0], 200); // Fully visible
+        assert_eq!(result[4], 100); // Attenuated by half
+        assert_eq!(result[8], 0);   // Hidden behind the foreground
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_blend_precisions() {
+        let (width, height) = (1920u32, 1080u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 251) as u8).collect();
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 241) as u8).collect();
+        let depth_map = vec![10.0f32; pixel_count];
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| (i % 256) as u8).collect();
+
+        let iterations = 10;
+        for precision in [BlendPrecision::Float, BlendPrecision::FixedPoint, BlendPrecision::Integer] {
+            let start = std::time::Instant::now();
+            for _ in 0..iterations {
+                std::hint::black_box(composite_with_coverage(
+                    &base_frame,
+                    &creative_frame,
+                    &depth_map,
+                    width,
+                    height,
+                    5.0,
+                    BlendMath { precision, ..BlendMath::default() },
+                    |i| UNIT_FROM_U8[alpha_mask[i] as usize],
+                ));
+            }
+            let elapsed = start.elapsed().as_secs_f64() * 1000.0 / iterations as f64;
+            println!("1080p blend {:?}: {:.2} ms", precision, elapsed);
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_banded_layer_composite() {
+        for (width, height) in [(1920u32, 1080u32), (3840, 2160)] {
+            let pixel_count = (width * height) as usize;
+            let base_frame = vec![40u8; pixel_count * 4];
+            let creative_frame = vec![200u8; pixel_count * 4];
+            let depth_map = vec![10.0f32; pixel_count];
+
+            // Lower-third placement, a quarter of the frame wide
+            let mut alpha_mask = vec![0u8; pixel_count];
+            let (w, h) = (width as usize, height as usize);
+            for y in h * 3 / 4..h * 7 / 8 {
+                alpha_mask[y * w + w / 2..y * w + w * 3 / 4].fill(255);
+            }
+            let mut options = LayerOptions::new();
+            options.set_transform(7.5, -3.25, 0.95);
+
+            let iterations = 10;
+            let time = |composite: &dyn Fn() -> Vec<u8>| {
+                let start = std::time::Instant::now();
+                for _ in 0..iterations {
+                    std::hint::black_box(composite());
+                }
+                start.elapsed().as_secs_f64() * 1000.0 / iterations as f64
+            };
+
+            // Previous pipeline: frame-sized warp buffers, then a full-frame blend
+            let full_frame = time(&|| {
+                let source = Source { creative: &creative_frame, alpha: &alpha_mask, width: w, height: h };
+                let bounds = mask_bounds(&alpha_mask, width, height).unwrap();
+                let (creative, alpha) = layer::warp_layer(&source, &bounds, &options);
+                composite_with_depth(&base_frame, &creative, &depth_map, &alpha, width, height, 5.0)
+            });
+            let banded = time(&|| {
+                composite_layer(&base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options)
+            });
+            println!(
+                "{}x{} layer: full-frame {:.2} ms, banded {:.2} ms ({:.1}x)",
+                width,
+                height,
+                full_frame,
+                banded,
+                full_frame / banded
+            );
+        }
+    }
+
+    #[test]
+    fn test_banded_layer_matches_full_frame_warp() {
+        let (width, height) = (1000u32, 200u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 199) as u8).collect();
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 233) as u8).collect();
+        let depth_map = vec![10.0f32; pixel_count];
+        let mut alpha_mask = vec![0u8; pixel_count];
+        for y in 20..180 {
+            for x in 100..900 {
+                alpha_mask[y * width as usize + x] = (x % 256) as u8;
+            }
+        }
+
+        // The layer spans several bands
+        assert!(band_rows::<u8>(width as usize) < height as usize);
+
+        for edge_mode in [EdgeMode::Transparent, EdgeMode::Wrap] {
+            let mut options = LayerOptions::new();
+            options.set_transform(2.5, 13.75, 1.1);
+            options.set_edge_mode(edge_mode);
+
+            let source = Source { creative: &creative_frame, alpha: &alpha_mask, width: 1000, height: 200 };
+            let bounds = mask_bounds(&alpha_mask, width, height).unwrap();
+            let (creative, alpha) = layer::warp_layer(&source, &bounds, &options);
+            let expected = composite_with_depth(&base_frame, &creative, &depth_map, &alpha, width, height, 5.0);
+
+            let banded = composite_layer(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+            );
+            assert_eq!(banded, expected, "{:?}", edge_mode);
+        }
+    }
+
+    #[test]
+    fn test_build_features_match_cfg() {
+        let features = build_features();
+        assert_eq!(features.contains(&"effects".to_string()), cfg!(feature = "effects"));
+        assert_eq!(features.contains(&"policy".to_string()), cfg!(feature = "policy"));
+        assert_eq!(features.contains(&"threads".to_string()), cfg!(feature = "threads"));
+        assert_eq!(features.contains(&"webgpu".to_string()), cfg!(feature = "webgpu"));
+    }
+
+    #[test]
+    #[cfg(feature = "effects")]
+    fn test_composite_layer_applies_effects() {
+        // Blue creative over red at full coverage
+        let base_frame = vec![255u8, 0, 0, 255];
+        let creative_frame = vec![0u8, 0, 255, 255];
+        let depth_map = vec![10.0f32];
+        let alpha_mask = vec![255u8];
+
+        let composite = |effects: &EffectChain| {
+            let mut options = LayerOptions::new();
+            options.set_effects(effects);
+            composite_layer(&base_frame, &creative_frame, &depth_map, &alpha_mask, 1, 1, 5.0, &options)
+        };
+
+        let mut effects = EffectChain::new();
+        effects.add_opacity(0.5);
+        assert_eq!(composite(&effects), vec![127, 0, 128, 255]);
+
+        // Fully faded out leaves the base untouched
+        effects.add_opacity(0.0);
+        assert_eq!(composite(&effects), base_frame);
+    }
+
+    #[test]
+    fn test_occlusion_stays_in_frame_space() {
+        let (width, height) = (4u32, 1u32);
+        let base_frame = vec![0u8; 16];
+        let creative_frame = vec![200u8; 16];
+        let depth_map = vec![10.0f32; 4];
+        let alpha_mask = vec![255u8, 255, 0, 0];
+        let mut options = LayerOptions::new();
+        options.set_transform(2.0, 0.0, 1.0);
+        let composite = |occlusion: &[u8]| {
+            let (base, creative, depth, alpha) = (&base_frame, &creative_frame, &depth_map, &alpha_mask);
+            composite_layer_occluded(base, creative, depth, alpha, occlusion, width, height, 5.0, &options)
+        };
+
+        // Clear occlusion is the plain composite
+        let plain = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+        assert_eq!(composite(&[0; 4]), plain);
+
+        // The layer moved to pixels 2 and 3; occlusion there applies, not under the mask
+        let frame = composite(&[255, 255, 255, 128]);
+        assert_eq!([frame[0], frame[4], frame[8], frame[12]], [0, 0, 0, 100]);
+
+        assert_eq!(composite(&[0; 2]), base_frame);
+    }
+
+    #[test]
+    fn test_u16_mask_keeps_gradient_precision() {
+        let (width, height) = (256u32, 16u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let creative_frame = vec![200u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let options = LayerOptions::new();
+
+        // A ramp finer than 8 bits lands on the correctly rounded level everywhere
+        let ramp: Vec<u16> = (0..pixel_count).map(|i| (i * 16) as u16).collect();
+        let frame = composite_layer_u16(
+            &base_frame, &creative_frame, &depth_map, &ramp, width, height, 5.0, &options,
+        );
+        let exact = |mask: u16| (mask as f64 * 200.0 / 65535.0).round() as u8;
+        assert!(ramp.iter().enumerate().all(|(i, &mask)| frame[i * 4] == exact(mask)));
+
+        // Cut to an 8-bit mask, coverage is rounded twice and some miss
+        let narrow: Vec<u8> = ramp.iter().map(|&mask| (mask as f32 / 257.0).round() as u8).collect();
+        let frame = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &narrow, width, height, 5.0, &options,
+        );
+        assert!(ramp.iter().enumerate().any(|(i, &mask)| frame[i * 4] != exact(mask)));
+
+        // 8-bit masks widened to 16 bits blend the same
+        let wide: Vec<u16> = narrow.iter().map(|&mask| mask as u16 * 257).collect();
+        let wide_frame = composite_layer_u16(
+            &base_frame, &creative_frame, &depth_map, &wide, width, height, 5.0, &options,
+        );
+        assert_eq!(wide_frame, frame);
+    }
+
+    #[test]
+    fn test_out_of_memory_degrades_frame() {
+        let (width, height) = (64u32, 32u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![40u8; pixel_count * 4];
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 233) as u8).collect();
+        let depth_map = vec![10.0f32; pixel_count];
+        let mut alpha_mask = vec![0u8; pixel_count];
+        alpha_mask[8 * 64..24 * 64].fill(200);
+
+        let mut options = LayerOptions::new();
+        options.set_transform(1.5, -2.25, 1.1);
+        let composite = |options: &LayerOptions, failing: u64| {
+            memory::fail_allocations(failing);
+            composite_layer(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, options,
+            )
+        };
+        let expected = composite(&options, 0);
+        let failures = allocation_failures();
+
+        // Fallible allocations in order: result, band creative and mask, then
+        // the single-row retry
+        assert!(composite(&options, 0b1).is_empty());
+        assert_eq!(composite(&options, 0b110), expected);
+        assert_eq!(composite(&options, 0b11110), base_frame);
+        assert!(allocation_failures() >= failures + 7);
+
+        #[cfg(feature = "effects")]
+        {
+            let mut effects = EffectChain::new();
+            effects.add_grain(0.2);
+            let mut with_effects = options.clone();
+            with_effects.set_effects(&effects);
+            assert_ne!(composite(&with_effects, 0), expected);
+            assert_eq!(composite(&with_effects, 0b1), expected);
+        }
+    }
+
+    #[test]
+    fn test_warm_up_handles_any_size() {
+        let formats = || ["rgba", "i420", "nv12", "rgba16", "p010", "lut", "bogus"].map(String::from).to_vec();
+        warm_up(0, 0, formats());
+        warm_up(1, 1, formats());
+        warm_up(63, 35, formats());
+    }
+
+    /// The first real frame in a format needs no more memory than warming it
+    /// up did, so memory the warm-up grew covers it
+    #[test]
+    fn test_warmed_up_first_frame_needs_no_new_memory() {
+        let (width, height) = (64u32, 36u32);
+        let pixel_count = (width * height) as usize;
+        let planes = pixel_count + 32 * 18 * 2;
+        let first_frame = |format: &str| {
+            let creative_frame = vec![90u8; pixel_count * 4];
+            let (depth_map, alpha_mask) = (vec![10.0f32; pixel_count], vec![255u8; pixel_count]);
+            let (creative, depth, alpha) = (&creative_frame[..], &depth_map[..], &alpha_mask[..]);
+            let mut options = LayerOptions::new();
+            options.set_transform(2.0, -1.0, 1.2);
+            let (transfer, nits) = (HdrTransfer::Pq, 203.0);
+            match format {
+                "rgba" | "lut" => {
+                    if format == "lut" {
+                        options.set_lut(&lut::Lut3d::identity(WARM_UP_LUT_SIZE));
+                    }
+                    let base_frame = vec![0u8; pixel_count * 4];
+                    composite_layer(&base_frame, creative, depth, alpha, width, height, 5.0, &options);
+                }
+                "i420" => {
+                    let (base_frame, matrix) = (vec![16u8; planes], YuvMatrix::Bt709);
+                    let (yuv, w, h) = (YuvFormat::I420, width, height);
+                    composite_layer_yuv(&base_frame, yuv, matrix, creative, depth, alpha, w, h, 5.0, &options);
+                }
+                "rgba16" => {
+                    let base_frame = vec![0u16; pixel_count * 4];
+                    let (w, h) = (width, height);
+                    composite_layer_rgba16(
+                        &base_frame, creative, depth, alpha, w, h, 5.0, transfer, nits, &options,
+                    );
+                }
+                _ => {
+                    let (base_frame, matrix) = (vec![0u16; planes], YuvMatrix::Bt2020);
+                    let (w, h) = (width, height);
+                    composite_layer_p010(
+                        &base_frame, matrix, creative, depth, alpha, w, h, 5.0, transfer, nits, &options,
+                    );
+                }
+            };
+        };
+        for format in ["rgba", "lut", "i420", "rgba16", "p010"] {
+            let start = memory::reset_thread_peak();
+            warm_up(width, height, vec![format.to_string()]);
+            let warmed = memory::thread_peak() - start;
+            memory::reset_thread_peak();
+            first_frame(format);
+            let first = memory::thread_peak() - start;
+            assert!(first > 0 && first <= warmed, "{}: {} over {}", format, first, warmed);
+        }
+    }
+
     #[test]
     fn test_get_version_info() {
         let version = get_version_info();
@@ -0,0 +1,226 @@
+//! Scene lighting estimation and relighting of creatives to match
+
+use std::borrow::Cow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::UNIT_FROM_U8;
+use crate::color::{linear_to_srgb, srgb_to_linear};
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::try_to_mut;
+
+/// Linear light of the mid-grey a neutrally lit scene averages to
+const MID_GREY: f32 = 0.18;
+
+/// Per-channel gain limits, so a near-black or blown-out region cannot
+/// crush or wash out the creative
+const MIN_GAIN: f32 = 0.25;
+const MAX_GAIN: f32 = 2.0;
+
+/// Irradiance constants of order-2 spherical harmonics (Ramamoorthi and
+/// Hanrahan, "An Efficient Representation for Irradiance Environment Maps")
+const SH_C1: f32 = 0.429043;
+const SH_C2: f32 = 0.511664;
+const SH_C3: f32 = 0.743125;
+const SH_C4: f32 = 0.886227;
+const SH_C5: f32 = 0.247708;
+
+/// Light falling on a placement, as a per-channel gain in linear light over
+/// neutral lighting: 1 leaves the creative as authored, below 1 dims it and
+/// unequal channels tint it
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneLight {
+    gain: [f32; 3],
+}
+
+impl Default for SceneLight {
+    fn default() -> Self {
+        Self { gain: [1.0; 3] }
+    }
+}
+
+#[wasm_bindgen]
+impl SceneLight {
+    /// Neutral light, leaving creatives unchanged
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SceneLight {
+        SceneLight::default()
+    }
+
+    /// Estimate the light on the placement from the base frame (sRGB RGBA)
+    /// under `region`, a frame-sized mask weighting each pixel: its mean
+    /// linear color over mid-grey. A region on no pixel gives neutral light
+    pub fn estimate(base_frame: &[u8], region: &[u8], width: u32, height: u32) -> SceneLight {
+        let pixel_count = (width * height) as usize;
+        if base_frame.len() < pixel_count * 4 || region.len() < pixel_count {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return SceneLight::default();
+        }
+        let (mut sum, mut weight) = ([0.0f32; 3], 0.0f32);
+        for (pixel, &coverage) in base_frame.chunks_exact(4).zip(&region[..pixel_count]) {
+            if coverage == 0 {
+                continue;
+            }
+            let w = UNIT_FROM_U8[coverage as usize];
+            for (total, &code) in sum.iter_mut().zip(pixel) {
+                *total += srgb_to_linear(UNIT_FROM_U8[code as usize]) * w;
+            }
+            weight += w;
+        }
+        if weight == 0.0 {
+            return SceneLight::default();
+        }
+        SceneLight::from_gain(sum.map(|total| total / weight / MID_GREY))
+    }
+
+    /// Light from the upstream lighting model's order-2 spherical harmonics:
+    /// 27 coefficients, `L00, L1-1, L10, L11, L2-2, L2-1, L20, L21, L22` for
+    /// red, then green, then blue, of radiance where 1 is neutral. The
+    /// irradiance is taken on a surface facing `normal_x, normal_y, normal_z`
+    /// (normalized here; `0, 0, 1` faces the camera). None unless there are
+    /// 27 finite coefficients and a nonzero normal
+    pub fn from_spherical_harmonics(
+        coefficients: &[f32],
+        normal_x: f32,
+        normal_y: f32,
+        normal_z: f32,
+    ) -> Option<SceneLight> {
+        let length = (normal_x * normal_x + normal_y * normal_y + normal_z * normal_z).sqrt();
+        if coefficients.len() != 27 || !coefficients.iter().all(|c| c.is_finite()) || !length.is_normal() {
+            return None;
+        }
+        let (x, y, z) = (normal_x / length, normal_y / length, normal_z / length);
+        let irradiance = |l: &[f32]| {
+            SH_C1 * l[8] * (x * x - y * y) + SH_C3 * l[6] * z * z + SH_C4 * l[0] - SH_C5 * l[6]
+                + 2.0 * SH_C1 * (l[4] * x * y + l[7] * x * z + l[5] * y * z)
+                + 2.0 * SH_C2 * (l[3] * x + l[1] * y + l[2] * z)
+        };
+        // Neutral radiance of 1 from every direction gives irradiance pi
+        let gain = [0, 1, 2].map(|c| irradiance(&coefficients[c * 9..c * 9 + 9]) / std::f32::consts::PI);
+        Some(SceneLight::from_gain(gain))
+    }
+
+    /// Relative luminance of the light, 1 for neutral
+    pub fn luminance(&self) -> f32 {
+        let [r, g, b] = self.gain;
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    /// Color of the light with its luminance divided out, `[r, g, b]`
+    pub fn tint(&self) -> Vec<f32> {
+        let luminance = self.luminance();
+        self.gain.iter().map(|gain| gain / luminance).collect()
+    }
+}
+
+impl SceneLight {
+    fn from_gain(gain: [f32; 3]) -> SceneLight {
+        SceneLight { gain: gain.map(|gain| gain.clamp(MIN_GAIN, MAX_GAIN)) }
+    }
+
+    /// Gain with `strength` of the way from neutral, geometrically so that
+    /// halving and doubling weigh the same
+    pub(crate) fn gain(&self, strength: f32) -> [f32; 3] {
+        let strength = strength.clamp(0.0, 1.0);
+        self.gain.map(|gain| gain.powf(strength))
+    }
+}
+
+/// RGBA `creative` lit by `gain` in linear light, borrowed through without
+/// one and relit in place when already owned. Short of memory the creative
+/// goes unlit
+pub(crate) fn relight_creative<'a>(mut creative: Cow<'a, [u8]>, gain: Option<[f32; 3]>) -> Cow<'a, [u8]> {
+    let Some(gain) = gain.filter(|gain| *gain != [1.0; 3]) else {
+        return creative;
+    };
+    let tables = gain.map(|gain| {
+        let mut table = [0u8; 256];
+        for (code, value) in table.iter_mut().enumerate() {
+            let light = srgb_to_linear(UNIT_FROM_U8[code]) * gain;
+            *value = (linear_to_srgb(light.min(1.0)) * 255.0 + 0.5) as u8;
+        }
+        table
+    });
+    match try_to_mut(&mut creative) {
+        Some(pixels) => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                for (channel, table) in pixel[..3].iter_mut().zip(&tables) {
+                    *channel = table[*channel as usize];
+                }
+            }
+        }
+        None => {
+            log_error("WASM compositor: Out of memory, creative left unlit");
+            health::degrade(DegradationTier::Reduced);
+        }
+    }
+    creative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 0.02)
+    }
+
+    #[test]
+    fn test_estimates_light_under_the_region() {
+        // Mid-grey on the left, a dim blue-ish wall on the right
+        let (width, height) = (4u32, 1u32);
+        let base_frame = [118u8, 118, 118, 255, 118, 118, 118, 255, 40, 40, 90, 255, 40, 40, 90, 255];
+        let neutral = SceneLight::estimate(&base_frame, &[255, 255, 0, 0], width, height);
+        assert!(close(&neutral.gain, &[1.0; 3]), "{:?}", neutral);
+        let wall = SceneLight::estimate(&base_frame, &[0, 0, 255, 128], width, height);
+        assert!(wall.luminance() < 0.5 && wall.tint()[2] > wall.tint()[0]);
+        // Gains are limited on dark regions
+        let black = SceneLight::estimate(&[0, 0, 0, 255], &[255], 1, 1);
+        assert_eq!(black.gain, [MIN_GAIN; 3]);
+        assert_eq!(SceneLight::estimate(&base_frame, &[0; 4], width, height), SceneLight::new());
+    }
+
+    #[test]
+    fn test_spherical_harmonics_light() {
+        // Uniform radiance 1: L00 = 2 sqrt(pi) on every channel
+        let mut ambient = [0.0f32; 27];
+        for c in 0..3 {
+            ambient[c * 9] = 2.0 * std::f32::consts::PI.sqrt();
+        }
+        let light = SceneLight::from_spherical_harmonics(&ambient, 0.0, 0.0, 2.0).unwrap();
+        assert!(close(&light.gain, &[1.0; 3]), "{:?}", light);
+
+        // Extra light from +z reaches a surface facing the camera, not one
+        // facing away
+        let mut frontal = ambient;
+        frontal[2] = 0.5;
+        let facing = SceneLight::from_spherical_harmonics(&frontal, 0.0, 0.0, 1.0).unwrap();
+        let away = SceneLight::from_spherical_harmonics(&frontal, 0.0, 0.0, -1.0).unwrap();
+        assert!(facing.luminance() > 1.0 && away.luminance() < 1.0);
+
+        assert!(SceneLight::from_spherical_harmonics(&ambient[..26], 0.0, 0.0, 1.0).is_none());
+        assert!(SceneLight::from_spherical_harmonics(&ambient, 0.0, 0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn test_layer_light_relights_the_creative() {
+        use crate::{composite_layer, LayerOptions};
+
+        let base_frame = [0u8, 0, 0, 255].repeat(2);
+        let creative_frame = [255u8, 118, 0, 255].repeat(2);
+        let (depth_map, alpha_mask) = ([10.0f32; 2], [255u8; 2]);
+        let dim = SceneLight::from_gain([0.5; 3]);
+        let mut options = LayerOptions::new();
+        options.set_scene_light(&dim, 1.0);
+        let (base, creative) = (&base_frame[..], &creative_frame[..]);
+        let frame = composite_layer(base, creative, &depth_map, &alpha_mask, 2, 1, 5.0, &options);
+        // Half the light: 255 to 188 and mid-grey to 85 in sRGB
+        assert_eq!(frame[..4], [188, 85, 0, 255]);
+
+        options.set_scene_light(&dim, 0.0);
+        let frame = composite_layer(base, creative, &depth_map, &alpha_mask, 2, 1, 5.0, &options);
+        assert_eq!(frame, creative_frame);
+    }
+}
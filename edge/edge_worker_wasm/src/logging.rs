@@ -0,0 +1,143 @@
+//! Structured log records carrying correlation ids
+
+use std::cell::RefCell;
+
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Level {
+    Info,
+    Error,
+}
+
+/// Ids joining a record to player and backend logs; empty means unknown
+#[derive(Clone, Debug, Default, PartialEq)]
+struct LogContext {
+    stream_id: String,
+    segment_id: String,
+    pts: Option<f64>,
+    placement_id: String,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<LogContext> = RefCell::new(LogContext::default());
+    static SINK: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+/// Send every log record, a JSON string, to `sink` instead of the console
+#[wasm_bindgen]
+pub fn set_log_sink(sink: js_sys::Function) {
+    SINK.with(|current| *current.borrow_mut() = Some(sink));
+}
+
+#[wasm_bindgen]
+pub fn clear_log_sink() {
+    SINK.with(|current| *current.borrow_mut() = None);
+}
+
+/// Stream and segment every following record belongs to
+#[wasm_bindgen]
+pub fn set_log_context(stream_id: &str, segment_id: &str) {
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        context.stream_id = stream_id.to_string();
+        context.segment_id = segment_id.to_string();
+    });
+}
+
+/// Frame and placement being composited, set once per frame
+#[wasm_bindgen]
+pub fn set_log_frame(pts_seconds: f64, placement_id: &str) {
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        context.pts = Some(pts_seconds);
+        context.placement_id = placement_id.to_string();
+    });
+}
+
+/// JSON string literal of `value`
+fn quoted(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `{"level","message","stream_id","segment_id","pts","placement_id"}`,
+/// unknown ids as null
+fn record(level: Level, message: &str, context: &LogContext) -> String {
+    let id = |id: &str| if id.is_empty() { "null".to_string() } else { quoted(id) };
+    format!(
+        r#"{{"level":"{}","message":{},"stream_id":{},"segment_id":{},"pts":{},"placement_id":{}}}"#,
+        match level {
+            Level::Info => "info",
+            Level::Error => "error",
+        },
+        quoted(message),
+        id(&context.stream_id),
+        id(&context.segment_id),
+        context.pts.map_or("null".to_string(), |pts| pts.to_string()),
+        id(&context.placement_id),
+    )
+}
+
+/// Emit a record through the registered sink, or the console without one
+pub(crate) fn emit(level: Level, message: &str) {
+    let record = CONTEXT.with(|context| record(level, message, &context.borrow()));
+    let sent = SINK.with(|sink| match &*sink.borrow() {
+        Some(sink) => {
+            let _ = sink.call1(&JsValue::NULL, &JsValue::from_str(&record));
+            true
+        }
+        None => false,
+    });
+    if !sent {
+        crate::log(&record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_carries_context() {
+        let mut context = LogContext::default();
+        assert_eq!(
+            record(Level::Info, "Processing frame", &context),
+            concat!(
+                r#"{"level":"info","message":"Processing frame","stream_id":null,"segment_id":null,"#,
+                r#""pts":null,"placement_id":null}"#
+            )
+        );
+
+        context.stream_id = "live-7".to_string();
+        context.segment_id = "seg-0042".to_string();
+        context.pts = Some(12.5);
+        context.placement_id = "billboard".to_string();
+        assert_eq!(
+            record(Level::Error, "bad \"size\"\n", &context),
+            concat!(
+                r#"{"level":"error","message":"bad \"size\"\u000a","stream_id":"live-7","#,
+                r#""segment_id":"seg-0042","pts":12.5,"placement_id":"billboard"}"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_context_setters() {
+        set_log_context("stream", "segment");
+        set_log_frame(3.0, "placement");
+        let context = CONTEXT.with(|context| context.borrow().clone());
+        assert_eq!((context.stream_id.as_str(), context.pts), ("stream", Some(3.0)));
+        assert_eq!(context.placement_id, "placement");
+    }
+}
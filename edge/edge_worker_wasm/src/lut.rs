@@ -0,0 +1,261 @@
+//! 3D LUT color grading of creatives, from `.cube` files
+
+use std::borrow::Cow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::try_to_mut;
+
+/// Largest `LUT_3D_SIZE` accepted, what grading tools export at most
+const MAX_CUBE_SIZE: usize = 65;
+
+/// A 3D color lookup table, as grading tools export it to tone footage:
+/// `size` steps along each axis over the input domain, sampled with
+/// trilinear interpolation
+#[wasm_bindgen]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut3d {
+    title: String,
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// Output colors, red varying fastest, then green, then blue
+    table: Vec<[f32; 3]>,
+}
+
+#[wasm_bindgen]
+impl Lut3d {
+    /// Parse the text of an Adobe/Resolve `.cube` file; the error names the
+    /// offending line
+    pub fn from_cube(cube: &str) -> Result<Lut3d, JsValue> {
+        Lut3d::parse(cube).map_err(|error| {
+            log_error(&format!("WASM compositor: LUT rejected, {}", error));
+            JsValue::from_str(&error)
+        })
+    }
+
+    pub fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Steps along each axis
+    pub fn size(&self) -> u32 {
+        self.size as u32
+    }
+}
+
+impl Lut3d {
+    /// The LUT grading every color to itself, `size` steps along each axis
+    pub(crate) fn identity(size: usize) -> Lut3d {
+        let step = |i: usize| i as f32 / (size - 1) as f32;
+        let entry = |i: usize| [step(i % size), step(i / size % size), step(i / size / size)];
+        let table = (0..size * size * size).map(entry);
+        Lut3d {
+            title: String::new(),
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table: table.collect(),
+        }
+    }
+
+    fn parse(cube: &str) -> Result<Lut3d, String> {
+        let mut lut = Lut3d {
+            title: String::new(),
+            size: 0,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table: Vec::new(),
+        };
+        for (number, line) in cube.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let at = |message: &str| format!("line {}: {}", number, message);
+            let bound = || triple(rest).ok_or_else(|| at(&format!("{} needs 3 numbers", keyword)));
+            match keyword {
+                "TITLE" => lut.title = rest.trim_matches('"').to_string(),
+                "LUT_3D_SIZE" => {
+                    let size = rest.parse().map_err(|_| at("LUT_3D_SIZE is not a whole number"))?;
+                    if !(2..=MAX_CUBE_SIZE).contains(&size) {
+                        return Err(at(&format!("LUT_3D_SIZE {} outside 2..={}", size, MAX_CUBE_SIZE)));
+                    }
+                    lut.size = size;
+                    lut.table.reserve_exact(size * size * size);
+                }
+                "LUT_1D_SIZE" => return Err(at("1D LUTs are not supported")),
+                "DOMAIN_MIN" => lut.domain_min = bound()?,
+                "DOMAIN_MAX" => lut.domain_max = bound()?,
+                // Resolve's form of a domain shared by all three channels
+                "LUT_3D_INPUT_RANGE" => {
+                    let [min, max] = pair(rest).ok_or_else(|| at("LUT_3D_INPUT_RANGE needs 2 numbers"))?;
+                    (lut.domain_min, lut.domain_max) = ([min; 3], [max; 3]);
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    // Other tools' keywords carry nothing the lookup needs
+                }
+                _ => {
+                    let color = triple(line).ok_or_else(|| at("a table entry needs 3 numbers"))?;
+                    if lut.size == 0 {
+                        return Err(at("table entries before LUT_3D_SIZE"));
+                    }
+                    lut.table.push(color);
+                }
+            }
+        }
+        if lut.size == 0 {
+            return Err("no LUT_3D_SIZE".to_string());
+        }
+        let expected = lut.size * lut.size * lut.size;
+        if lut.table.len() != expected {
+            let entries = lut.table.len();
+            return Err(format!("{} table entries, LUT_3D_SIZE {} needs {}", entries, lut.size, expected));
+        }
+        if (0..3).any(|c| lut.domain_min[c] >= lut.domain_max[c]) {
+            return Err("DOMAIN_MIN is not below DOMAIN_MAX".to_string());
+        }
+        Ok(lut)
+    }
+
+    /// Lower table step and the weight of the one above it for each 8-bit
+    /// code value of each channel
+    fn taps(&self) -> [[(usize, f32); 256]; 3] {
+        let last = (self.size - 1) as f32;
+        [0, 1, 2].map(|c| {
+            let span = self.domain_max[c] - self.domain_min[c];
+            let mut taps = [(0, 0.0); 256];
+            for (code, tap) in taps.iter_mut().enumerate() {
+                let position = ((code as f32 / 255.0 - self.domain_min[c]) / span).clamp(0.0, 1.0) * last;
+                let step = (position as usize).min(self.size - 2);
+                *tap = (step, position - step as f32);
+            }
+            taps
+        })
+    }
+
+    /// Grade RGBA pixels in place, alpha untouched
+    fn grade(&self, pixels: &mut [u8]) {
+        let taps = self.taps();
+        let (n, table) = (self.size, &self.table);
+        for pixel in pixels.chunks_exact_mut(4) {
+            let [(r, fr), (g, fg), (b, fb)] = [0, 1, 2].map(|c| taps[c][pixel[c] as usize]);
+            let at = |dr: usize, dg: usize, db: usize| table[(r + dr) + n * ((g + dg) + n * (b + db))];
+            for (c, channel) in pixel[..3].iter_mut().enumerate() {
+                let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+                let along_r = |dg, db| lerp(at(0, dg, db)[c], at(1, dg, db)[c], fr);
+                let along_g = |db| lerp(along_r(0, db), along_r(1, db), fg);
+                let value = lerp(along_g(0), along_g(1), fb);
+                *channel = (value.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+            }
+        }
+    }
+}
+
+fn numbers<const N: usize>(text: &str) -> Option<[f32; N]> {
+    let mut values = [0.0; N];
+    let mut fields = text.split_whitespace();
+    for value in &mut values {
+        *value = fields.next()?.parse().ok().filter(|value: &f32| value.is_finite())?;
+    }
+    fields.next().is_none().then_some(values)
+}
+
+fn triple(text: &str) -> Option<[f32; 3]> {
+    numbers(text)
+}
+
+fn pair(text: &str) -> Option<[f32; 2]> {
+    numbers(text)
+}
+
+/// RGBA `creative` graded by `lut`, borrowed through without one and graded
+/// in place when already owned. Short of memory the creative goes ungraded
+pub(crate) fn grade_creative<'a>(mut creative: Cow<'a, [u8]>, lut: Option<&Lut3d>) -> Cow<'a, [u8]> {
+    let Some(lut) = lut else {
+        return creative;
+    };
+    match try_to_mut(&mut creative) {
+        Some(pixels) => lut.grade(pixels),
+        None => {
+            log_error("WASM compositor: Out of memory, creative left ungraded");
+            health::degrade(DegradationTier::Reduced);
+        }
+    }
+    creative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `.cube` of `size` steps with each entry given by `color`
+    fn cube(size: usize, color: impl Fn([f32; 3]) -> [f32; 3]) -> String {
+        let mut text = format!("# Generated\nTITLE \"Test grade\"\nLUT_3D_SIZE {}\n\n", size);
+        let step = |i: usize| i as f32 / (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let [r, g, b] = color([step(r), step(g), step(b)]);
+                    text.push_str(&format!("{:.6} {:.6} {:.6}\n", r, g, b));
+                }
+            }
+        }
+        text
+    }
+
+    fn graded(lut: &Lut3d, pixels: &[u8]) -> Vec<u8> {
+        grade_creative(Cow::Borrowed(pixels), Some(lut)).into_owned()
+    }
+
+    #[test]
+    fn test_parse_cube() {
+        let identity = Lut3d::parse(&cube(17, |rgb| rgb)).unwrap();
+        assert_eq!((identity.title(), identity.size()), ("Test grade".to_string(), 17));
+        let pixels = [0u8, 37, 128, 200, 255, 254, 1, 9];
+        assert_eq!(graded(&identity, &pixels), pixels);
+
+        // Channels swapped and inverted, interpolated between steps
+        let swapped = Lut3d::parse(&cube(2, |[r, g, b]| [1.0 - r, b, g])).unwrap();
+        assert_eq!(graded(&swapped, &[200, 10, 90, 77]), [55, 90, 10, 77]);
+
+        // A domain wider than 0..1 only reaches halfway at full scale
+        let wide = format!("DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n{}", cube(2, |rgb| rgb));
+        assert_eq!(graded(&Lut3d::parse(&wide).unwrap(), &[255, 0, 128, 255]), [128, 0, 64, 255]);
+    }
+
+    #[test]
+    fn test_rejects_malformed_cubes() {
+        let error = |text: &str| Lut3d::parse(text).unwrap_err();
+        assert_eq!(error("TITLE \"Empty\"\n"), "no LUT_3D_SIZE");
+        assert_eq!(error("LUT_1D_SIZE 1024\n"), "line 1: 1D LUTs are not supported");
+        assert_eq!(error("LUT_3D_SIZE 200\n"), "line 1: LUT_3D_SIZE 200 outside 2..=65");
+        assert_eq!(error("0 0 0\nLUT_3D_SIZE 2\n"), "line 1: table entries before LUT_3D_SIZE");
+        assert_eq!(error("LUT_3D_SIZE 2\n0 0\n"), "line 2: a table entry needs 3 numbers");
+        let short = cube(2, |rgb| rgb).replace("1.000000 1.000000 1.000000\n", "");
+        assert_eq!(error(&short), "7 table entries, LUT_3D_SIZE 2 needs 8");
+    }
+
+    #[test]
+    fn test_layer_lut_grades_before_blending() {
+        use crate::{composite_layer, LayerOptions};
+
+        let base_frame = [0u8, 0, 0, 255].repeat(2);
+        let creative_frame = [200u8, 100, 50, 255].repeat(2);
+        let (depth_map, alpha_mask) = ([10.0f32; 2], [255u8, 0]);
+        // Warms the creative: red up, blue down
+        let warm = Lut3d::parse(&cube(9, |[r, g, b]| [(r * 1.1).min(1.0), g, b * 0.8])).unwrap();
+        let mut options = LayerOptions::new();
+        options.set_lut(&warm);
+        let (base, creative) = (&base_frame[..], &creative_frame[..]);
+        let frame = composite_layer(base, creative, &depth_map, &alpha_mask, 2, 1, 5.0, &options);
+        assert_eq!(frame, [220, 100, 40, 255, 0, 0, 0, 255]);
+
+        options.clear_lut();
+        let frame = composite_layer(base, creative, &depth_map, &alpha_mask, 2, 1, 5.0, &options);
+        assert_eq!(frame[..4], creative_frame[..4]);
+    }
+}
@@ -0,0 +1,141 @@
+//! `${NAME}` macro substitution for dynamic creative text
+
+use std::collections::BTreeMap;
+
+use wasm_bindgen::prelude::*;
+
+/// Resolves `${PRICE}`, `${CITY}` and `${COUNTDOWN:mm:ss}` style tokens in
+/// creative text from values supplied at init or per segment
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct MacroResolver {
+    values: BTreeMap<String, String>,
+    segment_values: BTreeMap<String, String>,
+    countdown_target: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl MacroResolver {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> MacroResolver {
+        MacroResolver::default()
+    }
+
+    /// Value that applies for the whole session
+    pub fn set_value(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    /// Value that overrides the session value until the next segment
+    pub fn set_segment_value(&mut self, name: &str, value: &str) {
+        self.segment_values.insert(name.to_string(), value.to_string());
+    }
+
+    /// Drop per-segment overrides when a new segment starts
+    pub fn begin_segment(&mut self) {
+        self.segment_values.clear();
+    }
+
+    /// Time (same clock as `resolve`'s `now_seconds`) the countdown reaches zero
+    pub fn set_countdown_target(&mut self, target_seconds: f64) {
+        self.countdown_target = Some(target_seconds);
+    }
+
+    /// Substitute every macro in `template` for the frame at `now_seconds`;
+    /// unknown macros are left in place so they are easy to spot
+    pub fn resolve(&self, template: &str, now_seconds: f64) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("${") {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            out.push_str(&rest[..start]);
+            let token = &rest[start + 2..start + len];
+            match self.lookup(token, now_seconds) {
+                Some(value) => out.push_str(&value),
+                None => out.push_str(&rest[start..=start + len]),
+            }
+            rest = &rest[start + len + 1..];
+        }
+
+        out.push_str(rest);
+        out
+    }
+}
+
+impl MacroResolver {
+    fn lookup(&self, token: &str, now_seconds: f64) -> Option<String> {
+        let (name, format) = match token.split_once(':') {
+            Some((name, format)) => (name, Some(format)),
+            None => (token, None),
+        };
+
+        if name == "COUNTDOWN" {
+            let target = self.countdown_target?;
+            return Some(format_countdown(target - now_seconds, format.unwrap_or("mm:ss")));
+        }
+
+        self.segment_values
+            .get(name)
+            .or_else(|| self.values.get(name))
+            .cloned()
+    }
+}
+
+/// Format remaining seconds (clamped at zero) using `hh`, `mm` and `ss`
+/// fields; the largest field present absorbs the remaining units
+pub fn format_countdown(remaining_seconds: f64, format: &str) -> String {
+    let total = remaining_seconds.max(0.0).ceil() as u64;
+    let has_hours = format.contains("hh");
+    let has_minutes = format.contains("mm");
+
+    let hours = total / 3600;
+    let minutes = if has_hours { (total / 60) % 60 } else { total / 60 };
+    let seconds = if has_minutes || has_hours { total % 60 } else { total };
+
+    format
+        .replace("hh", &format!("{:02}", hours))
+        .replace("mm", &format!("{:02}", minutes))
+        .replace("ss", &format!("{:02}", seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_values_and_segment_overrides() {
+        let mut resolver = MacroResolver::new();
+        resolver.set_value("PRICE", "$9.99");
+        resolver.set_value("CITY", "Austin");
+
+        assert_eq!(resolver.resolve("Only ${PRICE} in ${CITY}!", 0.0), "Only $9.99 in Austin!");
+
+        resolver.set_segment_value("CITY", "Denver");
+        assert_eq!(resolver.resolve("${CITY}", 0.0), "Denver");
+
+        resolver.begin_segment();
+        assert_eq!(resolver.resolve("${CITY}", 0.0), "Austin");
+    }
+
+    #[test]
+    fn test_unknown_and_unterminated_macros_are_kept() {
+        let resolver = MacroResolver::new();
+        assert_eq!(resolver.resolve("${NOPE} and ${COUNTDOWN}", 0.0), "${NOPE} and ${COUNTDOWN}");
+        assert_eq!(resolver.resolve("broken ${PRICE", 0.0), "broken ${PRICE");
+    }
+
+    #[test]
+    fn test_countdown_formats() {
+        let mut resolver = MacroResolver::new();
+        resolver.set_countdown_target(300.0);
+
+        // 4 minutes 32 seconds remaining
+        assert_eq!(resolver.resolve("starts in ${COUNTDOWN:mm:ss}", 28.0), "starts in 04:32");
+        assert_eq!(format_countdown(3725.0, "hh:mm:ss"), "01:02:05");
+        assert_eq!(format_countdown(3725.0, "mm:ss"), "62:05");
+        assert_eq!(format_countdown(-5.0, "mm:ss"), "00:00");
+    }
+}
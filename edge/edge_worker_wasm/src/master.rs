@@ -0,0 +1,114 @@
+//! Session-wide opacity over every layer, and the operations kill switch
+
+use std::cell::Cell;
+
+use wasm_bindgen::prelude::*;
+
+use crate::calibration::now_ms;
+use crate::log_info;
+
+/// Fade of the kill switch: from `from` down to zero over `duration_ms`
+/// of wall time from `start_ms`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Kill {
+    from: f32,
+    start_ms: f64,
+    duration_ms: f64,
+}
+
+thread_local! {
+    static OPACITY: Cell<f32> = const { Cell::new(1.0) };
+    static KILL: Cell<Option<Kill>> = const { Cell::new(None) };
+}
+
+/// Factor on every layer's opacity at wall time `now`
+fn factor_at(now: f64) -> f32 {
+    let opacity = OPACITY.with(Cell::get);
+    match KILL.with(Cell::get) {
+        Some(kill) if kill.duration_ms > 0.0 => {
+            let t = ((now - kill.start_ms) / kill.duration_ms).clamp(0.0, 1.0) as f32;
+            // Never brighter than the master opacity, even if it drops mid-fade
+            (kill.from * (1.0 - t)).min(opacity)
+        }
+        Some(_) => 0.0,
+        None => opacity,
+    }
+}
+
+fn kill_at(fade_seconds: f64, now: f64) {
+    if KILL.with(Cell::get).is_some() {
+        return;
+    }
+    let duration_ms = if fade_seconds.is_finite() { fade_seconds.max(0.0) * 1000.0 } else { 0.0 };
+    let kill = Kill { from: factor_at(now), start_ms: now, duration_ms };
+    KILL.with(|cell| cell.set(Some(kill)));
+}
+
+/// Factor on every layer's opacity for the frame being composited
+pub(crate) fn master_factor() -> f32 {
+    factor_at(now_ms())
+}
+
+/// 0..1 opacity multiplied into every layer of the session, for turning
+/// all placements down at once
+#[wasm_bindgen]
+pub fn set_master_opacity(opacity: f32) {
+    let opacity = if opacity.is_nan() { 1.0 } else { opacity.clamp(0.0, 1.0) };
+    OPACITY.with(|cell| cell.set(opacity));
+}
+
+/// Emergency removal of every placement: all layers fade out over
+/// `fade_seconds` of wall time instead of popping off, and stay hidden
+/// until `restore_placements`. A second call while in effect changes nothing
+#[wasm_bindgen]
+pub fn kill_placements(fade_seconds: f64) {
+    log_info("WASM compositor: Kill switch engaged, fading out all placements");
+    kill_at(fade_seconds, now_ms());
+}
+
+/// Lift the kill switch; layers return at the master opacity
+#[wasm_bindgen]
+pub fn restore_placements() {
+    KILL.with(|cell| cell.set(None));
+}
+
+/// Whether the kill switch is engaged, fading or done
+#[wasm_bindgen]
+pub fn placements_killed() -> bool {
+    KILL.with(Cell::get).is_some()
+}
+
+/// Factor every layer's opacity is multiplied by right now
+#[wasm_bindgen]
+pub fn master_opacity() -> f32 {
+    master_factor()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kill_fades_from_master_opacity() {
+        set_master_opacity(0.8);
+        assert_eq!(factor_at(0.0), 0.8);
+        kill_at(2.0, 1000.0);
+        assert!(placements_killed());
+        let factors = [1000.0, 2000.0, 3000.0, 9000.0].map(factor_at);
+        let expected = [0.8, 0.4, 0.0, 0.0];
+        assert!(factors.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-6), "{:?}", factors);
+
+        // Engaging again does not restart the fade
+        kill_at(2.0, 3000.0);
+        assert_eq!(factor_at(3000.0), 0.0);
+        restore_placements();
+        assert_eq!(factor_at(3000.0), 0.8);
+
+        // Without a fade it is immediate
+        kill_at(0.0, 5000.0);
+        assert_eq!(factor_at(5000.0), 0.0);
+        restore_placements();
+        set_master_opacity(f32::NAN);
+        assert_eq!(factor_at(0.0), 1.0);
+    }
+}
@@ -0,0 +1,384 @@
+//! Allocation counters per subsystem for leak detection in long soaks
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::ptr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use wasm_bindgen::prelude::*;
+
+/// Part of the worker an allocation is charged to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Subsystem {
+    Other = 0,
+    Composite = 1,
+    Warp = 2,
+    #[cfg(feature = "effects")]
+    Effects = 3,
+    Streaming = 4,
+}
+
+const SUBSYSTEM_NAMES: [&str; 5] = ["other", "composite", "warp", "effects", "streaming"];
+
+/// Smallest prefix in front of each allocation, holding the subsystem tag so
+/// frees are charged to whoever allocated, even after the buffer was handed
+/// to JS or another subsystem
+const HEADER: usize = 16;
+
+struct Counters {
+    allocs: AtomicU64,
+    frees: AtomicU64,
+    live_bytes: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: Counters = Counters {
+    allocs: AtomicU64::new(0),
+    frees: AtomicU64::new(0),
+    live_bytes: AtomicUsize::new(0),
+};
+
+static COUNTERS: [Counters; 5] = [ZERO; 5];
+
+/// Frame-sized allocations refused because wasm memory could not grow
+static ALLOCATION_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static CURRENT: Cell<u8> = const { Cell::new(Subsystem::Other as u8) };
+    /// Test hook: bit `n` fails the `n`th upcoming fallible allocation
+    #[cfg(test)]
+    static FAILING: Cell<u64> = const { Cell::new(0) };
+    /// Test hook: bytes this thread has live, and their high-water mark
+    #[cfg(test)]
+    static THREAD_BYTES: Cell<(isize, isize)> = const { Cell::new((0, 0)) };
+}
+
+/// Test hook: count `change` bytes against this thread
+#[cfg(test)]
+fn charge_thread(change: isize) {
+    let _ = THREAD_BYTES.try_with(|bytes| {
+        let (live, peak) = bytes.get();
+        bytes.set((live + change, peak.max(live + change)));
+    });
+}
+
+/// Charges allocations on this thread to `subsystem` until dropped
+pub(crate) struct Scope {
+    previous: u8,
+}
+
+impl Scope {
+    pub fn enter(subsystem: Subsystem) -> Scope {
+        let previous = CURRENT.with(|current| current.replace(subsystem as u8));
+        Scope { previous }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.previous));
+    }
+}
+
+/// System allocator that tags and counts every allocation; two relaxed
+/// atomics and a small header per block, negligible next to frame buffers
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+impl CountingAllocator {
+    /// Header size keeping the caller's alignment, and the layout of the
+    /// underlying block
+    fn outer(layout: Layout) -> Option<(usize, Layout)> {
+        let offset = layout.align().max(HEADER);
+        let size = layout.size().checked_add(offset)?;
+        Some((offset, Layout::from_size_align(size, layout.align()).ok()?))
+    }
+
+    unsafe fn tag(&self, block: *mut u8, offset: usize, size: usize) -> *mut u8 {
+        if block.is_null() {
+            return block;
+        }
+        // Thread-locals may be gone during thread teardown
+        let subsystem = CURRENT.try_with(Cell::get).unwrap_or(Subsystem::Other as u8);
+        block.write(subsystem);
+        let counters = &COUNTERS[subsystem as usize];
+        counters.allocs.fetch_add(1, Ordering::Relaxed);
+        counters.live_bytes.fetch_add(size, Ordering::Relaxed);
+        #[cfg(test)]
+        charge_thread(size as isize);
+        block.add(offset)
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match Self::outer(layout) {
+            Some((offset, outer)) => self.tag(System.alloc(outer), offset, layout.size()),
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        match Self::outer(layout) {
+            Some((offset, outer)) => self.tag(System.alloc_zeroed(outer), offset, layout.size()),
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some((offset, outer)) = Self::outer(layout) else {
+            return;
+        };
+        let block = ptr.sub(offset);
+        let counters = &COUNTERS[block.read() as usize];
+        counters.frees.fetch_add(1, Ordering::Relaxed);
+        counters.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        #[cfg(test)]
+        charge_thread(-(layout.size() as isize));
+        System.dealloc(block, outer);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let Some((offset, outer)) = Self::outer(layout) else {
+            return ptr::null_mut();
+        };
+        let Some(new_outer) = new_size.checked_add(offset) else {
+            return ptr::null_mut();
+        };
+        // The tag moves with the block, and stays with the original owner
+        let block = System.realloc(ptr.sub(offset), outer, new_outer);
+        if block.is_null() {
+            return block;
+        }
+        let counters = &COUNTERS[block.read() as usize];
+        counters.live_bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+        counters.live_bytes.fetch_add(new_size, Ordering::Relaxed);
+        #[cfg(test)]
+        charge_thread(new_size as isize - layout.size() as isize);
+        block.add(offset)
+    }
+}
+
+/// Empty buffer with room for `len` items, or None when memory cannot grow;
+/// used for frame-sized buffers so the caller can degrade instead of trapping
+fn try_with_capacity<T>(len: usize) -> Option<Vec<T>> {
+    let mut buffer = Vec::new();
+    #[cfg(test)]
+    let injected = FAILING.with(|failing| failing.replace(failing.get() >> 1)) & 1 == 1;
+    #[cfg(not(test))]
+    let injected = false;
+
+    if injected || buffer.try_reserve_exact(len).is_err() {
+        ALLOCATION_FAILURES.fetch_add(1, Ordering::Relaxed);
+        return None;
+    }
+    Some(buffer)
+}
+
+pub(crate) fn try_copy<T: Copy>(data: &[T]) -> Option<Vec<T>> {
+    let mut copy = try_with_capacity(data.len())?;
+    copy.extend_from_slice(data);
+    Some(copy)
+}
+
+/// `Cow::to_mut` that copies fallibly; None leaves `data` borrowed
+pub(crate) fn try_to_mut<'c, T: Copy>(data: &'c mut Cow<'_, [T]>) -> Option<&'c mut Vec<T>> {
+    if let Cow::Borrowed(borrowed) = data {
+        *data = Cow::Owned(try_copy(borrowed)?);
+    }
+    Some(data.to_mut())
+}
+
+/// Overwrite `buffer` with `data`, reusing its capacity and only allocating
+/// (fallibly) when it has to grow; false leaves the buffer empty
+pub(crate) fn try_copy_into<T: Copy>(buffer: &mut Vec<T>, data: &[T]) -> bool {
+    buffer.clear();
+    if buffer.capacity() < data.len() {
+        match try_copy(data) {
+            Some(copy) => *buffer = copy,
+            None => return false,
+        }
+    } else {
+        buffer.extend_from_slice(data);
+    }
+    true
+}
+
+/// Fallible `data.iter().map(convert).collect()`
+pub(crate) fn try_map<T: Copy, U>(data: &[T], convert: impl Fn(T) -> U) -> Option<Vec<U>> {
+    let mut converted = try_with_capacity(data.len())?;
+    converted.extend(data.iter().map(|&value| convert(value)));
+    Some(converted)
+}
+
+pub(crate) fn try_zeroed<T: Copy + Default>(len: usize) -> Option<Vec<T>> {
+    let mut buffer = try_with_capacity(len)?;
+    buffer.resize(len, T::default());
+    Some(buffer)
+}
+
+#[cfg(test)]
+pub(crate) fn fail_allocations(mask: u64) {
+    FAILING.with(|failing| failing.set(mask));
+}
+
+/// Test hook: restart this thread's high-water mark from the bytes it has
+/// live, which are returned
+#[cfg(test)]
+pub(crate) fn reset_thread_peak() -> isize {
+    THREAD_BYTES.with(|bytes| {
+        let (live, _) = bytes.get();
+        bytes.set((live, live));
+        live
+    })
+}
+
+/// Test hook: most bytes this thread had live since `reset_thread_peak`
+#[cfg(test)]
+pub(crate) fn thread_peak() -> isize {
+    THREAD_BYTES.with(|bytes| bytes.get().1)
+}
+
+/// Allocations the worker survived by degrading the frame: effects skipped,
+/// smaller warp bands, or the frame dropped (an empty result)
+#[wasm_bindgen]
+pub fn allocation_failures() -> u32 {
+    ALLOCATION_FAILURES.load(Ordering::Relaxed) as u32
+}
+
+/// Bytes currently allocated across all subsystems
+pub(crate) fn total_live_bytes() -> usize {
+    COUNTERS.iter().map(|counters| counters.live_bytes.load(Ordering::Relaxed)).sum()
+}
+
+/// Size of wasm linear memory, which live bytes approach under pressure;
+/// 0 natively, where there is no fixed heap
+pub(crate) fn heap_bytes() -> usize {
+    #[cfg(target_arch = "wasm32")]
+    return core::arch::wasm32::memory_size(0) * 65536;
+    #[cfg(not(target_arch = "wasm32"))]
+    0
+}
+
+/// Allocation and free counts and live bytes per subsystem, as JSON
+/// `{"composite": {"allocs", "frees", "live_bytes"}, ...}`; live bytes that
+/// keep growing across a soak point at the leaking subsystem
+#[wasm_bindgen]
+pub fn leak_report() -> String {
+    let entries: Vec<String> = SUBSYSTEM_NAMES
+        .iter()
+        .zip(COUNTERS.iter())
+        .map(|(name, counters)| {
+            format!(
+                r#""{}":{{"allocs":{},"frees":{},"live_bytes":{}}}"#,
+                name,
+                counters.allocs.load(Ordering::Relaxed),
+                counters.frees.load(Ordering::Relaxed),
+                counters.live_bytes.load(Ordering::Relaxed),
+            )
+        })
+        .collect();
+    format!("{{{}}}", entries.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{composite_layer, LayerOptions, SlabCompositor};
+
+    fn live_bytes(subsystem: Subsystem) -> usize {
+        COUNTERS[subsystem as usize].live_bytes.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn test_allocations_keep_alignment_and_contents() {
+        for align in [1, 8, 64, 4096] {
+            let layout = Layout::from_size_align(100, align).unwrap();
+            unsafe {
+                let block = ALLOCATOR.alloc(layout);
+                assert_eq!(block as usize % align, 0);
+                block.write_bytes(7, 100);
+
+                let grown = ALLOCATOR.realloc(block, layout, 5000);
+                assert_eq!(grown as usize % align, 0);
+                assert_eq!(*grown.add(99), 7);
+                ALLOCATOR.dealloc(grown, Layout::from_size_align(5000, align).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_report_lists_every_subsystem() {
+        let scope = Scope::enter(Subsystem::Streaming);
+        let buffer = vec![1u8; 1000];
+        drop(scope);
+
+        let report = leak_report();
+        for name in SUBSYSTEM_NAMES {
+            assert!(report.contains(&format!(r#""{}":{{"allocs":"#, name)), "{}", report);
+        }
+        assert!(report.starts_with('{') && report.ends_with('}'));
+        drop(buffer);
+    }
+
+    /// Soak: thousands of frames through every compositing path must leave
+    /// live memory where it was after warm-up. Counters are process wide, so
+    /// run alone: `cargo test --release -- --ignored --nocapture soak`
+    #[test]
+    #[ignore]
+    fn soak_memory_is_stable() {
+        let (width, height) = (640u32, 360u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 211) as u8).collect();
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 197) as u8).collect();
+        let depth_map = vec![10.0f32; pixel_count];
+        let mut alpha_mask = vec![0u8; pixel_count];
+        for y in 90..270 {
+            alpha_mask[y * width as usize + 160..y * width as usize + 480].fill(200);
+        }
+
+        let run = |frame: usize| {
+            let mut options = LayerOptions::new();
+            let step = frame as f32;
+            options.set_transform(step % 7.0 * 0.3, -(step % 5.0) * 0.2, 1.0 + step % 3.0 * 0.05);
+            #[cfg(feature = "effects")]
+            if frame.is_multiple_of(2) {
+                let mut effects = crate::EffectChain::new();
+                effects.add_grain(0.05);
+                options.set_effects(&effects);
+                options.set_frame_seed("soak", frame as f64 / 30.0);
+            }
+            let composited = composite_layer(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+            );
+            std::hint::black_box(composited);
+
+            let mut compositor =
+                SlabCompositor::new(&creative_frame, &alpha_mask, width, height, 5.0, &options);
+            let slab_pixels = 120 * width as usize;
+            for slab in 0..3 {
+                let pixels = slab * slab_pixels..(slab + 1) * slab_pixels;
+                let base_slab = &base_frame[pixels.start * 4..pixels.end * 4];
+                std::hint::black_box(compositor.composite_slab(base_slab, &depth_map[pixels]));
+            }
+        };
+
+        let subsystems = [
+            Subsystem::Composite,
+            Subsystem::Warp,
+            #[cfg(feature = "effects")]
+            Subsystem::Effects,
+            Subsystem::Streaming,
+        ];
+        (0..50).for_each(run);
+        let settled: Vec<usize> = subsystems.iter().map(|&s| live_bytes(s)).collect();
+        (50..5000).for_each(run);
+        let after: Vec<usize> = subsystems.iter().map(|&s| live_bytes(s)).collect();
+
+        println!("{}", leak_report());
+        assert_eq!(settled, after);
+    }
+}
@@ -0,0 +1,211 @@
+//! Mesh-warped placements for surfaces no single homography describes
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::{Homography, Rect};
+use crate::layer::LayerOptions;
+use crate::log_error;
+use crate::surfaces::{composite_surfaces, frame_hotspot, Mapping, Surface};
+
+/// How the creative is interpolated inside each mesh cell
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeshInterpolation {
+    /// Straight lines in the creative stay straight along cell edges; works
+    /// for any cell that does not fold over itself
+    #[default]
+    Bilinear = 0,
+    /// Each cell is a plane seen in perspective, for coarse meshes over
+    /// flat panels; cells that are not convex fall back to bilinear
+    Perspective = 1,
+}
+
+/// Creative warped over a grid of frame-space control points, for cloth
+/// banners and curved screens. Point `(i, j)` of a `columns x rows` mesh
+/// shows the creative at `(i / (columns - 1), j / (rows - 1))`; trackers
+/// update the points every frame
+#[wasm_bindgen]
+pub struct MeshWarp {
+    columns: usize,
+    rows: usize,
+    interpolation: MeshInterpolation,
+    cells: Vec<Surface>,
+    /// Boundary control points clockwise from the top-left
+    outline: Vec<(f32, f32)>,
+}
+
+#[wasm_bindgen]
+impl MeshWarp {
+    /// At least two points each way
+    #[wasm_bindgen(constructor)]
+    pub fn new(columns: u32, rows: u32, interpolation: MeshInterpolation) -> MeshWarp {
+        MeshWarp {
+            columns: columns.max(2) as usize,
+            rows: rows.max(2) as usize,
+            interpolation,
+            cells: Vec::new(),
+            outline: Vec::new(),
+        }
+    }
+
+    /// Control points for the coming frames, flat `[x, y]` in row-major
+    /// order. False, keeping the previous mesh, for the wrong number of
+    /// points or non-finite ones
+    pub fn set_points(&mut self, points: &[f32]) -> bool {
+        if points.len() != self.columns * self.rows * 2 || !points.iter().all(|p| p.is_finite()) {
+            log_error("WASM compositor: Mesh needs columns x rows finite points");
+            return false;
+        }
+
+        let point = |i: usize, j: usize| {
+            let k = (j * self.columns + i) * 2;
+            (points[k], points[k + 1])
+        };
+        let (cell_u, cell_v) = (1.0 / (self.columns - 1) as f32, 1.0 / (self.rows - 1) as f32);
+        self.cells.clear();
+        for j in 0..self.rows - 1 {
+            for i in 0..self.columns - 1 {
+                let corners = [point(i, j), point(i + 1, j), point(i + 1, j + 1), point(i, j + 1)];
+                let perspective = match self.interpolation {
+                    MeshInterpolation::Perspective => {
+                        Homography::square_to_quad(&corners).and_then(|map| map.invert())
+                    }
+                    MeshInterpolation::Bilinear => None,
+                };
+                let mapping = perspective.map_or(Mapping::Bilinear(corners), Mapping::Perspective);
+                let window = Rect::new(i as f32 * cell_u, j as f32 * cell_v, cell_u, cell_v);
+                self.cells.push(Surface::new(&corners, mapping, window));
+            }
+        }
+        let (last_column, last_row) = (self.columns - 1, self.rows - 1);
+        self.outline = (0..last_column)
+            .map(|i| point(i, 0))
+            .chain((0..last_row).map(|j| point(last_column, j)))
+            .chain((1..=last_column).rev().map(|i| point(i, last_row)))
+            .chain((1..=last_row).rev().map(|j| point(0, j)))
+            .collect();
+        true
+    }
+
+    /// Hotspot polygon (flat `[x, y, ...]`) of the mesh: its boundary
+    /// control points cut to the frame, in frame pixels. Empty before the
+    /// first `set_points`
+    pub fn hotspot(&self, width: u32, height: u32) -> Vec<f32> {
+        frame_hotspot(&self.outline, width, height)
+    }
+
+    /// Same as `SurfacePlacement::composite`, with a surface per mesh cell;
+    /// before the first `set_points` the frame passes through
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite(
+        &self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        creative_alpha: &[u8],
+        creative_width: u32,
+        creative_height: u32,
+        depth_map: &[f32],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        composite_surfaces(
+            &self.cells,
+            base_frame,
+            creative_frame,
+            creative_alpha,
+            creative_width,
+            creative_height,
+            depth_map,
+            width,
+            height,
+            creative_depth,
+            options,
+        )
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FilterMode;
+
+    /// Horizontal ramp creative, red channel equal to column * 32
+    fn ramp_creative() -> (Vec<u8>, Vec<u8>) {
+        let creative = (0..8u8).flat_map(|x| [x * 32, 0, 0, 255]).collect();
+        (creative, vec![255; 8])
+    }
+
+    #[test]
+    fn test_flat_mesh_matches_single_quad() {
+        let (width, height) = (16u32, 4u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let (creative, alpha) = ramp_creative();
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(FilterMode::Nearest);
+
+        let mut quad = crate::SurfacePlacement::new();
+        assert!(quad.add_surface(&[0.0, 0.0, 16.0, 0.0, 16.0, 4.0, 0.0, 4.0], 0.0, 0.0, 1.0, 1.0));
+        let expected = quad.composite(
+            &base_frame, &creative, &alpha, 8, 1, &depth_map, width, height, 5.0, &options,
+        );
+
+        // A 3x2 mesh over the same rectangle, in both interpolations
+        let points = [0.0, 0.0, 8.0, 0.0, 16.0, 0.0, 0.0, 4.0, 8.0, 4.0, 16.0, 4.0];
+        for interpolation in [MeshInterpolation::Bilinear, MeshInterpolation::Perspective] {
+            let mut mesh = MeshWarp::new(3, 2, interpolation);
+            assert!(mesh.set_points(&points));
+            assert_eq!(mesh.cell_count(), 2);
+            let result = mesh.composite(
+                &base_frame, &creative, &alpha, 8, 1, &depth_map, width, height, 5.0, &options,
+            );
+            assert_eq!(result, expected, "{:?}", interpolation);
+        }
+    }
+
+    #[test]
+    fn test_bent_mesh_follows_control_points() {
+        let (width, height) = (16u32, 8u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let (creative, alpha) = ramp_creative();
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(FilterMode::Nearest);
+
+        // The middle column of points sags by 4 pixels, like a hanging banner
+        let mut mesh = MeshWarp::new(3, 2, MeshInterpolation::Bilinear);
+        assert!(mesh.set_points(&[0.0, 0.0, 8.0, 4.0, 16.0, 0.0, 0.0, 4.0, 8.0, 8.0, 16.0, 4.0]));
+        let result = mesh.composite(
+            &base_frame, &creative, &alpha, 8, 1, &depth_map, width, height, 5.0, &options,
+        );
+        let covered = |x: usize, y: usize| result[(y * width as usize + x) * 4 + 3] == 255;
+        assert!(!covered(8, 1));
+        assert!(covered(8, 6));
+        assert!(covered(0, 1));
+
+        // The hotspot follows the sag, pixel for pixel
+        let hotspot = mesh.hotspot(width, height);
+        assert_eq!(hotspot.len(), 6 * 2);
+        for (x, y) in (0..16).flat_map(|x| (0..8).map(move |y| (x, y))) {
+            let hit = crate::hotspot_contains(&hotspot, x as f32 + 0.5, y as f32 + 0.5);
+            assert_eq!(hit, covered(x, y), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_rejects_wrong_point_count() {
+        let mut mesh = MeshWarp::new(3, 3, MeshInterpolation::Bilinear);
+        assert!(!mesh.set_points(&[0.0; 12]));
+        assert!(!mesh.set_points(&[f32::NAN; 18]));
+        assert_eq!(mesh.cell_count(), 0);
+        assert!(mesh.hotspot(16, 16).is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+//! Directional blur of creatives along the scene's motion
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+use crate::effects::{Effect, EffectContext, EffectFrame};
+use crate::flags;
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::try_copy;
+
+/// Most samples taken along a pixel's motion; longer smears are sampled
+/// more sparsely (except in reference mode)
+const MAX_TAPS: usize = 24;
+
+/// Smears shorter than this, in creative pixels, are left sharp
+const MIN_SMEAR: f32 = 0.5;
+
+/// Blur along the motion of the surface in the frame, as a camera exposing
+/// for `shutter` of the frame interval would record it: `dx, dy` frame
+/// pixels per frame for the whole placement, or per pixel from `flow`
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize, Deserialize), serde(default))]
+pub struct MotionBlur {
+    pub dx: f32,
+    pub dy: f32,
+    /// Exposed share of the frame interval, 0.5 for a 180 degree shutter
+    pub shutter: f32,
+    /// Interleaved `[dx, dy]` per pixel of the frame-sized creative, used
+    /// instead of the uniform vector when it covers every pixel
+    #[cfg_attr(feature = "json", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub flow: Vec<f32>,
+}
+
+impl Default for MotionBlur {
+    fn default() -> Self {
+        Self { dx: 0.0, dy: 0.0, shutter: 0.5, flow: Vec::new() }
+    }
+}
+
+impl Effect for MotionBlur {
+    fn apply(&self, frame: &mut EffectFrame, context: &EffectContext) {
+        let pixel_count = frame.width * frame.height;
+        let flow = (self.flow.len() >= pixel_count * 2).then_some(&self.flow[..]);
+        // Frame motion over the exposure, in creative pixels
+        let exposure = self.shutter.clamp(0.0, 1.0) / context.scale.max(f32::EPSILON);
+        let smear = |i: usize| {
+            let (dx, dy) = flow.map_or((self.dx, self.dy), |flow| (flow[i * 2], flow[i * 2 + 1]));
+            if dx.is_finite() && dy.is_finite() {
+                (dx * exposure, dy * exposure)
+            } else {
+                (0.0, 0.0)
+            }
+        };
+        let (sx, sy) = smear(0);
+        if flow.is_none() && sx.hypot(sy) < MIN_SMEAR {
+            return;
+        }
+        let copies = (try_copy(&frame.creative[..pixel_count * 4]), try_copy(&frame.alpha[..pixel_count]));
+        let (Some(creative), Some(alpha)) = copies else {
+            log_error("WASM compositor: Out of memory, motion blur skipped");
+            health::degrade(DegradationTier::Reduced);
+            return;
+        };
+        let max_taps = if flags::reference_mode() { usize::MAX } else { MAX_TAPS };
+        let (w, h) = (frame.width, frame.height);
+
+        // Coverage-weighted bilinear sample; outside the creative is empty
+        let sample = |x: f32, y: f32, color: &mut [f32; 4]| -> f32 {
+            let (x, y) = (x - 0.5, y - 0.5);
+            let (x0, y0) = (x.floor(), y.floor());
+            let (fx, fy) = (x - x0, y - y0);
+            let mut coverage = 0.0;
+            let corners = [(0, 0, (1.0 - fx) * (1.0 - fy)), (1, 0, fx * (1.0 - fy)), (0, 1, (1.0 - fx) * fy)];
+            for (ox, oy, weight) in corners.into_iter().chain([(1, 1, fx * fy)]) {
+                let (sx, sy) = (x0 as i64 + ox, y0 as i64 + oy);
+                if weight == 0.0 || sx < 0 || sy < 0 || sx >= w as i64 || sy >= h as i64 {
+                    continue;
+                }
+                let i = sy as usize * w + sx as usize;
+                let weight = weight * alpha[i] as f32;
+                for (total, &value) in color.iter_mut().zip(&creative[i * 4..i * 4 + 4]) {
+                    *total += value as f32 * weight;
+                }
+                coverage += weight;
+            }
+            coverage
+        };
+
+        for y in 0..h {
+            for x in 0..w {
+                let i = y * w + x;
+                if !frame.renders(i) {
+                    continue;
+                }
+                let (sx, sy) = smear(i);
+                let length = sx.hypot(sy);
+                if length < MIN_SMEAR {
+                    continue;
+                }
+                // Samples spread evenly over the exposure, centered on the pixel
+                let taps = (length.ceil() as usize + 1).min(max_taps);
+                let (mut color, mut coverage) = ([0.0f32; 4], 0.0f32);
+                for tap in 0..taps {
+                    let t = (tap as f32 + 0.5) / taps as f32 - 0.5;
+                    coverage += sample(x as f32 + 0.5 + sx * t, y as f32 + 0.5 + sy * t, &mut color);
+                }
+                frame.alpha[i] = (coverage / taps as f32 + 0.5) as u16;
+                if coverage > 0.0 {
+                    for (value, total) in frame.creative[i * 4..i * 4 + 4].iter_mut().zip(color) {
+                        *value = (total / coverage + 0.5).min(255.0) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blur(motion: &MotionBlur, creative: &mut [u8], alpha: &mut [u16], width: usize, scale: f32) {
+        let height = alpha.len() / width;
+        let mut frame = EffectFrame { creative, alpha, width, height, checkerboard: None };
+        motion.apply(&mut frame, &EffectContext { seed: 0, scale });
+    }
+
+    #[test]
+    fn test_blurs_along_the_motion() {
+        // A white bar one pixel wide, moving 8 pixels a frame to the right
+        let (width, height) = (16usize, 3usize);
+        let mut creative = [0u8, 0, 0, 255].repeat(width * height);
+        for y in 0..height {
+            creative[(y * width + 8) * 4..][..3].fill(255);
+        }
+        let mut alpha = vec![65535u16; width * height];
+        let pan = MotionBlur { dx: 8.0, ..MotionBlur::default() };
+        let sharp = creative.clone();
+        blur(&pan, &mut creative, &mut alpha, width, 1.0);
+        // Smeared over the 4 pixels a 180 degree shutter exposes, not up or down
+        let row: Vec<u8> = (0..width).map(|x| creative[(width + x) * 4]).collect();
+        assert_eq!(row[..6], [0; 6]);
+        assert!(row[6..11].iter().all(|&value| value > 0 && value < 255), "{:?}", row);
+        assert_eq!(row[12..], [0; 4]);
+        assert_eq!(creative[..width * 4], creative[width * 4..width * 8]);
+        // Coverage only fades where the smear reaches past the creative
+        assert!(alpha[width + 4..width + 12].iter().all(|&alpha| alpha == 65535));
+        assert!(alpha[width] < 65535);
+
+        // Twice the layer scale halves the smear; slow motion stays sharp
+        let mut halved = sharp.clone();
+        blur(&pan, &mut halved, &mut vec![65535u16; width * height], width, 2.0);
+        let spread = |frame: &[u8]| (0..width).filter(|&x| frame[(width + x) * 4] > 0).count();
+        assert!(spread(&halved) < spread(&creative));
+        let mut slow = sharp.clone();
+        blur(&MotionBlur { dx: 0.8, ..MotionBlur::default() }, &mut slow, &mut alpha, width, 1.0);
+        assert_eq!(slow, sharp);
+    }
+
+    #[test]
+    fn test_flow_field_blurs_per_pixel_and_the_edges() {
+        // Only the left half moves, vertically
+        let (width, height) = (4usize, 4usize);
+        let mut flow = vec![0.0f32; width * height * 2];
+        for y in 0..height {
+            for x in 0..2 {
+                flow[(y * width + x) * 2 + 1] = 6.0;
+            }
+        }
+        let mut creative = [200u8, 100, 50, 255].repeat(width * height);
+        let mut alpha = vec![65535u16; width * height];
+        let motion = MotionBlur { flow, ..MotionBlur::default() };
+        blur(&motion, &mut creative, &mut alpha, width, 1.0);
+        // Smearing past the top and bottom edges fades coverage, not color
+        assert!(alpha[0] < 65535 && alpha[width * 3] < 65535);
+        assert_eq!(alpha[3], 65535);
+        assert!(creative.chunks_exact(4).all(|pixel| pixel == [200, 100, 50, 255]));
+    }
+}
@@ -0,0 +1,123 @@
+//! Estimating the footage's noise level around a placement
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::{mask_bounds, Rect};
+use crate::log_error;
+
+/// Least margin around the placement sampled for noise, in pixels
+const MIN_MARGIN: f32 = 8.0;
+
+/// Histogram resolution of filter responses, in code values, and its range
+const RESPONSE_STEP: f32 = 0.25;
+const RESPONSE_BINS: usize = 1024;
+
+/// Standard deviation of the base frame's luma noise, in 8-bit code values,
+/// around the placement in `alpha_mask`: over its bounds grown by a quarter
+/// of their larger side (at least 8 pixels). Uses the median response of
+/// Immerkaer's noise filter, which cancels smooth gradients and, being a
+/// median, mostly ignores edges and texture. 0 without a placement
+#[wasm_bindgen]
+pub fn estimate_noise(base_frame: &[u8], alpha_mask: &[u8], width: u32, height: u32) -> f32 {
+    let pixel_count = (width * height) as usize;
+    if base_frame.len() < pixel_count * 4 || alpha_mask.len() < pixel_count {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return 0.0;
+    }
+    let Some(bounds) = mask_bounds(alpha_mask, width, height) else {
+        return 0.0;
+    };
+    let margin = (bounds.width.max(bounds.height) * 0.25).max(MIN_MARGIN);
+    let (x, y) = (bounds.x - margin, bounds.y - margin);
+    let grown = Rect::new(x, y, bounds.width + margin * 2.0, bounds.height + margin * 2.0);
+    // The filter reads one pixel either side
+    let inner = Rect::new(1.0, 1.0, width as f32 - 2.0, height as f32 - 2.0);
+    let Some(region) = grown.intersection(&inner) else {
+        return 0.0;
+    };
+
+    let w = width as usize;
+    let luma = |x: usize, y: usize| {
+        let p = &base_frame[(y * w + x) * 4..];
+        0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32
+    };
+    let mut histogram = [0u32; RESPONSE_BINS];
+    let mut samples = 0u32;
+    let (x0, x1) = (region.x as usize, region.right() as usize);
+    for y in region.y as usize..region.bottom() as usize {
+        for x in x0..x1 {
+            // [1 -2 1; -2 4 -2; 1 -2 1]: the difference of two Laplacians
+            let corners = luma(x - 1, y - 1) + luma(x + 1, y - 1) + luma(x - 1, y + 1) + luma(x + 1, y + 1);
+            let sides = luma(x, y - 1) + luma(x - 1, y) + luma(x + 1, y) + luma(x, y + 1);
+            let response = (corners - 2.0 * sides + 4.0 * luma(x, y)).abs();
+            histogram[((response / RESPONSE_STEP) as usize).min(RESPONSE_BINS - 1)] += 1;
+            samples += 1;
+        }
+    }
+    if samples == 0 {
+        return 0.0;
+    }
+
+    let mut below = 0;
+    let median_bin = histogram.iter().position(|&count| {
+        below += count;
+        below * 2 >= samples
+    });
+    match median_bin {
+        // Mostly exact zeros: flat footage
+        None | Some(0) => 0.0,
+        Some(bin) => {
+            // The squared taps sum to 36, so noise of deviation s filters to
+            // deviation 6 s, and a normal's median magnitude is 0.6745 of that
+            let median = (bin as f32 + 0.5) * RESPONSE_STEP;
+            median / 0.6745 / 6.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::PlacementRng;
+
+    #[test]
+    fn test_estimates_noise_around_the_placement() {
+        let (width, height) = (64u32, 64u32);
+        let mut alpha_mask = vec![0u8; 64 * 64];
+        for y in 24..40 {
+            alpha_mask[y * 64 + 24..y * 64 + 40].fill(255);
+        }
+        // Flat footage with a hard edge through it is noiseless
+        let mut base_frame = vec![255u8; 64 * 64 * 4];
+        for pixel in base_frame.chunks_exact_mut(4).take(64 * 32) {
+            pixel[..3].fill(20);
+        }
+        assert_eq!(estimate_noise(&base_frame, &alpha_mask, width, height), 0.0);
+
+        // Uniform noise of +-12 has a deviation of 12 / sqrt(3), ~6.9
+        let mut rng = PlacementRng::from_seed(3);
+        let noisy: Vec<u8> = (0..64 * 64)
+            .flat_map(|_| {
+                let value = (128.0 + (rng.next_f32() * 2.0 - 1.0) * 12.0).round() as u8;
+                [value, value, value, 255]
+            })
+            .collect();
+        let sigma = estimate_noise(&noisy, &alpha_mask, width, height);
+        assert!((sigma - 6.93).abs() < 0.7, "{}", sigma);
+
+        assert_eq!(estimate_noise(&noisy, &[0; 64 * 64], width, height), 0.0);
+
+        #[cfg(feature = "effects")]
+        {
+            use crate::effects::{EffectChain, EffectSpec, Grain};
+
+            // Matched grain has the footage's deviation, scaled by intensity
+            let mut chain = EffectChain::new();
+            chain.add_matched_grain(&noisy, &alpha_mask, width, height, 0.5);
+            let EffectSpec::Grain(Grain { amount }) = chain.specs()[0] else {
+                panic!("no grain added");
+            };
+            assert!((amount * 255.0 / 3f32.sqrt() - sigma * 0.5).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! The compositor's depth test on its own, for occlusion analytics
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::Rect;
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::{try_zeroed, Scope, Subsystem};
+
+/// Whether a creative at `creative_depth` shows over scene geometry at
+/// `scene_depth`; NaN on either side fails, as unknown depth never reveals
+/// the layer. Every composite path tests depth through this
+#[inline]
+pub(crate) fn in_front(creative_depth: f32, scene_depth: f32) -> bool {
+    creative_depth < scene_depth
+}
+
+/// Share of a creative at `creative_depth` shown over `scene_depth`: the
+/// hard `in_front` test as 0 or 1, or with a positive `feather` a smoothstep
+/// across a band that wide centered on the scene depth, so noisy depth fades
+/// the occlusion edge instead of making it pop. NaN still fails
+#[inline]
+pub(crate) fn visibility(creative_depth: f32, scene_depth: f32, feather: f32) -> f32 {
+    if feather <= 0.0 {
+        return in_front(creative_depth, scene_depth) as u8 as f32;
+    }
+    let t = (scene_depth - creative_depth) / feather + 0.5;
+    if t.is_nan() || t <= 0.0 {
+        return 0.0;
+    }
+    let t = t.min(1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Creative depth at pixel `i` of an optional per-pixel map, the scalar
+/// `creative_depth` where the map is empty or NaN
+#[inline]
+pub(crate) fn creative_depth_at(creative_depth_map: &[f32], i: usize, creative_depth: f32) -> f32 {
+    match creative_depth_map.get(i) {
+        Some(depth) if !depth.is_nan() => *depth,
+        _ => creative_depth,
+    }
+}
+
+/// Per-pixel visibility of a placement without compositing it: 255 where
+/// the creative would pass the depth test against `depth_map`, 0 where the
+/// scene occludes it or outside `region`, the flat `[x, y, w, h]` frame
+/// rectangle to test (the whole frame when empty), and in between across a
+/// `feather` band as `LayerOptions::set_depth_feather` sets it. The creative
+/// sits at `creative_depth`, or per pixel at `creative_depth_map` as in
+/// `composite_segment_depth_map`. Empty for invalid sizes or when memory
+/// runs out
+#[wasm_bindgen]
+pub fn compute_occlusion(
+    depth_map: &[f32],
+    creative_depth_map: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    feather: f32,
+    region: &[f32],
+) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    let (w, h) = (width as usize, height as usize);
+    if depth_map.len() < w * h || !(creative_depth_map.is_empty() || creative_depth_map.len() >= w * h) {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return Vec::new();
+    }
+    let (columns, rows) = match Rect::from_flat(region).first() {
+        Some(r) => {
+            let span = |start: f32, size: f32, limit: usize| {
+                let start = start.round().max(0.0) as usize;
+                start.min(limit)..(start + size.round().max(0.0) as usize).min(limit)
+            };
+            (span(r.x, r.width, w), span(r.y, r.height, h))
+        }
+        None => (0..w, 0..h),
+    };
+
+    let Some(mut mask) = try_zeroed::<u8>(w * h) else {
+        log_error("WASM compositor: Out of memory, occlusion mask dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    };
+    for y in rows {
+        for i in y * w + columns.start..y * w + columns.end {
+            let depth = creative_depth_at(creative_depth_map, i, creative_depth);
+            mask[i] = (visibility(depth, depth_map[i], feather) * 255.0 + 0.5) as u8;
+        }
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{composite_segment_depth_map, composite_with_depth};
+
+    #[test]
+    fn test_mask_matches_composite() {
+        let (width, height) = (4u32, 2u32);
+        let depth_map = [10.0f32, 1.0, 5.0, f32::NAN, 10.0, 10.0, 4.0, 6.0];
+        let base_frame = vec![0u8; 32];
+        let creative_frame = vec![255u8; 32];
+        let alpha_mask = vec![255u8; 8];
+        let (base, creative) = (&base_frame, &creative_frame);
+        let composited = composite_with_depth(base, creative, &depth_map, &alpha_mask, width, height, 5.0);
+        let mask = compute_occlusion(&depth_map, &[], width, height, 5.0, 0.0, &[]);
+        // The tie, the NaN and the nearer geometry all occlude
+        assert_eq!(mask, [255, 0, 0, 0, 255, 255, 0, 255]);
+        assert!(mask.iter().zip(composited.chunks_exact(4)).all(|(m, pixel)| *m == pixel[0]));
+
+        // Per-pixel creative depth, NaN falling back to the scalar
+        let creative_depths = [f32::NAN, 0.5, 4.0, 1.0, 20.0, f32::NAN, 3.0, 7.0];
+        let mask = compute_occlusion(&depth_map, &creative_depths, width, height, 5.0, 0.0, &[]);
+        assert_eq!(mask, [255, 255, 255, 0, 0, 255, 255, 0]);
+        let composited = composite_segment_depth_map(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, &creative_depths, width, height, 5.0,
+        );
+        assert!(mask.iter().zip(composited.chunks_exact(4)).all(|(m, pixel)| *m == pixel[0]));
+    }
+
+    #[test]
+    fn test_region_limits_the_mask() {
+        let depth_map = [10.0f32; 8];
+        let mask = compute_occlusion(&depth_map, &[], 4, 2, 5.0, 0.0, &[2.4, 1.0, 5.0, 3.0]);
+        assert_eq!(mask, [0, 0, 0, 0, 0, 0, 255, 255]);
+        assert!(compute_occlusion(&depth_map[..7], &[], 4, 2, 5.0, 0.0, &[]).is_empty());
+        assert!(compute_occlusion(&depth_map, &[1.0; 4], 4, 2, 5.0, 0.0, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_feather_fades_across_the_band() {
+        // Scene depth stepping through a band of 2 around the creative at 5
+        let depth_map = [3.0f32, 4.0, 4.5, 5.0, 5.5, 6.0, 7.0, f32::NAN];
+        let mask = compute_occlusion(&depth_map, &[], 8, 1, 5.0, 2.0, &[]);
+        assert_eq!(mask, [0, 0, 40, 128, 215, 255, 255, 0]);
+        assert!(mask.windows(2).take(6).all(|pair| pair[0] <= pair[1]));
+        // Without a band it is the hard test
+        assert_eq!(visibility(5.0, 5.0, 0.0), 0.0);
+        assert_eq!(visibility(5.0, 5.0, -1.0), 0.0);
+        assert_eq!(visibility(4.9, 5.0, 0.0), 1.0);
+    }
+}
@@ -0,0 +1,196 @@
+//! Bit-exact parity between the wasm and native builds
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::blend::BlendPrecision;
+use crate::compile_plan;
+use crate::flags::{with_flag, REFERENCE_MODE};
+use crate::log_error;
+use crate::test_support::{checksum, Inputs, CASES, HEIGHT, PLAN_CASES, WIDTH};
+
+/// Blends every layer vector runs through, each its own code path
+const PRECISIONS: [(BlendPrecision, &str); 3] = [
+    (BlendPrecision::Integer, "integer"),
+    (BlendPrecision::FixedPoint, "fixed_point"),
+    (BlendPrecision::Float, "float"),
+];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct ParityHash {
+    name: String,
+    hash: String,
+}
+
+/// What one build made of the parity vectors
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ParityReport {
+    target: String,
+    vectors: Vec<ParityHash>,
+}
+
+#[derive(Serialize)]
+struct Divergence<'a> {
+    name: &'a str,
+    ours: &'a str,
+    theirs: &'a str,
+}
+
+#[derive(Serialize)]
+struct Comparison<'a> {
+    target: &'a str,
+    other: &'a str,
+    matched: usize,
+    diverged: Vec<Divergence<'a>>,
+    /// Vectors only one of the builds ran
+    missing: Vec<&'a str>,
+    unknown: Vec<&'a str>,
+}
+
+/// The build this is: SIMD128 wasm blends differently from plain wasm
+fn target() -> &'static str {
+    if cfg!(all(target_arch = "wasm32", target_feature = "simd128")) {
+        "wasm32+simd128"
+    } else if cfg!(target_arch = "wasm32") {
+        "wasm32"
+    } else {
+        "native"
+    }
+}
+
+impl ParityReport {
+    /// Hash the conformance inputs through every layer vector at each
+    /// blend precision, the plan vectors and, in reference mode, the layer
+    /// vectors again
+    fn run() -> ParityReport {
+        let inputs = Inputs::new();
+        let mut vectors = Vec::new();
+        let mut add = |name: String, frame: Vec<u8>| vectors.push(ParityHash { name, hash: checksum(&frame) });
+        for case in &CASES {
+            for (precision, precision_name) in PRECISIONS {
+                let mut options = case.options();
+                options.set_blend_precision(precision);
+                add(format!("{}/{}", case.name, precision_name), inputs.composite(&options));
+            }
+        }
+        for case in &PLAN_CASES {
+            if let Ok(plan) = compile_plan(case.manifest, WIDTH, HEIGHT) {
+                add(case.name.to_string(), inputs.composite_plan(&plan, case.pts_seconds));
+            }
+        }
+        with_flag(REFERENCE_MODE, true, || {
+            for case in &CASES {
+                add(format!("{}/reference", case.name), inputs.composite(&case.options()));
+            }
+        });
+        ParityReport { target: target().to_string(), vectors }
+    }
+}
+
+/// This build's output hash for every parity vector, JSON, to check against
+/// another build with `check_parity`
+#[wasm_bindgen]
+pub fn parity_report() -> String {
+    serde_json::to_string(&ParityReport::run()).unwrap_or_default()
+}
+
+/// Result of checking another build's parity report against this build
+#[wasm_bindgen]
+pub struct ParityCheck {
+    identical: bool,
+    report: String,
+}
+
+#[wasm_bindgen]
+impl ParityCheck {
+    /// Every vector ran on both builds and came out bit-identical
+    pub fn identical(&self) -> bool {
+        self.identical
+    }
+
+    /// Both targets, the vectors that matched and, by name, those that
+    /// diverged or ran on only one build, as JSON
+    pub fn report(&self) -> String {
+        self.report.clone()
+    }
+}
+
+impl ParityCheck {
+    /// Check `report`, from `parity_report` on another build, against this
+    /// build; an error means it is not a parity report
+    pub fn compare(report: &str) -> Result<ParityCheck, String> {
+        let theirs: ParityReport = serde_json::from_str(report).map_err(|error| error.to_string())?;
+        let ours = ParityReport::run();
+        let mut comparison = Comparison {
+            target: &ours.target,
+            other: &theirs.target,
+            matched: 0,
+            diverged: Vec::new(),
+            missing: Vec::new(),
+            unknown: Vec::new(),
+        };
+        for vector in &ours.vectors {
+            match theirs.vectors.iter().find(|other| other.name == vector.name) {
+                Some(other) if other.hash == vector.hash => comparison.matched += 1,
+                Some(other) => comparison.diverged.push(Divergence {
+                    name: &vector.name,
+                    ours: &vector.hash,
+                    theirs: &other.hash,
+                }),
+                None => comparison.missing.push(&vector.name),
+            }
+        }
+        for vector in &theirs.vectors {
+            if !ours.vectors.iter().any(|other| other.name == vector.name) {
+                comparison.unknown.push(&vector.name);
+            }
+        }
+        let identical = comparison.diverged.is_empty()
+            && comparison.missing.is_empty()
+            && comparison.unknown.is_empty();
+        let report = serde_json::to_string(&comparison).unwrap_or_default();
+        Ok(ParityCheck { identical, report })
+    }
+}
+
+/// Check another build's `parity_report` against this build; the result
+/// says whether they agree bit for bit and lists every divergence
+#[wasm_bindgen]
+pub fn check_parity(report: &str) -> Result<ParityCheck, JsValue> {
+    ParityCheck::compare(report).map_err(|error| {
+        log_error(&format!("WASM compositor: Parity report rejected, {}", error));
+        JsValue::from_str(&error)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parity_reports_divergence() {
+        let report = parity_report();
+        let ours: ParityReport = serde_json::from_str(&report).unwrap();
+        assert_eq!(ours.target, "native");
+        assert_eq!(ours.vectors.len(), CASES.len() * 4 + PLAN_CASES.len());
+        // Reference mode overrides the blend precision
+        assert_eq!(ours.vectors[2].hash, ours.vectors[ours.vectors.len() - CASES.len()].hash);
+
+        let same = ParityCheck::compare(&report).unwrap();
+        assert!(same.identical(), "{}", same.report());
+
+        // Another build that blends one vector differently and skips one
+        let mut theirs = ParityReport { target: "wasm32+simd128".to_string(), vectors: ours.vectors.clone() };
+        theirs.vectors[0].hash = "0000000000000000".to_string();
+        let skipped = theirs.vectors.pop().unwrap();
+        let check = ParityCheck::compare(&serde_json::to_string(&theirs).unwrap()).unwrap();
+        assert!(!check.identical());
+        let comparison: serde_json::Value = serde_json::from_str(&check.report()).unwrap();
+        assert_eq!(comparison["other"], "wasm32+simd128");
+        assert_eq!(comparison["matched"], ours.vectors.len() - 2);
+        assert_eq!(comparison["diverged"][0]["name"], ours.vectors[0].name);
+        assert_eq!(comparison["missing"][0], skipped.name);
+
+        assert!(ParityCheck::compare("{\"vectors\": 3}").is_err());
+    }
+}
@@ -0,0 +1,688 @@
+//! Placement plans compiled ahead of playback from a sidecar manifest
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[cfg(feature = "effects")]
+use crate::effects::{EffectChain, EffectSpec};
+use crate::fusion::TransformFilter;
+use crate::geometry::Rect;
+#[cfg(feature = "effects")]
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::log_error;
+use crate::random::fnv1a;
+use crate::surfaces::{composite_surfaces, perspective_surface, Surface};
+
+/// Frame rate the sidecar packager assumes when the video's is not given
+const DEFAULT_FPS: f64 = 30.0;
+
+/// Leading bytes of a serialized plan
+const PLAN_MAGIC: &[u8; 4] = b"ISPL";
+/// Layout of `PlanRecord`; bump on any change so cached plans recompile
+const PLAN_FORMAT_VERSION: u16 = 1;
+/// Magic, format version, build features, payload checksum
+const PLAN_HEADER_BYTES: usize = 4 + 2 + 1 + 8;
+
+/// The parts of a `SidecarManifest` (render/sidecar_packager.py) playback
+/// needs; other fields are ignored
+#[derive(Deserialize)]
+struct Manifest {
+    manifest_id: String,
+    title_id: String,
+    opportunities: Vec<Opportunity>,
+    #[serde(default)]
+    video_metadata: VideoMetadata,
+}
+
+#[derive(Deserialize)]
+struct VideoMetadata {
+    #[serde(default = "default_fps")]
+    fps: f64,
+}
+
+impl Default for VideoMetadata {
+    fn default() -> VideoMetadata {
+        VideoMetadata { fps: DEFAULT_FPS }
+    }
+}
+
+fn default_fps() -> f64 {
+    DEFAULT_FPS
+}
+
+#[derive(Deserialize)]
+struct Opportunity {
+    opportunity_id: String,
+    #[serde(default)]
+    surface_id: String,
+    /// `[start, end)` frames
+    frame_range: (i64, i64),
+    /// Frame-pixel corners, clockwise from the creative's top-left
+    surface_coordinates: Vec<Vec<f32>>,
+    #[serde(default)]
+    prs_score: f64,
+    #[serde(default = "default_profile")]
+    smoothing_profile: String,
+    #[cfg(feature = "effects")]
+    #[serde(default)]
+    metadata: OpportunityMetadata,
+}
+
+fn default_profile() -> String {
+    "balanced".to_string()
+}
+
+#[cfg(feature = "effects")]
+#[derive(Default, Deserialize)]
+struct OpportunityMetadata {
+    /// Effects applied to the creative on this surface
+    #[serde(default)]
+    effects: Vec<EffectSpec>,
+}
+
+/// Changes live trafficking makes to a running plan: opportunities added,
+/// replaced whole by id, and removed by id
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlanDelta {
+    #[serde(default)]
+    add: Vec<Opportunity>,
+    #[serde(default)]
+    modify: Vec<Opportunity>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+/// One opportunity ready to composite
+struct Placement {
+    id: String,
+    surface_id: String,
+    start_frame: i64,
+    end_frame: i64,
+    /// The quad `surface` was solved from, kept for serialization
+    corners: [(f32, f32); 4],
+    surface: Surface,
+    smoothing_profile: String,
+    #[cfg(feature = "effects")]
+    effects: EffectChain,
+}
+
+/// A compiled plan as cached: placements keep their quads, re-solved on
+/// load, and effects in manifest form
+#[derive(Serialize, Deserialize)]
+struct PlanRecord {
+    manifest_id: String,
+    title_id: String,
+    width: u32,
+    height: u32,
+    fps: f64,
+    placements: Vec<PlacementRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlacementRecord {
+    id: String,
+    surface_id: String,
+    start_frame: i64,
+    end_frame: i64,
+    corners: [(f32, f32); 4],
+    smoothing_profile: String,
+    /// JSON, as bincode cannot read back internally tagged enums
+    effects: String,
+}
+
+/// A manifest validated and compiled once, ahead of playback: quads are
+/// solved into their perspective maps, effect chains built and placements
+/// ordered by start frame, so compositing a frame parses and checks nothing
+#[wasm_bindgen]
+pub struct CompiledPlan {
+    manifest_id: String,
+    title_id: String,
+    width: u32,
+    height: u32,
+    fps: f64,
+    placements: Vec<Placement>,
+}
+
+/// Validate and compile a sidecar manifest (JSON) for `width x height`
+/// frames. Every problem found is reported in the error, one per line
+#[wasm_bindgen]
+pub fn compile_plan(manifest: &str, width: u32, height: u32) -> Result<CompiledPlan, JsValue> {
+    CompiledPlan::compile(manifest, width, height).map_err(|errors| {
+        log_error(&format!("WASM compositor: Manifest rejected, {}", errors.join("; ")));
+        JsValue::from_str(&errors.join("\n"))
+    })
+}
+
+#[wasm_bindgen]
+impl CompiledPlan {
+    /// Composite every placement active at `pts_seconds` over `base_frame`
+    /// with the creative (`creative_width x creative_height` RGBA and mask),
+    /// each on its surface with its own effects, sampled per `options`. A
+    /// frame with no active placement passes through; an empty result
+    /// means the frame was dropped for memory
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_at(
+        &self,
+        pts_seconds: f64,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        creative_alpha: &[u8],
+        creative_width: u32,
+        creative_height: u32,
+        depth_map: &[f32],
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        let mut frame = base_frame.to_vec();
+        for placement in self.active(pts_seconds) {
+            let (width, height) = (self.width, self.height);
+            let (cw, ch) = (creative_width, creative_height);
+            let (creative, alpha) = (creative_frame, creative_alpha);
+            frame = placement.composite(
+                &frame, creative, alpha, cw, ch, depth_map, width, height, creative_depth, options,
+            );
+            if frame.is_empty() {
+                break;
+            }
+        }
+        frame
+    }
+
+    /// Ids of the opportunities active at `pts_seconds`
+    pub fn active_opportunities(&self, pts_seconds: f64) -> Vec<String> {
+        self.active(pts_seconds).map(|placement| placement.id.clone()).collect()
+    }
+
+    /// Surface of opportunity `id`, empty when the plan has none by the id
+    pub fn surface_id(&self, id: &str) -> String {
+        self.placement(id).map(|placement| placement.surface_id.clone()).unwrap_or_default()
+    }
+
+    /// Smoothing profile of opportunity `id`, for its `TransformFilter`
+    pub fn smoothing_profile(&self, id: &str) -> String {
+        self.placement(id).map(|placement| placement.smoothing_profile.clone()).unwrap_or_default()
+    }
+
+    pub fn opportunity_count(&self) -> usize {
+        self.placements.len()
+    }
+
+    pub fn manifest_id(&self) -> String {
+        self.manifest_id.clone()
+    }
+
+    pub fn title_id(&self) -> String {
+        self.title_id.clone()
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    /// Apply a delta (JSON `{"add": [...], "modify": [...], "remove": [...]}`,
+    /// opportunities in manifest form) between frames. Removals go first,
+    /// then replacements, then additions; the delta is validated whole and
+    /// either applies entirely or, with every problem reported one per line,
+    /// not at all, so the next frame never sees a half-updated plan
+    pub fn apply_delta(&mut self, delta: &str) -> Result<(), JsValue> {
+        self.apply(delta).map_err(|errors| {
+            log_error(&format!("WASM compositor: Plan delta rejected, {}", errors.join("; ")));
+            JsValue::from_str(&errors.join("\n"))
+        })
+    }
+
+    /// The plan as versioned, checksummed bytes for the host to cache (e.g.
+    /// in IndexedDB) and hand to `from_bytes` on a later visit
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode().unwrap_or_else(|error| {
+            log_error(&format!("WASM compositor: Plan not serialized, {}", error));
+            Vec::new()
+        })
+    }
+
+    /// Rehydrate a plan `to_bytes` produced without recompiling its
+    /// manifest. Bytes from another format version or build, or that fail
+    /// their checksum, are refused; the host then compiles afresh
+    pub fn from_bytes(bytes: &[u8]) -> Result<CompiledPlan, JsValue> {
+        CompiledPlan::decode(bytes).map_err(|error| {
+            log_error(&format!("WASM compositor: Cached plan refused, {}", error));
+            JsValue::from_str(&error)
+        })
+    }
+
+    /// Version of the `to_bytes` layout, for keying cached plans
+    pub fn format_version() -> u16 {
+        PLAN_FORMAT_VERSION
+    }
+}
+
+impl CompiledPlan {
+    fn compile(manifest: &str, width: u32, height: u32) -> Result<CompiledPlan, Vec<String>> {
+        let manifest: Manifest = serde_json::from_str(manifest).map_err(|error| vec![error.to_string()])?;
+        let mut errors = Vec::new();
+        if manifest.manifest_id.is_empty() {
+            errors.push("manifest_id is empty".to_string());
+        }
+        let fps = manifest.video_metadata.fps;
+        if !(fps.is_finite() && fps > 0.0) {
+            errors.push(format!("video_metadata.fps {} is not a frame rate", fps));
+        }
+        if width == 0 || height == 0 {
+            errors.push(format!("frame size {}x{} is empty", width, height));
+        }
+
+        let frame = Rect::new(0.0, 0.0, width as f32, height as f32);
+        let mut ids = BTreeSet::new();
+        let mut placements = Vec::new();
+        for (index, opportunity) in manifest.opportunities.into_iter().enumerate() {
+            let mut problem = |message: String| errors.push(format!("opportunities[{}]: {}", index, message));
+            if !ids.insert(opportunity.opportunity_id.clone()) {
+                problem(format!("duplicate opportunity_id {:?}", opportunity.opportunity_id));
+            }
+            placements.extend(Placement::compile(opportunity, &frame, &mut problem));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        placements.sort_by_key(|placement| placement.start_frame);
+        Ok(CompiledPlan {
+            manifest_id: manifest.manifest_id,
+            title_id: manifest.title_id,
+            width,
+            height,
+            fps,
+            placements,
+        })
+    }
+
+    fn apply(&mut self, delta: &str) -> Result<(), Vec<String>> {
+        let delta: PlanDelta = serde_json::from_str(delta).map_err(|error| vec![error.to_string()])?;
+        let frame = Rect::new(0.0, 0.0, self.width as f32, self.height as f32);
+        let mut errors = Vec::new();
+        let current = self.placements.iter().map(|placement| placement.id.clone());
+        let mut ids: BTreeSet<String> = current.collect();
+        for (index, id) in delta.remove.iter().enumerate() {
+            if !ids.remove(id) {
+                errors.push(format!("remove[{}]: no opportunity {:?}", index, id));
+            }
+        }
+        let mut replaced = BTreeSet::new();
+        let mut replacements = Vec::new();
+        for (index, opportunity) in delta.modify.into_iter().enumerate() {
+            let mut problem = |message: String| errors.push(format!("modify[{}]: {}", index, message));
+            let id = &opportunity.opportunity_id;
+            if !ids.contains(id) {
+                problem(format!("no opportunity {:?}", id));
+            } else if !replaced.insert(id.clone()) {
+                problem(format!("opportunity {:?} modified twice", id));
+            }
+            replacements.extend(Placement::compile(opportunity, &frame, &mut problem));
+        }
+        let mut additions = Vec::new();
+        for (index, opportunity) in delta.add.into_iter().enumerate() {
+            let mut problem = |message: String| errors.push(format!("add[{}]: {}", index, message));
+            if !ids.insert(opportunity.opportunity_id.clone()) {
+                problem(format!("duplicate opportunity_id {:?}", opportunity.opportunity_id));
+            }
+            additions.extend(Placement::compile(opportunity, &frame, &mut problem));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        self.placements.retain(|placement| !delta.remove.contains(&placement.id));
+        for replacement in replacements {
+            let current = self.placements.iter_mut().find(|placement| placement.id == replacement.id);
+            if let Some(placement) = current {
+                *placement = replacement;
+            }
+        }
+        self.placements.extend(additions);
+        self.placements.sort_by_key(|placement| placement.start_frame);
+        Ok(())
+    }
+
+    fn encode(&self) -> Result<Vec<u8>, String> {
+        let placements = self.placements.iter().map(|placement| PlacementRecord {
+            id: placement.id.clone(),
+            surface_id: placement.surface_id.clone(),
+            start_frame: placement.start_frame,
+            end_frame: placement.end_frame,
+            corners: placement.corners,
+            smoothing_profile: placement.smoothing_profile.clone(),
+            #[cfg(feature = "effects")]
+            effects: placement.effects.to_json(),
+            #[cfg(not(feature = "effects"))]
+            effects: String::new(),
+        });
+        let record = PlanRecord {
+            manifest_id: self.manifest_id.clone(),
+            title_id: self.title_id.clone(),
+            width: self.width,
+            height: self.height,
+            fps: self.fps,
+            placements: placements.collect(),
+        };
+        let payload = bincode::serialize(&record).map_err(|error| error.to_string())?;
+        let mut bytes = Vec::with_capacity(PLAN_HEADER_BYTES + payload.len());
+        bytes.extend_from_slice(PLAN_MAGIC);
+        bytes.extend_from_slice(&PLAN_FORMAT_VERSION.to_le_bytes());
+        bytes.push(build_flags());
+        bytes.extend_from_slice(&fnv1a(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<CompiledPlan, String> {
+        if bytes.len() < PLAN_HEADER_BYTES || &bytes[..4] != PLAN_MAGIC {
+            return Err("not a compiled plan".to_string());
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != PLAN_FORMAT_VERSION {
+            return Err(format!("plan format version {}, this build reads {}", version, PLAN_FORMAT_VERSION));
+        }
+        if bytes[6] != build_flags() {
+            return Err("plan was compiled by a build with other features".to_string());
+        }
+        let (checksum, payload) = bytes[7..].split_at(8);
+        if u64::from_le_bytes(checksum.try_into().unwrap_or_default()) != fnv1a(payload) {
+            return Err("plan checksum mismatch, the cached copy is corrupt".to_string());
+        }
+
+        let record: PlanRecord = bincode::deserialize(payload).map_err(|error| error.to_string())?;
+        let placements = record.placements.into_iter().map(|placement| {
+            let surface = quad_surface(&placement.corners)
+                .ok_or_else(|| format!("placement {:?} has no surface", placement.id))?;
+            #[cfg(feature = "effects")]
+            let effects = EffectChain::from_json(&placement.effects)
+                .ok_or_else(|| format!("placement {:?} has malformed effects", placement.id))?;
+            Ok(Placement {
+                id: placement.id,
+                surface_id: placement.surface_id,
+                start_frame: placement.start_frame,
+                end_frame: placement.end_frame,
+                corners: placement.corners,
+                surface,
+                smoothing_profile: placement.smoothing_profile,
+                #[cfg(feature = "effects")]
+                effects,
+            })
+        });
+        Ok(CompiledPlan {
+            manifest_id: record.manifest_id,
+            title_id: record.title_id,
+            width: record.width,
+            height: record.height,
+            fps: record.fps,
+            placements: placements.collect::<Result<_, String>>()?,
+        })
+    }
+
+    /// Placements whose frame range holds the frame presented at
+    /// `pts_seconds`, in start order
+    fn active(&self, pts_seconds: f64) -> impl Iterator<Item = &Placement> {
+        let frame = if pts_seconds.is_finite() { (pts_seconds * self.fps).round() as i64 } else { -1 };
+        self.placements
+            .iter()
+            .take_while(move |placement| placement.start_frame <= frame)
+            .filter(move |placement| frame < placement.end_frame)
+    }
+
+    /// Ids and frame quads of the placements active at `pts_seconds`
+    pub(crate) fn active_quads(&self, pts_seconds: f64) -> impl Iterator<Item = (&str, [(f32, f32); 4])> {
+        self.active(pts_seconds).map(|placement| (placement.id.as_str(), placement.corners))
+    }
+
+    pub(crate) fn frame_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn placement(&self, id: &str) -> Option<&Placement> {
+        self.placements.iter().find(|placement| placement.id == id)
+    }
+}
+
+/// Optional features that change a plan's layout
+fn build_flags() -> u8 {
+    cfg!(feature = "effects") as u8
+}
+
+/// Perspective surface showing the whole creative on a convex quad
+fn quad_surface(corners: &[(f32, f32); 4]) -> Option<Surface> {
+    perspective_surface(corners, Rect::new(0.0, 0.0, 1.0, 1.0))
+}
+
+/// Corners of a four-point polygon with finite coordinates
+fn quad_corners(points: &[Vec<f32>]) -> Option<[(f32, f32); 4]> {
+    let corners: Vec<(f32, f32)> = points
+        .iter()
+        .map(|point| match point[..] {
+            [x, y] if x.is_finite() && y.is_finite() => Some((x, y)),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    corners.try_into().ok()
+}
+
+/// Whether any part of the quad's bounding box is on the frame
+fn in_frame(corners: &[(f32, f32); 4], frame: &Rect) -> bool {
+    let (xs, ys) = (corners.map(|(x, _)| x), corners.map(|(_, y)| y));
+    let min = |values: [f32; 4]| values.into_iter().fold(f32::INFINITY, f32::min);
+    let max = |values: [f32; 4]| values.into_iter().fold(f32::NEG_INFINITY, f32::max);
+    let (left, top) = (min(xs), min(ys));
+    let bounds = Rect::new(left, top, max(xs) - left, max(ys) - top);
+    bounds.intersection(frame).is_some()
+}
+
+impl Placement {
+    /// Validate an opportunity and solve its surface, reporting each problem
+    /// to `problem`; None when the surface could not be solved
+    fn compile(
+        opportunity: Opportunity,
+        frame: &Rect,
+        problem: &mut impl FnMut(String),
+    ) -> Option<Placement> {
+        let (start_frame, end_frame) = opportunity.frame_range;
+        if start_frame < 0 || end_frame <= start_frame {
+            problem(format!("frame_range [{}, {}] is empty or negative", start_frame, end_frame));
+        }
+        if !(0.0..=100.0).contains(&opportunity.prs_score) {
+            problem(format!("prs_score {} is outside 0..100", opportunity.prs_score));
+        }
+        if TransformFilter::from_profile(&opportunity.smoothing_profile).is_none() {
+            problem(format!("unknown smoothing_profile {:?}", opportunity.smoothing_profile));
+        }
+        let surface = match quad_corners(&opportunity.surface_coordinates) {
+            None => {
+                problem("surface_coordinates needs four finite [x, y] corners".to_string());
+                None
+            }
+            Some(corners) if !in_frame(&corners, frame) => {
+                problem("surface_coordinates lie outside the frame".to_string());
+                None
+            }
+            Some(corners) => {
+                let surface = quad_surface(&corners);
+                if surface.is_none() {
+                    problem("surface_coordinates are not a convex quad".to_string());
+                }
+                surface.map(|surface| (corners, surface))
+            }
+        };
+        surface.map(|(corners, surface)| Placement {
+            id: opportunity.opportunity_id,
+            surface_id: opportunity.surface_id,
+            start_frame,
+            end_frame,
+            corners,
+            surface,
+            smoothing_profile: opportunity.smoothing_profile,
+            #[cfg(feature = "effects")]
+            effects: EffectChain::from_specs(opportunity.metadata.effects),
+        })
+    }
+
+    /// `composite_surfaces` on this placement's surface, over the output of
+    /// its effects when it has any
+    #[allow(clippy::too_many_arguments)]
+    fn composite(
+        &self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        creative_alpha: &[u8],
+        creative_width: u32,
+        creative_height: u32,
+        depth_map: &[f32],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        let surfaces = [self.surface];
+        let (cw, ch) = (creative_width, creative_height);
+        #[cfg(feature = "effects")]
+        if !self.effects.is_empty() {
+            let (w, h) = (cw as usize, ch as usize);
+            if creative_frame.len() < w * h * 4 || creative_alpha.len() < w * h {
+                log_error("WASM compositor: Invalid input buffer sizes");
+                return base_frame.to_vec();
+            }
+            let mut options = options.clone();
+            options.set_effects(&self.effects);
+            let processed = options.apply_effects(creative_frame, creative_alpha, w, h, None);
+            if let Some((creative, alpha)) = processed {
+                let (base, depth) = (base_frame, depth_map);
+                return composite_surfaces(
+                    &surfaces, base, &creative, &alpha, cw, ch, depth, width, height, creative_depth,
+                    &options,
+                );
+            }
+            log_error("WASM compositor: Out of memory, skipping effects");
+            health::degrade(DegradationTier::Reduced);
+        }
+        composite_surfaces(
+            &surfaces, base_frame, creative_frame, creative_alpha, cw, ch, depth_map, width, height,
+            creative_depth, options,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(opportunities: &str) -> String {
+        format!(
+            r#"{{"manifest_id": "m1", "title_id": "t1", "created_at": "2026-01-05T00:00:00",
+                "video_metadata": {{"fps": 25.0}}, "opportunities": [{}]}}"#,
+            opportunities
+        )
+    }
+
+    const WALL: &str = r#"{"opportunity_id": "wall", "surface_id": "s1", "frame_range": [25, 50],
+        "surface_coordinates": [[0, 0], [4, 0], [4, 2], [0, 2]], "prs_score": 72.5,
+        "smoothing_profile": "rigid", "metadata": {}}"#;
+
+    #[test]
+    fn test_composites_placements_in_their_frame_ranges() {
+        let plan = compile_plan(&manifest(WALL), 8, 2).unwrap_or_else(|_| panic!("plan rejected"));
+        assert_eq!((plan.opportunity_count(), plan.fps()), (1, 25.0));
+        assert_eq!(plan.smoothing_profile("wall"), "rigid");
+
+        let base_frame = vec![0u8; 64];
+        let depth_map = vec![10.0f32; 16];
+        let creative = [200u8, 100, 50, 255];
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(crate::FilterMode::Nearest);
+        let composite = |pts: f64| {
+            plan.composite_at(pts, &base_frame, &creative, &[255], 1, 1, &depth_map, 5.0, &options)
+        };
+        // Frames 25 up to but not including 50
+        assert_eq!(composite(0.96), base_frame);
+        assert_eq!(plan.active_opportunities(1.0), vec!["wall".to_string()]);
+        let frame = composite(1.0);
+        assert_eq!(frame[..4], creative);
+        assert_eq!(frame[4 * 4..4 * 4 + 4], [0, 0, 0, 0]);
+        assert_eq!(composite(2.0), base_frame);
+    }
+
+    #[test]
+    fn test_cached_plan_round_trips_and_checks_integrity() {
+        let plan = compile_plan(&manifest(WALL), 8, 2).unwrap_or_else(|_| panic!("plan rejected"));
+        let bytes = plan.to_bytes();
+        let cached = CompiledPlan::decode(&bytes).unwrap();
+        assert_eq!((cached.manifest_id(), cached.title_id()), ("m1".to_string(), "t1".to_string()));
+        assert_eq!(cached.active_opportunities(1.5), vec!["wall".to_string()]);
+        let (base_frame, depth_map) = (vec![0u8; 64], vec![10.0f32; 16]);
+        let options = LayerOptions::new();
+        let composite = |plan: &CompiledPlan| {
+            plan.composite_at(1.0, &base_frame, &[90, 90, 90, 255], &[255], 1, 1, &depth_map, 5.0, &options)
+        };
+        assert_eq!(composite(&cached), composite(&plan));
+
+        let refused = |bytes: &[u8]| CompiledPlan::decode(bytes).err().unwrap();
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(refused(&corrupt), "plan checksum mismatch, the cached copy is corrupt");
+        let mut newer = bytes.clone();
+        newer[4] += 1;
+        assert_eq!(refused(&newer), "plan format version 2, this build reads 1");
+        assert_eq!(refused(&bytes[..10]), "not a compiled plan");
+    }
+
+    #[test]
+    fn test_applies_deltas_whole_or_not_at_all() {
+        let mut plan = compile_plan(&manifest(WALL), 8, 2).unwrap_or_else(|_| panic!("plan rejected"));
+        let door = WALL.replace("\"wall\"", "\"door\"").replace("[25, 50]", "[0, 30]");
+        let wider = WALL.replace("[4, 0], [4, 2]", "[8, 0], [8, 2]").replace("\"rigid\"", "\"handheld\"");
+        let delta = format!(r#"{{"add": [{}], "modify": [{}]}}"#, door, wider);
+        plan.apply(&delta).unwrap();
+        assert_eq!(plan.opportunity_count(), 2);
+        // Placements stay in start order
+        assert_eq!(plan.active_opportunities(1.0), vec!["door".to_string(), "wall".to_string()]);
+        assert_eq!(plan.smoothing_profile("wall"), "handheld");
+
+        // One bad entry refuses the lot, leaving the plan as it was
+        let errors = plan.apply(r#"{"remove": ["door", "gate"], "modify": [{"opportunity_id": "door",
+            "frame_range": [0, 5], "surface_coordinates": [[0, 0], [4, 0], [4, 2], [0, 2]]}]}"#);
+        assert_eq!(
+            errors.err().unwrap(),
+            ["remove[1]: no opportunity \"gate\"", "modify[0]: no opportunity \"door\""]
+        );
+        assert_eq!(plan.opportunity_count(), 2);
+        assert!(plan.apply(r#"{"replace": []}"#).is_err());
+
+        plan.apply(r#"{"remove": ["door"]}"#).unwrap();
+        assert_eq!(plan.active_opportunities(1.0), vec!["wall".to_string()]);
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let bad = r#"{"opportunity_id": "wall", "frame_range": [10, 10],
+            "surface_coordinates": [[0, 0], [4, 4], [4, 0], [0, 4]], "prs_score": 120,
+            "smoothing_profile": "wobbly"}"#;
+        let offscreen = r#"{"opportunity_id": "sky", "frame_range": [0, 5],
+            "surface_coordinates": [[90, 0], [94, 0], [94, 2], [90, 2]]}"#;
+        let errors = CompiledPlan::compile(&manifest(&[WALL, bad, offscreen].join(",")), 8, 2)
+            .err()
+            .unwrap();
+        assert_eq!(
+            errors,
+            [
+                "opportunities[1]: duplicate opportunity_id \"wall\"",
+                "opportunities[1]: frame_range [10, 10] is empty or negative",
+                "opportunities[1]: prs_score 120 is outside 0..100",
+                "opportunities[1]: unknown smoothing_profile \"wobbly\"",
+                "opportunities[1]: surface_coordinates are not a convex quad",
+                "opportunities[2]: surface_coordinates lie outside the frame",
+            ]
+        );
+        // Missing required fields fail at parse
+        assert_eq!(CompiledPlan::compile(r#"{"manifest_id": "m1"}"#, 8, 2).err().unwrap().len(), 1);
+    }
+}
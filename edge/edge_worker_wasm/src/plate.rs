@@ -0,0 +1,350 @@
+//! Clean plates: removing a surface's original content before a layer replaces it
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::{blend_pixel, BlendMath};
+use crate::geometry::{mask_bounds, Rect};
+use crate::health::{self, DegradationTier};
+use crate::layer::{LayerOptions, LayerWarp};
+use crate::memory::{try_copy, try_map, try_zeroed, Scope, Subsystem};
+use crate::sampler::Source;
+use crate::transform::LayerTransform;
+use crate::{composite_layer, log_error};
+
+/// Fill the pixels of `frame` under `surface_mask` (any non-zero value) from
+/// the surrounding pixels, working inwards from the region's edge. The fill
+/// is a smooth diffusion, good for flat surfaces; textured ones want a
+/// supplied clean plate instead
+#[wasm_bindgen]
+pub fn inpaint_surface(frame: &[u8], surface_mask: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    let pixel_count = (width * height) as usize;
+    if frame.len() < pixel_count * 4 || surface_mask.len() < pixel_count {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return frame.to_vec();
+    }
+    let mut filled = frame.to_vec();
+    if inpaint(&mut filled, surface_mask, width as usize, height as usize).is_none() {
+        log_error("WASM compositor: Out of memory, surface not cleaned");
+        return frame.to_vec();
+    }
+    filled
+}
+
+/// `composite_layer` over a base frame whose surface content is removed
+/// first: under `surface_mask` the base is replaced by `clean_plate`, or by
+/// the `inpaint_surface` fill when `clean_plate` is empty, blending by the
+/// mask value at soft edges. Short of memory the layer goes over the
+/// original base
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_replacing(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    surface_mask: &[u8],
+    clean_plate: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let pixel_count = (width * height) as usize;
+    if base_frame.len() < pixel_count * 4
+        || surface_mask.len() < pixel_count
+        || (!clean_plate.is_empty() && clean_plate.len() < pixel_count * 4)
+    {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    let cleaned = clean_base(base_frame, surface_mask, clean_plate, width as usize, height as usize);
+    if cleaned.is_none() {
+        log_error("WASM compositor: Out of memory, surface not cleaned");
+    }
+    let base = cleaned.as_deref().unwrap_or(base_frame);
+    let frame =
+        composite_layer(base, creative_frame, depth_map, alpha_mask, width, height, creative_depth, options);
+    // The composite starts the frame's health over, so this comes after it
+    if cleaned.is_none() {
+        health::degrade(DegradationTier::Reduced);
+    }
+    frame
+}
+
+/// A shot's clean plate, registered once with its surface mask in the
+/// shot's reference frame and reprojected into each frame by the tracking
+/// transform, the way a layer is placed
+#[wasm_bindgen]
+pub struct CleanPlate {
+    plate: Vec<u8>,
+    surface: Vec<u8>,
+    width: u32,
+    height: u32,
+    /// Of the surface in the reference frame, None when it is empty
+    bounds: Option<Rect>,
+}
+
+#[wasm_bindgen]
+impl CleanPlate {
+    /// An invalid plate or mask registers an empty surface, which leaves
+    /// every frame's base as it is
+    #[wasm_bindgen(constructor)]
+    pub fn new(plate: &[u8], surface_mask: &[u8], width: u32, height: u32) -> CleanPlate {
+        let pixel_count = (width * height) as usize;
+        if plate.len() < pixel_count * 4 || surface_mask.len() < pixel_count {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return CleanPlate { plate: Vec::new(), surface: Vec::new(), width, height, bounds: None };
+        }
+        CleanPlate {
+            plate: plate[..pixel_count * 4].to_vec(),
+            surface: surface_mask[..pixel_count].to_vec(),
+            width,
+            height,
+            bounds: mask_bounds(surface_mask, width, height),
+        }
+    }
+
+    /// `composite_layer_replacing` with this plate and its surface placed
+    /// by `tracking`, at the plate's frame size
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_layer(
+        &self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        creative_depth: f32,
+        tracking: &LayerTransform,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        let (width, height, depth) = (self.width, self.height, creative_depth);
+        let composite = |base: &[u8]| {
+            composite_layer(base, creative_frame, depth_map, alpha_mask, width, height, depth, options)
+        };
+        if self.bounds.is_none() {
+            return composite(base_frame);
+        }
+        let Some((plate, surface)) = self.reproject(tracking) else {
+            log_error("WASM compositor: Out of memory, surface not cleaned");
+            let frame = composite(base_frame);
+            health::degrade(DegradationTier::Reduced);
+            return frame;
+        };
+        composite_layer_replacing(
+            base_frame,
+            creative_frame,
+            depth_map,
+            alpha_mask,
+            &surface,
+            &plate,
+            width,
+            height,
+            depth,
+            options,
+        )
+    }
+}
+
+impl CleanPlate {
+    /// Plate and surface mask warped into the frame; None when there is no
+    /// memory for them
+    fn reproject(&self, tracking: &LayerTransform) -> Option<(Vec<u8>, Vec<u8>)> {
+        let _memory = Scope::enter(Subsystem::Warp);
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut plate = try_zeroed(width * height * 4)?;
+        let mut surface = try_zeroed(width * height)?;
+        if let Some(bounds) = &self.bounds {
+            let source = Source { creative: &self.plate, alpha: &self.surface, width, height };
+            let mut options = LayerOptions::new();
+            options.set_layer_transform(tracking);
+            LayerWarp::new(&source, bounds, &options).warp_rows(0..height, &mut plate, &mut surface);
+        }
+        Some((plate, surface))
+    }
+}
+
+/// Base frame with the surface replaced by the plate, or its fill
+fn clean_base(base: &[u8], surface: &[u8], plate: &[u8], width: usize, height: usize) -> Option<Vec<u8>> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    let pixel_count = width * height;
+    let filled;
+    let plate = if plate.is_empty() {
+        let mut fill = try_copy(&base[..pixel_count * 4])?;
+        inpaint(&mut fill, surface, width, height)?;
+        filled = fill;
+        &filled[..]
+    } else {
+        &plate[..pixel_count * 4]
+    };
+
+    let mut cleaned = try_copy(&base[..pixel_count * 4])?;
+    let pixels = cleaned.chunks_exact_mut(4).zip(plate.chunks_exact(4)).zip(surface);
+    for ((pixel, plate), &coverage) in pixels {
+        if coverage > 0 {
+            blend_pixel(pixel, plate, coverage as f32 / 255.0, BlendMath::default());
+        }
+    }
+    Some(cleaned)
+}
+
+/// Onion-peel fill: each pass sets the unknown pixels touching known ones to
+/// the mean of their known 8-neighbours. None when there is no memory for
+/// the bookkeeping
+fn inpaint(frame: &mut [u8], region: &[u8], width: usize, height: usize) -> Option<()> {
+    let pixel_count = width * height;
+    let mut known = try_map(&region[..pixel_count], |coverage| coverage == 0)?;
+    if !known.contains(&true) {
+        // Nothing to fill from
+        return Some(());
+    }
+    let mut queued = known.clone();
+    let neighbours = |i| neighbours_of(i, width, height);
+
+    let mut frontier: Vec<usize> =
+        (0..pixel_count).filter(|&i| !known[i] && neighbours(i).any(|j| known[j])).collect();
+    for &i in &frontier {
+        queued[i] = true;
+    }
+    while !frontier.is_empty() {
+        // A whole pass reads only pixels known before it, so the fill does
+        // not drift in scan order
+        let fills: Vec<[u8; 4]> = frontier
+            .iter()
+            .map(|&i| {
+                let (mut sum, mut count) = ([0u32; 4], 0);
+                for j in neighbours(i).filter(|&j| known[j]) {
+                    for (channel, total) in sum.iter_mut().enumerate() {
+                        *total += frame[j * 4 + channel] as u32;
+                    }
+                    count += 1;
+                }
+                sum.map(|total| ((total + count / 2) / count) as u8)
+            })
+            .collect();
+
+        let mut next = Vec::new();
+        for (&i, fill) in frontier.iter().zip(fills) {
+            frame[i * 4..i * 4 + 4].copy_from_slice(&fill);
+            known[i] = true;
+            for j in neighbours(i) {
+                if !queued[j] {
+                    queued[j] = true;
+                    next.push(j);
+                }
+            }
+        }
+        frontier = next;
+    }
+    Some(())
+}
+
+/// Indices of the up to 8 pixels around pixel `i`
+fn neighbours_of(i: usize, width: usize, height: usize) -> impl Iterator<Item = usize> {
+    let (x, y) = ((i % width) as isize, (i / width) as isize);
+    let (width, height) = (width as isize, height as isize);
+    (-1..=1)
+        .flat_map(move |dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+        .filter(move |&(nx, ny)| (nx, ny) != (x, y) && nx >= 0 && ny >= 0 && nx < width && ny < height)
+        .map(move |(nx, ny)| (ny * width + nx) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height).flat_map(|i| [(i % width * 10) as u8, 80, 160, 255]).collect()
+    }
+
+    #[test]
+    fn test_inpaint_fills_from_surroundings() {
+        let (width, height) = (8usize, 6usize);
+        let mut region = vec![0u8; width * height];
+        for y in 2..4 {
+            region[y * width + 3..y * width + 5].fill(255);
+        }
+
+        // A flat surface is restored exactly, a ramp stays between its edges
+        let flat = vec![90u8; width * height * 4];
+        let mut hole = flat.clone();
+        for (i, &coverage) in region.iter().enumerate() {
+            if coverage > 0 {
+                hole[i * 4..i * 4 + 4].fill(0);
+            }
+        }
+        assert_eq!(inpaint_surface(&hole, &region, width as u32, height as u32), flat);
+
+        let ramp = gradient(width, height);
+        let filled = inpaint_surface(&ramp, &region, width as u32, height as u32);
+        for y in 2..4 {
+            for x in 3..5 {
+                let red = filled[(y * width + x) * 4];
+                assert!((20..=50).contains(&red), "{}", red);
+            }
+        }
+
+        // A region covering the whole frame has nothing to fill from
+        let everything = vec![255u8; width * height];
+        assert_eq!(inpaint_surface(&ramp, &everything, width as u32, height as u32), ramp);
+    }
+
+    #[test]
+    fn test_layer_replaces_surface_over_plate() {
+        let (width, height) = (4u32, 1u32);
+        let base_frame = [10u8, 10, 10, 255, 250, 250, 250, 255, 250, 250, 250, 255, 10, 10, 10, 255];
+        let clean_plate = vec![50u8; 16];
+        let creative_frame = vec![200u8; 16];
+        let depth_map = vec![10.0f32; 4];
+        let alpha_mask = [0u8, 255, 0, 0];
+        let surface_mask = [0u8, 255, 255, 128];
+        let options = LayerOptions::new();
+        let composite = |plate: &[u8]| {
+            let frame = composite_layer_replacing(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, &surface_mask, plate, width, height,
+                5.0, &options,
+            );
+            [frame[0], frame[4], frame[8], frame[12]]
+        };
+
+        // Creative over the plate, the old content gone, a soft edge blended
+        assert_eq!(composite(&clean_plate), [10, 200, 50, 30]);
+
+        // Without a plate the surface is filled from its surroundings
+        let [outside, creative, filled, _] = composite(&[]);
+        assert_eq!((outside, creative), (10, 200));
+        assert!(filled < 250);
+
+        assert_eq!(composite(&clean_plate[..8]), [10, 250, 250, 10]);
+    }
+
+    #[test]
+    fn test_registered_plate_follows_tracking() {
+        let (width, height) = (6u32, 1u32);
+        let base_frame = vec![250u8; 24];
+        let plate: Vec<u8> = (0..6).flat_map(|x| [x * 10, x * 10, x * 10, 255]).collect();
+        let surface_mask = [0u8, 255, 255, 0, 0, 0];
+        let creative_frame = vec![200u8; 24];
+        let depth_map = vec![10.0f32; 6];
+        let alpha_mask = vec![0u8; 6];
+        let options = LayerOptions::new();
+        let clean_plate = CleanPlate::new(&plate, &surface_mask, width, height);
+        let composite = |tracking: &LayerTransform| {
+            let frame = clean_plate.composite_layer(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0, tracking, &options,
+            );
+            frame.chunks_exact(4).map(|pixel| pixel[0]).collect::<Vec<_>>()
+        };
+
+        // The surface and its plate content move together with the shot
+        assert_eq!(composite(&LayerTransform::default()), [250, 10, 20, 250, 250, 250]);
+        assert_eq!(composite(&LayerTransform::new(2.0, 0.0, 1.0)), [250, 250, 250, 10, 20, 250]);
+
+        let empty = CleanPlate::new(&plate[..8], &surface_mask, width, height);
+        let frame = empty.composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, 5.0, &LayerTransform::default(), &options,
+        );
+        assert_eq!(frame, base_frame);
+    }
+}
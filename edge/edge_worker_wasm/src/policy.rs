@@ -0,0 +1,471 @@
+//! Per-session frequency capping and creative rotation
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::{Rc, Weak};
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::calibration::now_ms;
+use crate::log_info;
+
+/// Version tag written into exported state so stale snapshots are rejected
+const STATE_VERSION: u32 = 1;
+
+/// Viewer consent to personalized advertising, as the host's consent
+/// platform reports it
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConsentState {
+    /// No answer yet; treated as denied
+    #[default]
+    Unknown = 0,
+    Granted = 1,
+    /// Only contextual, non-personalized placements may render
+    Denied = 2,
+}
+
+impl ConsentState {
+    fn name(self) -> &'static str {
+        match self {
+            ConsentState::Unknown => "unknown",
+            ConsentState::Granted => "granted",
+            ConsentState::Denied => "denied",
+        }
+    }
+}
+
+/// Consent applied to the rotation, for segment reports
+#[derive(Serialize)]
+struct ConsentReport<'a> {
+    consent: &'static str,
+    personalized_allowed: bool,
+    withheld: Vec<&'a str>,
+}
+
+/// Tracks how often each creative has been shown this session and rotates
+/// among the creatives that are still under their cap
+#[wasm_bindgen]
+pub struct FrequencyCapPolicy {
+    max_per_session: u32,
+    creatives: Vec<String>,
+    /// Creatives targeted on personal data, eligible only with consent
+    personalized: BTreeSet<String>,
+    consent: ConsentState,
+    state: PolicyState,
+    decision: Option<ActivationDecision>,
+}
+
+/// Asks the host whether a creative may activate; the answer goes to the
+/// reply, right away or once the host has it
+type Decide = Box<dyn Fn(&str, Reply)>;
+
+/// Latest host answers per creative, shared with the replies in flight
+#[derive(Default)]
+struct Answers {
+    creatives: BTreeMap<String, Answer>,
+    questions: u64,
+}
+
+#[derive(Default)]
+struct Answer {
+    /// Last usable answer
+    allow: Option<bool>,
+    /// Question in flight, by number, and when it was asked
+    pending: Option<(u64, f64)>,
+}
+
+/// Delivers the host's answer to one question
+struct Reply {
+    answers: Weak<RefCell<Answers>>,
+    creative_id: String,
+    question: u64,
+    timeout_ms: f64,
+}
+
+impl Reply {
+    /// None for no usable answer; an answer later than the timeout is
+    /// dropped and the creative's last answer stands
+    fn send(self, allow: Option<bool>) {
+        let Some(answers) = self.answers.upgrade() else {
+            return;
+        };
+        let mut answers = answers.borrow_mut();
+        let Some(answer) = answers.creatives.get_mut(&self.creative_id) else {
+            return;
+        };
+        let Some((question, asked_at)) = answer.pending else {
+            return;
+        };
+        if question != self.question {
+            return;
+        }
+        answer.pending = None;
+        if now_ms() - asked_at <= self.timeout_ms {
+            answer.allow = allow;
+        } else {
+            log_info(&format!(
+                "WASM compositor: Activation decision for {} came after {:.0}ms, dropped",
+                self.creative_id, self.timeout_ms
+            ));
+        }
+    }
+}
+
+/// Host veto over each activation, for context only the host has (consent,
+/// ad blocking). Frames never wait on the host: each activation goes by the
+/// creative's last answer, and `default_allow` decides until there is one
+struct ActivationDecision {
+    decide: Decide,
+    answers: Rc<RefCell<Answers>>,
+    timeout_ms: f64,
+    default_allow: bool,
+    defaulted: u32,
+}
+
+impl ActivationDecision {
+    fn allows(&mut self, creative_id: &str) -> bool {
+        // Ask again unless a question is still in flight, so the answer
+        // follows the host's context
+        let reply = {
+            let mut answers = self.answers.borrow_mut();
+            answers.questions += 1;
+            let question = answers.questions;
+            let answer = answers.creatives.entry(creative_id.to_string()).or_default();
+            answer.pending.is_none().then(|| {
+                answer.pending = Some((question, now_ms()));
+                Reply {
+                    answers: Rc::downgrade(&self.answers),
+                    creative_id: creative_id.to_string(),
+                    question,
+                    timeout_ms: self.timeout_ms,
+                }
+            })
+        };
+        if let Some(reply) = reply {
+            (self.decide)(creative_id, reply);
+        }
+
+        let answer = self.answers.borrow().creatives.get(creative_id).and_then(|answer| answer.allow);
+        answer.unwrap_or_else(|| {
+            self.defaulted += 1;
+            log_info(&format!(
+                "WASM compositor: No activation decision for {} yet, {}",
+                creative_id,
+                if self.default_allow { "activating" } else { "skipping" }
+            ));
+            self.default_allow
+        })
+    }
+}
+
+/// Counters that survive page reloads via `export_state`/`import_state`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct PolicyState {
+    version: u32,
+    impressions: BTreeMap<String, u32>,
+    rotation_index: usize,
+}
+
+#[wasm_bindgen]
+impl FrequencyCapPolicy {
+    #[wasm_bindgen(constructor)]
+    pub fn new(max_per_session: u32) -> FrequencyCapPolicy {
+        FrequencyCapPolicy {
+            max_per_session,
+            creatives: Vec::new(),
+            personalized: BTreeSet::new(),
+            consent: ConsentState::Unknown,
+            state: PolicyState {
+                version: STATE_VERSION,
+                ..PolicyState::default()
+            },
+            decision: None,
+        }
+    }
+
+    /// Ask `callback(creative_id)` whether to activate a creative, with a
+    /// boolean or a promise of one. The frame never waits on a promise: each
+    /// activation goes by the creative's last answer while the next is
+    /// asked, and `default_allow` decides until there is one. Answers that
+    /// settle later than `timeout_ms`, rejections, throws and non-booleans
+    /// are not answers
+    pub fn set_decision_callback(
+        &mut self,
+        callback: js_sys::Function,
+        timeout_ms: f64,
+        default_allow: bool,
+    ) {
+        // Promise handlers per creative, alive until its next question,
+        // which is only asked once the promise has settled
+        let handlers = RefCell::new(BTreeMap::new());
+        let decide = move |creative_id: &str, reply: Reply| {
+            let answer = match callback.call1(&JsValue::NULL, &JsValue::from_str(creative_id)) {
+                Ok(answer) => answer,
+                Err(_) => return reply.send(None),
+            };
+            let promise = match answer.dyn_into::<js_sys::Promise>() {
+                Ok(promise) => promise,
+                Err(answer) => return reply.send(answer.as_bool()),
+            };
+            let reply = Rc::new(RefCell::new(Some(reply)));
+            let settled = reply.clone();
+            let resolve = Closure::new(move |answer: JsValue| {
+                if let Some(reply) = settled.borrow_mut().take() {
+                    reply.send(answer.as_bool());
+                }
+            });
+            let reject = Closure::new(move |_: JsValue| {
+                if let Some(reply) = reply.borrow_mut().take() {
+                    reply.send(None);
+                }
+            });
+            let _ = promise.then2(&resolve, &reject);
+            handlers.borrow_mut().insert(creative_id.to_string(), (resolve, reject));
+        };
+        self.set_decision(Box::new(decide), timeout_ms, default_allow);
+    }
+
+    pub fn clear_decision_callback(&mut self) {
+        self.decision = None;
+    }
+
+    /// Activations the default policy decided because the callback had not
+    /// given a usable answer in time
+    pub fn defaulted_decisions(&self) -> u32 {
+        self.decision.as_ref().map_or(0, |decision| decision.defaulted)
+    }
+
+    /// Add a creative to the rotation (duplicates are ignored)
+    pub fn register_creative(&mut self, creative_id: &str) {
+        if !self.creatives.iter().any(|c| c == creative_id) {
+            self.creatives.push(creative_id.to_string());
+        }
+    }
+
+    /// Add a creative targeted on personal data, which only rotates while
+    /// consent is granted
+    pub fn register_personalized_creative(&mut self, creative_id: &str) {
+        self.register_creative(creative_id);
+        self.personalized.insert(creative_id.to_string());
+    }
+
+    /// Consent state for the activations that follow
+    pub fn set_consent(&mut self, consent: ConsentState) {
+        if consent != self.consent {
+            log_info(&format!("WASM compositor: Consent {}", consent.name()));
+        }
+        self.consent = consent;
+    }
+
+    pub fn consent(&self) -> ConsentState {
+        self.consent
+    }
+
+    /// The consent state applied and the personalized creatives it holds
+    /// back, as JSON `{"consent","personalized_allowed","withheld"}` for the
+    /// host to attach to each segment report
+    pub fn consent_report(&self) -> String {
+        let allowed = self.personalized_allowed();
+        let withheld = self.personalized.iter().filter(|_| !allowed).map(String::as_str).collect();
+        let report = ConsentReport { consent: self.consent.name(), personalized_allowed: allowed, withheld };
+        // Serializing strings and a boolean cannot fail
+        serde_json::to_string(&report).unwrap_or_default()
+    }
+
+    pub fn impressions(&self, creative_id: &str) -> u32 {
+        self.state.impressions.get(creative_id).copied().unwrap_or(0)
+    }
+
+    pub fn is_eligible(&self, creative_id: &str) -> bool {
+        self.creatives.iter().any(|c| c == creative_id)
+            && self.impressions(creative_id) < self.max_per_session
+            && (self.personalized_allowed() || !self.personalized.contains(creative_id))
+    }
+
+    pub fn record_impression(&mut self, creative_id: &str) {
+        *self.state.impressions.entry(creative_id.to_string()).or_insert(0) += 1;
+    }
+
+    /// Activate `creative_id` if it is eligible and the decision callback,
+    /// if any, allows it, counting the impression
+    pub fn activate(&mut self, creative_id: &str) -> bool {
+        if !self.is_eligible(creative_id) {
+            return false;
+        }
+        if !self.decision.as_mut().is_none_or(|decision| decision.allows(creative_id)) {
+            return false;
+        }
+        self.record_impression(creative_id);
+        true
+    }
+
+    /// Pick the next eligible creative in rotation order that the decision
+    /// callback, if any, allows and count the impression; `None` once every
+    /// creative has hit its cap or been declined
+    pub fn activate_next(&mut self) -> Option<String> {
+        let count = self.creatives.len();
+        for step in 0..count {
+            let index = (self.state.rotation_index + step) % count;
+            let id = self.creatives[index].clone();
+            if self.activate(&id) {
+                self.state.rotation_index = (index + 1) % count;
+                return Some(id);
+            }
+        }
+        None
+    }
+
+    /// Serialize counters and rotation position as JSON
+    pub fn export_state(&self) -> String {
+        // Serializing a map of strings to integers cannot fail
+        serde_json::to_string(&self.state).unwrap_or_default()
+    }
+
+    /// Restore counters from `export_state`, returning false for invalid or
+    /// incompatible snapshots (the current state is left untouched)
+    pub fn import_state(&mut self, json: &str) -> bool {
+        match serde_json::from_str::<PolicyState>(json) {
+            Ok(state) if state.version == STATE_VERSION => {
+                self.state = state;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FrequencyCapPolicy {
+    fn personalized_allowed(&self) -> bool {
+        self.consent == ConsentState::Granted
+    }
+
+    fn set_decision(&mut self, decide: Decide, timeout_ms: f64, default_allow: bool) {
+        let timeout_ms = if timeout_ms.is_nan() { 0.0 } else { timeout_ms.max(0.0) };
+        self.decision = Some(ActivationDecision {
+            decide,
+            answers: Rc::default(),
+            timeout_ms,
+            default_allow,
+            defaulted: 0,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_respects_cap() {
+        let mut policy = FrequencyCapPolicy::new(2);
+        policy.register_creative("a");
+        policy.register_creative("b");
+
+        let shown: Vec<_> = std::iter::from_fn(|| policy.activate_next()).collect();
+        assert_eq!(shown, vec!["a", "b", "a", "b"]);
+        assert!(!policy.is_eligible("a"));
+        assert_eq!(policy.activate_next(), None);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut policy = FrequencyCapPolicy::new(3);
+        policy.register_creative("a");
+        policy.register_creative("b");
+        policy.activate_next();
+
+        let json = policy.export_state();
+
+        let mut restored = FrequencyCapPolicy::new(3);
+        restored.register_creative("a");
+        restored.register_creative("b");
+        assert!(restored.import_state(&json));
+        assert_eq!(restored.impressions("a"), 1);
+
+        // Rotation continues where the exported session left off
+        assert_eq!(restored.activate_next().as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_decision_callback_vetoes_without_blocking() {
+        let mut policy = FrequencyCapPolicy::new(5);
+        for id in ["a", "b", "c"] {
+            policy.register_creative(id);
+        }
+        // Declines "a" right away, gives no answer for "b" and answers "c"
+        // later, as a promise would
+        let held = Rc::new(RefCell::new(Vec::new()));
+        let later = held.clone();
+        let decide = move |id: &str, reply: Reply| match id {
+            "a" => reply.send(Some(false)),
+            "b" => reply.send(None),
+            _ => later.borrow_mut().push(reply),
+        };
+        policy.set_decision(Box::new(decide), 1000.0, true);
+        assert_eq!(policy.activate_next().as_deref(), Some("b"));
+        // "c" activates on the default while its answer is outstanding
+        assert_eq!(policy.activate_next().as_deref(), Some("c"));
+        assert_eq!(policy.defaulted_decisions(), 2);
+        assert_eq!(policy.impressions("a"), 0);
+
+        // Its answer arrives and holds from then on; no second question
+        // went out while the first was in flight
+        assert_eq!(held.borrow().len(), 1);
+        held.borrow_mut().pop().unwrap().send(Some(false));
+        assert_eq!(policy.activate_next().as_deref(), Some("b"));
+        assert_eq!(policy.activate_next().as_deref(), Some("b"));
+        assert_eq!(policy.impressions("c"), 1);
+
+        policy.clear_decision_callback();
+        assert_eq!(policy.activate_next().as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn test_late_decisions_are_dropped() {
+        let mut policy = FrequencyCapPolicy::new(5);
+        policy.register_creative("a");
+        let held = Rc::new(RefCell::new(Vec::new()));
+        let later = held.clone();
+        policy.set_decision(Box::new(move |_: &str, reply: Reply| later.borrow_mut().push(reply)), 5.0, false);
+        assert_eq!(policy.activate_next(), None);
+
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        held.borrow_mut().pop().unwrap().send(Some(true));
+        // Still no answer, and the next question is out
+        assert_eq!(policy.activate_next(), None);
+        assert_eq!(policy.defaulted_decisions(), 2);
+        held.borrow_mut().pop().unwrap().send(Some(true));
+        assert_eq!(policy.activate_next().as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_consent_filters_personalized_creatives() {
+        let mut policy = FrequencyCapPolicy::new(5);
+        policy.register_creative("contextual");
+        policy.register_personalized_creative("retargeted");
+        assert_eq!(policy.consent(), ConsentState::Unknown);
+        assert!(!policy.is_eligible("retargeted"));
+        assert_eq!(policy.activate_next().as_deref(), Some("contextual"));
+        assert_eq!(policy.activate_next().as_deref(), Some("contextual"));
+        assert_eq!(
+            policy.consent_report(),
+            r#"{"consent":"unknown","personalized_allowed":false,"withheld":["retargeted"]}"#
+        );
+
+        policy.set_consent(ConsentState::Granted);
+        assert_eq!(policy.activate_next().as_deref(), Some("retargeted"));
+        let report = policy.consent_report();
+        assert_eq!(report, r#"{"consent":"granted","personalized_allowed":true,"withheld":[]}"#);
+        policy.set_consent(ConsentState::Denied);
+        assert!(!policy.is_eligible("retargeted"));
+    }
+
+    #[test]
+    fn test_import_rejects_bad_state() {
+        let mut policy = FrequencyCapPolicy::new(1);
+        assert!(!policy.import_state("not json"));
+        assert!(!policy.import_state(r#"{"version":99,"impressions":{},"rotation_index":0}"#));
+    }
+}
@@ -0,0 +1,179 @@
+//! Double-buffered output for hosts that read frames straight from memory
+
+use wasm_bindgen::prelude::*;
+
+use crate::composite_layer;
+use crate::layer::LayerOptions;
+use crate::health;
+use crate::log_error;
+
+/// Front and back frame buffers. Composites only ever write the back buffer;
+/// `present` swaps it to the front, so a host reading the front buffer, even
+/// through a view of wasm memory while the next frame is being composited,
+/// always sees one complete frame
+#[wasm_bindgen]
+pub struct FrameBuffers {
+    front: Vec<u8>,
+    back: Vec<u8>,
+    width: u32,
+    height: u32,
+    /// A complete frame is waiting in the back buffer
+    pending: bool,
+    presented: u32,
+}
+
+#[wasm_bindgen]
+impl FrameBuffers {
+    /// Both buffers start as transparent black frames
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32) -> FrameBuffers {
+        let frame_bytes = (width * height * 4) as usize;
+        FrameBuffers {
+            front: vec![0; frame_bytes],
+            back: vec![0; frame_bytes],
+            width,
+            height,
+            pending: false,
+            presented: 0,
+        }
+    }
+
+    /// Same as `composite_layer` at the buffers' frame size, into the back
+    /// buffer; a frame that `composite_layer` drops is staged as the base
+    /// frame. Returns false, leaving the back buffer alone, for an invalid
+    /// base frame
+    pub fn composite_layer(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> bool {
+        if base_frame.len() != self.back.len() {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return false;
+        }
+        let (width, height) = (self.width, self.height);
+        let frame = composite_layer(
+            base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth, options,
+        );
+        if frame.len() == self.back.len() {
+            self.back.copy_from_slice(&frame);
+        } else {
+            self.back.copy_from_slice(base_frame);
+        }
+        self.set_pending(true);
+        true
+    }
+
+    /// Stage a frame composited elsewhere, e.g. from `SlabCompositor` slabs;
+    /// false for a frame of the wrong size
+    pub fn stage(&mut self, frame: &[u8]) -> bool {
+        if frame.len() != self.back.len() {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return false;
+        }
+        self.back.copy_from_slice(frame);
+        self.set_pending(true);
+        true
+    }
+
+    /// Make the staged frame the front buffer. Returns false, keeping the
+    /// current front frame, when nothing new was staged. The front buffer
+    /// moves on every present, so re-read `front_ptr` afterwards
+    pub fn present(&mut self) -> bool {
+        if !self.pending {
+            return false;
+        }
+        std::mem::swap(&mut self.front, &mut self.back);
+        self.set_pending(false);
+        self.presented += 1;
+        true
+    }
+
+    /// Copy of the last presented frame
+    pub fn front(&self) -> Vec<u8> {
+        self.front.clone()
+    }
+
+    /// Address of the last presented frame in wasm memory, valid until the
+    /// next `present`, for a zero-copy view
+    pub fn front_ptr(&self) -> *const u8 {
+        self.front.as_ptr()
+    }
+
+    pub fn frame_bytes(&self) -> usize {
+        self.front.len()
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.pending
+    }
+
+    pub fn presented_frames(&self) -> u32 {
+        self.presented
+    }
+}
+
+impl FrameBuffers {
+    /// Track the staged frame in the health queue depth
+    fn set_pending(&mut self, pending: bool) {
+        if pending != self.pending {
+            health::frame_queued(pending);
+        }
+        self.pending = pending;
+    }
+}
+
+impl Drop for FrameBuffers {
+    fn drop(&mut self) {
+        self.set_pending(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_front_changes_only_on_present() {
+        let (width, height) = (4u32, 2u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![60u8; pixel_count * 4];
+        let creative_frame = vec![200u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let alpha_mask = vec![255u8; pixel_count];
+        let options = LayerOptions::new();
+        let expected = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+
+        let mut buffers = FrameBuffers::new(width, height);
+        assert!(!buffers.present());
+        let (creative, depth, alpha) = (&creative_frame[..], &depth_map[..], &alpha_mask[..]);
+        assert!(buffers.composite_layer(&base_frame, creative, depth, alpha, 5.0, &options));
+        assert_eq!(buffers.front(), vec![0; pixel_count * 4]);
+
+        // The next frame goes to the back while the first stays in front
+        assert!(buffers.present());
+        let front = buffers.front_ptr();
+        assert!(buffers.stage(&base_frame));
+        assert_eq!(buffers.front(), expected);
+        assert_eq!(buffers.front_ptr(), front);
+
+        assert!(buffers.present());
+        assert_eq!(buffers.front(), base_frame);
+        assert_eq!(buffers.presented_frames(), 2);
+        assert!(!buffers.has_pending());
+    }
+
+    #[test]
+    fn test_rejects_wrong_size_frames() {
+        let mut buffers = FrameBuffers::new(4, 2);
+        assert!(!buffers.stage(&[0; 8]));
+        assert!(!buffers.composite_layer(&[0; 8], &[], &[], &[], 5.0, &LayerOptions::new()));
+        assert!(!buffers.has_pending());
+    }
+}
@@ -0,0 +1,284 @@
+//! Single-frame previews of a plan for the trafficking UI
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::blend::UNIT_FROM_U8;
+use crate::calibration::now_ms;
+use crate::geometry::polygon_contains;
+use crate::layer::LayerOptions;
+use crate::log_error;
+use crate::occlusion::visibility;
+use crate::plan::CompiledPlan;
+
+/// Outline colors of placements, in turn
+const OUTLINE_COLORS: [[u8; 3]; 4] = [[255, 0, 200], [0, 220, 255], [255, 200, 0], [80, 255, 80]];
+
+/// How faithfully a preview is rendered, cheapest first
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreviewQuality {
+    /// Nearest sampling and fixed-point blending, for scrubbing
+    Draft = 0,
+    /// What most devices play: bilinear sampling, integer blending
+    #[default]
+    Standard = 1,
+    /// Float blending throughout, as the best devices play
+    Final = 2,
+}
+
+impl PreviewQuality {
+    fn preset(self) -> &'static str {
+        match self {
+            PreviewQuality::Draft => "mobile-low",
+            PreviewQuality::Standard => "broadcast",
+            PreviewQuality::Final => "cinematic",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PreviewQuality::Draft => "draft",
+            PreviewQuality::Standard => "standard",
+            PreviewQuality::Final => "final",
+        }
+    }
+}
+
+/// How one placement reads in the previewed frame
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct PlacementMetrics {
+    id: String,
+    /// Share of the frame inside the placement's quad
+    screen_share: f32,
+    /// Share of the quad the scene's depth leaves visible
+    visible_share: f32,
+    /// Mean luma change the creative makes inside the quad, 0..1
+    luma_shift: f32,
+}
+
+#[derive(Serialize)]
+struct PreviewMetrics<'a> {
+    quality: &'static str,
+    render_ms: f64,
+    placements: &'a [PlacementMetrics],
+}
+
+/// A previewed frame: the composite as played, the same with every active
+/// placement outlined, and how each placement reads in it
+#[wasm_bindgen]
+pub struct Preview {
+    quality: PreviewQuality,
+    frame: Vec<u8>,
+    annotated: Vec<u8>,
+    render_ms: f64,
+    placements: Vec<PlacementMetrics>,
+}
+
+#[wasm_bindgen]
+impl Preview {
+    /// The composite as a device at this quality would play it
+    pub fn frame(&self) -> Vec<u8> {
+        self.frame.clone()
+    }
+
+    /// The composite with each active placement's quad outlined
+    pub fn annotated(&self) -> Vec<u8> {
+        self.annotated.clone()
+    }
+
+    pub fn quality(&self) -> PreviewQuality {
+        self.quality
+    }
+
+    /// Time the composite took, in ms
+    pub fn render_ms(&self) -> f64 {
+        self.render_ms
+    }
+
+    /// Ids of the placements previewed, in outline color order
+    pub fn placements(&self) -> Vec<String> {
+        self.placements.iter().map(|metrics| metrics.id.clone()).collect()
+    }
+
+    /// Quality, render time and per-placement metrics as JSON
+    pub fn metrics_json(&self) -> String {
+        let metrics = PreviewMetrics {
+            quality: self.quality.name(),
+            render_ms: self.render_ms,
+            placements: &self.placements,
+        };
+        serde_json::to_string(&metrics).unwrap_or_default()
+    }
+}
+
+/// Composite the frame `plan` shows at `pts_seconds` as `quality` renders
+/// it, every active placement with its own effects, for the trafficking
+/// UI. Buffers as for `CompiledPlan::composite_at`; invalid ones preview the
+/// base frame alone
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn render_preview(
+    plan: &CompiledPlan,
+    pts_seconds: f64,
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    creative_alpha: &[u8],
+    creative_width: u32,
+    creative_height: u32,
+    depth_map: &[f32],
+    creative_depth: f32,
+    quality: PreviewQuality,
+) -> Preview {
+    let (width, height) = plan.frame_size();
+    let pixel_count = (width * height) as usize;
+    let mut preview = Preview {
+        quality,
+        frame: base_frame.to_vec(),
+        annotated: base_frame.to_vec(),
+        render_ms: 0.0,
+        placements: Vec::new(),
+    };
+    if base_frame.len() != pixel_count * 4 || depth_map.len() < pixel_count {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return preview;
+    }
+
+    let options = LayerOptions::from_preset(quality.preset()).unwrap_or_default();
+    let start = now_ms();
+    let (creative, alpha, cw, ch) = (creative_frame, creative_alpha, creative_width, creative_height);
+    let (pts, depth) = (pts_seconds, depth_map);
+    let frame = plan.composite_at(pts, base_frame, creative, alpha, cw, ch, depth, creative_depth, &options);
+    preview.render_ms = now_ms() - start;
+    if frame.is_empty() {
+        // Dropped for memory, as playback would
+        preview.frame.clear();
+        preview.annotated.clear();
+        return preview;
+    }
+
+    let mut annotated = frame.clone();
+    for (index, (id, corners)) in plan.active_quads(pts_seconds).enumerate() {
+        let inside = QuadPixels { corners, width, height };
+        let metrics = inside.metrics(id, base_frame, &frame, depth_map, creative_depth, &options);
+        preview.placements.push(metrics);
+        outline(&mut annotated, width, height, &corners, OUTLINE_COLORS[index % OUTLINE_COLORS.len()]);
+    }
+    preview.frame = frame;
+    preview.annotated = annotated;
+    preview
+}
+
+/// Pixels whose centers fall inside a placement quad
+struct QuadPixels {
+    corners: [(f32, f32); 4],
+    width: u32,
+    height: u32,
+}
+
+impl QuadPixels {
+    fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        let (xs, ys) = (self.corners.map(|(x, _)| x), self.corners.map(|(_, y)| y));
+        let span = |values: [f32; 4], limit: u32| {
+            let low = values.into_iter().fold(f32::INFINITY, f32::min).floor().max(0.0) as u32;
+            let high = values.into_iter().fold(f32::NEG_INFINITY, f32::max).ceil().min(limit as f32) as u32;
+            low..high.max(low)
+        };
+        let columns = span(xs, self.width);
+        span(ys, self.height).flat_map(move |y| {
+            columns.clone().filter_map(move |x| {
+                let inside = polygon_contains(&self.corners, x as f32 + 0.5, y as f32 + 0.5);
+                inside.then_some((y * self.width + x) as usize)
+            })
+        })
+    }
+
+    fn metrics(
+        &self,
+        id: &str,
+        base_frame: &[u8],
+        frame: &[u8],
+        depth_map: &[f32],
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> PlacementMetrics {
+        let luma = |rgba: &[u8]| {
+            0.2126 * UNIT_FROM_U8[rgba[0] as usize]
+                + 0.7152 * UNIT_FROM_U8[rgba[1] as usize]
+                + 0.0722 * UNIT_FROM_U8[rgba[2] as usize]
+        };
+        let (mut pixels, mut visible, mut shift) = (0usize, 0.0f32, 0.0f32);
+        for i in self.indices() {
+            pixels += 1;
+            visible += visibility(creative_depth, depth_map[i], options.depth_feather);
+            shift += (luma(&frame[i * 4..i * 4 + 4]) - luma(&base_frame[i * 4..i * 4 + 4])).abs();
+        }
+        let share = |total: f32| if pixels > 0 { total / pixels as f32 } else { 0.0 };
+        PlacementMetrics {
+            id: id.to_string(),
+            screen_share: pixels as f32 / (self.width * self.height) as f32,
+            visible_share: share(visible),
+            luma_shift: share(shift),
+        }
+    }
+}
+
+/// Draw the quad's edges one pixel wide in `color`, clipped to the frame
+fn outline(frame: &mut [u8], width: u32, height: u32, corners: &[(f32, f32); 4], color: [u8; 3]) {
+    for (k, &(x0, y0)) in corners.iter().enumerate() {
+        let (x1, y1) = corners[(k + 1) % 4];
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f32 / steps as f32;
+            let (x, y) = ((x0 + (x1 - x0) * t).floor(), (y0 + (y1 - y0) * t).floor());
+            // Edges on the far frame border land on its last pixel
+            let (x, y) = (x.min(width as f32 - 1.0), y.min(height as f32 - 1.0));
+            if x >= 0.0 && y >= 0.0 {
+                let i = (y as usize * width as usize + x as usize) * 4;
+                frame[i..i + 3].copy_from_slice(&color);
+                frame[i + 3] = 255;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile_plan;
+
+    #[test]
+    fn test_preview_annotates_and_measures() {
+        let manifest = r#"{"manifest_id": "m1", "title_id": "t1", "video_metadata": {"fps": 25.0},
+            "opportunities": [{"opportunity_id": "wall", "frame_range": [0, 50],
+            "surface_coordinates": [[0, 0], [4, 0], [4, 4], [0, 4]]}]}"#;
+        let plan = compile_plan(manifest, 8, 4).unwrap_or_else(|_| panic!("plan rejected"));
+        let base_frame = [0u8, 0, 0, 255].repeat(32);
+        // The scene is in front of the creative on the bottom row
+        let mut depth_map = vec![10.0f32; 32];
+        depth_map[24..].fill(1.0);
+        let creative = [255u8, 255, 255, 255];
+
+        let preview = |pts: f64, quality: PreviewQuality| {
+            render_preview(&plan, pts, &base_frame, &creative, &[255], 1, 1, &depth_map, 5.0, quality)
+        };
+
+        let draft = preview(1.0, PreviewQuality::Draft);
+        assert_eq!(draft.placements(), vec!["wall".to_string()]);
+        let metrics = &draft.placements[0];
+        assert_eq!((metrics.screen_share, metrics.visible_share), (0.5, 0.75));
+        assert!((metrics.luma_shift - 0.75).abs() < 1e-6);
+        // The annotated frame outlines the quad; the clean one does not
+        let frame = draft.frame();
+        assert_eq!(frame[4 * 9..4 * 9 + 4], [255, 255, 255, 255]);
+        let annotated = draft.annotated();
+        assert_eq!(annotated[..4], [255, 0, 200, 255]);
+        assert_eq!(annotated[4 * 9..4 * 9 + 4], frame[4 * 9..4 * 9 + 4]);
+        assert!(draft.metrics_json().starts_with(r#"{"quality":"draft","render_ms":"#));
+
+        // Nothing active: the base frame, unannotated
+        let idle = preview(5.0, PreviewQuality::Final);
+        assert_eq!((idle.frame(), idle.annotated()), (base_frame.clone(), base_frame));
+        assert!(idle.placements().is_empty());
+    }
+}
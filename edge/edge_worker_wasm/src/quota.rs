@@ -0,0 +1,186 @@
+//! Capping a placement's qualified exposure time
+
+use wasm_bindgen::prelude::*;
+
+use crate::layer::LayerOptions;
+use crate::occlusion::in_front;
+use crate::{composite_layer, log_info};
+
+/// Longest gap between frames counted as exposure; a seek or a stalled
+/// player books nothing for its jump
+const MAX_FRAME_GAP: f64 = 0.25;
+
+/// Wraps `composite_layer` for one placement with an exposure quota: time
+/// spent on frames where at least `min_visible` of the mask's pixels pass
+/// the depth test counts towards `quota_seconds`, measured on the layer's
+/// presentation time. Once the quota is used up the layer fades out over
+/// `fade_seconds` and is no longer composited
+#[wasm_bindgen]
+pub struct PlacementQuota {
+    quota_seconds: f64,
+    fade_seconds: f64,
+    min_visible: f32,
+    qualified_seconds: f64,
+    last_time: Option<f64>,
+    cutoff_time: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl PlacementQuota {
+    #[wasm_bindgen(constructor)]
+    pub fn new(quota_seconds: f64, fade_seconds: f64, min_visible: f32) -> PlacementQuota {
+        PlacementQuota {
+            quota_seconds: quota_seconds.max(0.0),
+            fade_seconds: fade_seconds.max(0.0),
+            min_visible: min_visible.clamp(0.0, 1.0),
+            qualified_seconds: 0.0,
+            last_time: None,
+            cutoff_time: None,
+        }
+    }
+
+    /// Same as `composite_layer` at the options' time, fading out once the
+    /// quota is reached and returning the base frame after the fade
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_layer(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        let time = options.time;
+        let qualified = self.cutoff_time.is_none() && {
+            let visible = visible_fraction(depth_map, alpha_mask, creative_depth);
+            visible >= self.min_visible && visible > 0.0
+        };
+        self.record(time, qualified);
+        let remaining = self.remaining_opacity(time);
+        if remaining <= 0.0 {
+            return base_frame.to_vec();
+        }
+        let composite = |options: &LayerOptions| {
+            composite_layer(
+                base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth, options,
+            )
+        };
+        if remaining >= 1.0 {
+            return composite(options);
+        }
+        let mut faded = options.clone();
+        faded.set_opacity(options.opacity() * remaining);
+        composite(&faded)
+    }
+
+    /// Qualified exposure booked so far
+    pub fn qualified_seconds(&self) -> f64 {
+        self.qualified_seconds
+    }
+
+    /// Presentation time the quota was reached at, once it has been
+    pub fn cutoff_time(&self) -> Option<f64> {
+        self.cutoff_time
+    }
+
+    /// The quota is reached and the fade is over
+    pub fn exhausted(&self) -> bool {
+        self.cutoff_time.is_some() && self.last_time.is_some_and(|time| self.remaining_opacity(time) <= 0.0)
+    }
+
+    /// Start a new session's quota
+    pub fn reset(&mut self) {
+        self.qualified_seconds = 0.0;
+        self.last_time = None;
+        self.cutoff_time = None;
+    }
+}
+
+impl PlacementQuota {
+    fn record(&mut self, time: f64, qualified: bool) {
+        let gap = self.last_time.map_or(0.0, |last| time - last);
+        self.last_time = Some(time);
+        if self.cutoff_time.is_some() || !qualified || !(0.0..=MAX_FRAME_GAP).contains(&gap) {
+            return;
+        }
+        self.qualified_seconds += gap;
+        if self.qualified_seconds >= self.quota_seconds {
+            self.cutoff_time = Some(time);
+            log_info(&format!(
+                "WASM compositor: Placement quota of {:.1}s reached at {:.3}s, fading out",
+                self.quota_seconds, time
+            ));
+        }
+    }
+
+    /// Factor on the layer's opacity at `time`: 1 until the cutoff, then
+    /// down to 0 across the fade
+    fn remaining_opacity(&self, time: f64) -> f32 {
+        match self.cutoff_time {
+            None => 1.0,
+            Some(_) if self.fade_seconds <= 0.0 => 0.0,
+            Some(cutoff) => (1.0 - (time - cutoff) / self.fade_seconds).clamp(0.0, 1.0) as f32,
+        }
+    }
+}
+
+/// Part of the mask's pixels in front of the scene
+fn visible_fraction(depth_map: &[f32], alpha_mask: &[u8], creative_depth: f32) -> f32 {
+    let (covered, visible) = alpha_mask
+        .iter()
+        .zip(depth_map)
+        .filter(|&(&alpha, _)| alpha > 0)
+        .fold((0u32, 0u32), |(covered, visible), (_, &depth)| {
+            (covered + 1, visible + in_front(creative_depth, depth) as u32)
+        });
+    if covered == 0 {
+        0.0
+    } else {
+        visible as f32 / covered as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cuts_off_after_quota_with_fade() {
+        let base_frame = vec![0u8; 8];
+        let creative_frame = vec![200u8; 8];
+        let alpha_mask = [255u8, 255];
+        let mut quota = PlacementQuota::new(0.95, 0.5, 0.5);
+        let mut options = LayerOptions::new();
+        let mut composite = |quota: &mut PlacementQuota, time: f64, depth_map: &[f32]| {
+            options.set_time(time);
+            let (base, creative) = (&base_frame, &creative_frame);
+            quota.composite_layer(base, creative, depth_map, &alpha_mask, 2, 1, 5.0, &options)[0]
+        };
+
+        // Half occluded still qualifies; fully occluded and seeks do not count
+        for frame in 0..=4 {
+            assert_eq!(composite(&mut quota, frame as f64 * 0.1, &[10.0, 1.0]), 200);
+        }
+        composite(&mut quota, 0.5, &[1.0, 1.0]);
+        composite(&mut quota, 10.0, &[10.0, 10.0]);
+        for frame in 1..=5 {
+            assert_eq!(composite(&mut quota, 10.0 + frame as f64 * 0.1, &[10.0, 10.0]), 200);
+        }
+        assert!(quota.cutoff_time().is_none());
+
+        // Reached at 10.6, then fades over half a second
+        assert_eq!(composite(&mut quota, 10.6, &[10.0, 10.0]), 200);
+        assert!((quota.cutoff_time().unwrap() - 10.6).abs() < 1e-9);
+        assert_eq!(composite(&mut quota, 10.85, &[10.0, 10.0]), 100);
+        assert!(!quota.exhausted());
+        assert_eq!(composite(&mut quota, 11.1, &[10.0, 10.0]), 0);
+        assert!(quota.exhausted());
+        assert!((quota.qualified_seconds() - 1.0).abs() < 1e-9);
+
+        quota.reset();
+        assert_eq!(composite(&mut quota, 20.0, &[10.0, 10.0]), 200);
+    }
+}
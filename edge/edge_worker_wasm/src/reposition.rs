@@ -0,0 +1,246 @@
+//! Constraint solver that nudges a placement to keep it visible
+
+use wasm_bindgen::prelude::*;
+
+use crate::coords::CoordinateSystem;
+use crate::geometry::{mask_bounds, visible_area, Rect};
+
+/// Scales tried when the placement no longer fits at full size
+const SCALE_STEPS: [f32; 6] = [1.0, 0.9, 0.8, 0.7, 0.6, 0.5];
+
+/// Candidate positions tried per axis inside the allowed region
+const GRID_STEPS: usize = 8;
+
+/// Shifts and scales a placement within an allowed region to maximize its
+/// visible (non-excluded) area, holding its position until a candidate is
+/// clearly better so the placement does not jitter between frames
+#[wasm_bindgen]
+pub struct PlacementSolver {
+    /// In the solver's coordinate system
+    allowed: Rect,
+    coordinates: CoordinateSystem,
+    hysteresis: f32,
+    min_scale: f32,
+    current: Option<Placement>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Placement {
+    dx: f32,
+    dy: f32,
+    scale: f32,
+}
+
+impl Placement {
+    const IDEAL: Placement = Placement { dx: 0.0, dy: 0.0, scale: 1.0 };
+}
+
+#[wasm_bindgen]
+impl PlacementSolver {
+    /// `hysteresis` is the fractional gain in visible area required before
+    /// moving away from the current position (e.g. 0.1 = 10% more area)
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        allowed_x: f32,
+        allowed_y: f32,
+        allowed_width: f32,
+        allowed_height: f32,
+        hysteresis: f32,
+    ) -> PlacementSolver {
+        PlacementSolver {
+            allowed: Rect::new(allowed_x, allowed_y, allowed_width, allowed_height),
+            coordinates: CoordinateSystem::default(),
+            hysteresis: hysteresis.max(0.0),
+            min_scale: 0.5,
+            current: None,
+        }
+    }
+
+    /// Smallest scale the solver may shrink the placement to
+    pub fn set_min_scale(&mut self, min_scale: f32) {
+        self.min_scale = min_scale.clamp(0.0, 1.0);
+    }
+
+    /// Conventions of the allowed region, exclusion rectangles, mask and the
+    /// returned shift; top-left pixels by default
+    pub fn set_coordinates(&mut self, coordinates: &CoordinateSystem) {
+        self.coordinates = *coordinates;
+    }
+
+    /// Forget the held position, e.g. after a scene cut
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    /// Solve for this frame, returning `[dx, dy, scale, visible_fraction]`
+    pub fn solve(
+        &mut self,
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        exclusion_rects: &[f32],
+    ) -> Vec<f32> {
+        let pixel_count = (width * height) as usize;
+        let bounds = if alpha_mask.len() >= pixel_count {
+            mask_bounds(alpha_mask, width, height)
+        } else {
+            None
+        };
+        let Some(mut bounds) = bounds else {
+            return vec![0.0, 0.0, 1.0, 0.0];
+        };
+        if self.coordinates.flip_masks() {
+            bounds.y = height as f32 - bounds.bottom();
+        }
+
+        let to_pixels = |rect: &Rect| self.coordinates.rect_to_pixels(rect, width, height);
+        let allowed = to_pixels(&self.allowed);
+        let exclusions: Vec<Rect> = Rect::from_flat(exclusion_rects).iter().map(to_pixels).collect();
+        let (placement, area) = self.solve_bounds(&bounds, &allowed, &exclusions);
+        let (dx, dy) = self.coordinates.offset_from_pixels(placement.dx, placement.dy, width, height);
+        vec![dx, dy, placement.scale, area / bounds.area()]
+    }
+}
+
+impl PlacementSolver {
+    fn solve_bounds(&mut self, bounds: &Rect, allowed: &Rect, exclusions: &[Rect]) -> (Placement, f32) {
+        let score = |p: &Placement| self.score(bounds, allowed, exclusions, p);
+
+        let (best, best_area) = self
+            .candidates(bounds, allowed)
+            .into_iter()
+            .filter_map(|p| score(&p).map(|area| (p, area)))
+            .fold((Placement::IDEAL, f32::MIN), |(bp, ba), (p, area)| {
+                // Prefer more visible area, then the smallest displacement
+                let better = area > ba
+                    || (area == ba && p.dx.hypot(p.dy) < bp.dx.hypot(bp.dy));
+                if better { (p, area) } else { (bp, ba) }
+            });
+
+        // Hold the current placement unless the best candidate clearly wins
+        if let Some(current) = self.current {
+            if let Some(current_area) = score(&current) {
+                if best_area <= current_area * (1.0 + self.hysteresis) {
+                    return (current, current_area);
+                }
+            }
+        }
+
+        let best_area = best_area.max(0.0);
+        self.current = Some(best);
+        (best, best_area)
+    }
+
+    /// Visible area of a candidate, or None when it leaves the allowed region
+    fn score(
+        &self,
+        bounds: &Rect,
+        allowed: &Rect,
+        exclusions: &[Rect],
+        placement: &Placement,
+    ) -> Option<f32> {
+        let rect = bounds.offset_scaled(placement.dx, placement.dy, placement.scale);
+        if placement.scale < self.min_scale || !allowed.contains_rect(&rect) {
+            return None;
+        }
+        Some(visible_area(&rect, allowed, exclusions))
+    }
+
+    fn candidates(&self, bounds: &Rect, allowed: &Rect) -> Vec<Placement> {
+        let mut candidates = vec![Placement::IDEAL];
+        let (cx, cy) = bounds.center();
+
+        for &scale in SCALE_STEPS.iter().filter(|&&s| s >= self.min_scale) {
+            let width = bounds.width * scale;
+            let height = bounds.height * scale;
+            let free_x = (allowed.width - width).max(0.0);
+            let free_y = (allowed.height - height).max(0.0);
+
+            for i in 0..=GRID_STEPS {
+                for j in 0..=GRID_STEPS {
+                    let x = allowed.x + free_x * i as f32 / GRID_STEPS as f32;
+                    let y = allowed.y + free_y * j as f32 / GRID_STEPS as f32;
+                    candidates.push(Placement {
+                        dx: x + width * 0.5 - cx,
+                        dy: y + height * 0.5 - cy,
+                        scale,
+                    });
+                }
+            }
+        }
+
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coords::Origin;
+
+    fn square_mask(width: u32, height: u32, rect: Rect) -> Vec<u8> {
+        let mut mask = vec![0u8; (width * height) as usize];
+        for y in rect.y as u32..rect.bottom() as u32 {
+            for x in rect.x as u32..rect.right() as u32 {
+                mask[(y * width + x) as usize] = 255;
+            }
+        }
+        mask
+    }
+
+    #[test]
+    fn test_solver_keeps_unobstructed_placement() {
+        let mask = square_mask(32, 32, Rect::new(8.0, 8.0, 8.0, 8.0));
+        let mut solver = PlacementSolver::new(0.0, 0.0, 32.0, 32.0, 0.1);
+
+        let result = solver.solve(&mask, 32, 32, &[]);
+        assert_eq!(result, vec![0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_solver_moves_away_from_exclusion() {
+        let mask = square_mask(32, 32, Rect::new(0.0, 0.0, 8.0, 8.0));
+        let mut solver = PlacementSolver::new(0.0, 0.0, 32.0, 32.0, 0.1);
+
+        // Exclusion covers the ideal spot
+        let result = solver.solve(&mask, 32, 32, &[0.0, 0.0, 8.0, 8.0]);
+        let moved = Rect::new(0.0, 0.0, 8.0, 8.0).offset_scaled(result[0], result[1], result[2]);
+
+        assert_eq!(result[3], 1.0);
+        assert!(!moved.intersects(&Rect::new(0.0, 0.0, 8.0, 8.0)));
+    }
+
+    #[test]
+    fn test_solver_hysteresis_holds_position() {
+        let mask = square_mask(32, 32, Rect::new(0.0, 0.0, 8.0, 8.0));
+        let mut solver = PlacementSolver::new(0.0, 0.0, 32.0, 32.0, 0.5);
+
+        let first = solver.solve(&mask, 32, 32, &[0.0, 0.0, 8.0, 8.0]);
+
+        // Exclusion clears: moving back would only gain nothing, so hold
+        let second = solver.solve(&mask, 32, 32, &[]);
+        assert_eq!(first[..3], second[..3]);
+
+        // After a reset the ideal spot is chosen again
+        solver.reset();
+        let third = solver.solve(&mask, 32, 32, &[]);
+        assert_eq!(third, vec![0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_solver_honors_coordinate_system() {
+        let mask = square_mask(32, 32, Rect::new(0.0, 0.0, 8.0, 8.0));
+        let mut pixels = PlacementSolver::new(0.0, 0.0, 32.0, 32.0, 0.1);
+        let expected = pixels.solve(&mask, 32, 32, &[0.0, 0.0, 8.0, 8.0]);
+
+        // Same scene with a bottom-up mask and bottom-left normalized geometry
+        let mut coordinates = CoordinateSystem::new(Origin::BottomLeft, true);
+        coordinates.set_flip_masks(true);
+        let mut normalized = PlacementSolver::new(0.0, 0.0, 1.0, 1.0, 0.1);
+        normalized.set_coordinates(&coordinates);
+        let flipped = crate::coords::flip_rows(&mask, 32, 32);
+        let result = normalized.solve(&flipped, 32, 32, &[0.0, 0.75, 0.25, 0.25]);
+
+        assert_eq!(result, vec![expected[0] / 32.0, -expected[1] / 32.0, expected[2], expected[3]]);
+    }
+}
@@ -0,0 +1,173 @@
+//! Bilinear resampling of depth maps and masks to the frame's resolution
+
+use wasm_bindgen::prelude::*;
+
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::{try_map, try_zeroed, Scope, Subsystem};
+
+/// How resampling reads past a map's edge, which decides the outermost
+/// output pixels whenever the resolutions differ
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleEdge {
+    /// Repeat the edge sample, so the border holds its value flat
+    #[default]
+    Clamp = 0,
+    /// Reflect about the edge sample without repeating it, so the border
+    /// continues the slope of the map inside (a repeated edge would sample
+    /// exactly like clamp under bilinear filtering)
+    Mirror = 1,
+}
+
+impl ResampleEdge {
+    /// Index in `0..size` read for possibly out-of-range `index`
+    fn resolve(self, index: i64, size: usize) -> usize {
+        let last = size as i64 - 1;
+        (match self {
+            ResampleEdge::Clamp => index.clamp(0, last),
+            ResampleEdge::Mirror if last == 0 => 0,
+            ResampleEdge::Mirror => {
+                let period = index.rem_euclid(2 * last);
+                if period <= last {
+                    period
+                } else {
+                    2 * last - period
+                }
+            }
+        }) as usize
+    }
+
+    /// Two taps and the second's weight for output pixel `n` of `out`,
+    /// pixel centers aligned across both resolutions
+    fn taps(self, n: usize, out: usize, size: usize) -> ([usize; 2], f32) {
+        let coord = (n as f32 + 0.5) * size as f32 / out as f32 - 0.5;
+        let first = coord.floor();
+        let index = first as i64;
+        ([self.resolve(index, size), self.resolve(index + 1, size)], coord - first)
+    }
+}
+
+/// Bilinear resample of a `map_width x map_height` map read through `value`,
+/// None when memory runs out. NaN samples are skipped and the rest
+/// renormalized, so holes stay holes without spreading
+fn resample(
+    value: impl Fn(usize) -> f32,
+    map_width: usize,
+    map_height: usize,
+    width: usize,
+    height: usize,
+    edge: ResampleEdge,
+) -> Option<Vec<f32>> {
+    let mut resampled = try_zeroed::<f32>(width * height)?;
+    let columns: Vec<_> = (0..width).map(|x| edge.taps(x, width, map_width)).collect();
+    for y in 0..height {
+        let (rows, fy) = edge.taps(y, height, map_height);
+        for (x, &(columns, fx)) in columns.iter().enumerate() {
+            let (mut sum, mut total) = (0.0, 0.0);
+            for (row, weight_y) in rows.into_iter().zip([1.0 - fy, fy]) {
+                for (column, weight_x) in columns.into_iter().zip([1.0 - fx, fx]) {
+                    let (sample, weight) = (value(row * map_width + column), weight_x * weight_y);
+                    if weight > 0.0 && !sample.is_nan() {
+                        sum += sample * weight;
+                        total += weight;
+                    }
+                }
+            }
+            resampled[y * width + x] = if total > 0.0 { sum / total } else { f32::NAN };
+        }
+    }
+    Some(resampled)
+}
+
+/// Resample a `map_width x map_height` depth map to `width x height`, e.g.
+/// a quarter-resolution depth estimate up to the frame. NaN entries stay
+/// unknown rather than averaging in. Empty for invalid sizes or when memory
+/// runs out
+#[wasm_bindgen]
+pub fn resample_depth(
+    depth_map: &[f32],
+    map_width: u32,
+    map_height: u32,
+    width: u32,
+    height: u32,
+    edge: ResampleEdge,
+) -> Vec<f32> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    let (map_width, map_height) = (map_width as usize, map_height as usize);
+    if map_width == 0 || map_height == 0 || depth_map.len() < map_width * map_height {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return Vec::new();
+    }
+    let value = |i: usize| depth_map[i];
+    resample(value, map_width, map_height, width as usize, height as usize, edge).unwrap_or_else(|| {
+        log_error("WASM compositor: Out of memory, depth map dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        Vec::new()
+    })
+}
+
+/// `resample_depth` for an 8-bit coverage or occlusion mask
+#[wasm_bindgen]
+pub fn resample_mask(
+    mask: &[u8],
+    map_width: u32,
+    map_height: u32,
+    width: u32,
+    height: u32,
+    edge: ResampleEdge,
+) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    let (map_width, map_height) = (map_width as usize, map_height as usize);
+    if map_width == 0 || map_height == 0 || mask.len() < map_width * map_height {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return Vec::new();
+    }
+    let value = |i: usize| mask[i] as f32;
+    let resampled = resample(value, map_width, map_height, width as usize, height as usize, edge);
+    match resampled.and_then(|resampled| try_map(&resampled, |value| (value + 0.5) as u8)) {
+        Some(mask) => mask,
+        None => {
+            log_error("WASM compositor: Out of memory, mask dropped");
+            health::degrade(DegradationTier::FrameDropped);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upsampled_border_per_edge_policy() {
+        // A ramp doubled in width: output centers fall a quarter pixel
+        // inside each source pixel, the outermost ones past the edge center
+        let depth_map = [10.0f32, 20.0, 30.0];
+        let clamp = resample_depth(&depth_map, 3, 1, 6, 1, ResampleEdge::Clamp);
+        assert_eq!(clamp, [10.0, 12.5, 17.5, 22.5, 27.5, 30.0]);
+        let mirror = resample_depth(&depth_map, 3, 1, 6, 1, ResampleEdge::Mirror);
+        assert_eq!(mirror, [12.5, 12.5, 17.5, 22.5, 27.5, 27.5]);
+
+        // Vertically alike, and a one-pixel map has nothing to reflect
+        let column = resample_depth(&depth_map, 1, 3, 1, 6, ResampleEdge::Mirror);
+        assert_eq!(column, mirror);
+        assert_eq!(resample_depth(&[4.0], 1, 1, 2, 2, ResampleEdge::Mirror), [4.0; 4]);
+    }
+
+    #[test]
+    fn test_downsample_and_holes() {
+        // Halving lands every output center between two source centers
+        let mask = [0u8, 255, 255, 0];
+        assert_eq!(resample_mask(&mask, 4, 1, 2, 1, ResampleEdge::Clamp), [128, 128]);
+        // At the same size every tap lands on its own pixel
+        assert_eq!(resample_mask(&mask, 4, 1, 4, 1, ResampleEdge::Mirror), mask);
+
+        // Unknown depth is left out of the average, not spread
+        let depth_map = [f32::NAN, 8.0, f32::NAN, f32::NAN];
+        let resampled = resample_depth(&depth_map, 2, 2, 4, 4, ResampleEdge::Clamp);
+        assert!(resampled[..4].iter().skip(1).all(|&depth| depth == 8.0), "{:?}", resampled);
+        assert!(resampled[0].is_nan() && resampled[12].is_nan());
+        assert!(resample_depth(&depth_map[..3], 2, 2, 4, 4, ResampleEdge::Clamp).is_empty());
+    }
+}
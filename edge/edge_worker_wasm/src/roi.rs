@@ -0,0 +1,233 @@
+//! Compositing creatives sized to their placement rectangle
+
+use wasm_bindgen::prelude::*;
+
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::log_error;
+use crate::memory::{try_copy, Scope, Subsystem};
+use crate::resize::{resize, ResizeFilter};
+use crate::{blend_span, UNIT_FROM_U8};
+
+/// Depth-tested composite of a creative that covers only `roi`, the flat
+/// `[x, y, w, h]` frame rectangle it is placed at (rounded to whole
+/// pixels), so small placements need no frame-sized creative and mask.
+/// `creative_frame` and `alpha_mask` are `w x h`; only the rectangle's
+/// pixels inside the frame are visited. The rectangle places the creative,
+/// so the layer's transform, fit and window are not applied; its blend
+/// settings are. An empty result means the frame was dropped for memory
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_roi(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    alpha_mask: &[u8],
+    roi: &[f32],
+    depth_map: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let (w, h) = (width as usize, height as usize);
+    let [left, top, roi_width, roi_height] = match roi {
+        &[x, y, roi_width, roi_height, ..] => {
+            [x, y, roi_width.max(0.0), roi_height.max(0.0)].map(|value| value.round() as i64)
+        }
+        _ => {
+            log_error("WASM compositor: Placement needs a rectangle");
+            return base_frame.to_vec();
+        }
+    };
+    let roi_count = (roi_width * roi_height) as usize;
+    if base_frame.len() < w * h * 4
+        || depth_map.len() < w * h
+        || creative_frame.len() < roi_count * 4
+        || alpha_mask.len() < roi_count
+    {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    let Some(mut result) = try_copy(base_frame) else {
+        log_error("WASM compositor: Out of memory, frame dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    };
+    let rect = [left, top, roi_width, roi_height];
+    blend_rect(&mut result, creative_frame, alpha_mask, rect, depth_map, w, h, creative_depth, options);
+    result
+}
+
+/// `composite_layer_roi` for a `creative_width x creative_height` creative
+/// of any size, resized with `filter` to the rectangle's, e.g. a 512x512
+/// asset into whatever region the placement covers this frame
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_resized(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    alpha_mask: &[u8],
+    creative_width: u32,
+    creative_height: u32,
+    roi: &[f32],
+    depth_map: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    filter: ResizeFilter,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    let creative_count = (creative_width * creative_height) as usize;
+    let size = match roi {
+        &[_, _, roi_width, roi_height, ..] => {
+            [roi_width, roi_height].map(|value| value.max(0.0).round() as usize)
+        }
+        _ => [0, 0],
+    };
+    if creative_count == 0 || creative_frame.len() < creative_count * 4 || alpha_mask.len() < creative_count {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+    let place = |creative: &[u8], alpha: &[u8]| {
+        let (base, depth) = (base_frame, depth_map);
+        composite_layer_roi(base, creative, alpha, roi, depth, width, height, creative_depth, options)
+    };
+    if size == [creative_width as usize, creative_height as usize] || size.contains(&0) {
+        return place(creative_frame, alpha_mask);
+    }
+
+    let (cw, ch) = (creative_width as usize, creative_height as usize);
+    let Some((creative, alpha)) = resize(creative_frame, alpha_mask, cw, ch, size[0], size[1], filter) else {
+        log_error("WASM compositor: Out of memory, layer dropped");
+        health::degrade(DegradationTier::LayerDropped);
+        return base_frame.to_vec();
+    };
+    place(&creative, &alpha)
+}
+
+/// Blend a creative covering whole-pixel `[x, y, w, h]` `rect` into `result`
+#[allow(clippy::too_many_arguments)]
+fn blend_rect(
+    result: &mut [u8],
+    creative: &[u8],
+    alpha_mask: &[u8],
+    rect: [i64; 4],
+    depth_map: &[f32],
+    w: usize,
+    h: usize,
+    creative_depth: f32,
+    options: &LayerOptions,
+) {
+    let [left, top, roi_width, roi_height] = rect;
+    // The part of the rectangle inside the frame, in frame pixels
+    let columns = left.clamp(0, w as i64) as usize..(left + roi_width).clamp(0, w as i64) as usize;
+    let rows = top.clamp(0, h as i64) as usize..(top + roi_height).clamp(0, h as i64) as usize;
+    if columns.is_empty() {
+        return;
+    }
+
+    let math = options.blend_math();
+    for y in rows {
+        // Row of the creative under this frame row, from its first visible column
+        let start = (y as i64 - top) as usize * roi_width as usize + (columns.start as i64 - left) as usize;
+        let span = start..start + columns.len();
+        let frame = y * w + columns.start..y * w + columns.end;
+        let alpha = &alpha_mask[span.clone()];
+        blend_span(
+            &mut result[frame.start * 4..frame.end * 4],
+            &creative[span.start * 4..span.end * 4],
+            &depth_map[frame],
+            creative_depth,
+            math,
+            |i| UNIT_FROM_U8[alpha[i] as usize],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_layer;
+
+    #[test]
+    fn test_matches_full_frame_placement() {
+        let (width, height) = (6u32, 4u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i * 7 % 256) as u8).collect();
+        let depth_map: Vec<f32> = (0..pixel_count).map(|i| if i % 5 == 0 { 1.0 } else { 10.0 }).collect();
+        // A 3x2 creative placed at (2, 1)
+        let creative: Vec<u8> = (0..24).map(|i| (255 - i * 9) as u8).collect();
+        let alpha = [255u8, 128, 64, 0, 200, 255];
+        let options = LayerOptions::new();
+        let frame = composite_layer_roi(
+            &base_frame, &creative, &alpha, &[2.0, 1.0, 3.0, 2.0], &depth_map, width, height, 5.0, &options,
+        );
+
+        // The same creative spread across a frame-sized buffer
+        let mut full_creative = vec![0u8; pixel_count * 4];
+        let mut full_alpha = vec![0u8; pixel_count];
+        for k in 0..6 {
+            let i = (1 + k / 3) * width as usize + 2 + k % 3;
+            full_creative[i * 4..i * 4 + 4].copy_from_slice(&creative[k * 4..k * 4 + 4]);
+            full_alpha[i] = alpha[k];
+        }
+        let expected = composite_layer(
+            &base_frame, &full_creative, &depth_map, &full_alpha, width, height, 5.0, &options,
+        );
+        assert_eq!(frame, expected);
+    }
+
+    #[test]
+    fn test_resized_creative_fills_the_rectangle() {
+        let (width, height) = (8u32, 4u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let options = LayerOptions::new();
+        // A 2x2 flat creative blown up into a 6x3 region at (1, 1)
+        let (creative, alpha) = ([40u8, 80, 120, 255].repeat(4), [255u8; 4]);
+        let roi = [1.0, 1.0, 6.0, 3.0];
+        for filter in [ResizeFilter::Bilinear, ResizeFilter::Lanczos] {
+            let frame = composite_layer_resized(
+                &base_frame, &creative, &alpha, 2, 2, &roi, &depth_map, width, height, 5.0, filter, &options,
+            );
+            let inside = |i: usize| (1..7).contains(&(i % 8)) && i / 8 >= 1;
+            for (i, pixel) in frame.chunks_exact(4).enumerate() {
+                let expected = if inside(i) { [40, 80, 120, 255] } else { [0; 4] };
+                assert_eq!(pixel, expected, "{} {:?}", i, filter);
+            }
+        }
+
+        // At the rectangle's own size nothing is resampled
+        let (creative, alpha) = ((0..72).map(|i| (i * 3) as u8).collect::<Vec<u8>>(), [200u8; 18]);
+        let resized = composite_layer_resized(
+            &base_frame, &creative, &alpha, 6, 3, &roi, &depth_map, width, height, 5.0, ResizeFilter::Lanczos,
+            &options,
+        );
+        let (base, depth) = (&base_frame, &depth_map);
+        let placed = composite_layer_roi(base, &creative, &alpha, &roi, depth, width, height, 5.0, &options);
+        assert_eq!(resized, placed);
+    }
+
+    #[test]
+    fn test_rectangle_past_the_frame_edge_is_clipped() {
+        let base_frame = vec![0u8; 16];
+        let (creative, alpha) = ([10u8, 20, 30, 40].repeat(4), [255u8; 4]);
+        let composite = |roi: &[f32], alpha: &[u8]| {
+            let options = LayerOptions::new();
+            composite_layer_roi(&base_frame, &creative, alpha, roi, &[10.0; 4], 2, 2, 5.0, &options)
+        };
+
+        // Only the rectangle's bottom-right pixel lands on the frame's top-left
+        let frame = composite(&[-1.0, -1.0, 2.0, 2.0], &alpha);
+        assert_eq!(frame[..4], [10, 20, 30, 40]);
+        assert_eq!(frame[4..], [0; 12]);
+        assert_eq!(composite(&[5.0, 0.0, 2.0, 2.0], &alpha), base_frame);
+        assert_eq!(composite(&[0.0, 0.0, 2.0, 2.0], &alpha[..3]), base_frame);
+        assert_eq!(composite(&[0.0, 0.0], &alpha), base_frame);
+    }
+}
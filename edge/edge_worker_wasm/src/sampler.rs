@@ -0,0 +1,264 @@
+//! Creative sampling with configurable edge handling
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::UNIT_FROM_U8;
+
+/// How samples that fall outside the creative are resolved
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EdgeMode {
+    /// Repeat the nearest edge pixel
+    Clamp = 0,
+    /// Reflect the creative at its edges
+    Mirror = 1,
+    /// Tile the creative
+    Wrap = 2,
+    /// Fully transparent outside, with the edge color extended underneath so
+    /// filtering across the boundary does not pull in dark fringes
+    #[default]
+    Transparent = 3,
+}
+
+/// Reconstruction filter used when sampling the creative
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Snap to the nearest source pixel (steps on slow sub-pixel motion)
+    Nearest = 0,
+    /// Interpolate the four surrounding pixels
+    #[default]
+    Bilinear = 1,
+}
+
+/// Per-pixel coverage of a mask: 8-bit, or 16-bit for soft gradient masks
+/// that would band at 256 levels
+pub trait Coverage: Copy + Default + PartialEq + Into<f32> + Send + Sync {
+    const ZERO: Self;
+
+    /// Coverage as 0..1
+    fn unit(self) -> f32;
+
+    /// Round a value on this type's scale (0 to its maximum)
+    fn quantize(value: f32) -> Self;
+
+    /// The same coverage on the 16-bit scale
+    #[cfg(feature = "effects")]
+    fn to_u16(self) -> u16;
+}
+
+impl Coverage for u8 {
+    const ZERO: u8 = 0;
+
+    fn unit(self) -> f32 {
+        UNIT_FROM_U8[self as usize]
+    }
+
+    fn quantize(value: f32) -> u8 {
+        quantize(value)
+    }
+
+    #[cfg(feature = "effects")]
+    fn to_u16(self) -> u16 {
+        self as u16 * 257
+    }
+}
+
+impl Coverage for u16 {
+    const ZERO: u16 = 0;
+
+    fn unit(self) -> f32 {
+        self as f32 * (1.0 / 65535.0)
+    }
+
+    fn quantize(value: f32) -> u16 {
+        (value + 0.5).clamp(0.0, 65535.0) as u16
+    }
+
+    #[cfg(feature = "effects")]
+    fn to_u16(self) -> u16 {
+        self
+    }
+}
+
+/// RGBA creative plus its coverage mask, both `width * height`
+pub struct Source<'a, A: Coverage = u8> {
+    pub creative: &'a [u8],
+    pub alpha: &'a [A],
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Resolved source taps along one axis for a sample coordinate; rows and
+/// columns of an axis-aligned warp share them, so they are computed once
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisTap {
+    pub index: [usize; 2],
+    pub inside: [bool; 2],
+    /// Weight of the second tap (zero for nearest sampling)
+    pub frac: f32,
+}
+
+impl AxisTap {
+    pub fn new(coord: f32, size: usize, edge: EdgeMode, filter: FilterMode) -> AxisTap {
+        match filter {
+            FilterMode::Nearest => {
+                let (index, inside) = resolve(coord.floor() as i64, size, edge);
+                AxisTap { index: [index; 2], inside: [inside; 2], frac: 0.0 }
+            }
+            FilterMode::Bilinear => {
+                // Pixel centers sit at n + 0.5
+                let shifted = coord - 0.5;
+                let first = shifted.floor();
+                let (i0, inside0) = resolve(first as i64, size, edge);
+                let (i1, inside1) = resolve(first as i64 + 1, size, edge);
+                AxisTap { index: [i0, i1], inside: [inside0, inside1], frac: shifted - first }
+            }
+        }
+    }
+
+    /// True when a tap lands on the source range `start..end`
+    pub fn reaches(&self, start: f32, end: f32) -> bool {
+        (0..2).any(|k| self.inside[k] && (start..end).contains(&(self.index[k] as f32)))
+    }
+
+    /// Index of the tap closest to the sample point
+    fn nearest(&self) -> usize {
+        self.index[(self.frac >= 0.5) as usize]
+    }
+}
+
+impl<A: Coverage> Source<'_, A> {
+    /// Sample at continuous source coordinates (pixel centers at `n + 0.5`),
+    /// resolving taps for this point alone; for mappings that are not
+    /// separable, and the per-pixel reference for tests
+    pub fn sample(&self, x: f32, y: f32, edge: EdgeMode, filter: FilterMode) -> ([u8; 4], A) {
+        let tap_x = AxisTap::new(x, self.width, edge, filter);
+        let tap_y = AxisTap::new(y, self.height, edge, filter);
+        self.sample_taps(&tap_x, &tap_y)
+    }
+
+    /// Combine the separable taps of one output pixel; color is weighted by
+    /// coverage so transparent neighbours never darken the edge
+    pub fn sample_taps(&self, tap_x: &AxisTap, tap_y: &AxisTap) -> ([u8; 4], A) {
+        let weights_x = [1.0 - tap_x.frac, tap_x.frac];
+        let weights_y = [1.0 - tap_y.frac, tap_y.frac];
+
+        let mut weighted = [0.0f32; 4];
+        let mut coverage = 0.0f32;
+        for (row, weight_y) in weights_y.into_iter().enumerate() {
+            if !tap_y.inside[row] {
+                continue;
+            }
+            for (column, weight_x) in weights_x.into_iter().enumerate() {
+                let weight = weight_x * weight_y;
+                if weight <= 0.0 || !tap_x.inside[column] {
+                    continue;
+                }
+
+                let index = tap_y.index[row] * self.width + tap_x.index[column];
+                let alpha = weight * self.alpha[index].into();
+                coverage += alpha;
+                for (channel, value) in weighted.iter_mut().enumerate() {
+                    *value += alpha * self.creative[index * 4 + channel] as f32;
+                }
+            }
+        }
+
+        // Without coverage the color is irrelevant to blending; keep the
+        // nearest one so edge extension still holds
+        if coverage <= 0.0 {
+            let index = tap_y.nearest() * self.width + tap_x.nearest();
+            let mut color = [0u8; 4];
+            color.copy_from_slice(&self.creative[index * 4..index * 4 + 4]);
+            return (color, A::ZERO);
+        }
+
+        let color = weighted.map(|value| quantize(value / coverage));
+        (color, A::quantize(coverage))
+    }
+}
+
+/// Round a 0..255 float to the nearest u8
+fn quantize(value: f32) -> u8 {
+    (value + 0.5).clamp(0.0, 255.0) as u8
+}
+
+/// Map a possibly out-of-range coordinate to a valid index; the flag is
+/// false when the sample must be treated as transparent
+fn resolve(coord: i64, size: usize, edge: EdgeMode) -> (usize, bool) {
+    let size = size as i64;
+    let last = size - 1;
+    match edge {
+        EdgeMode::Clamp => (coord.clamp(0, last) as usize, true),
+        EdgeMode::Wrap => (coord.rem_euclid(size) as usize, true),
+        EdgeMode::Mirror => {
+            // Period of 2 * size: 0..size forwards, then size..2*size reflected
+            let period = coord.rem_euclid(2 * size);
+            let index = if period < size { period } else { 2 * size - 1 - period };
+            (index as usize, true)
+        }
+        EdgeMode::Transparent => (coord.clamp(0, last) as usize, (0..size).contains(&coord)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_edge_modes() {
+        // Coordinates -2..6 over a 4 pixel wide source
+        let coords = -2..6;
+        let clamp: Vec<_> = coords.clone().map(|c| resolve(c, 4, EdgeMode::Clamp).0).collect();
+        let wrap: Vec<_> = coords.clone().map(|c| resolve(c, 4, EdgeMode::Wrap).0).collect();
+        let mirror: Vec<_> = coords.clone().map(|c| resolve(c, 4, EdgeMode::Mirror).0).collect();
+
+        assert_eq!(clamp, vec![0, 0, 0, 1, 2, 3, 3, 3]);
+        assert_eq!(wrap, vec![2, 3, 0, 1, 2, 3, 0, 1]);
+        assert_eq!(mirror, vec![1, 0, 0, 1, 2, 3, 3, 2]);
+    }
+
+    #[test]
+    fn test_bilinear_is_exact_at_pixel_centers() {
+        let creative = [0u8, 0, 0, 255, 100, 100, 100, 255];
+        let alpha = [255u8, 255];
+        let source = Source { creative: &creative, alpha: &alpha, width: 2, height: 1 };
+
+        let bilinear = |x, y| source.sample(x, y, EdgeMode::Clamp, FilterMode::Bilinear);
+
+        assert_eq!(bilinear(0.5, 0.5), ([0, 0, 0, 255], 255));
+        assert_eq!(bilinear(1.5, 0.5), ([100, 100, 100, 255], 255));
+        assert_eq!(bilinear(1.0, 0.5), ([50, 50, 50, 255], 255));
+    }
+
+    #[test]
+    fn test_bilinear_edge_has_no_dark_fringe() {
+        let creative = [200u8, 200, 200, 255];
+        let alpha = [255u8];
+        let source = Source { creative: &creative, alpha: &alpha, width: 1, height: 1 };
+
+        // Halfway off the edge: coverage halves but the color stays bright
+        let (color, coverage) = source.sample(1.0, 0.5, EdgeMode::Transparent, FilterMode::Bilinear);
+        assert_eq!(color, [200, 200, 200, 255]);
+        assert_eq!(coverage, 128);
+
+        // A 16-bit mask keeps the exact half
+        let alpha = [65535u16];
+        let source = Source { creative: &creative, alpha: &alpha, width: 1, height: 1 };
+        let (_, coverage) = source.sample(1.0, 0.5, EdgeMode::Transparent, FilterMode::Bilinear);
+        assert_eq!(coverage, 32768);
+    }
+
+    #[test]
+    fn test_transparent_extends_edge_color() {
+        let creative = [10u8, 20, 30, 255, 40, 50, 60, 255];
+        let alpha = [255u8, 255];
+        let source = Source { creative: &creative, alpha: &alpha, width: 2, height: 1 };
+
+        // Outside on the right: edge color kept, coverage dropped
+        let nearest = |edge| source.sample(3.5, 0.5, edge, FilterMode::Nearest);
+        assert_eq!(nearest(EdgeMode::Transparent), ([40, 50, 60, 255], 0));
+        assert_eq!(nearest(EdgeMode::Clamp), ([40, 50, 60, 255], 255));
+    }
+}
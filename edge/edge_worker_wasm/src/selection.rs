@@ -0,0 +1,228 @@
+//! Runtime selection among several candidate placement surfaces
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::{visible_area, Rect};
+
+/// Candidate speed, in frame diagonals per second, at which stability halves
+const STABILITY_HALF_SPEED: f32 = 3.0;
+
+/// Picks the best candidate surface per frame window by scoring visibility,
+/// stability and size, locking the choice in for a period so the placement
+/// does not flap between surfaces; all timing follows the supplied frame
+/// timestamps so variable frame rate content behaves the same as constant
+#[wasm_bindgen]
+pub struct CandidateSelector {
+    lock_in_seconds: f64,
+    visibility_weight: f32,
+    stability_weight: f32,
+    size_weight: f32,
+    selected: Option<usize>,
+    switched_at: f64,
+    previous: Vec<Rect>,
+    previous_pts: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl CandidateSelector {
+    #[wasm_bindgen(constructor)]
+    pub fn new(lock_in_seconds: f64) -> CandidateSelector {
+        CandidateSelector {
+            lock_in_seconds,
+            visibility_weight: 0.5,
+            stability_weight: 0.3,
+            size_weight: 0.2,
+            selected: None,
+            switched_at: 0.0,
+            previous: Vec::new(),
+            previous_pts: None,
+        }
+    }
+
+    /// Relative importance of each scoring term (normalized internally)
+    pub fn set_weights(&mut self, visibility: f32, stability: f32, size: f32) {
+        let total = visibility.max(0.0) + stability.max(0.0) + size.max(0.0);
+        if total <= 0.0 {
+            return;
+        }
+        self.visibility_weight = visibility.max(0.0) / total;
+        self.stability_weight = stability.max(0.0) / total;
+        self.size_weight = size.max(0.0) / total;
+    }
+
+    /// Drop the current choice and motion history, e.g. on a new scene
+    pub fn reset(&mut self) {
+        self.selected = None;
+        self.previous.clear();
+        self.previous_pts = None;
+    }
+
+    /// Score the candidates (flat `[x, y, w, h, ...]` in pixels) of the frame
+    /// at `pts_seconds` and return the selected index, or -1 when none is usable
+    pub fn select(
+        &mut self,
+        candidate_rects: &[f32],
+        width: u32,
+        height: u32,
+        exclusion_rects: &[f32],
+        pts_seconds: f64,
+    ) -> i32 {
+        let candidates = Rect::from_flat(candidate_rects);
+        let exclusions = Rect::from_flat(exclusion_rects);
+        let frame = Rect::new(0.0, 0.0, width as f32, height as f32);
+
+        // Frame interval for velocity; unknown or non-monotonic counts as no motion
+        let dt = self
+            .previous_pts
+            .map(|prev| pts_seconds - prev)
+            .filter(|&dt| dt > 0.0);
+
+        let scores: Vec<f32> = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, rect)| self.score(i, rect, &frame, &exclusions, dt))
+            .collect();
+        self.previous = candidates;
+        self.previous_pts = Some(pts_seconds);
+
+        // Keep the locked-in candidate while it is still visible at all. A
+        // frame before the switch means a seek backwards, which ends the
+        // lock-in instead of holding it until playback catches up
+        let seeked_back = pts_seconds < self.switched_at;
+        if let Some(current) = self.selected {
+            let usable = scores.get(current).is_some_and(|&s| s > 0.0);
+            if usable && !seeked_back && pts_seconds - self.switched_at < self.lock_in_seconds {
+                return current as i32;
+            }
+        }
+
+        let best = scores
+            .iter()
+            .enumerate()
+            .filter(|(_, &s)| s > 0.0)
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| i);
+
+        if best != self.selected || seeked_back {
+            self.selected = best;
+            self.switched_at = pts_seconds;
+        }
+        best.map_or(-1, |i| i as i32)
+    }
+}
+
+impl CandidateSelector {
+    /// Weighted score in 0..1; zero when the candidate is not visible
+    fn score(
+        &self,
+        index: usize,
+        rect: &Rect,
+        frame: &Rect,
+        exclusions: &[Rect],
+        dt: Option<f64>,
+    ) -> f32 {
+        if rect.is_empty() {
+            return 0.0;
+        }
+
+        let visibility = visible_area(rect, frame, exclusions) / rect.area();
+        if visibility <= 0.0 {
+            return 0.0;
+        }
+
+        // Fast-moving candidates are less trustworthy; measured per second
+        // rather than per frame so the score is frame-rate independent
+        let stability = match (self.previous.get(index), dt) {
+            (Some(prev), Some(dt)) => {
+                let (px, py) = prev.center();
+                let (cx, cy) = rect.center();
+                let diagonal = frame.width.hypot(frame.height).max(1.0);
+                let speed = (cx - px).hypot(cy - py) / diagonal / dt as f32;
+                1.0 / (1.0 + speed / STABILITY_HALF_SPEED)
+            }
+            _ => 1.0,
+        };
+
+        let size = (rect.area() / frame.area().max(1.0)).min(1.0);
+
+        self.visibility_weight * visibility
+            + self.stability_weight * stability
+            + self.size_weight * size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selects_largest_visible_candidate() {
+        let mut selector = CandidateSelector::new(0.0);
+
+        // Small and large candidate, both fully visible
+        let candidates = [0.0, 0.0, 10.0, 10.0, 50.0, 50.0, 40.0, 40.0];
+        assert_eq!(selector.select(&candidates, 100, 100, &[], 0.0), 1);
+
+        // Exclude the large one entirely
+        let excluded = [50.0, 50.0, 40.0, 40.0];
+        assert_eq!(selector.select(&candidates, 100, 100, &excluded, 0.04), 0);
+    }
+
+    #[test]
+    fn test_lock_in_prevents_flapping() {
+        let mut selector = CandidateSelector::new(0.5);
+        let candidates = [0.0, 0.0, 20.0, 20.0, 50.0, 50.0, 20.0, 20.0];
+
+        // Half of candidate 1 is excluded, so candidate 0 wins
+        let half = [50.0, 50.0, 10.0, 20.0];
+        assert_eq!(selector.select(&candidates, 100, 100, &half, 0.0), 0);
+
+        // Candidate 0 becomes half excluded, candidate 1 clear: still locked in,
+        // however irregular the frame spacing is
+        let other_half = [0.0, 0.0, 10.0, 20.0];
+        for pts in [0.1, 0.15, 0.4, 0.49] {
+            assert_eq!(selector.select(&candidates, 100, 100, &other_half, pts), 0);
+        }
+
+        // Lock-in period has elapsed
+        assert_eq!(selector.select(&candidates, 100, 100, &other_half, 0.5), 1);
+    }
+
+    #[test]
+    fn test_seek_backwards_ends_lock_in() {
+        let mut selector = CandidateSelector::new(0.5);
+        let candidates = [0.0, 0.0, 20.0, 20.0, 50.0, 50.0, 20.0, 20.0];
+        let (half, other_half) = ([50.0, 50.0, 10.0, 20.0], [0.0, 0.0, 10.0, 20.0]);
+        assert_eq!(selector.select(&candidates, 100, 100, &half, 10.0), 0);
+        assert_eq!(selector.select(&candidates, 100, 100, &other_half, 10.2), 0);
+
+        // Seeking back picks afresh, then locks in from the seek point
+        assert_eq!(selector.select(&candidates, 100, 100, &other_half, 2.0), 1);
+        assert_eq!(selector.select(&candidates, 100, 100, &half, 2.3), 1);
+        assert_eq!(selector.select(&candidates, 100, 100, &half, 2.5), 0);
+    }
+
+    #[test]
+    fn test_stability_is_frame_rate_independent() {
+        let frame = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let mut at_30fps = CandidateSelector::new(0.0);
+        let mut at_60fps = CandidateSelector::new(0.0);
+
+        // Same motion speed sampled at two frame rates
+        at_30fps.previous = vec![Rect::new(0.0, 0.0, 10.0, 10.0)];
+        at_60fps.previous = vec![Rect::new(1.0, 0.0, 10.0, 10.0)];
+        let rect = Rect::new(2.0, 0.0, 10.0, 10.0);
+
+        let score_30 = at_30fps.score(0, &rect, &frame, &[], Some(1.0 / 30.0));
+        let score_60 = at_60fps.score(0, &rect, &frame, &[], Some(1.0 / 60.0));
+        assert!((score_30 - score_60).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_no_usable_candidates() {
+        let mut selector = CandidateSelector::new(0.1);
+        assert_eq!(selector.select(&[], 100, 100, &[], 0.0), -1);
+        let covered = [0.0, 0.0, 10.0, 10.0];
+        assert_eq!(selector.select(&covered, 100, 100, &covered, 0.04), -1);
+    }
+}
@@ -0,0 +1,263 @@
+//! Frame sequence checks: duplicated, out-of-order and missing frames
+
+use wasm_bindgen::prelude::*;
+
+/// Timestamps closer than this are the same frame, matching the microsecond
+/// resolution of frame seeds
+const SAME_PTS_SECONDS: f64 = 1e-6;
+
+/// Weight of each new interval in the running frame interval estimate
+const INTERVAL_SMOOTHING: f64 = 0.1;
+
+/// What is wrong with a frame's place in the sequence
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SequenceIssue {
+    #[default]
+    None = 0,
+    /// Same PTS as the previous frame
+    Duplicate = 1,
+    /// PTS before the previous frame's
+    OutOfOrder = 2,
+    /// Frames missing before this one
+    Gap = 3,
+}
+
+/// How the host should treat a frame
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SequenceAction {
+    /// Composite it as usual
+    #[default]
+    Accept = 0,
+    /// Skip it; stateful filters never see it
+    Drop = 1,
+    /// Composite it after resetting stateful filters such as
+    /// `PlacementSolver` and `CandidateSelector`
+    Reset = 2,
+}
+
+/// Checks each frame's PTS against the last accepted one before it reaches
+/// the stateful filters, with a configurable action per issue
+#[wasm_bindgen]
+pub struct SequenceValidator {
+    duplicate_action: SequenceAction,
+    out_of_order_action: SequenceAction,
+    gap_action: SequenceAction,
+    /// A gap is an interval over this many frame intervals
+    gap_intervals: f64,
+    /// Nominal frame interval, or 0 to estimate it from the stream
+    nominal_interval: f64,
+    estimated_interval: Option<f64>,
+    last_pts: Option<f64>,
+    last_issue: SequenceIssue,
+    frames: u64,
+    duplicates: u64,
+    out_of_order: u64,
+    gaps: u64,
+    dropped: u64,
+}
+
+impl Default for SequenceValidator {
+    fn default() -> Self {
+        Self {
+            duplicate_action: SequenceAction::Drop,
+            out_of_order_action: SequenceAction::Drop,
+            gap_action: SequenceAction::Reset,
+            gap_intervals: 2.5,
+            nominal_interval: 0.0,
+            estimated_interval: None,
+            last_pts: None,
+            last_issue: SequenceIssue::None,
+            frames: 0,
+            duplicates: 0,
+            out_of_order: 0,
+            gaps: 0,
+            dropped: 0,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl SequenceValidator {
+    /// Drops duplicates and out-of-order frames, resets on gaps
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SequenceValidator {
+        SequenceValidator::default()
+    }
+
+    pub fn set_duplicate_action(&mut self, action: SequenceAction) {
+        self.duplicate_action = action;
+    }
+
+    /// `Reset` treats a backwards jump as a seek and accepts it
+    pub fn set_out_of_order_action(&mut self, action: SequenceAction) {
+        self.out_of_order_action = action;
+    }
+
+    pub fn set_gap_action(&mut self, action: SequenceAction) {
+        self.gap_action = action;
+    }
+
+    /// Intervals longer than `intervals` frame intervals count as a gap
+    pub fn set_gap_threshold(&mut self, intervals: f64) {
+        self.gap_intervals = intervals.max(1.0);
+    }
+
+    /// Known stream frame rate; 0 estimates the interval from the stream,
+    /// which suits variable frame rate content
+    pub fn set_frame_rate(&mut self, fps: f64) {
+        self.nominal_interval = if fps > 0.0 { 1.0 / fps } else { 0.0 };
+    }
+
+    /// Check the next frame and say what to do with it
+    pub fn check(&mut self, pts_seconds: f64) -> SequenceAction {
+        self.frames += 1;
+        let Some(last) = self.last_pts else {
+            self.last_pts = Some(pts_seconds);
+            self.last_issue = SequenceIssue::None;
+            return SequenceAction::Accept;
+        };
+
+        let delta = pts_seconds - last;
+        let interval = self.interval();
+        let issue = if delta.abs() < SAME_PTS_SECONDS {
+            self.duplicates += 1;
+            SequenceIssue::Duplicate
+        } else if delta < 0.0 {
+            self.out_of_order += 1;
+            SequenceIssue::OutOfOrder
+        } else if interval.is_some_and(|interval| delta > interval * self.gap_intervals) {
+            self.gaps += 1;
+            SequenceIssue::Gap
+        } else {
+            SequenceIssue::None
+        };
+        self.last_issue = issue;
+
+        let action = match issue {
+            SequenceIssue::None => SequenceAction::Accept,
+            SequenceIssue::Duplicate => self.duplicate_action,
+            SequenceIssue::OutOfOrder => self.out_of_order_action,
+            SequenceIssue::Gap => self.gap_action,
+        };
+        if action == SequenceAction::Drop {
+            self.dropped += 1;
+            return action;
+        }
+
+        // Only regular intervals feed the estimate, so a gap cannot stretch it
+        if issue == SequenceIssue::None {
+            self.estimated_interval = Some(match self.estimated_interval {
+                Some(estimate) => estimate + (delta - estimate) * INTERVAL_SMOOTHING,
+                None => delta,
+            });
+        }
+        self.last_pts = Some(pts_seconds);
+        action
+    }
+
+    /// Issue found by the last `check`
+    pub fn last_issue(&self) -> SequenceIssue {
+        self.last_issue
+    }
+
+    /// Forget the last frame after an intentional seek, so the jump is not
+    /// reported
+    pub fn reset(&mut self) {
+        self.last_pts = None;
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order
+    }
+
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Counters as JSON, for the host's stats reporting
+    pub fn stats(&self) -> String {
+        format!(
+            "{{\"frames\":{},\"duplicates\":{},\"out_of_order\":{},\"gaps\":{},\"dropped\":{}}}",
+            self.frames, self.duplicates, self.out_of_order, self.gaps, self.dropped
+        )
+    }
+}
+
+impl SequenceValidator {
+    fn interval(&self) -> Option<f64> {
+        if self.nominal_interval > 0.0 {
+            Some(self.nominal_interval)
+        } else {
+            self.estimated_interval
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates_and_reordering_are_dropped() {
+        use SequenceAction::*;
+        let mut validator = SequenceValidator::new();
+        let actions: Vec<SequenceAction> =
+            [0.0, 0.04, 0.04, 0.02, 0.08].iter().map(|&pts| validator.check(pts)).collect();
+        assert_eq!(actions, vec![Accept, Accept, Drop, Drop, Accept]);
+        assert_eq!((validator.duplicates(), validator.out_of_order(), validator.dropped()), (1, 1, 2));
+        assert_eq!(
+            validator.stats(),
+            "{\"frames\":5,\"duplicates\":1,\"out_of_order\":1,\"gaps\":0,\"dropped\":2}"
+        );
+    }
+
+    #[test]
+    fn test_gaps_use_estimated_or_nominal_interval() {
+        let mut validator = SequenceValidator::new();
+        for i in 0..10 {
+            assert_eq!(validator.check(i as f64 * 0.04), SequenceAction::Accept);
+        }
+        // Three frames missing
+        assert_eq!(validator.check(0.52), SequenceAction::Reset);
+        assert_eq!(validator.last_issue(), SequenceIssue::Gap);
+        assert_eq!(validator.check(0.56), SequenceAction::Accept);
+
+        // A 25 fps stream: two missing frames are a gap from the second frame on
+        let mut validator = SequenceValidator::new();
+        validator.set_frame_rate(25.0);
+        validator.check(0.0);
+        assert_eq!(validator.check(0.12), SequenceAction::Reset);
+        assert_eq!(validator.gaps(), 1);
+    }
+
+    #[test]
+    fn test_configured_actions_and_reset() {
+        let mut validator = SequenceValidator::new();
+        validator.set_out_of_order_action(SequenceAction::Reset);
+        validator.check(5.0);
+        validator.check(5.04);
+
+        // A rewind treated as a seek becomes the new reference point
+        assert_eq!(validator.check(1.0), SequenceAction::Reset);
+        assert_eq!(validator.check(1.04), SequenceAction::Accept);
+
+        // An intentional seek is not reported at all
+        validator.reset();
+        assert_eq!(validator.check(30.0), SequenceAction::Accept);
+        assert_eq!(validator.last_issue(), SequenceIssue::None);
+    }
+}
@@ -0,0 +1,234 @@
+//! Shadow compositing: comparing a candidate pipeline against the live one
+
+use wasm_bindgen::prelude::*;
+
+use crate::composite_layer;
+use crate::layer::LayerOptions;
+use crate::random::fnv1a;
+
+/// SSIM window edge in pixels
+const WINDOW: usize = 8;
+
+/// SSIM stabilizers for 8-bit luma, `(0.01 * 255)^2` and `(0.03 * 255)^2`
+const C1: f64 = 6.5025;
+const C2: f64 = 58.5225;
+
+/// Runs a second, experimental set of `LayerOptions` (another blend
+/// precision, filter, effect chain...) on sampled frames next to the live
+/// options and records how far the results diverge. Only the live frame is
+/// ever returned
+#[wasm_bindgen]
+pub struct ShadowCompositor {
+    shadow: LayerOptions,
+    sample_every: u32,
+    /// Sampled frames with SSIM below this count as diverged
+    ssim_threshold: f64,
+    frames: u64,
+    sampled: u64,
+    mismatched: u64,
+    diverged: u64,
+    min_ssim: Option<f64>,
+    last: Option<Comparison>,
+}
+
+/// Outcome of one sampled frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Comparison {
+    live_hash: u64,
+    shadow_hash: u64,
+    ssim: f64,
+    max_difference: u8,
+}
+
+#[wasm_bindgen]
+impl ShadowCompositor {
+    /// Compare against `shadow` on one frame in every `sample_every`
+    #[wasm_bindgen(constructor)]
+    pub fn new(shadow: &LayerOptions, sample_every: u32) -> ShadowCompositor {
+        ShadowCompositor {
+            shadow: shadow.clone(),
+            sample_every: sample_every.max(1),
+            ssim_threshold: 0.99,
+            frames: 0,
+            sampled: 0,
+            mismatched: 0,
+            diverged: 0,
+            min_ssim: None,
+            last: None,
+        }
+    }
+
+    pub fn set_ssim_threshold(&mut self, threshold: f64) {
+        self.ssim_threshold = threshold;
+    }
+
+    /// Same as `composite_layer` with the live `options`; sampled frames are
+    /// also composited with the shadow options and compared
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_layer(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        let sample = self.frames.is_multiple_of(self.sample_every as u64);
+        self.frames += 1;
+
+        // The shadow runs first so the live frame has the last word in health
+        let composite = |options: &LayerOptions| {
+            composite_layer(
+                base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth, options,
+            )
+        };
+        let shadow = if sample { composite(&self.shadow) } else { Vec::new() };
+        let live = composite(options);
+
+        // A frame either side dropped for memory has nothing to compare
+        if sample && !shadow.is_empty() && shadow.len() == live.len() {
+            self.record(compare(&live, &shadow, width as usize, height as usize));
+        }
+        live
+    }
+
+    /// Comparison counters as JSON: `frames`, `sampled`, `mismatched`
+    /// (hashes differ), `diverged` (SSIM under the threshold), `min_ssim`,
+    /// and the last sample's `ssim`, `max_difference` and hex hashes
+    pub fn report(&self) -> String {
+        let last = match &self.last {
+            Some(last) => format!(
+                r#"{{"ssim":{:.6},"max_difference":{},"live_hash":"{:016x}","shadow_hash":"{:016x}"}}"#,
+                last.ssim, last.max_difference, last.live_hash, last.shadow_hash
+            ),
+            None => "null".to_string(),
+        };
+        format!(
+            r#"{{"frames":{},"sampled":{},"mismatched":{},"diverged":{},"min_ssim":{},"last":{}}}"#,
+            self.frames,
+            self.sampled,
+            self.mismatched,
+            self.diverged,
+            self.min_ssim.map_or("null".to_string(), |ssim| format!("{:.6}", ssim)),
+            last,
+        )
+    }
+
+    pub fn diverged(&self) -> u64 {
+        self.diverged
+    }
+}
+
+impl ShadowCompositor {
+    fn record(&mut self, comparison: Comparison) {
+        self.sampled += 1;
+        if comparison.live_hash != comparison.shadow_hash {
+            self.mismatched += 1;
+        }
+        if comparison.ssim < self.ssim_threshold {
+            self.diverged += 1;
+        }
+        self.min_ssim = Some(self.min_ssim.map_or(comparison.ssim, |min| min.min(comparison.ssim)));
+        self.last = Some(comparison);
+    }
+}
+
+fn compare(live: &[u8], shadow: &[u8], width: usize, height: usize) -> Comparison {
+    let max_difference = live.iter().zip(shadow).map(|(&a, &b)| a.abs_diff(b)).max().unwrap_or(0);
+    Comparison {
+        live_hash: fnv1a(live),
+        shadow_hash: fnv1a(shadow),
+        ssim: ssim(live, shadow, width, height),
+        max_difference,
+    }
+}
+
+/// BT.601 luma of an RGBA pixel
+fn luma(pixel: &[u8]) -> f64 {
+    0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+}
+
+/// Mean luma SSIM over non-overlapping 8x8 windows (partial windows at the
+/// right and bottom edges included)
+fn ssim(a: &[u8], b: &[u8], width: usize, height: usize) -> f64 {
+    let (mut total, mut windows) = (0.0, 0);
+    for top in (0..height).step_by(WINDOW) {
+        for left in (0..width).step_by(WINDOW) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            let mut count = 0.0;
+            for y in top..(top + WINDOW).min(height) {
+                for x in left..(left + WINDOW).min(width) {
+                    let index = (y * width + x) * 4;
+                    let (la, lb) = (luma(&a[index..]), luma(&b[index..]));
+                    sum_a += la;
+                    sum_b += lb;
+                    sum_aa += la * la;
+                    sum_bb += lb * lb;
+                    sum_ab += la * lb;
+                    count += 1.0;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let variance_a = sum_aa / count - mean_a * mean_a;
+            let variance_b = sum_bb / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rounding;
+
+    #[test]
+    fn test_ssim_of_identical_and_different_frames() {
+        let frame: Vec<u8> = (0..16 * 16 * 4).map(|i| (i * 7 % 256) as u8).collect();
+        assert!((ssim(&frame, &frame, 16, 16) - 1.0).abs() < 1e-9);
+
+        let inverted: Vec<u8> = frame.iter().map(|&v| 255 - v).collect();
+        assert!(ssim(&frame, &inverted, 16, 16) < 0.5);
+    }
+
+    #[test]
+    fn test_shadow_samples_without_changing_output() {
+        let (width, height) = (16u32, 16u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 199) as u8).collect();
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 211) as u8).collect();
+        let depth_map = vec![10.0f32; pixel_count];
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| (i % 256) as u8).collect();
+
+        let live = LayerOptions::new();
+        let mut candidate = LayerOptions::new();
+        candidate.set_rounding(Rounding::Truncate);
+        let expected = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &live,
+        );
+
+        let mut shadow = ShadowCompositor::new(&candidate, 2);
+        for _ in 0..4 {
+            let frame = shadow.composite_layer(
+                &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &live,
+            );
+            assert_eq!(frame, expected);
+        }
+
+        // Truncating instead of rounding: hashes differ, SSIM barely moves
+        let report = shadow.report();
+        assert!(report.starts_with(r#"{"frames":4,"sampled":2,"mismatched":2,"diverged":0,"#));
+        assert!(report.contains(r#""max_difference":1,"#));
+        assert_eq!(shadow.diverged(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+//! Four-pixel integer blend, on WASM SIMD128 when the build enables it
+
+use crate::blend::{blend_pixel, integer_weight, BlendMath, Rounding, WEIGHT_ONE};
+use crate::occlusion::visibility;
+
+/// Pixels blended per kernel call
+const LANES: usize = 4;
+
+/// `BlendPrecision::Integer` depth-tested blend of a run of pixels, four at
+/// a time; bit-identical to blending each with `blend_pixel`. Pixels that
+/// fail the depth test get weight zero, which leaves them unchanged. With
+/// alpha modes set, groups with a non-opaque pixel blend one by one
+pub fn blend_span4(
+    result: &mut [u8],
+    creative: &[u8],
+    depth: &[f32],
+    creative_depth: f32,
+    math: BlendMath,
+    coverage: impl Fn(usize) -> f32,
+) {
+    let alpha = |i: usize, scene_depth: f32| {
+        let visible = visibility(creative_depth, scene_depth, math.depth_feather);
+        if visible > 0.0 {
+            coverage(i) * visible
+        } else {
+            0.0
+        }
+    };
+    let weight = |i: usize, scene_depth: f32| {
+        let alpha = alpha(i, scene_depth);
+        if alpha > 0.0 {
+            integer_weight(alpha * math.opacity)
+        } else {
+            0
+        }
+    };
+    let quads = depth.len() / LANES;
+    let groups = result
+        .chunks_exact_mut(LANES * 4)
+        .zip(creative.chunks_exact(LANES * 4))
+        .zip(depth.chunks_exact(LANES));
+    for (quad, ((pixels, creative), depth)) in groups.enumerate() {
+        let weights: [u32; LANES] = std::array::from_fn(|k| weight(quad * LANES + k, depth[k]));
+        if weights == [0; LANES] {
+            continue;
+        }
+        let opaque = |bytes: &[u8]| bytes.chunks_exact(4).all(|pixel| pixel[3] == 255);
+        if math.alpha.is_none() || (opaque(pixels) && opaque(creative)) {
+            blend4(pixels, creative, weights, math.rounding);
+            continue;
+        }
+        for k in (0..LANES).filter(|&k| weights[k] > 0) {
+            let (pixel, layer) = (&mut pixels[k * 4..k * 4 + 4], &creative[k * 4..k * 4 + 4]);
+            blend_pixel(pixel, layer, alpha(quad * LANES + k, depth[k]), math);
+        }
+    }
+
+    // The tail that does not fill a kernel call
+    for i in quads * LANES..depth.len() {
+        let alpha = alpha(i, depth[i]);
+        if alpha > 0.0 {
+            blend_pixel(&mut result[i * 4..i * 4 + 4], &creative[i * 4..i * 4 + 4], alpha, math);
+        }
+    }
+}
+
+/// Rounding bias added before dividing by `WEIGHT_ONE`; its divisor is odd,
+/// so every to-nearest mode agrees
+fn rounding_bias(rounding: Rounding) -> u32 {
+    match rounding {
+        Rounding::Truncate => 0,
+        Rounding::HalfUp | Rounding::HalfEven => WEIGHT_ONE / 2,
+    }
+}
+
+/// `value / WEIGHT_ONE` by shifts, exact over the blend's range
+#[cfg(any(test, not(all(target_arch = "wasm32", target_feature = "simd128"))))]
+fn divide(value: u32) -> u32 {
+    (value + 1 + (value >> 16)) >> 16
+}
+
+/// Blend four RGBA pixels (16 bytes each side) with per-pixel weights
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+fn blend4(pixels: &mut [u8], creative: &[u8], weights: [u32; LANES], rounding: Rounding) {
+    use core::arch::wasm32::*;
+
+    let (pixels, creative) = (&mut pixels[..16], &creative[..16]);
+    // SAFETY: both slices hold 16 bytes, and v128 loads and stores take
+    // unaligned addresses
+    let (base, layer) =
+        unsafe { (v128_load(pixels.as_ptr() as *const v128), v128_load(creative.as_ptr() as *const v128)) };
+    // One u32x4 of channels per pixel
+    let widen = |bytes: v128| {
+        let (low, high) = (u16x8_extend_low_u8x16(bytes), u16x8_extend_high_u8x16(bytes));
+        [
+            u32x4_extend_low_u16x8(low),
+            u32x4_extend_high_u16x8(low),
+            u32x4_extend_low_u16x8(high),
+            u32x4_extend_high_u16x8(high),
+        ]
+    };
+    let (base, layer) = (widen(base), widen(layer));
+    let (bias, one) = (u32x4_splat(rounding_bias(rounding)), u32x4_splat(1));
+    let blended: [v128; LANES] = std::array::from_fn(|k| {
+        let (weight, inverse) = (u32x4_splat(weights[k]), u32x4_splat(WEIGHT_ONE - weights[k]));
+        let value = i32x4_add(i32x4_add(i32x4_mul(layer[k], weight), i32x4_mul(base[k], inverse)), bias);
+        u32x4_shr(i32x4_add(i32x4_add(value, one), u32x4_shr(value, 16)), 16)
+    });
+    let packed = u8x16_narrow_i16x8(
+        u16x8_narrow_i32x4(blended[0], blended[1]),
+        u16x8_narrow_i32x4(blended[2], blended[3]),
+    );
+    // SAFETY: as for the loads
+    unsafe { v128_store(pixels.as_mut_ptr() as *mut v128, packed) };
+}
+
+/// The same arithmetic one lane at a time, for builds without SIMD128
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+fn blend4(pixels: &mut [u8], creative: &[u8], weights: [u32; LANES], rounding: Rounding) {
+    let bias = rounding_bias(rounding);
+    for (k, weight) in weights.into_iter().enumerate() {
+        for channel in k * 4..k * 4 + 4 {
+            let value = creative[channel] as u32 * weight + pixels[channel] as u32 * (WEIGHT_ONE - weight);
+            pixels[channel] = divide(value + bias) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blend::{AlphaModes, BlendPrecision};
+
+    #[test]
+    fn test_shift_division_is_exact() {
+        for value in 0..=255 * WEIGHT_ONE + WEIGHT_ONE / 2 {
+            assert_eq!(divide(value), value / WEIGHT_ONE, "value {}", value);
+        }
+    }
+
+    #[test]
+    fn test_matches_scalar_blend() {
+        // 11 pixels: two kernel calls and a tail of three
+        let pixel_count = 11;
+        let base: Vec<u8> = (0..pixel_count * 4).map(|i| (i * 37 % 256) as u8).collect();
+        let creative: Vec<u8> = (0..pixel_count * 4).map(|i| (i * 91 % 256) as u8).collect();
+        let depth: Vec<f32> = (0..pixel_count).map(|i| if i % 4 == 1 { 1.0 } else { 10.0 }).collect();
+        let coverage = |i: usize| [0.0, 0.25, 1.0, 0.5, 0.9999, 0.1, 0.77, 0.0, 0.3, 1.0, 0.6][i];
+
+        // The last pass takes the "over" for its non-opaque pixels
+        let over = Some(AlphaModes::default());
+        let passes = [(Rounding::Truncate, None), (Rounding::HalfUp, None), (Rounding::HalfEven, over)];
+        for (rounding, alpha) in passes {
+            let precision = BlendPrecision::Integer;
+            let math = BlendMath { precision, rounding, alpha, ..BlendMath::default() };
+            let mut expected = base.clone();
+            for i in (0..pixel_count).filter(|&i| 5.0 < depth[i] && coverage(i) > 0.0) {
+                blend_pixel(&mut expected[i * 4..i * 4 + 4], &creative[i * 4..i * 4 + 4], coverage(i), math);
+            }
+
+            let mut result = base.clone();
+            blend_span4(&mut result, &creative, &depth, 5.0, math, coverage);
+            assert_eq!(result, expected, "{:?} {:?}", rounding, alpha);
+        }
+    }
+}
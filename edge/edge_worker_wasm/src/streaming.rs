@@ -0,0 +1,250 @@
+//! Progressive compositing of frames supplied in horizontal slabs
+
+use std::ops::Range;
+
+use wasm_bindgen::prelude::*;
+
+use crate::fit::fit_to_frame;
+use crate::geometry::{mask_bounds, Rect};
+use crate::layer::{LayerOptions, LayerWarp};
+use crate::memory::{try_copy, Scope, Subsystem};
+use crate::sampler::{Coverage, Source};
+use crate::window::apply_window;
+use crate::health::{self, DegradationTier};
+use crate::{composite_layer_rows, grade_to_frame, log_error, Bands};
+
+/// Composites a layer onto a frame too large to hold at once. The creative
+/// and mask are kept for the whole frame, since the layer transform may
+/// sample any source row; the base frame and depth arrive top to bottom in
+/// horizontal slabs and each slab is returned blended, so working memory is
+/// one slab plus a band of warp scratch. The only state carried across slab
+/// boundaries is the next output row
+#[wasm_bindgen]
+pub struct SlabCompositor {
+    creative: Vec<u8>,
+    alpha: Mask,
+    width: usize,
+    height: usize,
+    creative_depth: f32,
+    options: LayerOptions,
+    bounds: Option<Rect>,
+    next_row: usize,
+}
+
+/// The layer's kept mask; effects leave it at 16 bits
+enum Mask {
+    Narrow(Vec<u8>),
+    #[cfg(feature = "effects")]
+    Wide(Vec<u16>),
+}
+
+#[wasm_bindgen]
+impl SlabCompositor {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        creative_frame: &[u8],
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> SlabCompositor {
+        let _memory = Scope::enter(Subsystem::Streaming);
+        health::frame_started();
+        let pixel_count = (width * height) as usize;
+        let mut compositor = SlabCompositor {
+            creative: Vec::new(),
+            alpha: Mask::Narrow(Vec::new()),
+            width: width as usize,
+            height: height as usize,
+            creative_depth,
+            options: options.clone(),
+            bounds: None,
+            next_row: 0,
+        };
+
+        // Without a usable layer every slab passes through untouched
+        let fitted = fit_to_frame(creative_frame, alpha_mask, width, height, options);
+        let Some((creative_frame, alpha_mask)) = &fitted else {
+            return compositor;
+        };
+        let windowed = apply_window(creative_frame, width, height, options);
+        let graded = grade_to_frame(windowed, options, options.frame_color);
+        let (creative_frame, alpha_mask) = (&graded[..], &alpha_mask[..]);
+        if creative_frame.len() < pixel_count * 4 || alpha_mask.len() < pixel_count {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return compositor;
+        }
+        if options.scale <= 0.0 {
+            return compositor;
+        }
+
+        // Short of memory the layer goes without effects, and failing that
+        // every slab passes through
+        #[cfg(feature = "effects")]
+        if !options.effects.is_empty() {
+            let (width, height) = (compositor.width, compositor.height);
+            match options.apply_effects(creative_frame, alpha_mask, width, height, None) {
+                Some((creative, alpha)) => {
+                    (compositor.creative, compositor.alpha) = (creative, Mask::Wide(alpha));
+                }
+                None => {
+                    log_error("WASM compositor: Out of memory, skipping effects");
+                    health::degrade(DegradationTier::Reduced);
+                }
+            }
+        }
+        if compositor.creative.is_empty() {
+            let creative = try_copy(&creative_frame[..pixel_count * 4]);
+            let Some((creative, alpha)) = creative.zip(try_copy(&alpha_mask[..pixel_count])) else {
+                log_error("WASM compositor: Out of memory, layer dropped");
+                health::degrade(DegradationTier::LayerDropped);
+                return compositor;
+            };
+            (compositor.creative, compositor.alpha) = (creative, Mask::Narrow(alpha));
+        }
+        compositor.bounds = match &compositor.alpha {
+            Mask::Narrow(alpha) => mask_bounds(alpha, width, height),
+            #[cfg(feature = "effects")]
+            Mask::Wide(alpha) => mask_bounds(alpha, width, height),
+        };
+        compositor
+    }
+
+    /// Blend the next slab: whole rows of RGBA base frame plus their depth.
+    /// An invalid slab is returned unchanged and does not advance the cursor
+    pub fn composite_slab(&mut self, base_slab: &[u8], depth_slab: &[f32]) -> Vec<u8> {
+        let _memory = Scope::enter(Subsystem::Streaming);
+        let row_bytes = self.width * 4;
+        if row_bytes == 0 || !base_slab.len().is_multiple_of(row_bytes) {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return base_slab.to_vec();
+        }
+
+        let end = (self.next_row + base_slab.len() / row_bytes).min(self.height);
+        let rows = self.next_row..end;
+        if depth_slab.len() < rows.len() * self.width {
+            log_error("WASM compositor: Invalid input buffer sizes");
+            return base_slab.to_vec();
+        }
+
+        let mut result = base_slab.to_vec();
+        if let Some(bounds) = self.bounds {
+            let (result, rows) = (&mut result, rows.clone());
+            match &self.alpha {
+                Mask::Narrow(alpha) => self.composite_rows(alpha, &bounds, result, depth_slab, rows),
+                #[cfg(feature = "effects")]
+                Mask::Wide(alpha) => self.composite_rows(alpha, &bounds, result, depth_slab, rows),
+            }
+        }
+
+        self.next_row = rows.end;
+        result
+    }
+
+    /// First frame row of the next slab
+    pub fn next_row(&self) -> usize {
+        self.next_row
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_row >= self.height
+    }
+
+    /// Start again from the top row
+    pub fn reset(&mut self) {
+        self.next_row = 0;
+    }
+}
+
+impl SlabCompositor {
+    fn composite_rows<A: Coverage>(
+        &self,
+        alpha: &[A],
+        bounds: &Rect,
+        result: &mut [u8],
+        depth_slab: &[f32],
+        rows: Range<usize>,
+    ) {
+        let source = Source { creative: &self.creative, alpha, width: self.width, height: self.height };
+        let warp = LayerWarp::new(&source, bounds, &self.options);
+        composite_layer_rows(
+            result,
+            &mut Bands::default(),
+            None,
+            depth_slab,
+            None,
+            &warp,
+            rows,
+            self.width,
+            self.creative_depth,
+            self.options.blend_math(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_layer;
+
+    #[test]
+    fn test_slabs_match_whole_frame() {
+        let (width, height) = (600u32, 300u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 211) as u8).collect();
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 197) as u8).collect();
+        // Scattered foreground pixels exercise the per-slab depth
+        let depth_map: Vec<f32> =
+            (0..pixel_count).map(|i| if i % 7 == 0 { 1.0 } else { 10.0 }).collect();
+        let mut alpha_mask = vec![0u8; pixel_count];
+        for y in 40..260 {
+            for x in 100..500 {
+                alpha_mask[y * width as usize + x] = (y % 256) as u8;
+            }
+        }
+
+        let mut options = LayerOptions::new();
+        options.set_transform(-6.5, 21.25, 1.2);
+        let expected = composite_layer(
+            &base_frame, &creative_frame, &depth_map, &alpha_mask, width, height, 5.0, &options,
+        );
+
+        // Uneven slab heights, including ones straddling the placement edges
+        let mut compositor = SlabCompositor::new(&creative_frame, &alpha_mask, width, height, 5.0, &options);
+        let mut streamed = Vec::new();
+        for slab_rows in [1, 37, 64, 100, 98] {
+            let rows = compositor.next_row()..compositor.next_row() + slab_rows;
+            let pixels = rows.start * width as usize..rows.end * width as usize;
+            let slab = compositor.composite_slab(
+                &base_frame[pixels.start * 4..pixels.end * 4],
+                &depth_map[pixels],
+            );
+            streamed.extend(slab);
+        }
+
+        assert!(compositor.is_complete());
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_invalid_slab_passes_through() {
+        let creative_frame = vec![255u8; 4 * 4];
+        let alpha_mask = vec![255u8; 4];
+        let options = LayerOptions::new();
+        let mut compositor = SlabCompositor::new(&creative_frame, &alpha_mask, 2, 2, 5.0, &options);
+
+        // Partial row
+        let slab = vec![7u8; 6];
+        assert_eq!(compositor.composite_slab(&slab, &[10.0; 2]), slab);
+        assert_eq!(compositor.next_row(), 0);
+
+        // Missing depth
+        let slab = vec![7u8; 8];
+        assert_eq!(compositor.composite_slab(&slab, &[]), slab);
+        assert_eq!(compositor.next_row(), 0);
+
+        assert_eq!(compositor.composite_slab(&slab, &[10.0; 2]), vec![255u8; 8]);
+        assert_eq!(compositor.next_row(), 1);
+    }
+}
@@ -0,0 +1,482 @@
+//! Placements spanning several disjoint surfaces with one creative
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::blend_pixel;
+use crate::geometry::{clip_polygon, Homography, Rect};
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::memory::{try_copy, try_zeroed, Scope, Subsystem};
+use crate::occlusion::visibility;
+use crate::sampler::{Coverage, Source};
+use crate::window::clamp_window;
+use crate::{log_error, log_info};
+
+/// How a surface's quad maps onto the unit square
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Mapping {
+    /// Frame point to unit square, for a planar surface seen in perspective
+    Perspective(Homography),
+    /// Corners clockwise from the top-left, interpolated bilinearly
+    Bilinear([(f32, f32); 4]),
+}
+
+impl Mapping {
+    fn unit(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        match self {
+            Mapping::Perspective(to_square) => Some(to_square.apply(x, y)),
+            Mapping::Bilinear(corners) => inverse_bilinear(corners, x, y),
+        }
+    }
+}
+
+/// One surface of a placement: a frame quad showing a window of the creative
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Surface {
+    mapping: Mapping,
+    /// Frame-space bounding box of the quad
+    bounds: Rect,
+    /// Normalized part of the creative shown on the quad
+    window: Rect,
+}
+
+impl Surface {
+    pub(crate) fn new(corners: &[(f32, f32); 4], mapping: Mapping, window: Rect) -> Surface {
+        let span = |values: [f32; 4]| {
+            let min = values.into_iter().fold(f32::INFINITY, f32::min);
+            (min, values.into_iter().fold(f32::NEG_INFINITY, f32::max))
+        };
+        let ((left, right), (top, bottom)) = (span(corners.map(|(x, _)| x)), span(corners.map(|(_, y)| y)));
+        Surface { mapping, bounds: Rect::new(left, top, right - left, bottom - top), window }
+    }
+
+    /// Position on the quad of a frame point, when it lands on it
+    fn locate(&self, x: f32, y: f32) -> Option<(f32, f32)> {
+        let (s, t) = self.mapping.unit(x, y)?;
+        ((0.0..1.0).contains(&s) && (0.0..1.0).contains(&t)).then_some((s, t))
+    }
+}
+
+/// Unit-square position `(s, t)` of a point inside a bilinear quad, solving
+/// `p = p0 + s e + t f + s t g` for the root that lands on the quad
+fn inverse_bilinear(corners: &[(f32, f32); 4], x: f32, y: f32) -> Option<(f32, f32)> {
+    let cross = |a: (f32, f32), b: (f32, f32)| a.0 * b.1 - a.1 * b.0;
+    let [p0, p1, p2, p3] = *corners;
+    let e = (p1.0 - p0.0, p1.1 - p0.1);
+    let f = (p3.0 - p0.0, p3.1 - p0.1);
+    let g = (p0.0 - p1.0 + p2.0 - p3.0, p0.1 - p1.1 + p2.1 - p3.1);
+    let h = (x - p0.0, y - p0.1);
+    let (k2, k1, k0) = (cross(g, f), cross(e, f) + cross(h, g), cross(h, e));
+
+    // `s` from whichever axis is better conditioned
+    let s_at = |t: f32| {
+        let (den_x, den_y) = (e.0 + g.0 * t, e.1 + g.1 * t);
+        if den_x.abs() > den_y.abs() {
+            (h.0 - f.0 * t) / den_x
+        } else {
+            (h.1 - f.1 * t) / den_y
+        }
+    };
+    let on_quad = |t: f32| (0.0..1.0).contains(&t).then(|| (s_at(t), t));
+    if k2.abs() < 1e-6 * k1.abs().max(1.0) {
+        // Opposite edges parallel: linear in t
+        return (k1 != 0.0).then(|| (s_at(-k0 / k1), -k0 / k1));
+    }
+    let discriminant = k1 * k1 - 4.0 * k0 * k2;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    [(-k1 - root) / (2.0 * k2), (-k1 + root) / (2.0 * k2)]
+        .into_iter()
+        .filter_map(on_quad)
+        .find(|&(s, _)| (0.0..1.0).contains(&s))
+}
+
+/// Depth-tested composite of `surfaces` sharing one creative; each frame
+/// pixel is taken by the first surface it lands on, so shared seams and
+/// overlaps are blended once. An empty result means the frame was dropped
+/// for memory
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn composite_surfaces<A: Coverage>(
+    surfaces: &[Surface],
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    creative_alpha: &[A],
+    creative_width: u32,
+    creative_height: u32,
+    depth_map: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let pixel_count = (width * height) as usize;
+    let creative_count = (creative_width * creative_height) as usize;
+    if base_frame.len() < pixel_count * 4
+        || depth_map.len() < pixel_count
+        || creative_count == 0
+        || creative_frame.len() < creative_count * 4
+        || creative_alpha.len() < creative_count
+    {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+    if surfaces.is_empty() {
+        log_info("WASM compositor: Placement has no surfaces");
+        return base_frame.to_vec();
+    }
+
+    let Some((mut result, mut claimed)) = try_copy(base_frame).zip(try_zeroed::<bool>(pixel_count)) else {
+        log_error("WASM compositor: Out of memory, frame dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    };
+    let source = Source {
+        creative: creative_frame,
+        alpha: creative_alpha,
+        width: creative_width as usize,
+        height: creative_height as usize,
+    };
+    let frame = Rect::new(0.0, 0.0, width as f32, height as f32);
+    let math = options.blend_math();
+    for surface in surfaces {
+        let Some(area) = surface.bounds.intersection(&frame) else {
+            continue;
+        };
+        let columns = area.x.floor() as usize..(area.right().ceil() as usize).min(width as usize);
+        for y in area.y.floor() as usize..(area.bottom().ceil() as usize).min(height as usize) {
+            for x in columns.clone() {
+                let i = y * width as usize + x;
+                if claimed[i] {
+                    continue;
+                }
+                let Some((s, t)) = surface.locate(x as f32 + 0.5, y as f32 + 0.5) else {
+                    continue;
+                };
+                claimed[i] = true;
+                let visible = visibility(creative_depth, depth_map[i], math.depth_feather);
+                if visible == 0.0 {
+                    continue;
+                }
+
+                let window = &surface.window;
+                let u = (window.x + s * window.width) * source.width as f32;
+                let v = (window.y + t * window.height) * source.height as f32;
+                let (color, coverage) = source.sample(u, v, options.edge_mode, options.filter_mode);
+                if coverage != A::ZERO {
+                    blend_pixel(&mut result[i * 4..i * 4 + 4], &color, coverage.unit() * visible, math);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Planar surface showing `window` on a convex quad, None for any other
+pub(crate) fn perspective_surface(corners: &[(f32, f32); 4], window: Rect) -> Option<Surface> {
+    let to_square = Homography::square_to_quad(corners)?.invert()?;
+    Some(Surface::new(corners, Mapping::Perspective(to_square), window))
+}
+
+/// A logical placement made of warped sub-quads sharing one creative, each
+/// showing its own part of it, e.g. a banner running across two adjacent
+/// LED boards. Surfaces are composited together into one result, so a
+/// frame never shows some of them and not others
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct SurfacePlacement {
+    surfaces: Vec<Surface>,
+}
+
+#[wasm_bindgen]
+impl SurfacePlacement {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SurfacePlacement {
+        SurfacePlacement::default()
+    }
+
+    /// Add a surface: its frame quad (flat `[x, y]` for four corners,
+    /// clockwise from the one showing the window's top-left) and the
+    /// normalized creative window `u, v, width, height` it shows. False,
+    /// leaving the placement unchanged, for a quad that is not convex
+    pub fn add_surface(&mut self, quad: &[f32], u: f32, v: f32, width: f32, height: f32) -> bool {
+        let corners: Vec<(f32, f32)> = quad.chunks_exact(2).map(|p| (p[0], p[1])).collect();
+        let Ok(corners) = <[(f32, f32); 4]>::try_from(corners) else {
+            log_error("WASM compositor: Surface quad needs four corners");
+            return false;
+        };
+        let Some(surface) = perspective_surface(&corners, clamp_window(u, v, width, height)) else {
+            log_error("WASM compositor: Surface quad is not convex");
+            return false;
+        };
+        self.surfaces.push(surface);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.surfaces.clear();
+    }
+
+    pub fn surface_count(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    /// Depth-tested composite of every surface, sampling the creative
+    /// (`creative_width x creative_height` RGBA and mask) per `options`
+    /// edge and filter modes. Pixels on a seam shared by two quads belong
+    /// to the surface added first. An empty result means the frame was
+    /// dropped for memory
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite(
+        &self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        creative_alpha: &[u8],
+        creative_width: u32,
+        creative_height: u32,
+        depth_map: &[f32],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        composite_surfaces(
+            &self.surfaces,
+            base_frame,
+            creative_frame,
+            creative_alpha,
+            creative_width,
+            creative_height,
+            depth_map,
+            width,
+            height,
+            creative_depth,
+            options,
+        )
+    }
+}
+
+/// Depth-tested composite of a creative (`creative_width x creative_height`
+/// RGBA and mask) warped in perspective by `matrix`, a row-major 3x3
+/// homography from creative pixels to frame pixels such as a tracker or
+/// `findHomography` produces. Sampled per `options` edge and filter modes
+/// with no pre-warped frame-size creative; a matrix that is singular or
+/// folds the creative across the horizon passes the frame through. An
+/// empty result means the frame was dropped for memory
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_homography(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    creative_alpha: &[u8],
+    creative_width: u32,
+    creative_height: u32,
+    depth_map: &[f32],
+    matrix: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let Some(to_frame) = Homography::from_matrix(matrix) else {
+        log_error("WASM compositor: Homography needs nine finite entries");
+        return base_frame.to_vec();
+    };
+    let unit_to_frame = to_frame.scale_input(creative_width as f32, creative_height as f32);
+    let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(s, t)| unit_to_frame.apply(s, t));
+    let Some(surface) = perspective_surface(&corners, Rect::new(0.0, 0.0, 1.0, 1.0)) else {
+        log_error("WASM compositor: Homography does not map the creative to a convex quad");
+        return base_frame.to_vec();
+    };
+    composite_surfaces(
+        &[surface],
+        base_frame,
+        creative_frame,
+        creative_alpha,
+        creative_width,
+        creative_height,
+        depth_map,
+        width,
+        height,
+        creative_depth,
+        options,
+    )
+}
+
+/// `composite_layer_homography` with the map given by the frame quad (flat
+/// `[x, y]` corners, clockwise from the creative's top-left) instead
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_quad(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    creative_alpha: &[u8],
+    creative_width: u32,
+    creative_height: u32,
+    depth_map: &[f32],
+    quad: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let mut placement = SurfacePlacement::new();
+    if !placement.add_surface(quad, 0.0, 0.0, 1.0, 1.0) {
+        return base_frame.to_vec();
+    }
+    placement.composite(
+        base_frame,
+        creative_frame,
+        creative_alpha,
+        creative_width,
+        creative_height,
+        depth_map,
+        width,
+        height,
+        creative_depth,
+        options,
+    )
+}
+
+/// Hotspot polygon (flat `[x, y, ...]`) of a `composite_layer_homography`
+/// placement: the creative's outline warped by `matrix` and cut to the
+/// frame, in frame pixels. Empty for a matrix that composites nothing
+#[wasm_bindgen]
+pub fn homography_hotspot(
+    matrix: &[f32],
+    creative_width: u32,
+    creative_height: u32,
+    width: u32,
+    height: u32,
+) -> Vec<f32> {
+    let Some(to_frame) = Homography::from_matrix(matrix) else {
+        return Vec::new();
+    };
+    let unit_to_frame = to_frame.scale_input(creative_width as f32, creative_height as f32);
+    let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(s, t)| unit_to_frame.apply(s, t));
+    if perspective_surface(&corners, Rect::new(0.0, 0.0, 1.0, 1.0)).is_none() {
+        return Vec::new();
+    }
+    frame_hotspot(&corners, width, height)
+}
+
+/// Flat `[x, y, ...]` of a frame-space outline cut to the frame
+pub(crate) fn frame_hotspot(outline: &[(f32, f32)], width: u32, height: u32) -> Vec<f32> {
+    let frame = Rect::new(0.0, 0.0, width as f32, height as f32);
+    clip_polygon(outline, &frame).into_iter().flat_map(<[f32; 2]>::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Left half red, right half blue, fully opaque
+    fn split_creative() -> (Vec<u8>, Vec<u8>) {
+        let creative = (0..8).flat_map(|x| if x < 4 { [255, 0, 0, 255] } else { [0, 0, 255, 255] }).collect();
+        (creative, vec![255; 8])
+    }
+
+    #[test]
+    fn test_surfaces_show_their_windows() {
+        let (width, height) = (12u32, 2u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let (creative, alpha) = split_creative();
+
+        // Two boards apart: the left one shows the creative's left half
+        let mut placement = SurfacePlacement::new();
+        assert!(placement.add_surface(&[0.0, 0.0, 4.0, 0.0, 4.0, 2.0, 0.0, 2.0], 0.0, 0.0, 0.5, 1.0));
+        assert!(placement.add_surface(&[8.0, 0.0, 12.0, 0.0, 12.0, 2.0, 8.0, 2.0], 0.5, 0.0, 0.5, 1.0));
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(crate::FilterMode::Nearest);
+        let result = placement.composite(
+            &base_frame, &creative, &alpha, 8, 1, &depth_map, width, height, 5.0, &options,
+        );
+
+        let pixel = |x: usize| &result[x * 4..x * 4 + 4];
+        assert_eq!(pixel(1), [255, 0, 0, 255]);
+        assert_eq!(pixel(6), [0, 0, 0, 0]);
+        assert_eq!(pixel(10), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_shared_seam_is_blended_once() {
+        let (width, height) = (8u32, 1u32);
+        let base_frame = vec![0u8; 32];
+        let depth_map = vec![10.0f32; 8];
+        let creative = vec![200u8; 4];
+        let alpha = vec![128u8];
+
+        // Overlapping quads: the overlap takes half coverage, not three quarters
+        let mut placement = SurfacePlacement::new();
+        assert!(placement.add_surface(&[0.0, 0.0, 5.0, 0.0, 5.0, 1.0, 0.0, 1.0], 0.0, 0.0, 1.0, 1.0));
+        assert!(placement.add_surface(&[3.0, 0.0, 8.0, 0.0, 8.0, 1.0, 3.0, 1.0], 0.0, 0.0, 1.0, 1.0));
+        let mut options = LayerOptions::new();
+        options.set_edge_mode(crate::EdgeMode::Clamp);
+        let result = placement.composite(
+            &base_frame, &creative, &alpha, 1, 1, &depth_map, width, height, 5.0, &options,
+        );
+        assert!(result.chunks_exact(4).all(|pixel| pixel == &result[..4]));
+        assert_eq!(result[0], 100);
+    }
+
+    #[test]
+    fn test_rejects_non_convex_quads() {
+        let mut placement = SurfacePlacement::new();
+        assert!(!placement.add_surface(&[0.0, 0.0, 4.0, 4.0, 4.0, 0.0, 0.0, 4.0], 0.0, 0.0, 1.0, 1.0));
+        assert!(!placement.add_surface(&[0.0, 0.0, 4.0, 0.0], 0.0, 0.0, 1.0, 1.0));
+        assert_eq!(placement.surface_count(), 0);
+
+        // Without surfaces the frame passes through
+        let base_frame = vec![9u8; 4];
+        let options = LayerOptions::new();
+        let result = placement.composite(&base_frame, &[0; 4], &[255], 1, 1, &[10.0], 1, 1, 5.0, &options);
+        assert_eq!(result, base_frame);
+    }
+
+    #[test]
+    fn test_homography_matches_its_quad() {
+        let (width, height) = (16u32, 12u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        let (creative, alpha) = split_creative();
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(crate::FilterMode::Nearest);
+        let warp = |matrix: &[f32]| {
+            composite_layer_homography(
+                &base_frame, &creative, &alpha, 8, 1, &depth_map, matrix, width, height, 5.0, &options,
+            )
+        };
+
+        // Creative pixels to frame with some perspective
+        let matrix = [1.5, 0.2, 2.0, 0.1, 8.0, 1.0, 0.01, 0.02, 1.0];
+        let to_frame = Homography::from_matrix(&matrix).unwrap();
+        let corners = [(0.0, 0.0), (8.0, 0.0), (8.0, 1.0), (0.0, 1.0)];
+        let quad: Vec<f32> =
+            corners.iter().flat_map(|&(x, y)| <[f32; 2]>::from(to_frame.apply(x, y))).collect();
+        let expected = composite_layer_quad(
+            &base_frame, &creative, &alpha, 8, 1, &depth_map, &quad, width, height, 5.0, &options,
+        );
+        let warped = warp(&matrix);
+        assert_eq!(warped, expected);
+        assert!(warped.chunks_exact(4).any(|pixel| pixel == [255, 0, 0, 255]));
+
+        // Its hotspot covers just the pixels the perspective quad took
+        let hotspot = homography_hotspot(&matrix, 8, 1, width, height);
+        for (i, pixel) in warped.chunks_exact(4).enumerate() {
+            let (x, y) = ((i % 16) as f32 + 0.5, (i / 16) as f32 + 0.5);
+            assert_eq!(crate::hotspot_contains(&hotspot, x, y), pixel[3] == 255, "({}, {})", x, y);
+        }
+        assert!(homography_hotspot(&[0.0; 9], 8, 1, width, height).is_empty());
+
+        // Singular, short and horizon-crossing matrices pass through
+        assert_eq!(warp(&[0.0; 9]), base_frame);
+        assert_eq!(warp(&matrix[..8]), base_frame);
+        assert_eq!(warp(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0, -0.25, 0.0, 1.0]), base_frame);
+    }
+}
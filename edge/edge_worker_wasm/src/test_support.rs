@@ -0,0 +1,427 @@
+//! Test support for partners certifying their own compositors
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::blend::BlendMode;
+use crate::flags::{with_flag, REFERENCE_MODE};
+use crate::layer::LayerOptions;
+use crate::plan::CompiledPlan;
+use crate::random::fnv1a;
+use crate::sampler::{EdgeMode, FilterMode};
+use crate::{compile_plan, composite_layer};
+
+/// Size of the conformance frames
+pub(crate) const WIDTH: u32 = 16;
+pub(crate) const HEIGHT: u32 = 8;
+const CREATIVE_DEPTH: f32 = 5.0;
+
+/// Size of the creative plan bundles place on their surfaces
+const CREATIVE_WIDTH: u32 = 8;
+const CREATIVE_HEIGHT: u32 = 4;
+
+/// The layer options a vector exercises; everything else is the default
+#[derive(Clone, Copy)]
+pub(crate) struct Case {
+    pub(crate) name: &'static str,
+    transform: (f32, f32, f32),
+    filter_mode: FilterMode,
+    edge_mode: EdgeMode,
+    blend_mode: BlendMode,
+    opacity: f32,
+    depth_feather: f32,
+}
+
+pub(crate) const CASES: [Case; 5] = [
+    Case {
+        name: "identity_normal",
+        transform: (0.0, 0.0, 1.0),
+        filter_mode: FilterMode::Bilinear,
+        edge_mode: EdgeMode::Transparent,
+        blend_mode: BlendMode::Normal,
+        opacity: 1.0,
+        depth_feather: 0.0,
+    },
+    Case {
+        name: "subpixel_bilinear",
+        transform: (0.37, -0.21, 1.0),
+        filter_mode: FilterMode::Bilinear,
+        edge_mode: EdgeMode::Transparent,
+        blend_mode: BlendMode::Normal,
+        opacity: 1.0,
+        depth_feather: 0.0,
+    },
+    Case {
+        name: "scaled_nearest_clamp",
+        transform: (1.0, 0.5, 0.75),
+        filter_mode: FilterMode::Nearest,
+        edge_mode: EdgeMode::Clamp,
+        blend_mode: BlendMode::Normal,
+        opacity: 1.0,
+        depth_feather: 0.0,
+    },
+    Case {
+        name: "multiply_opacity",
+        transform: (0.0, 0.0, 1.0),
+        filter_mode: FilterMode::Bilinear,
+        edge_mode: EdgeMode::Transparent,
+        blend_mode: BlendMode::Multiply,
+        opacity: 0.6,
+        depth_feather: 0.0,
+    },
+    Case {
+        name: "screen_feathered_depth",
+        transform: (-0.5, 0.25, 1.1),
+        filter_mode: FilterMode::Bilinear,
+        edge_mode: EdgeMode::Mirror,
+        blend_mode: BlendMode::Screen,
+        opacity: 1.0,
+        depth_feather: 2.0,
+    },
+];
+
+/// A manifest composited at one presentation time, with default options
+pub(crate) struct PlanCase {
+    pub(crate) name: &'static str,
+    pub(crate) manifest: &'static str,
+    pub(crate) pts_seconds: f64,
+}
+
+pub(crate) const PLAN_CASES: [PlanCase; 2] = [
+    PlanCase {
+        name: "plan_single_surface",
+        manifest: r#"{"manifest_id": "conformance", "title_id": "single", "video_metadata": {"fps": 25.0},
+            "opportunities": [{"opportunity_id": "wall", "frame_range": [0, 50],
+            "surface_coordinates": [[2, 1], [10, 1], [10, 7], [2, 7]]}]}"#,
+        pts_seconds: 1.0,
+    },
+    PlanCase {
+        name: "plan_overlapping_perspective",
+        manifest: r#"{"manifest_id": "conformance", "title_id": "pair", "video_metadata": {"fps": 25.0},
+            "opportunities": [
+            {"opportunity_id": "floor", "frame_range": [0, 25], "smoothing_profile": "rigid",
+            "surface_coordinates": [[1, 4], [15, 4], [13.5, 8], [0.5, 8]]},
+            {"opportunity_id": "sign", "frame_range": [10, 40],
+            "surface_coordinates": [[9.25, 0.5], [14.75, 1.5], [14.25, 6.5], [9.5, 5.75]]}]}"#,
+        pts_seconds: 0.6,
+    },
+];
+
+impl Case {
+    pub(crate) fn options(&self) -> LayerOptions {
+        let mut options = LayerOptions::new();
+        let (dx, dy, scale) = self.transform;
+        options.set_transform(dx, dy, scale);
+        options.set_filter_mode(self.filter_mode);
+        options.set_edge_mode(self.edge_mode);
+        options.set_blend_mode(self.blend_mode);
+        options.set_opacity(self.opacity);
+        options.set_depth_feather(self.depth_feather);
+        options
+    }
+
+    fn vector_options(&self) -> VectorOptions {
+        let (dx, dy, scale) = self.transform;
+        VectorOptions {
+            dx,
+            dy,
+            scale,
+            filter_mode: variant_name(self.filter_mode),
+            edge_mode: variant_name(self.edge_mode),
+            blend_mode: variant_name(self.blend_mode),
+            opacity: self.opacity,
+            depth_feather: self.depth_feather,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct VectorOptions {
+    dx: f32,
+    dy: f32,
+    scale: f32,
+    filter_mode: String,
+    edge_mode: String,
+    blend_mode: String,
+    opacity: f32,
+    depth_feather: f32,
+}
+
+/// One `composite_layer` call and its reference output
+#[derive(Serialize)]
+struct ConformanceVector {
+    name: &'static str,
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: VectorOptions,
+    base_frame: Vec<u8>,
+    creative_frame: Vec<u8>,
+    alpha_mask: Vec<u8>,
+    depth_map: Vec<f32>,
+    expected: Vec<u8>,
+    /// FNV-1a of `expected`, hex
+    checksum: String,
+}
+
+/// Inputs shared by every vector: gradients, a mask with a soft rim and a
+/// foreground object over the right quarter of the frame
+pub(crate) struct Inputs {
+    base_frame: Vec<u8>,
+    creative_frame: Vec<u8>,
+    alpha_mask: Vec<u8>,
+    depth_map: Vec<f32>,
+}
+
+impl Inputs {
+    pub(crate) fn new() -> Inputs {
+        let (w, h) = (WIDTH as usize, HEIGHT as usize);
+        let pixels = || (0..h).flat_map(move |y| (0..w).map(move |x| (x, y)));
+        let mask = |x: usize, y: usize| match (x, y) {
+            (4..=11, 2..=5) => 255,
+            (3..=12, 1..=6) => 128,
+            _ => 0,
+        };
+        let base = |x: usize, y: usize| [x as u8 * 16, y as u8 * 32, (x + y) as u8 * 8, 255];
+        let creative = |x: usize, y: usize| [255 - x as u8 * 12, 40 + y as u8 * 20, 128, 255];
+        Inputs {
+            base_frame: pixels().flat_map(|(x, y)| base(x, y)).collect(),
+            creative_frame: pixels().flat_map(|(x, y)| creative(x, y)).collect(),
+            alpha_mask: pixels().map(|(x, y)| mask(x, y)).collect(),
+            depth_map: pixels().map(|(x, _)| if x >= 12 { 4.0 } else { 10.0 }).collect(),
+        }
+    }
+
+    pub(crate) fn composite(&self, options: &LayerOptions) -> Vec<u8> {
+        let (base, creative) = (&self.base_frame[..], &self.creative_frame[..]);
+        let (depth, alpha) = (&self.depth_map[..], &self.alpha_mask[..]);
+        composite_layer(base, creative, depth, alpha, WIDTH, HEIGHT, CREATIVE_DEPTH, options)
+    }
+
+    /// The creative cropped to plan size, with a soft rim as alpha
+    fn plan_creative(&self) -> (Vec<u8>, Vec<u8>) {
+        let (w, h) = (CREATIVE_WIDTH as usize, CREATIVE_HEIGHT as usize);
+        let rows = (0..h).map(|y| y * WIDTH as usize + 4);
+        let creative = rows.flat_map(|row| &self.creative_frame[row * 4..(row + w) * 4]).copied();
+        let rim = |x: usize, y: usize| if x == 0 || x == w - 1 || y == 0 || y == h - 1 { 128 } else { 255 };
+        let alpha = (0..h).flat_map(|y| (0..w).map(move |x| rim(x, y)));
+        (creative.collect(), alpha.collect())
+    }
+
+    pub(crate) fn composite_plan(&self, plan: &CompiledPlan, pts_seconds: f64) -> Vec<u8> {
+        let (creative, alpha) = self.plan_creative();
+        let (cw, ch) = (CREATIVE_WIDTH, CREATIVE_HEIGHT);
+        let (base, depth) = (&self.base_frame[..], &self.depth_map[..]);
+        let options = LayerOptions::new();
+        plan.composite_at(pts_seconds, base, &creative, &alpha, cw, ch, depth, CREATIVE_DEPTH, &options)
+    }
+}
+
+pub(crate) fn checksum(frame: &[u8]) -> String {
+    format!("{:016x}", fnv1a(frame))
+}
+
+/// Lowercase name of a wasm enum variant, as vectors spell options
+fn variant_name(variant: impl std::fmt::Debug) -> String {
+    format!("{:?}", variant).to_lowercase()
+}
+
+/// The conformance vectors, JSON: an array of `composite_layer` calls, with
+/// every input, the options exercised and the output in reference mode
+/// (the `reference_mode` flag), which a certified implementation must
+/// reproduce byte for byte. Reference mode is on only while they are made
+#[wasm_bindgen]
+pub fn conformance_vectors() -> String {
+    let inputs = Inputs::new();
+    let vectors: Vec<ConformanceVector> = with_flag(REFERENCE_MODE, true, || {
+        CASES
+            .iter()
+            .map(|case| {
+                let expected = inputs.composite(&case.options());
+                ConformanceVector {
+                    name: case.name,
+                    width: WIDTH,
+                    height: HEIGHT,
+                    creative_depth: CREATIVE_DEPTH,
+                    options: case.vector_options(),
+                    base_frame: inputs.base_frame.clone(),
+                    creative_frame: inputs.creative_frame.clone(),
+                    alpha_mask: inputs.alpha_mask.clone(),
+                    depth_map: inputs.depth_map.clone(),
+                    checksum: checksum(&expected),
+                    expected,
+                }
+            })
+            .collect()
+    });
+    serde_json::to_string(&vectors).unwrap_or_default()
+}
+
+/// How a bundle's inputs are composited; raw inputs are in files beside it
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum BundleCall {
+    /// `composite_layer` of `base_frame.rgba`, `creative_frame.rgba`,
+    /// `depth_map.f32` and `alpha_mask.u8`
+    Layer { options: VectorOptions },
+    /// `compile_plan` of `manifest.json`, then `composite_at` of
+    /// `base_frame.rgba`, `creative_frame.rgba`, `creative_alpha.u8` and
+    /// `depth_map.f32` with default options
+    Plan { pts_seconds: f64, creative_width: u32, creative_height: u32 },
+}
+
+#[derive(Serialize)]
+struct Bundle {
+    name: &'static str,
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    #[serde(flatten)]
+    call: BundleCall,
+    /// FNV-1a of the RGBA output, hex
+    expected_hash: String,
+}
+
+/// The conformance suite as files to write under one directory: a folder
+/// of input bundles per case (`bundle.json` describing the call, raw 8-bit
+/// frames and masks, little-endian f32 depth, and any manifest) and
+/// `hashes.txt`, each case's expected output hash and name on a line. The
+/// outputs are reference mode's, as for `conformance_vectors`
+pub fn conformance_suite() -> Vec<(String, Vec<u8>)> {
+    let inputs = Inputs::new();
+    let depth_map: Vec<u8> = inputs.depth_map.iter().flat_map(|depth| depth.to_le_bytes()).collect();
+    let mut files = Vec::new();
+    let mut hashes = String::new();
+    let mut add = |bundle: Bundle, mut bundle_files: Vec<(&str, Vec<u8>)>| {
+        hashes.push_str(&format!("{}  {}\n", bundle.expected_hash, bundle.name));
+        let description = serde_json::to_vec_pretty(&bundle).unwrap_or_default();
+        bundle_files.push(("bundle.json", description));
+        bundle_files.push(("base_frame.rgba", inputs.base_frame.clone()));
+        bundle_files.push(("depth_map.f32", depth_map.clone()));
+        for (file, contents) in bundle_files {
+            files.push((format!("{}/{}", bundle.name, file), contents));
+        }
+    };
+
+    with_flag(REFERENCE_MODE, true, || {
+        for case in &CASES {
+            let options = case.vector_options();
+            let bundle = Bundle {
+                name: case.name,
+                width: WIDTH,
+                height: HEIGHT,
+                creative_depth: CREATIVE_DEPTH,
+                call: BundleCall::Layer { options },
+                expected_hash: checksum(&inputs.composite(&case.options())),
+            };
+            let creative = ("creative_frame.rgba", inputs.creative_frame.clone());
+            add(bundle, vec![creative, ("alpha_mask.u8", inputs.alpha_mask.clone())]);
+        }
+        for case in &PLAN_CASES {
+            // The cases are known to compile
+            let Ok(plan) = compile_plan(case.manifest, WIDTH, HEIGHT) else {
+                continue;
+            };
+            let bundle = Bundle {
+                name: case.name,
+                width: WIDTH,
+                height: HEIGHT,
+                creative_depth: CREATIVE_DEPTH,
+                call: BundleCall::Plan {
+                    pts_seconds: case.pts_seconds,
+                    creative_width: CREATIVE_WIDTH,
+                    creative_height: CREATIVE_HEIGHT,
+                },
+                expected_hash: checksum(&inputs.composite_plan(&plan, case.pts_seconds)),
+            };
+            let (creative, alpha) = inputs.plan_creative();
+            let manifest = ("manifest.json", case.manifest.as_bytes().to_vec());
+            add(bundle, vec![manifest, ("creative_frame.rgba", creative), ("creative_alpha.u8", alpha)]);
+        }
+    });
+    files.push(("hashes.txt".to_string(), hashes.into_bytes()));
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blend::BlendPrecision;
+    use crate::flags::flag_enabled;
+
+    #[test]
+    fn test_conformance_vectors_are_reference_output() {
+        let vectors: serde_json::Value = serde_json::from_str(&conformance_vectors()).unwrap();
+        let vectors = vectors.as_array().unwrap();
+        assert_eq!(vectors.len(), CASES.len());
+        assert!(!flag_enabled(REFERENCE_MODE));
+
+        let inputs = Inputs::new();
+        for (vector, case) in vectors.iter().zip(CASES) {
+            let expected: Vec<u8> = serde_json::from_value(vector["expected"].clone()).unwrap();
+            assert_eq!(vector["options"]["filter_mode"], variant_name(case.filter_mode));
+            // Reference output is the float blend, whatever the layer asks
+            let mut float = case.options();
+            float.set_blend_precision(BlendPrecision::Float);
+            assert_eq!(expected, inputs.composite(&float), "{}", case.name);
+            let mut fixed = case.options();
+            fixed.set_blend_precision(BlendPrecision::FixedPoint);
+            let reference = with_flag(REFERENCE_MODE, true, || inputs.composite(&fixed));
+            assert_eq!(expected, reference, "{}", case.name);
+        }
+        // The layer is placed inside the mask and occluded on the right
+        let identity: Vec<u8> = serde_json::from_value(vectors[0]["expected"].clone()).unwrap();
+        let pixel = |frame: &[u8], x: usize| frame[(3 * 16 + x) * 4..][..4].to_vec();
+        assert_eq!(pixel(&identity, 6), pixel(&inputs.creative_frame, 6));
+        assert_eq!(pixel(&identity, 12), pixel(&inputs.base_frame, 12));
+        assert_eq!(vectors[0]["checksum"], format!("{:016x}", fnv1a(&identity)));
+    }
+
+    #[test]
+    fn test_reference_mode_samples_uniformly() {
+        let inputs = Inputs::new();
+        let mut options = CASES[1].options();
+        options.set_blend_precision(BlendPrecision::Float);
+        let uniform = inputs.composite(&options);
+        // Nothing is important: nearest sampling throughout, unless canonical
+        options.set_importance_map(&[0; (WIDTH * HEIGHT) as usize], 128);
+        assert_ne!(inputs.composite(&options), uniform);
+        let reference = with_flag(REFERENCE_MODE, true, || inputs.composite(&options));
+        assert_eq!(reference, uniform);
+    }
+
+    #[test]
+    fn test_conformance_suite_bundles() {
+        let suite = conformance_suite();
+        assert_eq!(suite, conformance_suite());
+        let file = |path: &str| &suite.iter().find(|(name, _)| name == path).unwrap().1;
+        let hashes = String::from_utf8(file("hashes.txt").clone()).unwrap();
+        assert_eq!(hashes.lines().count(), CASES.len() + PLAN_CASES.len());
+
+        // Layer bundles hash the published vectors' output
+        let vectors: serde_json::Value = serde_json::from_str(&conformance_vectors()).unwrap();
+        let first = format!("{}  {}", vectors[0]["checksum"].as_str().unwrap(), CASES[0].name);
+        assert_eq!(hashes.lines().next(), Some(first.as_str()));
+
+        // Plan bundles recomposite from their files alone to the hash
+        let inputs = Inputs::new();
+        for case in &PLAN_CASES {
+            let bundle_file = |file_name: &str| file(&format!("{}/{}", case.name, file_name));
+            let bundle: serde_json::Value = serde_json::from_slice(bundle_file("bundle.json")).unwrap();
+            assert_eq!((bundle["kind"].as_str(), bundle["creative_width"].as_u64()), (Some("plan"), Some(8)));
+            let manifest = std::str::from_utf8(bundle_file("manifest.json")).unwrap();
+            let plan = compile_plan(manifest, WIDTH, HEIGHT).unwrap_or_else(|_| panic!("{}", case.name));
+            assert!(!plan.active_opportunities(case.pts_seconds).is_empty());
+            let depth: Vec<f32> = bundle_file("depth_map.f32")
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            assert_eq!(depth, inputs.depth_map);
+            let output = with_flag(REFERENCE_MODE, true, || inputs.composite_plan(&plan, case.pts_seconds));
+            assert_ne!(output, inputs.base_frame);
+            let line = format!("{}  {}", checksum(&output), case.name);
+            assert!(hashes.lines().any(|hash| hash == line), "{}", case.name);
+            assert_eq!(bundle["expected_hash"].as_str(), Some(&checksum(&output)[..]));
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! Tiled compositing on the Web Worker pool of wasm-bindgen-rayon
+
+use std::sync::Mutex;
+
+use rayon::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::layer::{LayerOptions, LayerWarp};
+use crate::sampler::Coverage;
+use crate::{composite_layer_rows, reserve_bands, Bands, WarpedLayer};
+
+/// Fewest rows worth handing to another thread
+const MIN_TILE_ROWS: usize = 32;
+
+/// Frames each strategy is timed on before auto mode settles, after the
+/// stream's first frame, which pays for allocations
+const TRIAL_FRAMES: u32 = 3;
+
+/// How a frame's rows are split across the thread pool
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileStrategy {
+    /// Time the others on a stream's first frames and keep the fastest
+    /// (see `Compositor::tile_stats`); a single composite uses `Rows`
+    #[default]
+    Auto = 0,
+    /// One strip of rows per thread
+    Rows = 1,
+    /// Strips of 32 rows taken by whichever thread is free, balancing
+    /// layers whose cost varies down the frame
+    Tiles = 2,
+    /// Only the rows the layer reaches, split evenly across the threads,
+    /// so a small placement still keeps every thread busy
+    Layer = 3,
+}
+
+/// Strategies auto mode chooses from
+const STRATEGIES: [TileStrategy; 3] = [TileStrategy::Rows, TileStrategy::Tiles, TileStrategy::Layer];
+
+impl TileStrategy {
+    fn name(self) -> &'static str {
+        match self {
+            TileStrategy::Auto => "auto",
+            TileStrategy::Rows => "rows",
+            TileStrategy::Tiles => "tiles",
+            TileStrategy::Layer => "layer",
+        }
+    }
+}
+
+/// Frames composited with one strategy and their wall time
+#[derive(Clone, Copy, Debug, Default)]
+struct Timing {
+    frames: u32,
+    total_ms: f64,
+}
+
+impl Timing {
+    fn mean_ms(&self) -> f64 {
+        if self.frames > 0 {
+            self.total_ms / self.frames as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A stream's timings per strategy, and what auto mode settled on
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Tiling {
+    timings: [Timing; 3],
+    /// The stream's first frame, left out of the timings
+    started: bool,
+    chosen: Option<TileStrategy>,
+}
+
+impl Tiling {
+    /// Strategy to composite the next frame with when `requested`: auto
+    /// takes turns through the strategies until it has settled
+    pub(crate) fn next(&self, requested: TileStrategy) -> TileStrategy {
+        if requested != TileStrategy::Auto {
+            return requested;
+        }
+        if let Some(chosen) = self.chosen {
+            return chosen;
+        }
+        let trials: u32 = self.timings.iter().map(|timing| timing.frames).sum();
+        STRATEGIES[trials as usize % STRATEGIES.len()]
+    }
+
+    /// Count a frame `strategy` composited in `ms`, settling auto mode once
+    /// every strategy has had its trial frames
+    pub(crate) fn record(&mut self, strategy: TileStrategy, ms: f64) {
+        if !std::mem::replace(&mut self.started, true) {
+            return;
+        }
+        let Some(index) = STRATEGIES.iter().position(|&s| s == strategy) else {
+            return;
+        };
+        self.timings[index].frames += 1;
+        self.timings[index].total_ms += ms;
+        if self.chosen.is_none() && self.timings.iter().all(|timing| timing.frames >= TRIAL_FRAMES) {
+            let fastest = (0..STRATEGIES.len())
+                .min_by(|&a, &b| self.timings[a].mean_ms().total_cmp(&self.timings[b].mean_ms()));
+            self.chosen = fastest.map(|index| STRATEGIES[index]);
+        }
+    }
+
+    /// `{"chosen": name or null, "rows": {"frames", "mean_ms"}, ...}`
+    pub(crate) fn report(&self) -> String {
+        let chosen = self.chosen.map_or("null".to_string(), |strategy| format!(r#""{}""#, strategy.name()));
+        let timings: Vec<String> = STRATEGIES
+            .iter()
+            .zip(&self.timings)
+            .map(|(strategy, timing)| {
+                let (frames, mean_ms) = (timing.frames, timing.mean_ms());
+                format!(r#""{}":{{"frames":{},"mean_ms":{:.3}}}"#, strategy.name(), frames, mean_ms)
+            })
+            .collect();
+        format!(r#"{{"chosen":{},{}}}"#, chosen, timings.join(","))
+    }
+
+    /// Start measuring afresh, e.g. when the content changes character
+    pub(crate) fn reset(&mut self) {
+        *self = Tiling::default();
+    }
+}
+
+/// Whether `rows` rows are split across the current pool (see
+/// `initThreadPool`) rather than composited on the calling thread
+pub(crate) fn splits(rows: usize) -> bool {
+    rayon::current_num_threads() >= 2 && rows >= MIN_TILE_ROWS * 2
+}
+
+/// Composite a placed layer in horizontal tiles per the options' strategy,
+/// so a 4K frame is not bound to one core. Band scratch is reserved up
+/// front on the calling thread, one per thread, which keeps health and
+/// allocation accounting where the host reads them. Returns false, leaving
+/// `result` alone, when the pool has a single thread (see `initThreadPool`)
+/// or there are too few rows to split
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn composite_tiles<A: Coverage>(
+    result: &mut [u8],
+    keep: Option<&mut WarpedLayer>,
+    depth_map: &[f32],
+    occlusion: Option<&[u8]>,
+    warp: &LayerWarp<A>,
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> bool {
+    let (width, height) = (width as usize, height as usize);
+    let threads = rayon::current_num_threads();
+    let strategy = options.tile_strategy;
+    // Rows the layer does not reach keep the base frame already in `result`
+    let rows = if strategy == TileStrategy::Layer { warp.rows() } else { 0..height };
+    if !splits(rows.len()) {
+        return false;
+    }
+
+    let tile_rows = match strategy {
+        TileStrategy::Tiles => MIN_TILE_ROWS,
+        _ => rows.len().div_ceil(threads).max(MIN_TILE_ROWS),
+    };
+    let tiles = rows.len().div_ceil(tile_rows);
+    let mut pool: Vec<Bands<A>> = (0..threads.min(tiles)).map(|_| Bands::default()).collect();
+    if !pool.iter_mut().all(|bands| reserve_bands(bands, width)) {
+        return true;
+    }
+    let pool = Mutex::new(pool);
+
+    let math = options.blend_math();
+    let span = rows.start * width..rows.end * width;
+    // The kept layer, if any, is split into the same tiles as `result`
+    let mut kept: Vec<Option<(&mut [u8], &mut [u8])>> = match keep {
+        Some(layer) => layer.creative[span.start * 4..span.end * 4]
+            .chunks_mut(tile_rows * width * 4)
+            .zip(layer.coverage[span.clone()].chunks_mut(tile_rows * width))
+            .map(Some)
+            .collect(),
+        None => (0..tiles).map(|_| None).collect(),
+    };
+    result[span.start * 4..span.end * 4]
+        .par_chunks_mut(tile_rows * width * 4)
+        .zip(depth_map[span].par_chunks(tile_rows * width))
+        .zip(kept.par_iter_mut())
+        .enumerate()
+        .for_each(|(tile, ((result, depth), keep))| {
+            let start = rows.start + tile * tile_rows;
+            let tile = start..(start + tile_rows).min(rows.end);
+            let occlusion = occlusion.map(|occlusion| &occlusion[tile.start * width..tile.end * width]);
+            // No more tiles run at once than there are threads, so the pool
+            // only runs dry if the caller is itself one of them
+            let mut bands = pool.lock().ok().and_then(|mut pool| pool.pop()).unwrap_or_default();
+            composite_layer_rows(
+                result,
+                &mut bands,
+                keep.take(),
+                depth,
+                occlusion,
+                warp,
+                tile,
+                width,
+                creative_depth,
+                math,
+            );
+            if let Ok(mut pool) = pool.lock() {
+                pool.push(bands);
+            }
+        });
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::mask_bounds;
+    use crate::sampler::Source;
+
+    #[test]
+    fn test_tiles_match_single_thread() {
+        let (width, height) = (200u32, 130u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 211) as u8).collect();
+        let creative_frame: Vec<u8> = (0..pixel_count * 4).map(|i| (i % 197) as u8).collect();
+        let depth_map: Vec<f32> = (0..pixel_count).map(|i| if i % 7 == 0 { 1.0 } else { 10.0 }).collect();
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| (i / width as usize * 2) as u8).collect();
+        let occlusion: Vec<u8> = (0..pixel_count).map(|i| (i % 5 * 60) as u8).collect();
+        let mut options = LayerOptions::new();
+        options.set_transform(3.5, -7.25, 0.9);
+
+        let (w, h) = (width as usize, height as usize);
+        let source = Source { creative: &creative_frame, alpha: &alpha_mask, width: w, height: h };
+        let bounds = mask_bounds(&alpha_mask, width, height).unwrap();
+        let warp = LayerWarp::new(&source, &bounds, &options);
+        let math = options.blend_math();
+        let mut expected = base_frame.clone();
+        let mut bands = Bands::default();
+        let occlusion = Some(&occlusion[..]);
+        let mut layer = WarpedLayer { creative: vec![0; pixel_count * 4], coverage: vec![0; pixel_count] };
+        let keep = Some((&mut layer.creative[..], &mut layer.coverage[..]));
+        let depth = &depth_map[..];
+        composite_layer_rows(&mut expected, &mut bands, keep, depth, occlusion, &warp, 0..h, w, 5.0, math);
+        let expected = (expected, layer.creative, layer.coverage);
+
+        // Every strategy gives the single-threaded frame and warped layer on
+        // a pool of four
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let composite = |options: &LayerOptions| {
+            let warp = LayerWarp::new(&source, &bounds, options);
+            let mut tiled = base_frame.clone();
+            let mut layer = WarpedLayer { creative: vec![0; pixel_count * 4], coverage: vec![0; pixel_count] };
+            let keep = Some(&mut layer);
+            let split =
+                composite_tiles(&mut tiled, keep, &depth_map, occlusion, &warp, width, height, 5.0, options);
+            split.then_some((tiled, layer.creative, layer.coverage))
+        };
+        for strategy in STRATEGIES {
+            let mut options = options.clone();
+            options.set_tile_strategy(strategy);
+            assert_eq!(pool.install(|| composite(&options)).as_ref(), Some(&expected), "{:?}", strategy);
+        }
+
+        // A single-thread pool composites on the calling thread instead
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        assert_eq!(pool.install(|| composite(&options)), None);
+    }
+
+    #[test]
+    fn test_auto_settles_on_the_fastest_strategy() {
+        let mut tiling = Tiling::default();
+        // The first frame is not timed; explicit strategies are just counted
+        tiling.record(TileStrategy::Rows, 100.0);
+        assert_eq!(tiling.next(TileStrategy::Layer), TileStrategy::Layer);
+
+        let cost = |strategy| match strategy {
+            TileStrategy::Rows => 4.0,
+            TileStrategy::Tiles => 2.5,
+            _ => 3.0,
+        };
+        for _ in 0..TRIAL_FRAMES * 3 {
+            assert!(tiling.chosen.is_none());
+            let strategy = tiling.next(TileStrategy::Auto);
+            tiling.record(strategy, cost(strategy));
+        }
+        assert_eq!(tiling.next(TileStrategy::Auto), TileStrategy::Tiles);
+        let report = tiling.report();
+        assert!(report.starts_with(r#"{"chosen":"tiles","rows":{"frames":3,"mean_ms":4.000},"#), "{}", report);
+        assert!(report.ends_with(r#""layer":{"frames":3,"mean_ms":3.000}}"#), "{}", report);
+
+        tiling.reset();
+        assert_eq!(tiling.next(TileStrategy::Auto), TileStrategy::Rows);
+    }
+}
@@ -0,0 +1,375 @@
+//! Placement timeline driven by frame presentation timestamps
+
+use wasm_bindgen::prelude::*;
+
+#[cfg(feature = "policy")]
+use crate::policy::FrequencyCapPolicy;
+
+/// A placement scheduled on the media timeline over `[start, end)` seconds
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    id: String,
+    /// Creative the frequency cap counts; under a policy the placement
+    /// does not activate until it has one
+    #[cfg(feature = "policy")]
+    creative: Option<String>,
+    start: f64,
+    end: f64,
+    /// While active, whether this activation was allowed to render
+    activation: Option<bool>,
+}
+
+impl Entry {
+    fn is_active(&self, position: f64) -> bool {
+        position >= self.start && position < self.end
+    }
+}
+
+/// Tracks the playback position and which placements are active, with
+/// explicit pause/resume/seek so stateful filters can be reset correctly
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Timeline {
+    entries: Vec<Entry>,
+    position: Option<f64>,
+    paused: bool,
+    discontinuity: bool,
+    played: f64,
+    /// PTS of the last decoded (non-synthesized) frame
+    last_real: Option<f64>,
+    real_frames: u64,
+    synthesized: bool,
+    /// Gates each activation of a placement on its creative's cap
+    #[cfg(feature = "policy")]
+    policy: Option<FrequencyCapPolicy>,
+}
+
+#[wasm_bindgen]
+impl Timeline {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Timeline {
+        Timeline::default()
+    }
+
+    /// Schedule a placement over `[start_seconds, end_seconds)`
+    pub fn add_placement(&mut self, id: &str, start_seconds: f64, end_seconds: f64) {
+        self.entries.retain(|e| e.id != id);
+        self.entries.push(Entry {
+            id: id.to_string(),
+            #[cfg(feature = "policy")]
+            creative: None,
+            start: start_seconds,
+            end: end_seconds,
+            activation: None,
+        });
+        self.update_activations();
+    }
+
+    pub fn remove_placement(&mut self, id: &str) {
+        self.entries.retain(|e| e.id != id);
+    }
+
+    /// Feed the PTS of the next frame; returns true when this frame follows a
+    /// discontinuity (seek or rewind), meaning temporal filters such as
+    /// `PlacementSolver` and `CandidateSelector` should be reset
+    pub fn advance(&mut self, pts_seconds: f64) -> bool {
+        self.advance_frame(pts_seconds, false)
+    }
+
+    /// Like `advance`, but `synthesized` marks frames inserted by player-side
+    /// frame-rate conversion: they move animations but are not counted as
+    /// played time, and keep the placements of the last decoded frame, whose
+    /// warped layer `Compositor::reuse_frame` blends onto them
+    pub fn advance_frame(&mut self, pts_seconds: f64, synthesized: bool) -> bool {
+        if self.paused {
+            // Repeated frames while paused neither move time nor animations
+            return false;
+        }
+
+        // A backwards jump without an explicit seek is still a discontinuity
+        if self.position.is_some_and(|p| pts_seconds < p) {
+            self.discontinuity = true;
+        }
+        if self.discontinuity {
+            self.last_real = None;
+        }
+
+        // Accumulate intervals between decoded frames, so variable frame rate
+        // and interpolated frames are both handled
+        if !synthesized {
+            if let Some(previous) = self.last_real {
+                self.played += pts_seconds - previous;
+            }
+            self.last_real = Some(pts_seconds);
+            self.real_frames += 1;
+        }
+        self.synthesized = synthesized;
+        self.position = Some(pts_seconds);
+        if !synthesized {
+            self.update_activations();
+        }
+
+        std::mem::take(&mut self.discontinuity)
+    }
+
+    /// True when the current frame was marked as synthesized
+    pub fn is_synthesized(&self) -> bool {
+        self.synthesized
+    }
+
+    /// Number of decoded (non-synthesized) frames seen
+    pub fn real_frames(&self) -> u64 {
+        self.real_frames
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Jump to `pts_seconds`; the next `advance` reports a discontinuity
+    pub fn seek(&mut self, pts_seconds: f64) {
+        self.position = Some(pts_seconds);
+        self.discontinuity = true;
+        self.update_activations();
+    }
+
+    /// Seconds of media actually played, excluding pauses, seek jumps and
+    /// synthesized frames
+    pub fn played_seconds(&self) -> f64 {
+        self.played
+    }
+
+    /// Current media position in seconds (0 before the first frame)
+    pub fn position(&self) -> f64 {
+        self.position.unwrap_or(0.0)
+    }
+
+    /// Ids of placements active at the current position, without those the
+    /// frequency cap held back
+    pub fn active_placements(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|e| e.activation == Some(true))
+            .map(|e| e.id.clone())
+            .collect()
+    }
+
+    /// Animation progress of a placement in 0..1, or -1 when it is not active
+    pub fn placement_progress(&self, id: &str) -> f64 {
+        let position = self.position();
+        match self.entries.iter().find(|e| e.id == id) {
+            Some(e) if e.activation == Some(true) && e.end > e.start => {
+                (position - e.start) / (e.end - e.start)
+            }
+            _ => -1.0,
+        }
+    }
+}
+
+/// Frequency capping: each time a placement becomes active, from playback
+/// or a seek, its creative must be activated in the policy to render, and
+/// counts an impression when it is. A placement without a creative waits,
+/// inactive and uncounted, until `set_placement_creative` gives it one
+#[cfg(feature = "policy")]
+#[wasm_bindgen]
+impl Timeline {
+    pub fn set_policy(&mut self, policy: FrequencyCapPolicy) {
+        self.policy = Some(policy);
+    }
+
+    /// Creative shown by placement `id`, counted against the cap; a
+    /// placement already due activates with it right away
+    pub fn set_placement_creative(&mut self, id: &str, creative_id: &str) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) {
+            entry.creative = Some(creative_id.to_string());
+            self.update_activations();
+        }
+    }
+
+    /// Stop capping, handing the policy back, e.g. to `export_state` it
+    pub fn clear_policy(&mut self) -> Option<FrequencyCapPolicy> {
+        self.policy.take()
+    }
+}
+
+impl Timeline {
+    /// Decide each placement that became active, forget those that ended
+    fn update_activations(&mut self) {
+        let Some(position) = self.position else {
+            return;
+        };
+        for entry in &mut self.entries {
+            if !entry.is_active(position) {
+                entry.activation = None;
+            } else if entry.activation.is_none() {
+                #[cfg(feature = "policy")]
+                let allowed = match (self.policy.as_mut(), entry.creative.as_deref()) {
+                    (None, _) => Some(true),
+                    // Nothing is counted before the creative is known
+                    (Some(_), None) => None,
+                    (Some(policy), Some(creative)) => Some(policy.activate(creative)),
+                };
+                #[cfg(not(feature = "policy"))]
+                let allowed = Some(true);
+                entry.activation = allowed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_placements_follow_position() {
+        let mut timeline = Timeline::new();
+        timeline.add_placement("a", 0.0, 2.0);
+        timeline.add_placement("b", 1.0, 3.0);
+
+        timeline.advance(0.5);
+        assert_eq!(timeline.active_placements(), vec!["a"]);
+
+        timeline.advance(1.5);
+        assert_eq!(timeline.active_placements(), vec!["a", "b"]);
+        assert_eq!(timeline.placement_progress("b"), 0.25);
+
+        timeline.advance(2.5);
+        assert_eq!(timeline.active_placements(), vec!["b"]);
+        assert_eq!(timeline.placement_progress("a"), -1.0);
+    }
+
+    #[test]
+    fn test_pause_freezes_position() {
+        let mut timeline = Timeline::new();
+        timeline.advance(1.0);
+        timeline.pause();
+
+        assert!(!timeline.advance(1.2));
+        assert_eq!(timeline.position(), 1.0);
+
+        timeline.resume();
+        timeline.advance(1.2);
+        assert_eq!(timeline.position(), 1.2);
+    }
+
+    #[test]
+    fn test_seek_recomputes_and_flags_discontinuity() {
+        let mut timeline = Timeline::new();
+        timeline.add_placement("a", 10.0, 20.0);
+
+        assert!(!timeline.advance(1.0));
+        timeline.seek(15.0);
+        assert_eq!(timeline.active_placements(), vec!["a"]);
+        assert_eq!(timeline.placement_progress("a"), 0.5);
+
+        // Discontinuity is reported once, on the first frame after the seek
+        assert!(timeline.advance(15.0));
+        assert!(!timeline.advance(15.04));
+
+        // Rewinding without a seek call is detected too
+        assert!(timeline.advance(12.0));
+    }
+
+    #[test]
+    fn test_played_seconds_follows_timestamps() {
+        let mut timeline = Timeline::new();
+
+        // Irregular frame spacing, as in variable frame rate content
+        for pts in [0.0, 0.033, 0.05, 0.1, 0.2] {
+            timeline.advance(pts);
+        }
+        assert!((timeline.played_seconds() - 0.2).abs() < 1e-9);
+
+        // The seek jump itself is not counted as played time
+        timeline.seek(10.0);
+        timeline.advance(10.0);
+        timeline.advance(10.5);
+        assert!((timeline.played_seconds() - 0.7).abs() < 1e-9);
+    }
+
+    #[cfg(feature = "policy")]
+    #[test]
+    fn test_policy_suppresses_over_cap_placements() {
+        let mut policy = FrequencyCapPolicy::new(1);
+        policy.register_creative("a");
+        policy.register_creative("shared");
+        let mut timeline = Timeline::new();
+        timeline.set_policy(policy);
+        timeline.add_placement("a", 0.0, 1.0);
+        timeline.add_placement("b", 2.0, 3.0);
+        timeline.add_placement("c", 4.0, 5.0);
+        timeline.set_placement_creative("a", "a");
+        timeline.set_placement_creative("b", "shared");
+        timeline.set_placement_creative("c", "shared");
+
+        // Decided once per activation, not per frame
+        timeline.advance(0.5);
+        timeline.advance(0.6);
+        assert_eq!(timeline.active_placements(), vec!["a"]);
+        // Nor on frames the player made up
+        timeline.advance_frame(2.1, true);
+        assert_eq!(timeline.active_placements(), vec!["a"]);
+        timeline.advance(2.5);
+        assert_eq!(timeline.active_placements(), vec!["b"]);
+
+        // "c" shows the creative "b" used up
+        timeline.advance(4.5);
+        assert!(timeline.active_placements().is_empty());
+        assert_eq!(timeline.placement_progress("c"), -1.0);
+
+        // Seeking back activates "a" again, over its cap
+        timeline.seek(0.2);
+        assert!(timeline.active_placements().is_empty());
+        let policy = timeline.clear_policy().unwrap();
+        assert_eq!((policy.impressions("a"), policy.impressions("shared")), (1, 1));
+    }
+
+    #[cfg(feature = "policy")]
+    #[test]
+    fn test_placements_wait_for_their_creative() {
+        let mut policy = FrequencyCapPolicy::new(2);
+        policy.register_creative("spot");
+        let mut timeline = Timeline::new();
+        timeline.set_policy(policy);
+        timeline.advance(0.5);
+
+        // Due as soon as it is added, but nothing to count yet
+        timeline.add_placement("a", 0.0, 1.0);
+        timeline.advance(0.6);
+        assert!(timeline.active_placements().is_empty());
+        timeline.set_placement_creative("a", "spot");
+        assert_eq!(timeline.active_placements(), vec!["a"]);
+        timeline.advance(0.7);
+        let policy = timeline.clear_policy().unwrap();
+        assert_eq!((policy.impressions("a"), policy.impressions("spot")), (0, 1));
+    }
+
+    #[test]
+    fn test_synthesized_frames_are_not_counted() {
+        let mut timeline = Timeline::new();
+        timeline.add_placement("a", 0.0, 1.0);
+
+        // 60 fps content doubled to 120 fps by the player
+        for i in 0..=12 {
+            let pts = i as f64 / 120.0;
+            timeline.advance_frame(pts, i % 2 == 1);
+        }
+
+        assert_eq!(timeline.real_frames(), 7);
+        assert!((timeline.played_seconds() - 0.1).abs() < 1e-9);
+
+        // Animation still follows the interpolated frame position
+        timeline.advance_frame(13.0 / 120.0, true);
+        assert!(timeline.is_synthesized());
+        assert!((timeline.placement_progress("a") - 13.0 / 120.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,187 @@
+//! Layer transform algebra matching the warp stage
+
+use wasm_bindgen::prelude::*;
+
+/// Below this the scale is treated as zero and the transform as singular
+const MIN_SCALE: f32 = 1e-6;
+
+/// Shift plus uniform scale about the placement center, the only transform
+/// the warp applies: a placement point `p` lands at `c + d + s * (p - c)`
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LayerTransform {
+    dx: f32,
+    dy: f32,
+    scale: f32,
+}
+
+impl Default for LayerTransform {
+    fn default() -> Self {
+        Self { dx: 0.0, dy: 0.0, scale: 1.0 }
+    }
+}
+
+#[wasm_bindgen]
+impl LayerTransform {
+    /// Same arguments as `LayerOptions::set_transform`
+    #[wasm_bindgen(constructor)]
+    pub fn new(dx: f32, dy: f32, scale: f32) -> LayerTransform {
+        LayerTransform { dx, dy, scale }
+    }
+
+    pub fn dx(&self) -> f32 {
+        self.dx
+    }
+
+    pub fn dy(&self) -> f32 {
+        self.dy
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// This transform followed by `next`
+    pub fn then(&self, next: &LayerTransform) -> LayerTransform {
+        LayerTransform {
+            dx: next.dx + next.scale * self.dx,
+            dy: next.dy + next.scale * self.dy,
+            scale: self.scale * next.scale,
+        }
+    }
+
+    /// None for a zero scale
+    pub fn invert(&self) -> Option<LayerTransform> {
+        if self.scale.abs() < MIN_SCALE {
+            return None;
+        }
+        Some(LayerTransform { dx: -self.dx / self.scale, dy: -self.dy / self.scale, scale: 1.0 / self.scale })
+    }
+
+    /// Blend towards `other` by `t`: shift linearly, scale geometrically so
+    /// a zoom runs at an even rate
+    pub fn interpolate(&self, other: &LayerTransform, t: f32) -> LayerTransform {
+        let scale = if self.scale > 0.0 && other.scale > 0.0 {
+            self.scale * (other.scale / self.scale).powf(t)
+        } else {
+            self.scale + (other.scale - self.scale) * t
+        };
+        LayerTransform {
+            dx: self.dx + (other.dx - self.dx) * t,
+            dy: self.dy + (other.dy - self.dy) * t,
+            scale,
+        }
+    }
+
+    /// Row-major 3x3 frame-space matrix for a placement centered at `(cx, cy)`
+    pub fn to_matrix(&self, cx: f32, cy: f32) -> Vec<f32> {
+        let s = self.scale;
+        vec![s, 0.0, cx + self.dx - s * cx, 0.0, s, cy + self.dy - s * cy, 0.0, 0.0, 1.0]
+    }
+
+    /// Recover the transform from a matrix about `(cx, cy)`; None when the
+    /// matrix rotates, shears or scales unevenly, which the warp cannot do
+    pub fn from_matrix(matrix: &[f32], cx: f32, cy: f32) -> Option<LayerTransform> {
+        let [tx, ty, rotation, scale_x, scale_y] = decompose(matrix)?;
+        let tolerance = 1e-4 * scale_x.abs().max(1.0);
+        if rotation.abs() > 1e-4 || (scale_x - scale_y).abs() > tolerance || matrix[1].abs() > tolerance {
+            return None;
+        }
+        let scale = scale_x;
+        Some(LayerTransform { dx: tx - cx + scale * cx, dy: ty - cy + scale * cy, scale })
+    }
+}
+
+/// Translation, rotation (radians) and per-axis scale of a row-major affine
+/// 3x3 matrix, taking any shear into the y scale; None for a malformed or
+/// singular matrix
+fn decompose(matrix: &[f32]) -> Option<[f32; 5]> {
+    let &[a, b, tx, c, d, ty, _, _, _] = matrix.get(..9)? else {
+        return None;
+    };
+    let scale_x = a.hypot(c);
+    if scale_x < MIN_SCALE {
+        return None;
+    }
+    let determinant = a * d - b * c;
+    Some([tx, ty, c.atan2(a), scale_x, determinant / scale_x])
+}
+
+/// `[tx, ty, rotation, scale_x, scale_y]` of a row-major 3x3 affine matrix,
+/// for tracker output the warp cannot apply directly; empty when singular
+#[wasm_bindgen]
+pub fn decompose_matrix(matrix: &[f32]) -> Vec<f32> {
+    decompose(matrix).map(Vec::from).unwrap_or_default()
+}
+
+/// Row-major product `a * b` of two 3x3 matrices (`b` applied first)
+#[wasm_bindgen]
+pub fn compose_matrices(a: &[f32], b: &[f32]) -> Vec<f32> {
+    if a.len() < 9 || b.len() < 9 {
+        return Vec::new();
+    }
+    (0..9).map(|i| (0..3).map(|k| a[i / 3 * 3 + k] * b[k * 3 + i % 3]).sum()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Rect;
+
+    fn apply(matrix: &[f32], (x, y): (f32, f32)) -> (f32, f32) {
+        (matrix[0] * x + matrix[1] * y + matrix[2], matrix[3] * x + matrix[4] * y + matrix[5])
+    }
+
+    #[test]
+    fn test_matrix_matches_warp_placement() {
+        let rect = Rect::new(10.0, 20.0, 40.0, 30.0);
+        let (cx, cy) = rect.center();
+        let transform = LayerTransform::new(5.0, -3.0, 1.5);
+
+        // The matrix moves the corners where the warp puts them
+        let matrix = transform.to_matrix(cx, cy);
+        let placed = rect.offset_scaled(5.0, -3.0, 1.5).corners();
+        for (corner, expected) in rect.corners().into_iter().zip(placed) {
+            let (x, y) = apply(&matrix, corner);
+            assert!((x - expected.0).abs() < 1e-4 && (y - expected.1).abs() < 1e-4);
+        }
+        assert_eq!(LayerTransform::from_matrix(&matrix, cx, cy), Some(transform));
+    }
+
+    #[test]
+    fn test_compose_and_invert() {
+        let a = LayerTransform::new(4.0, 2.0, 2.0);
+        let b = LayerTransform::new(-1.0, 3.0, 0.5);
+        let (cx, cy) = (7.0, 9.0);
+
+        let composed = a.then(&b).to_matrix(cx, cy);
+        let multiplied = compose_matrices(&b.to_matrix(cx, cy), &a.to_matrix(cx, cy));
+        assert_eq!(composed, multiplied);
+
+        assert_eq!(a.then(&a.invert().unwrap()), LayerTransform::default());
+        assert!(LayerTransform::new(1.0, 1.0, 0.0).invert().is_none());
+    }
+
+    #[test]
+    fn test_interpolate_scales_geometrically() {
+        let start = LayerTransform::new(0.0, 0.0, 1.0);
+        let end = LayerTransform::new(10.0, -4.0, 4.0);
+        let middle = start.interpolate(&end, 0.5);
+        assert_eq!(middle, LayerTransform::new(5.0, -2.0, 2.0));
+        assert_eq!(start.interpolate(&end, 1.0), end);
+    }
+
+    #[test]
+    fn test_decompose_rotation() {
+        let (sin, cos) = 0.5f32.sin_cos();
+        let rotated = [2.0 * cos, -3.0 * sin, 4.0, 2.0 * sin, 3.0 * cos, 5.0, 0.0, 0.0, 1.0];
+        let parts = decompose_matrix(&rotated);
+        assert!((parts[2] - 0.5).abs() < 1e-5);
+        assert!((parts[3] - 2.0).abs() < 1e-5 && (parts[4] - 3.0).abs() < 1e-5);
+        assert_eq!(parts[..2], [4.0, 5.0]);
+
+        // The warp cannot rotate
+        assert!(LayerTransform::from_matrix(&rotated, 0.0, 0.0).is_none());
+        assert!(decompose_matrix(&[0.0; 9]).is_empty());
+    }
+}
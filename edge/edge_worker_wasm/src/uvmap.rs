@@ -0,0 +1,143 @@
+//! Sampling creatives through externally supplied per-pixel UV maps
+
+use wasm_bindgen::prelude::*;
+
+use crate::blend::blend_pixel;
+use crate::geometry::Rect;
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::log_error;
+use crate::memory::{try_copy, Scope, Subsystem};
+use crate::occlusion::visibility;
+use crate::sampler::{Coverage, Source};
+
+/// Depth-tested composite of a creative (`creative_width x creative_height`
+/// RGBA and mask) sampled through a dense UV map, e.g. exported from scene
+/// reconstruction, instead of an analytic transform. `region` is the flat
+/// `[x, y, w, h]` frame rectangle the map covers, rounded to whole pixels,
+/// and `uv_map` holds a normalized `(u, v)` per region pixel in row-major
+/// order. Coordinates outside 0..1 resolve by the options' edge mode and
+/// NaN marks pixels the map does not cover. An empty result means the frame
+/// was dropped for memory
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_uv(
+    base_frame: &[u8],
+    creative_frame: &[u8],
+    creative_alpha: &[u8],
+    creative_width: u32,
+    creative_height: u32,
+    depth_map: &[f32],
+    uv_map: &[f32],
+    region: &[f32],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let pixel_count = (width * height) as usize;
+    let creative_count = (creative_width * creative_height) as usize;
+    let region = Rect::from_flat(region).first().map(|r| {
+        let (x, y) = (r.x.round().max(0.0), r.y.round().max(0.0));
+        (x as usize, y as usize, r.width.round().max(0.0) as usize, r.height.round().max(0.0) as usize)
+    });
+    let Some((left, top, region_width, region_height)) = region else {
+        log_error("WASM compositor: UV map needs a region");
+        return base_frame.to_vec();
+    };
+    if base_frame.len() < pixel_count * 4
+        || depth_map.len() < pixel_count
+        || creative_count == 0
+        || creative_frame.len() < creative_count * 4
+        || creative_alpha.len() < creative_count
+        || uv_map.len() < region_width * region_height * 2
+    {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    let Some(mut result) = try_copy(base_frame) else {
+        log_error("WASM compositor: Out of memory, frame dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    };
+    let source = Source {
+        creative: creative_frame,
+        alpha: creative_alpha,
+        width: creative_width as usize,
+        height: creative_height as usize,
+    };
+    let math = options.blend_math();
+    // The part of the region inside the frame
+    let columns = left.min(width as usize)..(left + region_width).min(width as usize);
+    for y in top.min(height as usize)..(top + region_height).min(height as usize) {
+        for x in columns.clone() {
+            let k = ((y - top) * region_width + x - left) * 2;
+            let (u, v) = (uv_map[k], uv_map[k + 1]);
+            let i = y * width as usize + x;
+            let visible = visibility(creative_depth, depth_map[i], math.depth_feather);
+            if u.is_nan() || v.is_nan() || visible == 0.0 {
+                continue;
+            }
+
+            let (u, v) = (u * source.width as f32, v * source.height as f32);
+            let (color, coverage) = source.sample(u, v, options.edge_mode, options.filter_mode);
+            if coverage > 0 {
+                blend_pixel(&mut result[i * 4..i * 4 + 4], &color, coverage.unit() * visible, math);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FilterMode;
+
+    #[test]
+    fn test_samples_through_uv_map() {
+        let (width, height) = (4u32, 2u32);
+        let pixel_count = (width * height) as usize;
+        let base_frame = vec![0u8; pixel_count * 4];
+        let depth_map = vec![10.0f32; pixel_count];
+        // Two pixel creative, dark then bright
+        let creative = [10u8, 10, 10, 255, 250, 250, 250, 255];
+        let alpha = [255u8, 255];
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(FilterMode::Nearest);
+
+        // A 2x1 region at (1, 1) showing the creative mirrored, then a hole
+        let uv_map = [0.75, 0.5, f32::NAN, 0.5];
+        let region = [1.0, 1.0, 2.0, 1.0];
+        let result = composite_layer_uv(
+            &base_frame, &creative, &alpha, 2, 1, &depth_map, &uv_map, &region, width, height, 5.0, &options,
+        );
+        let pixel = |x: usize, y: usize| &result[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4];
+        assert_eq!(pixel(1, 1), [250, 250, 250, 255]);
+        assert_eq!(pixel(2, 1), [0; 4]);
+        assert_eq!(pixel(1, 0), [0; 4]);
+    }
+
+    #[test]
+    fn test_region_past_the_frame_edge_is_clipped() {
+        let base_frame = vec![0u8; 4 * 4];
+        let uv_map = [0.5f32; 8];
+        let (creative, alpha) = ([200u8; 4], [255u8]);
+        let mut options = LayerOptions::new();
+        options.set_filter_mode(FilterMode::Nearest);
+        let composite = |region: &[f32], uv_map: &[f32]| {
+            composite_layer_uv(
+                &base_frame, &creative, &alpha, 1, 1, &[10.0; 4], uv_map, region, 2, 2, 5.0, &options,
+            )
+        };
+
+        // Only the region's top-left pixel lands in the frame
+        let result = composite(&[1.0, 1.0, 2.0, 2.0], &uv_map);
+        assert_eq!(result[12..], [200; 4]);
+        assert_eq!(result[..12], [0; 12]);
+        assert_eq!(composite(&[0.0, 0.0, 2.0, 2.0], &uv_map[..6]), base_frame);
+    }
+}
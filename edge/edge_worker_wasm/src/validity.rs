@@ -0,0 +1,127 @@
+//! Gating composites on an external surface validity score
+
+use wasm_bindgen::prelude::*;
+
+use crate::layer::LayerOptions;
+use crate::{composite_layer, log_info};
+
+/// Passes frames through untouched while the placement surface does not
+/// show what it should (a DRM slate, a different ad, a cut away), judged by
+/// a per-frame validity score from an external detector. Once the score
+/// drops under `threshold` the gate stays shut until it has held for
+/// `recover_frames` frames in a row, so a flickering score does not make
+/// the layer blink
+#[wasm_bindgen]
+pub struct ValidityGate {
+    threshold: f32,
+    recover_frames: u32,
+    open: bool,
+    /// Consecutive valid frames seen while shut
+    valid_streak: u32,
+    suppressed: u64,
+}
+
+#[wasm_bindgen]
+impl ValidityGate {
+    #[wasm_bindgen(constructor)]
+    pub fn new(threshold: f32, recover_frames: u32) -> ValidityGate {
+        ValidityGate {
+            threshold,
+            recover_frames: recover_frames.max(1),
+            open: true,
+            valid_streak: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Record this frame's score (NaN counts as invalid) and decide whether
+    /// the layer may be composited, for hosts gating other composite paths
+    pub fn admit(&mut self, validity: f32) -> bool {
+        let valid = validity >= self.threshold;
+        if self.open && !valid {
+            self.open = false;
+            self.valid_streak = 0;
+            log_info(&format!(
+                "WASM compositor: Surface validity {:.3} under {:.3}, passing frames through",
+                validity, self.threshold
+            ));
+        } else if !self.open {
+            self.valid_streak = if valid { self.valid_streak + 1 } else { 0 };
+            if self.valid_streak >= self.recover_frames {
+                self.open = true;
+                log_info("WASM compositor: Surface valid again, compositing resumed");
+            }
+        }
+        if !self.open {
+            self.suppressed += 1;
+        }
+        self.open
+    }
+
+    /// Same as `composite_layer`, returning the base frame while the gate
+    /// is shut
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite_layer(
+        &mut self,
+        base_frame: &[u8],
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        width: u32,
+        height: u32,
+        creative_depth: f32,
+        validity: f32,
+        options: &LayerOptions,
+    ) -> Vec<u8> {
+        if !self.admit(validity) {
+            return base_frame.to_vec();
+        }
+        composite_layer(
+            base_frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth, options,
+        )
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Frames passed through so far
+    pub fn suppressed_frames(&self) -> u64 {
+        self.suppressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuts_on_low_score_and_recovers_after_streak() {
+        let mut gate = ValidityGate::new(0.8, 3);
+        assert!(gate.admit(0.95));
+        assert!(!gate.admit(0.2));
+        assert!(!gate.admit(f32::NAN));
+
+        // A valid frame inside the streak does not reopen it, an invalid one restarts it
+        assert!(!gate.admit(0.9));
+        assert!(!gate.admit(0.1));
+        assert!(!gate.admit(0.9));
+        assert!(!gate.admit(0.9));
+        assert!(gate.admit(0.9));
+        assert_eq!(gate.suppressed_frames(), 6);
+    }
+
+    #[test]
+    fn test_shut_gate_passes_base_through() {
+        let base_frame = vec![30u8; 4];
+        let creative_frame = vec![200u8; 4];
+        let options = LayerOptions::new();
+        let mut gate = ValidityGate::new(0.5, 1);
+        let mut composite = |validity| {
+            gate.composite_layer(&base_frame, &creative_frame, &[10.0], &[255], 1, 1, 5.0, validity, &options)
+        };
+        assert_eq!(composite(0.7), creative_frame);
+        assert_eq!(composite(0.3), base_frame);
+        assert_eq!(composite(0.7), creative_frame);
+    }
+}
@@ -0,0 +1,189 @@
+//! Choosing among a creative's registered variants by locale and local time
+
+use std::cmp::{Ordering, Reverse};
+
+use wasm_bindgen::prelude::*;
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// One registered variant: where and when it applies
+#[derive(Clone, Debug, PartialEq)]
+struct Variant {
+    id: String,
+    /// Normalized language tags, e.g. `en-gb`; `*` matches any locale
+    locales: Vec<String>,
+    /// Local minutes of the day, `start..end`, wrapping past midnight; equal
+    /// ends cover the whole day
+    start_minute: i64,
+    end_minute: i64,
+}
+
+impl Variant {
+    /// How closely `locale` matches: subtags of the matching tag, 0 for `*`,
+    /// None when no tag matches
+    fn locale_rank(&self, locale: &str) -> Option<usize> {
+        self.locales
+            .iter()
+            .filter_map(|tag| match tag.as_str() {
+                "*" => Some(0),
+                tag if within_tag(locale, tag) => Some(tag.split('-').count()),
+                _ => None,
+            })
+            .max()
+    }
+
+    fn all_day(&self) -> bool {
+        self.start_minute == self.end_minute
+    }
+
+    fn covers(&self, minute: i64) -> bool {
+        let (start, end) = (self.start_minute, self.end_minute);
+        match start.cmp(&end) {
+            Ordering::Equal => true,
+            Ordering::Less => (start..end).contains(&minute),
+            Ordering::Greater => minute >= start || minute < end,
+        }
+    }
+}
+
+/// Picks which registered variant of a dynamic creative to show, e.g. the
+/// breakfast or the dinner menu in the viewer's language, from the viewer's
+/// locale and local time. The closest locale match wins, then a variant
+/// with a time window over an all-day one, then the first registered
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct VariantSelector {
+    variants: Vec<Variant>,
+}
+
+#[wasm_bindgen]
+impl VariantSelector {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> VariantSelector {
+        VariantSelector::default()
+    }
+
+    /// Register (or replace) a variant for the comma-separated language tags
+    /// in `locales` (`en`, `en-GB`, or `*` / empty for any), shown from
+    /// `start_hour` to `end_hour` local time. Windows may wrap past midnight;
+    /// equal hours mean all day
+    pub fn register_variant(&mut self, variant_id: &str, locales: &str, start_hour: f32, end_hour: f32) {
+        let mut tags: Vec<String> = locales
+            .split(',')
+            .map(normalize_locale)
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        if tags.is_empty() {
+            tags.push("*".to_string());
+        }
+        let minute = |hour: f32| {
+            let hour = if hour.is_finite() { hour } else { 0.0 };
+            ((hour * 60.0).round() as i64).rem_euclid(MINUTES_PER_DAY)
+        };
+        let variant = Variant {
+            id: variant_id.to_string(),
+            locales: tags,
+            start_minute: minute(start_hour),
+            end_minute: minute(end_hour),
+        };
+        match self.variants.iter_mut().find(|v| v.id == variant_id) {
+            Some(existing) => *existing = variant,
+            None => self.variants.push(variant),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.variants.clear();
+    }
+
+    /// Variant for a viewer in `locale` at `unix_seconds`, in a time zone
+    /// `utc_offset_minutes` ahead of UTC; `None` when none applies
+    pub fn select(&self, locale: &str, unix_seconds: f64, utc_offset_minutes: i32) -> Option<String> {
+        if !unix_seconds.is_finite() {
+            return None;
+        }
+        let local_minute = (unix_seconds / 60.0).floor() as i64 + utc_offset_minutes as i64;
+        let minute = local_minute.rem_euclid(MINUTES_PER_DAY);
+        let locale = normalize_locale(locale);
+        self.variants
+            .iter()
+            .enumerate()
+            .filter(|(_, variant)| variant.covers(minute))
+            .filter_map(|(order, variant)| {
+                let rank = variant.locale_rank(&locale)?;
+                Some(((rank, !variant.all_day(), Reverse(order)), variant))
+            })
+            .max_by_key(|&(key, _)| key)
+            .map(|(_, variant)| variant.id.clone())
+    }
+
+    /// `select` at the device's current time in its own time zone, so the
+    /// host only passes the locale
+    pub fn select_local(&self, locale: &str) -> Option<String> {
+        let (unix_seconds, utc_offset_minutes) = local_clock();
+        self.select(locale, unix_seconds, utc_offset_minutes)
+    }
+}
+
+/// `locale` is `tag` or a more specific form of it, e.g. `en-gb` of `en`
+fn within_tag(locale: &str, tag: &str) -> bool {
+    locale.strip_prefix(tag).is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+}
+
+/// Lowercase with `-` separators, so `en_GB` and `en-gb` are one tag
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_clock() -> (f64, i32) {
+    let now = js_sys::Date::new_0();
+    // getTimezoneOffset is UTC minus local time
+    (now.get_time() / 1000.0, -(now.get_timezone_offset() as i32))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn local_clock() -> (f64, i32) {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    (now.as_secs_f64(), 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-01-05 00:00 UTC
+    const MIDNIGHT: f64 = 1_767_571_200.0;
+
+    fn at(hour: f64) -> f64 {
+        MIDNIGHT + hour * 3600.0
+    }
+
+    #[test]
+    fn test_picks_by_local_time_and_locale() {
+        let mut selector = VariantSelector::new();
+        selector.register_variant("menu_default", "", 0.0, 0.0);
+        selector.register_variant("breakfast", "*", 5.0, 11.0);
+        selector.register_variant("dinner", "en", 17.0, 2.0);
+        selector.register_variant("dinner_uk", "en-GB, en-IE", 17.0, 2.0);
+
+        assert_eq!(selector.select("de-DE", at(8.0), 0).as_deref(), Some("breakfast"));
+        assert_eq!(selector.select("de-DE", at(19.0), 0).as_deref(), Some("menu_default"));
+        assert_eq!(selector.select("en-US", at(19.0), 0).as_deref(), Some("dinner"));
+        assert_eq!(selector.select("en_gb", at(1.5), 0).as_deref(), Some("dinner_uk"));
+        // A tag only matches on a subtag boundary
+        assert_eq!(selector.select("eng", at(19.0), 0).as_deref(), Some("menu_default"));
+
+        // 03:00 UTC is 19:00 the previous day in UTC-8, and 08:30 in UTC+5:30
+        assert_eq!(selector.select("en-US", at(3.0), -480).as_deref(), Some("dinner"));
+        assert_eq!(selector.select("en-US", at(3.0), 330).as_deref(), Some("breakfast"));
+
+        // Re-registering replaces the window
+        selector.register_variant("breakfast", "*", 6.0, 7.0);
+        assert_eq!(selector.select("de-DE", at(8.0), 0).as_deref(), Some("menu_default"));
+        selector.clear();
+        assert_eq!(selector.select("de-DE", at(8.0), 0), None);
+    }
+}
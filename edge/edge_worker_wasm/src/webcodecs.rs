@@ -0,0 +1,114 @@
+//! WebCodecs `VideoFrame` input and output
+
+use js_sys::{Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{PlaneLayout, VideoFrame, VideoFrameBufferInit, VideoFrameCopyToOptions, VideoPixelFormat};
+
+use crate::composite_layer;
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::log_error;
+use crate::memory::try_zeroed;
+
+/// Composites decoded `VideoFrame`s and hands back new ones for the encoder,
+/// so a decode -> composite -> encode pipeline moves no pixels through JS.
+/// Each frame is copied as RGBA straight into a buffer in wasm memory
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct VideoFrameCompositor {
+    frame: Vec<u8>,
+    width: u32,
+    height: u32,
+    timestamp: f64,
+    duration: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl VideoFrameCompositor {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> VideoFrameCompositor {
+        VideoFrameCompositor::default()
+    }
+
+    /// Start copying the visible part of `frame` into wasm memory as RGBA;
+    /// composite once the returned promise resolves, after which the frame
+    /// may be closed. Nothing else may run in the module until then: memory
+    /// growing mid-copy would detach the destination
+    pub fn load(&mut self, frame: &VideoFrame) -> Result<Promise, JsValue> {
+        let (width, height) = match frame.visible_rect() {
+            Some(rect) => (rect.width() as u32, rect.height() as u32),
+            None => (frame.coded_width(), frame.coded_height()),
+        };
+        let frame_bytes = (width * height * 4) as usize;
+        if self.frame.len() != frame_bytes {
+            let Some(buffer) = try_zeroed::<u8>(frame_bytes) else {
+                log_error("WASM compositor: Out of memory, frame dropped");
+                health::degrade(DegradationTier::FrameDropped);
+                return Err(JsValue::from_str("out of memory"));
+            };
+            self.frame = buffer;
+        }
+        (self.width, self.height) = (width, height);
+        self.timestamp = frame.timestamp();
+        self.duration = frame.duration();
+
+        let options = VideoFrameCopyToOptions::new();
+        options.set_format(VideoPixelFormat::Rgba);
+        options.set_layout(&[PlaneLayout::new(0, width * 4)]);
+        // SAFETY: the view covers exactly `self.frame`, which is not resized
+        // or freed before the copy completes (see above)
+        let destination = unsafe { Uint8Array::view_mut_raw(self.frame.as_mut_ptr(), frame_bytes) };
+        Ok(frame.copy_to_with_u8_array_and_options(&destination, &options).unchecked_into())
+    }
+
+    /// Same as `composite_layer` over the loaded frame, returned as a new
+    /// RGBA `VideoFrame` with the source's timestamp and duration. A frame
+    /// `composite_layer` drops comes back unchanged
+    pub fn composite_layer(
+        &self,
+        creative_frame: &[u8],
+        depth_map: &[f32],
+        alpha_mask: &[u8],
+        creative_depth: f32,
+        options: &LayerOptions,
+    ) -> Result<VideoFrame, JsValue> {
+        let (width, height) = (self.width, self.height);
+        let mut frame = composite_layer(
+            &self.frame, creative_frame, depth_map, alpha_mask, width, height, creative_depth, options,
+        );
+        if frame.len() != self.frame.len() {
+            frame.clone_from(&self.frame);
+        }
+        let init = VideoFrameBufferInit::new_with_f64(height, width, VideoPixelFormat::Rgba, self.timestamp);
+        if let Some(duration) = self.duration {
+            init.set_duration_f64(duration);
+        }
+        VideoFrame::new_with_u8_slice_and_video_frame_buffer_init(&mut frame, &init)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// RGBA `VideoFrame` from a frame composited elsewhere, at `timestamp`
+/// microseconds
+#[wasm_bindgen]
+pub fn video_frame_from_rgba(
+    frame: &[u8],
+    width: u32,
+    height: u32,
+    timestamp: f64,
+) -> Result<VideoFrame, JsValue> {
+    if frame.len() != (width * height * 4) as usize {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return Err(JsValue::from_str("invalid frame size"));
+    }
+    let init = VideoFrameBufferInit::new_with_f64(height, width, VideoPixelFormat::Rgba, timestamp);
+    VideoFrame::new_with_u8_slice_and_video_frame_buffer_init(&mut frame.to_vec(), &init)
+}
@@ -0,0 +1,207 @@
+//! Source windows: showing only part of a creative, optionally moving
+
+use std::borrow::Cow;
+
+use wasm_bindgen::prelude::*;
+
+use crate::geometry::Rect;
+use crate::layer::LayerOptions;
+use crate::health::{self, DegradationTier};
+use crate::log_error;
+use crate::memory::try_zeroed;
+use crate::sampler::{AxisTap, EdgeMode};
+
+/// The whole creative, in normalized units
+pub(crate) const FULL_WINDOW: Rect = Rect { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+/// Progress curve of an animated window
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Easing {
+    Linear = 0,
+    /// Slow start
+    EaseIn = 1,
+    /// Slow finish
+    EaseOut = 2,
+    /// Slow start and finish (smoothstep)
+    #[default]
+    EaseInOut = 3,
+}
+
+impl Easing {
+    pub(crate) fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Ken Burns move: the window travels from `start` to `end` over `duration`
+/// seconds from `start_time`, then holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct KenBurns {
+    pub start: Rect,
+    pub end: Rect,
+    pub start_time: f64,
+    pub duration: f64,
+    pub easing: Easing,
+}
+
+impl KenBurns {
+    pub fn window_at(&self, time: f64) -> Rect {
+        let t = if self.duration > 0.0 { ((time - self.start_time) / self.duration) as f32 } else { 1.0 };
+        let t = self.easing.apply(t);
+
+        // Size changes geometrically so the zoom looks even; the center moves
+        // linearly
+        let size = |from: f32, to: f32| from * (to / from).powf(t);
+        let width = size(self.start.width, self.end.width);
+        let height = size(self.start.height, self.end.height);
+        let (start_x, start_y) = self.start.center();
+        let (end_x, end_y) = self.end.center();
+        let cx = start_x + (end_x - start_x) * t;
+        let cy = start_y + (end_y - start_y) * t;
+        Rect::new(
+            (cx - width * 0.5).clamp(0.0, 1.0 - width),
+            (cy - height * 0.5).clamp(0.0, 1.0 - height),
+            width,
+            height,
+        )
+    }
+}
+
+/// Normalized window from a flat `[u, v, width, height]`, clamped inside
+/// the creative
+pub(crate) fn clamp_window(u: f32, v: f32, width: f32, height: f32) -> Rect {
+    let width = width.clamp(f32::EPSILON, 1.0);
+    let height = height.clamp(f32::EPSILON, 1.0);
+    Rect::new(u.clamp(0.0, 1.0 - width), v.clamp(0.0, 1.0 - height), width, height)
+}
+
+/// Window panned for `time` seconds at `speed` (normalized units per second),
+/// bouncing off the creative's edges so it never jumps
+pub(crate) fn panned(window: &Rect, speed: (f32, f32), time: f64) -> Rect {
+    let travel = |start: f32, size: f32, speed: f32| {
+        let span = (1.0 - size) as f64;
+        if span <= 0.0 || speed == 0.0 {
+            return start;
+        }
+        let position = (start as f64 + speed as f64 * time).rem_euclid(2.0 * span);
+        (if position > span { 2.0 * span - position } else { position }) as f32
+    };
+    Rect {
+        x: travel(window.x, window.width, speed.0),
+        y: travel(window.y, window.height, speed.1),
+        ..*window
+    }
+}
+
+/// Creative with its source window stretched over the full creative area,
+/// borrowed when the whole creative is shown; the mask is untouched since it
+/// marks the surface, not the creative
+pub(crate) fn apply_window<'a>(
+    creative: &'a [u8],
+    width: u32,
+    height: u32,
+    options: &LayerOptions,
+) -> Cow<'a, [u8]> {
+    let window = options.window_at_time();
+    let (width, height) = (width as usize, height as usize);
+    if window == FULL_WINDOW || creative.len() < width * height * 4 {
+        return Cow::Borrowed(creative);
+    }
+    let Some(mut windowed) = try_zeroed(width * height * 4) else {
+        log_error("WASM compositor: Out of memory, showing the whole creative");
+        health::degrade(DegradationTier::Reduced);
+        return Cow::Borrowed(creative);
+    };
+
+    // Output pixel center n + 0.5 maps to the window's matching point
+    let tap = |n: usize, start: f32, size: f32, extent: usize| {
+        let coord = extent as f32 * start + (n as f32 + 0.5) * size;
+        AxisTap::new(coord, extent, EdgeMode::Clamp, options.filter_mode)
+    };
+    let columns: Vec<AxisTap> = (0..width).map(|x| tap(x, window.x, window.width, width)).collect();
+    for (y, out) in windowed.chunks_exact_mut(width * 4).enumerate() {
+        let row = tap(y, window.y, window.height, height);
+        let weights_y = [1.0 - row.frac, row.frac];
+        for (column, pixel) in columns.iter().zip(out.chunks_exact_mut(4)) {
+            let weights_x = [1.0 - column.frac, column.frac];
+            let mut value = [0.0f32; 4];
+            for (&source_y, weight_y) in row.index.iter().zip(weights_y) {
+                for (&source_x, weight_x) in column.index.iter().zip(weights_x) {
+                    let index = (source_y * width + source_x) * 4;
+                    for (channel, sum) in value.iter_mut().enumerate() {
+                        *sum += weight_x * weight_y * creative[index + channel] as f32;
+                    }
+                }
+            }
+            for (channel, sum) in pixel.iter_mut().zip(value) {
+                *channel = (sum + 0.5) as u8;
+            }
+        }
+    }
+    Cow::Owned(windowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_shows_creative_portion() {
+        // 4x1 creative, red ramp
+        let creative: Vec<u8> = [0u8, 80, 160, 240].iter().flat_map(|&r| [r, 0, 0, 255]).collect();
+        let mut options = LayerOptions::new();
+        assert!(matches!(apply_window(&creative, 4, 1, &options), Cow::Borrowed(_)));
+
+        // Right half stretched over the whole width
+        options.set_source_window(0.5, 0.0, 0.5, 1.0);
+        options.set_filter_mode(crate::FilterMode::Nearest);
+        let windowed = apply_window(&creative, 4, 1, &options);
+        let red: Vec<u8> = windowed.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(red, vec![160, 160, 240, 240]);
+    }
+
+    #[test]
+    fn test_ken_burns_eases_between_windows() {
+        let ken_burns = KenBurns {
+            start: FULL_WINDOW,
+            end: Rect::new(0.5, 0.5, 0.25, 0.25),
+            start_time: 10.0,
+            duration: 4.0,
+            easing: Easing::EaseInOut,
+        };
+        assert_eq!(ken_burns.window_at(0.0), FULL_WINDOW);
+        assert_eq!(ken_burns.window_at(14.0), ken_burns.end);
+        assert_eq!(ken_burns.window_at(99.0), ken_burns.end);
+
+        // Halfway: geometric size, linear center
+        let middle = ken_burns.window_at(12.0);
+        assert_eq!((middle.width, middle.height), (0.5, 0.5));
+        assert_eq!(middle.center(), (0.5625, 0.5625));
+
+        // Easing: slow start
+        let early = ken_burns.window_at(10.4);
+        let linear = KenBurns { easing: Easing::Linear, ..ken_burns }.window_at(10.4);
+        assert!(early.width > linear.width);
+    }
+
+    #[test]
+    fn test_pan_bounces_between_edges() {
+        let window = Rect::new(0.0, 0.25, 0.5, 0.5);
+        let speed = (0.25, 0.0);
+        assert_eq!(panned(&window, speed, 1.0).x, 0.25);
+        assert_eq!(panned(&window, speed, 2.0).x, 0.5);
+        assert_eq!(panned(&window, speed, 3.0).x, 0.25);
+        assert_eq!(panned(&window, speed, 4.0).x, 0.0);
+        assert_eq!(panned(&window, speed, 3.0).y, 0.25);
+
+        // A full-width window has nowhere to pan
+        assert_eq!(panned(&FULL_WINDOW, speed, 3.0), FULL_WINDOW);
+    }
+}
@@ -0,0 +1,236 @@
+//! Compositing straight onto planar YUV 4:2:0 frames from the decoder
+
+use wasm_bindgen::prelude::*;
+
+use crate::color::{rgb_to_ycbcr, ColorRange, ColorSpace};
+use crate::health::{self, DegradationTier};
+use crate::layer::LayerOptions;
+use crate::memory::{try_copy, Scope, Subsystem};
+use crate::occlusion::visibility;
+use crate::{log_error, warp_to_frame_in, UNIT_FROM_U8};
+
+/// Plane layout of a 4:2:0 frame, as WebCodecs `VideoFrame.format` names it
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum YuvFormat {
+    /// Y plane, then U and V planes at half size each way
+    #[default]
+    I420 = 0,
+    /// Y plane, then one half-size plane of interleaved U and V
+    Nv12 = 1,
+}
+
+/// Luma weights of the video's color matrix; both limited range
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum YuvMatrix {
+    /// SD content
+    Bt601 = 0,
+    /// HD content, what most streams carry
+    #[default]
+    Bt709 = 1,
+    /// UHD and HDR10 content
+    Bt2020 = 2,
+}
+
+impl YuvMatrix {
+    /// Red and blue luma weights
+    pub(crate) fn weights(self) -> (f32, f32) {
+        match self {
+            YuvMatrix::Bt601 => (0.299, 0.114),
+            YuvMatrix::Bt709 => (0.2126, 0.0722),
+            YuvMatrix::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+
+    /// Limited-range Y, U and V of an 8-bit RGB color, unrounded
+    fn to_yuv(self, rgb: &[u8]) -> [f32; 3] {
+        self.unit_to_yuv([0, 1, 2].map(|c| UNIT_FROM_U8[rgb[c] as usize]))
+    }
+
+    /// Limited-range Y, U and V of a 0..1 RGB color in 8-bit code values,
+    /// unrounded; 10-bit code values are four times these
+    pub(crate) fn unit_to_yuv(self, rgb: [f32; 3]) -> [f32; 3] {
+        rgb_to_ycbcr(self, ColorRange::Limited, rgb)
+    }
+}
+
+/// Byte offsets of chroma sample `k`'s U and V in a frame whose luma and
+/// each chroma plane hold `luma` and `chroma` samples
+fn chroma_layout(format: YuvFormat, luma: usize, chroma: usize, k: usize) -> (usize, usize) {
+    match format {
+        YuvFormat::I420 => (luma + k, luma + chroma + k),
+        YuvFormat::Nv12 => (luma + k * 2, luma + k * 2 + 1),
+    }
+}
+
+/// `composite_layer` onto a 4:2:0 `base_frame` in `format`, returning the
+/// same format so decoded frames need no RGBA round trip. The creative is
+/// warped as usual and converted to YUV per `matrix`; luma blends per
+/// pixel and each chroma sample by the mean coverage of the pixels it
+/// spans. Odd sizes round the chroma planes up. An empty result means the
+/// frame was dropped for memory
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn composite_layer_yuv(
+    base_frame: &[u8],
+    format: YuvFormat,
+    matrix: YuvMatrix,
+    creative_frame: &[u8],
+    depth_map: &[f32],
+    alpha_mask: &[u8],
+    width: u32,
+    height: u32,
+    creative_depth: f32,
+    options: &LayerOptions,
+) -> Vec<u8> {
+    let _memory = Scope::enter(Subsystem::Composite);
+    health::frame_started();
+    let (w, h) = (width as usize, height as usize);
+    let (chroma_width, chroma_height) = (w.div_ceil(2), h.div_ceil(2));
+    let (luma, chroma) = (w * h, chroma_width * chroma_height);
+    if base_frame.len() < luma + chroma * 2 || depth_map.len() < luma {
+        log_error("WASM compositor: Invalid input buffer sizes");
+        return base_frame.to_vec();
+    }
+
+    // The creative takes the matrix's primaries before it is encoded
+    let rgb = ColorSpace::new(matrix, ColorRange::Full);
+    let warped = warp_to_frame_in(creative_frame, alpha_mask, width, height, options, rgb);
+    let Some((creative, coverage)) = warped else {
+        return base_frame.to_vec();
+    };
+    let Some(mut result) = try_copy(base_frame) else {
+        log_error("WASM compositor: Out of memory, frame dropped");
+        health::degrade(DegradationTier::FrameDropped);
+        return Vec::new();
+    };
+    // Coverage after the depth test
+    let math = options.blend_math();
+    let weight = |i: usize| {
+        if coverage[i] > 0 {
+            let visible = visibility(creative_depth, depth_map[i], math.depth_feather);
+            UNIT_FROM_U8[coverage[i] as usize] * visible * math.opacity
+        } else {
+            0.0
+        }
+    };
+    let mix = |base: u8, value: f32, alpha: f32| (base as f32 + (value - base as f32) * alpha + 0.5) as u8;
+    let chroma_at = |k| chroma_layout(format, luma, chroma, k);
+    let creative_yuv = |i: usize| matrix.to_yuv(&creative[i * 4..i * 4 + 3]);
+    blend_420(w, h, weight, creative_yuv, chroma_at, |at, value, alpha| {
+        result[at] = mix(result[at], value, alpha)
+    });
+    result
+}
+
+/// Blend a warped creative into a 4:2:0 frame: `mix(offset, value, alpha)`
+/// mixes `value` into the sample at `offset` for each covered luma pixel
+/// and chroma sample, given the creative's YUV and depth-tested coverage
+/// per pixel and the U and V offsets of each chroma sample
+pub(crate) fn blend_420(
+    width: usize,
+    height: usize,
+    weight: impl Fn(usize) -> f32,
+    creative_yuv: impl Fn(usize) -> [f32; 3],
+    chroma_at: impl Fn(usize) -> (usize, usize),
+    mut mix: impl FnMut(usize, f32, f32),
+) {
+    let (w, h) = (width, height);
+    let chroma_width = w.div_ceil(2);
+    for cy in 0..h.div_ceil(2) {
+        for cx in 0..chroma_width {
+            // Coverage-weighted chroma of the pixels under this sample
+            let (mut total, mut u, mut v, mut count) = (0.0, 0.0, 0.0, 0.0);
+            for y in cy * 2..(cy * 2 + 2).min(h) {
+                for x in cx * 2..(cx * 2 + 2).min(w) {
+                    count += 1.0;
+                    let i = y * w + x;
+                    let alpha = weight(i);
+                    if alpha == 0.0 {
+                        continue;
+                    }
+                    let [luma_value, u_value, v_value] = creative_yuv(i);
+                    mix(i, luma_value, alpha);
+                    total += alpha;
+                    u += u_value * alpha;
+                    v += v_value * alpha;
+                }
+            }
+            if total == 0.0 {
+                continue;
+            }
+
+            let (u_at, v_at) = chroma_at(cy * chroma_width + cx);
+            mix(u_at, u / total, total / count);
+            mix(v_at, v / total, total / count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Black limited-range frame
+    fn black(width: usize, height: usize) -> Vec<u8> {
+        let chroma = width.div_ceil(2) * height.div_ceil(2);
+        let mut frame = vec![16u8; width * height];
+        frame.resize(width * height + chroma * 2, 128);
+        frame
+    }
+
+    #[test]
+    fn test_color_matrices() {
+        let white = YuvMatrix::Bt709.to_yuv(&[255, 255, 255]);
+        assert!(white.iter().zip([235.0, 128.0, 128.0]).all(|(a, b)| (a - b).abs() < 1e-3), "{:?}", white);
+        let red = YuvMatrix::Bt601.to_yuv(&[255, 0, 0]).map(|value| (value + 0.5) as u8);
+        assert_eq!(red, [81, 90, 240]);
+    }
+
+    #[test]
+    fn test_composites_in_both_layouts() {
+        let pixel_count = 8;
+        let depth = vec![10.0f32; pixel_count];
+        // Pure blue over the left half
+        let creative: Vec<u8> = (0..pixel_count).flat_map(|_| [0, 0, 255, 255]).collect();
+        let alpha_mask: Vec<u8> = (0..pixel_count).map(|i| if i % 4 < 2 { 255 } else { 0 }).collect();
+        let options = LayerOptions::new();
+        let composite = |format| {
+            let base = black(4, 2);
+            let matrix = YuvMatrix::Bt709;
+            composite_layer_yuv(&base, format, matrix, &creative, &depth, &alpha_mask, 4, 2, 5.0, &options)
+        };
+
+        let i420 = composite(YuvFormat::I420);
+        let [y, u, v] = YuvMatrix::Bt709.to_yuv(&[0, 0, 255]).map(|value| (value + 0.5) as u8);
+        assert_eq!(i420[..8], [y, y, 16, 16, y, y, 16, 16]);
+        assert_eq!(i420[8..], [u, 128, v, 128]);
+
+        // NV12 holds the same samples interleaved
+        let nv12 = composite(YuvFormat::Nv12);
+        assert_eq!(nv12[..8], i420[..8]);
+        assert_eq!(nv12[8..], [u, v, 128, 128]);
+    }
+
+    #[test]
+    fn test_chroma_takes_mean_coverage_and_depth() {
+        let creative = [255u8, 255, 255, 255].repeat(4);
+        // One pixel of four behind the scene, one uncovered
+        let depth_map = [10.0f32, 1.0, 10.0, 10.0];
+        let alpha_mask = [255u8, 255, 0, 255];
+        let options = LayerOptions::new();
+        let composite = |base: &[u8]| {
+            let (format, matrix) = (YuvFormat::I420, YuvMatrix::Bt709);
+            composite_layer_yuv(base, format, matrix, &creative, &depth_map, &alpha_mask, 2, 2, 5.0, &options)
+        };
+
+        let mut base = black(2, 2);
+        base[4..].copy_from_slice(&[200, 60]);
+        let result = composite(&base);
+        assert_eq!(result[..4], [235, 16, 16, 235]);
+        // Half the block shows neutral white chroma
+        assert_eq!(result[4..], [164, 94]);
+        assert_eq!(composite(&base[..5]), base[..5]);
+    }
+}
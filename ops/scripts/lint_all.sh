@@ -113,7 +113,19 @@ if [ -d "edge" ] && [ -f "edge/Cargo.toml" ]; then
         echo "✗ clippy found issues"
         ((LINT_ERRORS++))
     fi
-    
+
+    # Minimal profiles of the WASM worker: code only one feature uses must
+    # be gated on it, or these fail on dead code
+    echo "Running clippy over the WASM worker's feature profiles..."
+    for features in "" "effects" "json" "policy" "parity"; do
+        if cargo clippy -p edge_worker_wasm --all-targets --no-default-features --features "$features" -- -D warnings; then
+            echo "✓ clippy passed (features: ${features:-none})"
+        else
+            echo "✗ clippy found issues (features: ${features:-none})"
+            ((LINT_ERRORS++))
+        fi
+    done
+
     cd ..
 fi
 
@@ -17,6 +17,9 @@ import xml.etree.ElementTree as ET
 
 logger = logging.getLogger(__name__)
 
+# Temporal smoothing profiles the edge compositor knows (TransformFilter.from_profile)
+SMOOTHING_PROFILES = ("balanced", "rigid", "handheld")
+
 @dataclass
 class PlacementOpportunity:
     """Individual placement opportunity data"""
@@ -33,6 +36,7 @@ class PlacementOpportunity:
     brand_safety_rating: str
     rights_status: str
     metadata: Dict[str, Any]
+    smoothing_profile: str = "balanced"  # Tripod shots "rigid", shaky ones "handheld"
 
 @dataclass
 class SidecarManifest:
@@ -129,6 +133,11 @@ class SidecarPackager:
             
             # Extract surface coordinates
             coordinates = opp_data.get("surface_coordinates", [[0, 0], [100, 0], [100, 100], [0, 100]])
+
+            smoothing_profile = opp_data.get("smoothing_profile", "balanced")
+            if smoothing_profile not in SMOOTHING_PROFILES:
+                logger.warning(f"Unknown smoothing profile {smoothing_profile!r}, using balanced")
+                smoothing_profile = "balanced"
             
             opportunity = PlacementOpportunity(
                 opportunity_id=str(uuid.uuid4()),
@@ -143,7 +152,8 @@ class SidecarPackager:
                 recommended_content_size=opp_data.get("recommended_content_size", (512, 512)),
                 brand_safety_rating=opp_data.get("brand_safety_rating", "safe"),
                 rights_status=opp_data.get("rights_status", "available"),
-                metadata=opp_data.get("metadata", {})
+                metadata=opp_data.get("metadata", {}),
+                smoothing_profile=smoothing_profile
             )
             
             return opportunity
@@ -282,6 +292,7 @@ class SidecarPackager:
                 opp_elem.set("id", opp.opportunity_id)
                 opp_elem.set("surface_id", opp.surface_id)
                 opp_elem.set("prs_score", str(opp.prs_score))
+                opp_elem.set("smoothing_profile", opp.smoothing_profile)
                 
                 # Frame range
                 frame_range = ET.SubElement(opp_elem, "frame_range")
@@ -324,7 +335,7 @@ class SidecarPackager:
                 fieldnames = [
                     'opportunity_id', 'surface_id', 'start_frame', 'end_frame',
                     'start_timecode', 'end_timecode', 'prs_score', 'placement_type',
-                    'brand_safety_rating', 'rights_status', 'surface_coords'
+                    'brand_safety_rating', 'rights_status', 'smoothing_profile', 'surface_coords'
                 ]
                 
                 writer = csv.DictWriter(csvfile, fieldnames=fieldnames)
@@ -345,6 +356,7 @@ class SidecarPackager:
                         'placement_type': opp.placement_type,
                         'brand_safety_rating': opp.brand_safety_rating,
                         'rights_status': opp.rights_status,
+                        'smoothing_profile': opp.smoothing_profile,
                         'surface_coords': coords_str
                     }
                     